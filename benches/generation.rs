@@ -0,0 +1,68 @@
+// Пропускная способность цикла генерации: сколько котировок в секунду успевает
+// выдать генератор для N тикеров, на каждый из которых подписан клиент
+// (полный путь: цена, bid/ask, стакан, сериализация и рассылка).
+//
+//     cargo bench --bench generation [-- <тикеров> <секунд> <потоков>]
+use quote_common::generator::OverflowPolicy;
+use quote_common::{QuoteGenerator, ServerMetrics, TickerSpec};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TICKERS: usize = 5000;
+const DEFAULT_SECONDS: u64 = 10;
+
+fn main() {
+    // cargo bench добавляет свой флаг --bench
+    let args: Vec<u64> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    let tickers = args.first().map_or(DEFAULT_TICKERS, |&n| n as usize);
+    let seconds = args.get(1).copied().unwrap_or(DEFAULT_SECONDS);
+    let workers = args.get(2).map_or(1, |&n| n as usize);
+
+    let names: Vec<String> = (0..tickers).map(|i| format!("T{:05}", i)).collect();
+    let specs = names
+        .iter()
+        .map(|name| (name.clone(), TickerSpec::default()))
+        .collect();
+    let metrics = Arc::new(ServerMetrics::new());
+    let generator = QuoteGenerator::with_specs(specs, 0.01, Some(42))
+        .with_depth(5)
+        .with_idle_slowdown(1)
+        .with_workers(workers)
+        .with_channel_capacity(16, OverflowPolicy::DropNewest)
+        .with_metrics(metrics.clone());
+
+    // Очереди подписчика никто не читает: когда они заполнятся, новые события
+    // отбрасываются без ожидания, и замер не делит процессор с читателем
+    let (_, _receivers) = generator.subscribe_to_tickers(names);
+
+    // Интервал 1 мс: каждый цикл обходит все тикеры, генератор загружен полностью
+    let handle = generator.start(1);
+    thread::sleep(Duration::from_millis(500));
+    // Скорость по секундным окнам; медиана устойчива к помехам от других процессов
+    let mut rates = Vec::new();
+    let mut trades = metrics.trades_generated();
+    let mut started = Instant::now();
+    for _ in 0..seconds {
+        thread::sleep(Duration::from_secs(1));
+        let now = metrics.trades_generated();
+        rates.push((now - trades) as f64 / started.elapsed().as_secs_f64());
+        trades = now;
+        started = Instant::now();
+    }
+    handle.shutdown();
+
+    rates.sort_by(f64::total_cmp);
+    println!(
+        "generation: {} tickers, {} workers: median {:.0} quotes/s (min {:.0}, max {:.0}) over {}s",
+        tickers,
+        workers,
+        rates[rates.len() / 2],
+        rates[0],
+        rates[rates.len() - 1],
+        seconds
+    );
+}
@@ -0,0 +1,27 @@
+use quote_common::TestServer;
+use std::time::Duration;
+
+// Сервер и клиент в одном процессе: STREAM, PING и STOP без запуска бинарников
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let server = TestServer::builder()
+        .with_tickers(&["AAPL", "TSLA"])
+        .start()?;
+    println!(
+        "Server on {}, ping on {}",
+        server.addr(),
+        server.ping_addr()
+    );
+
+    let mut client = server.client()?;
+    println!("Session {}", client.session());
+    println!("STREAM: {}", client.stream(&["AAPL"], "")?);
+
+    for quote in client.recv_quotes(5, Duration::from_secs(2))? {
+        println!("{} {:.2} {}", quote.ticker, quote.price, quote.volume);
+    }
+    println!("PING: {}", if client.ping()? { "PONG" } else { "no reply" });
+    println!("STOP: {}", client.stop()?);
+
+    server.shutdown();
+    Ok(())
+}
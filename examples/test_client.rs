@@ -21,7 +21,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Читаем приветственное сообщение
     let mut buf = [0; 1024];
     let n = tcp_stream.read(&mut buf)?;
-    println!("{}", String::from_utf8_lossy(&buf[..n]));
+    let greeting = String::from_utf8_lossy(&buf[..n]).to_string();
+    println!("{}", greeting);
+
+    // Токен сессии из первой строки приветствия: им подписываются PING
+    let session = greeting
+        .lines()
+        .find_map(|line| line.strip_prefix("SESSION "))
+        .unwrap_or_default()
+        .to_string();
 
     // Отправляем команду STREAM
     let stream_command = "STREAM udp://127.0.0.1:34255 AAPL,TSLA\n";
@@ -35,10 +43,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Запускаем поток для отправки PING сообщений
     let server_ping_addr = "127.0.0.1:34254"; // Сервер слушает на порту ping_port
+    let ping_message = format!("PING {}", session);
     let ping_thread = thread::spawn(move || {
         loop {
             thread::sleep(Duration::from_secs(1));
-            let _ = ping_socket.send_to(b"PING", server_ping_addr);
+            let _ = ping_socket.send_to(ping_message.as_bytes(), server_ping_addr);
         }
     });
 
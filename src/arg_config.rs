@@ -0,0 +1,187 @@
+use crate::toml_lite;
+use serde_json::Value;
+use std::ffi::OsString;
+
+// Откуда кроме командной строки берутся флаги программы
+pub struct ArgSources<'a> {
+    // Загружается без --config, если лежит в текущем каталоге
+    pub default_config: &'a str,
+    // Префикс переменных окружения (QUOTE_SERVER_); None - без переменных
+    pub env_prefix: Option<&'a str>,
+}
+
+// Файл конфигурации: ключи - длинные имена флагов (server_addr или
+// server-addr), таблицы только группируют ключи ([sinks], [alerts]). Значения
+// и переменные окружения превращаются в флаги перед аргументами командной
+// строки. Приоритет: командная строка, затем переменная окружения, затем файл;
+// флаг из более приоритетного источника заменяет значение целиком.
+pub fn merge_args(
+    command: &clap::Command,
+    argv: Vec<OsString>,
+    sources: &ArgSources,
+) -> Result<Vec<OsString>, String> {
+    let cli: Vec<String> = argv
+        .iter()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let env = EnvArgs {
+        prefix: sources.env_prefix,
+    };
+
+    let mut merged = vec![argv[0].clone()];
+    let (path, explicit) = match config_flag(&cli).or_else(|| env.get("config")) {
+        Some(path) => (path, true),
+        None => (sources.default_config.to_string(), false),
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            let config = toml_lite::parse(&content).map_err(|e| format!("{}: {}", path, e))?;
+            if !given_on_command_line(&cli, "config", None) {
+                merged.push(format!("--config={}", path).into());
+            }
+            collect(command, &path, &config, &cli, &env, &mut merged)?;
+        }
+        Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(format!("cannot read {}: {}", path, e)),
+    }
+    env.collect(command, &cli, &mut merged)?;
+    merged.extend(argv.into_iter().skip(1));
+    Ok(merged)
+}
+
+struct EnvArgs<'a> {
+    prefix: Option<&'a str>,
+}
+
+impl EnvArgs<'_> {
+    // QUOTE_SERVER_PING_PORT для --ping-port
+    fn name(&self, long: &str) -> Option<String> {
+        self.prefix
+            .map(|prefix| format!("{}{}", prefix, long.replace('-', "_").to_uppercase()))
+    }
+
+    fn get(&self, long: &str) -> Option<String> {
+        std::env::var(self.name(long)?).ok()
+    }
+
+    fn collect(
+        &self,
+        command: &clap::Command,
+        cli: &[String],
+        out: &mut Vec<OsString>,
+    ) -> Result<(), String> {
+        if self.prefix.is_none() {
+            return Ok(());
+        }
+        for arg in command.get_arguments() {
+            let Some(long) = arg.get_long() else {
+                continue;
+            };
+            if matches!(long, "config" | "help" | "version")
+                || given_on_command_line(cli, long, arg.get_short())
+            {
+                continue;
+            }
+            let Some(value) = self.get(long) else {
+                continue;
+            };
+            if arg.get_action().takes_values() {
+                out.push(format!("--{}={}", long, value).into());
+                continue;
+            }
+            match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" => out.push(format!("--{}", long).into()),
+                "0" | "false" | "no" | "" => {}
+                _ => {
+                    return Err(format!(
+                        "{}: expected true or false, got '{}'",
+                        self.name(long).unwrap_or_default(),
+                        value
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn config_flag(cli: &[String]) -> Option<String> {
+    let mut args = cli.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().cloned();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+fn collect(
+    command: &clap::Command,
+    path: &str,
+    table: &Value,
+    cli: &[String],
+    env: &EnvArgs,
+    out: &mut Vec<OsString>,
+) -> Result<(), String> {
+    let Some(table) = table.as_object() else {
+        return Ok(());
+    };
+    for (key, value) in table {
+        if value.is_object() {
+            collect(command, path, value, cli, env, out)?;
+            continue;
+        }
+        let name = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| {
+                arg.get_long() == Some(name.as_str())
+                    || arg
+                        .get_all_aliases()
+                        .is_some_and(|aliases| aliases.contains(&name.as_str()))
+            })
+            .filter(|arg| arg.get_id() != "config")
+            .ok_or_else(|| format!("unknown option '{}' in {}", key, path))?;
+        let long = arg.get_long().unwrap_or(name.as_str());
+        if given_on_command_line(cli, long, arg.get_short()) || env.get(long).is_some() {
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(items) => items.iter().collect(),
+            _ => vec![value],
+        };
+        for value in values {
+            let text = match value {
+                // Флаг без значения (--tui) ставится только для true
+                Value::Bool(flag) if !arg.get_action().takes_values() => {
+                    if *flag {
+                        out.push(format!("--{}", long).into());
+                    }
+                    continue;
+                }
+                Value::Bool(flag) => flag.to_string(),
+                Value::Number(number) => number.to_string(),
+                Value::String(text) => text.clone(),
+                _ => return Err(format!("unsupported value for '{}' in {}", key, path)),
+            };
+            out.push(format!("--{}={}", long, text).into());
+        }
+    }
+    Ok(())
+}
+
+fn given_on_command_line(cli: &[String], long: &str, short: Option<char>) -> bool {
+    let long_flag = format!("--{}", long);
+    cli.iter().any(|arg| {
+        arg == &long_flag
+            || arg.starts_with(&format!("{}=", long_flag))
+            || short.is_some_and(|short| {
+                !arg.starts_with("--") && arg.starts_with(&format!("-{}", short))
+            })
+    })
+}
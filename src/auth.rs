@@ -0,0 +1,114 @@
+use crate::error::QuoteError;
+use crate::models::CommandError;
+use crate::toml_lite;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::info;
+
+// Права одного API ключа: какие тикеры и сколько сразу можно подписать,
+// дает ли ключ административные команды
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    pub key: String,
+    // Имя для журнала и ответа AUTH_OK; сам ключ в журнал не пишется
+    pub name: String,
+    // Разрешенные тикеры; не задано - все
+    #[serde(default)]
+    pub tickers: Option<Vec<String>>,
+    // Наибольшее число тикеров в одной команде STREAM
+    #[serde(default)]
+    pub max_tickers: Option<usize>,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+impl ApiKey {
+    pub fn allows_ticker(&self, ticker: &str) -> bool {
+        self.tickers
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|t| t == ticker))
+    }
+
+    // Проверка подписки STREAM по правам ключа
+    pub fn check_stream(&self, tickers: &[String]) -> Result<(), CommandError> {
+        if let Some(max) = self.max_tickers
+            && tickers.len() > max
+        {
+            return Err(CommandError::Forbidden(format!(
+                "key {} allows at most {} tickers",
+                self.name, max
+            )));
+        }
+        if let Some(ticker) = tickers.iter().find(|t| !self.allows_ticker(t)) {
+            return Err(CommandError::Forbidden(format!(
+                "key {} is not allowed to stream {}",
+                self.name, ticker
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    #[serde(default)]
+    keys: Vec<ApiKey>,
+}
+
+// Ключи из файла (TOML, секции [[keys]]), по значению ключа
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyStore {
+    keys: HashMap<String, ApiKey>,
+}
+
+impl KeyStore {
+    pub fn load(path: &str) -> Result<Self, QuoteError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| QuoteError::Config(format!("cannot read keys file {}: {}", path, e)))?;
+        let store = Self::parse(&content)
+            .map_err(|e| QuoteError::Config(format!("keys file {}: {}", path, e)))?;
+        info!("Loaded {} API keys from {}", store.len(), path);
+        Ok(store)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let file: KeysFile = toml_lite::from_str(content).map_err(|e| e.to_string())?;
+        Self::from_keys(file.keys)
+    }
+
+    pub fn from_keys(keys: Vec<ApiKey>) -> Result<Self, String> {
+        let mut store = KeyStore::default();
+        for mut key in keys {
+            if key.key.is_empty() {
+                return Err(format!("key {} has an empty value", key.name));
+            }
+            if key.max_tickers == Some(0) {
+                return Err(format!("key {}: max_tickers must be positive", key.name));
+            }
+            if let Some(tickers) = &mut key.tickers {
+                for ticker in tickers.iter_mut() {
+                    *ticker = ticker.to_uppercase();
+                }
+            }
+            if store.keys.contains_key(&key.key) {
+                return Err(format!("key {} is listed twice", key.name));
+            }
+            store.keys.insert(key.key.clone(), key);
+        }
+        Ok(store)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ApiKey> {
+        self.keys.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
@@ -0,0 +1,231 @@
+use crate::models::StockQuote;
+use crossbeam_channel::{Receiver, Sender, unbounded};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use tracing::{debug, trace};
+
+// Сколько закрытых баров хранится для каждой пары тикер/интервал
+const MAX_BARS_PER_SERIES: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BarInterval {
+    #[serde(rename = "1s")]
+    OneSecond,
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+}
+
+impl BarInterval {
+    pub const ALL: [BarInterval; 3] = [
+        BarInterval::OneSecond,
+        BarInterval::OneMinute,
+        BarInterval::FiveMinutes,
+    ];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "1s" => Some(BarInterval::OneSecond),
+            "1m" => Some(BarInterval::OneMinute),
+            "5m" => Some(BarInterval::FiveMinutes),
+            _ => None,
+        }
+    }
+
+    pub fn as_millis(&self) -> u64 {
+        match self {
+            BarInterval::OneSecond => 1_000,
+            BarInterval::OneMinute => 60_000,
+            BarInterval::FiveMinutes => 300_000,
+        }
+    }
+
+    // Начало интервала, в который попадает метка времени (мс)
+    pub fn bucket_start(&self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.as_millis()
+    }
+
+    // Имя интервала, как в поле interval бара
+    pub fn name(&self) -> &'static str {
+        match self {
+            BarInterval::OneSecond => "1s",
+            BarInterval::OneMinute => "1m",
+            BarInterval::FiveMinutes => "5m",
+        }
+    }
+}
+
+impl fmt::Display for BarInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// OHLCV бар тикера за интервал [start, end)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bar {
+    pub ticker: String,
+    pub interval: String,
+    pub start: u64,
+    pub end: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: u64,
+    pub trades: u32,
+}
+
+impl Bar {
+    fn new(quote: &StockQuote, interval: BarInterval) -> Self {
+        let start = interval.bucket_start(quote.timestamp);
+        Bar {
+            ticker: quote.ticker.clone(),
+            interval: interval.to_string(),
+            start,
+            end: start + interval.as_millis(),
+            open: quote.price,
+            high: quote.price,
+            low: quote.price,
+            close: quote.price,
+            volume: quote.volume as u64,
+            trades: 1,
+        }
+    }
+
+    fn apply(&mut self, quote: &StockQuote) {
+        self.high = self.high.max(quote.price);
+        self.low = self.low.min(quote.price);
+        self.close = quote.price;
+        self.volume += quote.volume as u64;
+        self.trades += 1;
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+struct BarSubscriber {
+    interval: BarInterval,
+    tickers: Vec<String>,
+    sender: Sender<Bar>,
+}
+
+// Централизованная агрегация сделок в бары: считается один раз для всех клиентов
+pub struct BarAggregator {
+    intervals: Vec<BarInterval>,
+    current: HashMap<(String, BarInterval), Bar>,
+    completed: HashMap<(String, BarInterval), VecDeque<Bar>>,
+    subscribers: Vec<BarSubscriber>,
+}
+
+impl BarAggregator {
+    pub fn new(intervals: Vec<BarInterval>) -> Self {
+        BarAggregator {
+            intervals,
+            current: HashMap::new(),
+            completed: HashMap::new(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    // Учет сделки; бар, в интервал которого сделка уже не попадает, закрывается.
+    // Возвращает закрытые бары.
+    pub fn on_trade(&mut self, quote: &StockQuote) -> Vec<Bar> {
+        let mut closed = Vec::new();
+        for i in 0..self.intervals.len() {
+            let interval = self.intervals[i];
+            let key = (quote.ticker.clone(), interval);
+            let bucket = interval.bucket_start(quote.timestamp);
+
+            match self.current.get_mut(&key) {
+                Some(bar) if bar.start == bucket => bar.apply(quote),
+                _ => {
+                    if let Some(finished) = self.current.insert(key, Bar::new(quote, interval)) {
+                        closed.push(self.complete(finished, interval));
+                    }
+                }
+            }
+        }
+        closed
+    }
+
+    // Закрытие баров, интервал которых истек, даже если новых сделок не было
+    pub fn close_expired(&mut self, now: u64) -> Vec<Bar> {
+        let expired: Vec<(String, BarInterval)> = self
+            .current
+            .iter()
+            .filter(|(_, bar)| now >= bar.end)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut closed = Vec::new();
+        for key in expired {
+            if let Some(bar) = self.current.remove(&key) {
+                closed.push(self.complete(bar, key.1));
+            }
+        }
+        closed
+    }
+
+    fn complete(&mut self, bar: Bar, interval: BarInterval) -> Bar {
+        trace!(
+            "Closed {} bar for {}: O={:.2} H={:.2} L={:.2} C={:.2} V={}",
+            bar.interval, bar.ticker, bar.open, bar.high, bar.low, bar.close, bar.volume
+        );
+
+        self.subscribers.retain(|subscriber| {
+            if subscriber.interval != interval || !subscriber.tickers.contains(&bar.ticker) {
+                return true;
+            }
+            subscriber.sender.send(bar.clone()).is_ok()
+        });
+
+        let series = self
+            .completed
+            .entry((bar.ticker.clone(), interval))
+            .or_default();
+        series.push_back(bar.clone());
+        if series.len() > MAX_BARS_PER_SERIES {
+            series.pop_front();
+        }
+        bar
+    }
+
+    // Последние count закрытых баров тикера, от старых к новым
+    pub fn recent(&self, ticker: &str, interval: BarInterval, count: usize) -> Vec<Bar> {
+        match self.completed.get(&(ticker.to_string(), interval)) {
+            Some(series) => series
+                .iter()
+                .skip(series.len().saturating_sub(count))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Подписка на закрытые бары выбранных тикеров
+    pub fn subscribe(&mut self, tickers: Vec<String>, interval: BarInterval) -> Receiver<Bar> {
+        let (tx, rx) = unbounded();
+        self.add_subscriber(tickers, interval, tx);
+        rx
+    }
+
+    // Подписка с готовым каналом: так один получатель слушает несколько агрегаторов
+    pub fn add_subscriber(
+        &mut self,
+        tickers: Vec<String>,
+        interval: BarInterval,
+        sender: Sender<Bar>,
+    ) {
+        debug!("Bar subscription {} for {:?}", interval, tickers);
+        self.subscribers.push(BarSubscriber {
+            interval,
+            tickers,
+            sender,
+        });
+    }
+}
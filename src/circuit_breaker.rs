@@ -0,0 +1,73 @@
+use crate::ticker_config::PriceBand;
+use std::collections::VecDeque;
+
+// Остановка торгов тикера (limit-up/limit-down): опорная цена - средняя за окно,
+// выход цены за коридор вокруг нее останавливает торги на заданное время
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    band: f64,
+    window_ms: u64,
+    halt_ms: u64,
+    // Цены за окно: (время в мс, цена)
+    history: VecDeque<(u64, f64)>,
+    halted_until: Option<u64>,
+}
+
+impl CircuitBreaker {
+    pub fn new(band: PriceBand) -> Self {
+        CircuitBreaker {
+            band: band.percent / 100.0,
+            window_ms: band.window_secs * 1000,
+            halt_ms: band.halt_secs * 1000,
+            history: VecDeque::new(),
+            halted_until: None,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted_until.is_some()
+    }
+
+    // Учет новой цены. Возвращает true, если цена вышла за коридор и торги остановлены.
+    pub fn on_price(&mut self, price: f64, now: u64) -> bool {
+        while let Some(&(timestamp, _)) = self.history.front() {
+            if now.saturating_sub(timestamp) > self.window_ms {
+                self.history.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let breached = match self.reference_price() {
+            Some(reference) => (price / reference - 1.0).abs() > self.band,
+            None => false,
+        };
+
+        if breached {
+            self.halted_until = Some(now + self.halt_ms);
+            self.history.clear();
+        } else {
+            self.history.push_back((now, price));
+        }
+        breached
+    }
+
+    // Возобновление торгов по истечении остановки. Возвращает true в момент возобновления.
+    pub fn poll_resume(&mut self, now: u64) -> bool {
+        match self.halted_until {
+            Some(until) if now >= until => {
+                self.halted_until = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn reference_price(&self) -> Option<f64> {
+        if self.history.is_empty() {
+            return None;
+        }
+        let sum: f64 = self.history.iter().map(|&(_, price)| price).sum();
+        Some(sum / self.history.len() as f64)
+    }
+}
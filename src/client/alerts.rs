@@ -0,0 +1,280 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+// Дольше вебхук не задерживает свой поток
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Above,
+    AtOrAbove,
+    Below,
+    AtOrBelow,
+}
+
+impl Comparison {
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Above => ">",
+            Comparison::AtOrAbove => ">=",
+            Comparison::Below => "<",
+            Comparison::AtOrBelow => "<=",
+        }
+    }
+
+    fn matches(self, price: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => price > threshold,
+            Comparison::AtOrAbove => price >= threshold,
+            Comparison::Below => price < threshold,
+            Comparison::AtOrBelow => price <= threshold,
+        }
+    }
+}
+
+// Правило вида AAPL>250: срабатывает один раз, на первой подходящей сделке
+#[derive(Debug, Clone)]
+pub struct AlertRule {
+    pub ticker: String,
+    comparison: Comparison,
+    threshold: f64,
+    triggered: bool,
+}
+
+impl AlertRule {
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let rule = rule.trim();
+        let position = rule
+            .find(['>', '<'])
+            .ok_or_else(|| format!("invalid alert '{}' (expected e.g. AAPL>250)", rule))?;
+        let (ticker, condition) = rule.split_at(position);
+        let (comparison, threshold) = if let Some(value) = condition.strip_prefix(">=") {
+            (Comparison::AtOrAbove, value)
+        } else if let Some(value) = condition.strip_prefix("<=") {
+            (Comparison::AtOrBelow, value)
+        } else if let Some(value) = condition.strip_prefix('>') {
+            (Comparison::Above, value)
+        } else {
+            (Comparison::Below, &condition[1..])
+        };
+
+        let ticker = ticker.trim().to_uppercase();
+        if ticker.is_empty() {
+            return Err(format!("missing ticker in alert '{}'", rule));
+        }
+        let threshold: f64 = threshold
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid price in alert '{}'", rule))?;
+        Ok(AlertRule {
+            ticker,
+            comparison,
+            threshold,
+            triggered: false,
+        })
+    }
+}
+
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.ticker,
+            self.comparison.symbol(),
+            self.threshold
+        )
+    }
+}
+
+// Сработавшее правило и сделка, на которой это произошло
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub ticker: String,
+    pub price: f64,
+    pub timestamp: u64,
+}
+
+impl Alert {
+    pub fn message(&self, decimals: usize) -> String {
+        format!(
+            "ALERT {}: {} traded at {:.*}",
+            self.rule, self.ticker, decimals, self.price
+        )
+    }
+}
+
+// Адрес http:// вебхука, разобранный при запуске
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    host: String,
+    addr: String,
+    path: String,
+}
+
+impl Webhook {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(format!(
+                "unsupported webhook '{}' (only http:// URLs are supported)",
+                url
+            ));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(position) => rest.split_at(position),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("missing host in webhook '{}'", url));
+        }
+        let addr = if authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()))
+        {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        Ok(Webhook {
+            host: authority.to_string(),
+            addr,
+            path: path.to_string(),
+        })
+    }
+
+    // POST с JSON сработавшего правила; Err - сеть или ответ не 2xx
+    fn post(&self, body: &str) -> Result<(), String> {
+        let mut stream = TcpStream::connect(&self.addr).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(WEBHOOK_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(WEBHOOK_TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut response = [0; 512];
+        let n = stream.read(&mut response).map_err(|e| e.to_string())?;
+        let response = String::from_utf8_lossy(&response[..n]);
+        let status_line = response.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(format!("unexpected response '{}'", status_line)),
+        }
+    }
+}
+
+// Что запускается при срабатывании правила. Все хуки выполняются в фоне и
+// не задерживают прием котировок.
+#[derive(Debug, Clone, Default)]
+pub struct AlertHooks {
+    // Команда оболочки, данные в переменных окружения (--alert-command)
+    pub shell: Option<String>,
+    // Программа с аргументами; правило, тикер, цена и метка времени
+    // дописываются последними аргументами (--on-alert-exec)
+    pub exec: Option<Vec<String>>,
+    pub webhook: Option<Webhook>,
+}
+
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    hooks: AlertHooks,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<AlertRule>, hooks: AlertHooks) -> Self {
+        AlertEngine { rules, hooks }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Правила, впервые сработавшие на этой сделке
+    pub fn check(&mut self, ticker: &str, price: f64, timestamp: u64) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for rule in &mut self.rules {
+            if rule.triggered
+                || rule.ticker != ticker
+                || !rule.comparison.matches(price, rule.threshold)
+            {
+                continue;
+            }
+            rule.triggered = true;
+            alerts.push(Alert {
+                rule: rule.to_string(),
+                ticker: ticker.to_string(),
+                price,
+                timestamp,
+            });
+        }
+        alerts
+    }
+
+    pub fn run_hooks(&self, alert: &Alert) {
+        if let Some(hook) = &self.hooks.shell {
+            let spawned = Command::new("sh")
+                .arg("-c")
+                .arg(hook)
+                .env("ALERT_RULE", &alert.rule)
+                .env("ALERT_TICKER", &alert.ticker)
+                .env("ALERT_PRICE", alert.price.to_string())
+                .env("ALERT_TIMESTAMP", alert.timestamp.to_string())
+                .spawn();
+            wait_in_background("Alert hook", spawned);
+        }
+
+        if let Some((program, args)) = self.hooks.exec.as_ref().and_then(|exec| exec.split_first())
+        {
+            let spawned = Command::new(program)
+                .args(args)
+                .arg(&alert.rule)
+                .arg(&alert.ticker)
+                .arg(alert.price.to_string())
+                .arg(alert.timestamp.to_string())
+                .spawn();
+            wait_in_background("Alert command", spawned);
+        }
+
+        if let Some(webhook) = &self.hooks.webhook {
+            let webhook = webhook.clone();
+            let body = serde_json::to_string(alert).unwrap_or_default();
+            std::thread::spawn(move || {
+                if let Err(e) = webhook.post(&body) {
+                    tracing::warn!(
+                        "Alert webhook {}{} failed: {}",
+                        webhook.host,
+                        webhook.path,
+                        e
+                    );
+                }
+            });
+        }
+    }
+}
+
+// Ожидание в отдельном потоке, чтобы не оставлять зомби
+fn wait_in_background(name: &'static str, spawned: std::io::Result<Child>) {
+    match spawned {
+        Ok(mut child) => {
+            std::thread::spawn(move || {
+                if let Ok(status) = child.wait()
+                    && !status.success()
+                {
+                    tracing::warn!("{} exited with {}", name, status);
+                }
+            });
+        }
+        Err(e) => tracing::error!("Failed to run {}: {}", name.to_lowercase(), e),
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Интервал бара из строки вида 30s, 1m, 5m, 1h
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let value = value.trim().to_lowercase();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in bar interval '{}' (use s, m or h)", value))?;
+    let (count, unit) = value.split_at(split);
+    let count: u64 = count
+        .parse()
+        .map_err(|_| format!("invalid bar interval '{}'", value))?;
+    let seconds = match unit {
+        "s" => count,
+        "m" => count * 60,
+        "h" => count * 3600,
+        _ => return Err(format!("unknown unit in bar interval '{}'", value)),
+    };
+    if seconds == 0 {
+        return Err("bar interval must be positive".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+// OHLCV бар тикера за интервал [start, start + interval); так же выглядят
+// бары, которые присылает сервер (--server-bars)
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Bar {
+    pub ticker: String,
+    pub interval: String,
+    pub start: u64,
+    pub end: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: u64,
+    pub trades: u32,
+    #[serde(skip)]
+    pub decimals: usize,
+}
+
+impl Bar {
+    pub fn format(&self, format: &str) -> String {
+        if format == "json" {
+            return serde_json::to_string(self).unwrap_or_default();
+        }
+        let start = chrono::DateTime::from_timestamp_millis(self.start as i64)
+            .map(|time| {
+                time.with_timezone(&chrono::Local)
+                    .format("%H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|| self.start.to_string());
+        format!(
+            "[{}] {} {} O {:.*} H {:.*} L {:.*} C {:.*} V {} ({} trades)",
+            start,
+            self.ticker,
+            self.interval,
+            self.decimals,
+            self.open,
+            self.decimals,
+            self.high,
+            self.decimals,
+            self.low,
+            self.decimals,
+            self.close,
+            self.volume,
+            self.trades
+        )
+    }
+}
+
+// Локальная агрегация сделок в бары одного интервала. Бар закрывается, когда
+// приходит сделка следующего интервала или когда интервал истек по часам
+// сервера (оцениваются по меткам времени последних сделок).
+pub struct BarAggregator {
+    interval: Duration,
+    label: String,
+    current: HashMap<String, Bar>,
+    // Конец последнего закрытого бара тикера: опоздавшие сделки не открывают его заново
+    closed_until: HashMap<String, u64>,
+    // Разница между временем сделок и локальными часами, мс
+    clock_offset: i64,
+}
+
+impl BarAggregator {
+    pub fn new(interval: Duration, label: &str) -> Self {
+        BarAggregator {
+            interval,
+            label: label.to_string(),
+            current: HashMap::new(),
+            closed_until: HashMap::new(),
+            clock_offset: 0,
+        }
+    }
+
+    // Учет сделки; возвращает закрытый ею бар прошлого интервала
+    pub fn on_trade(
+        &mut self,
+        ticker: &str,
+        price: f64,
+        volume: Option<u32>,
+        timestamp: u64,
+        decimals: usize,
+    ) -> Option<Bar> {
+        self.clock_offset = timestamp as i64 - local_ms() as i64;
+        let interval_ms = self.interval.as_millis() as u64;
+        let start = timestamp - timestamp % interval_ms;
+        let volume = volume.unwrap_or(0) as u64;
+        if self
+            .closed_until
+            .get(ticker)
+            .is_some_and(|&closed| timestamp < closed)
+        {
+            return None;
+        }
+
+        if let Some(bar) = self.current.get_mut(ticker)
+            && bar.start == start
+        {
+            bar.high = bar.high.max(price);
+            bar.low = bar.low.min(price);
+            bar.close = price;
+            bar.volume += volume;
+            bar.trades += 1;
+            return None;
+        }
+
+        let bar = Bar {
+            ticker: ticker.to_string(),
+            interval: self.label.clone(),
+            start,
+            end: start + interval_ms,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume,
+            trades: 1,
+            decimals,
+        };
+        let finished = self.current.insert(ticker.to_string(), bar)?;
+        self.closed_until.insert(ticker.to_string(), finished.end);
+        Some(finished)
+    }
+
+    // Бары, интервал которых истек без новых сделок, по порядку тикеров
+    pub fn close_expired(&mut self) -> Vec<Bar> {
+        let now = (local_ms() as i64 + self.clock_offset).max(0) as u64;
+        let mut expired: Vec<String> = self
+            .current
+            .iter()
+            .filter(|(_, bar)| now >= bar.end)
+            .map(|(ticker, _)| ticker.clone())
+            .collect();
+        expired.sort();
+        let mut bars = Vec::new();
+        for ticker in expired {
+            if let Some(bar) = self.current.remove(&ticker) {
+                self.closed_until.insert(ticker, bar.end);
+                bars.push(bar);
+            }
+        }
+        bars
+    }
+}
+
+fn local_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
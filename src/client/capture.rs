@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Заголовок файла записи; за ним записи: микросекунды от начала (u64 LE),
+// длина датаграммы (u32 LE) и сама датаграмма
+const MAGIC: &[u8] = b"QUOTECAP1\n";
+
+// Запись датаграмм UDP сессии как есть, вместе со временем приема
+pub struct CaptureWriter {
+    writer: BufWriter<File>,
+    started: Instant,
+    flush_every: Duration,
+    last_flush: Instant,
+    datagrams: u64,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &str, flush_every: Duration) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(CaptureWriter {
+            writer,
+            started: Instant::now(),
+            flush_every,
+            last_flush: Instant::now(),
+            datagrams: 0,
+        })
+    }
+
+    pub fn write(&mut self, datagram: &[u8]) -> io::Result<()> {
+        let offset = self.started.elapsed().as_micros() as u64;
+        self.writer.write_all(&offset.to_le_bytes())?;
+        self.writer
+            .write_all(&(datagram.len() as u32).to_le_bytes())?;
+        self.writer.write_all(datagram)?;
+        self.datagrams += 1;
+        self.flush_if_due()
+    }
+
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() >= self.flush_every {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+
+    pub fn datagrams(&self) -> u64 {
+        self.datagrams
+    }
+}
+
+// Источник датаграмм, воспроизводящий запись с исходными интервалами
+// (ускоренными в speed раз; 0 - без пауз)
+pub struct CaptureReplay {
+    reader: BufReader<File>,
+    speed: f64,
+    started: Instant,
+    // Следующая прочитанная, но еще не отданная датаграмма
+    pending: Option<(u64, Vec<u8>)>,
+    // Дольше ожидание не длится, как таймаут чтения сокета
+    max_wait: Duration,
+}
+
+impl CaptureReplay {
+    pub fn open(path: &str, speed: f64, max_wait: Duration) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a client capture file", path),
+            ));
+        }
+        Ok(CaptureReplay {
+            reader,
+            speed,
+            started: Instant::now(),
+            pending: None,
+            max_wait,
+        })
+    }
+
+    // Как UdpSocket::recv_from: TimedOut, если очередная датаграмма еще не
+    // наступила, UnexpectedEof в конце записи
+    pub fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (offset, datagram) = match self.pending.take() {
+            Some(next) => next,
+            None => self.read_record()?,
+        };
+
+        if self.speed > 0.0 {
+            let due = Duration::from_micros((offset as f64 / self.speed) as u64);
+            let elapsed = self.started.elapsed();
+            if due > elapsed {
+                let wait = due - elapsed;
+                if wait > self.max_wait {
+                    thread::sleep(self.max_wait);
+                    self.pending = Some((offset, datagram));
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "waiting for replay",
+                    ));
+                }
+                thread::sleep(wait);
+            }
+        }
+
+        let size = datagram.len().min(buf.len());
+        buf[..size].copy_from_slice(&datagram[..size]);
+        Ok((size, SocketAddr::from(([127, 0, 0, 1], 0))))
+    }
+
+    fn read_record(&mut self) -> io::Result<(u64, Vec<u8>)> {
+        let mut offset = [0; 8];
+        self.reader.read_exact(&mut offset)?;
+        let mut len = [0; 4];
+        self.reader.read_exact(&mut len)?;
+        let mut datagram = vec![0; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut datagram)?;
+        Ok((u64::from_le_bytes(offset), datagram))
+    }
+}
+
+// Откуда главный цикл берет датаграммы: сокет живого потока или запись
+pub enum Feed {
+    Udp(UdpSocket),
+    Replay(CaptureReplay),
+}
+
+impl Feed {
+    pub fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self {
+            Feed::Udp(socket) => socket.recv_from(buf),
+            Feed::Replay(replay) => replay.recv_from(buf),
+        }
+    }
+}
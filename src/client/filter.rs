@@ -0,0 +1,288 @@
+// Выражение фильтра котировок вида price>100 && volume>2000. Поддерживаются
+// сравнения > >= < <= == !=, операторы && || !, скобки, числа и строки в
+// кавычках; слова - поля JSON сообщения.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Field(String),
+    Number(f64),
+    Text(String),
+    Compare(CompareOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Field(String),
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Compare(Operand, CompareOp, Operand),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+// Значение операнда в конкретном сообщении
+enum Value<'a> {
+    Number(f64),
+    Text(&'a str),
+    Missing,
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!(
+                "unexpected {} in filter '{}'",
+                describe(Some(token.clone())),
+                source
+            ));
+        }
+        Ok(Filter { expr })
+    }
+
+    // Сравнение с отсутствующим полем или значением другого типа ложно
+    pub fn matches(&self, json: &serde_json::Value) -> bool {
+        evaluate(&self.expr, json)
+    }
+}
+
+fn evaluate(expr: &Expr, json: &serde_json::Value) -> bool {
+    match expr {
+        Expr::And(left, right) => evaluate(left, json) && evaluate(right, json),
+        Expr::Or(left, right) => evaluate(left, json) || evaluate(right, json),
+        Expr::Not(inner) => !evaluate(inner, json),
+        Expr::Compare(left, op, right) => match (resolve(left, json), resolve(right, json)) {
+            (Value::Number(a), Value::Number(b)) => compare(a.partial_cmp(&b), *op),
+            (Value::Text(a), Value::Text(b)) => compare(Some(a.cmp(b)), *op),
+            _ => false,
+        },
+    }
+}
+
+fn resolve<'a>(operand: &'a Operand, json: &'a serde_json::Value) -> Value<'a> {
+    match operand {
+        Operand::Number(number) => Value::Number(*number),
+        Operand::Text(text) => Value::Text(text),
+        Operand::Field(name) => match json.get(name) {
+            Some(serde_json::Value::Number(number)) => {
+                number.as_f64().map_or(Value::Missing, Value::Number)
+            }
+            Some(serde_json::Value::String(text)) => Value::Text(text),
+            _ => Value::Missing,
+        },
+    }
+}
+
+fn compare(ordering: Option<std::cmp::Ordering>, op: CompareOp) -> bool {
+    use std::cmp::Ordering::*;
+    let Some(ordering) = ordering else {
+        return false;
+    };
+    match op {
+        CompareOp::Greater => ordering == Greater,
+        CompareOp::GreaterOrEqual => ordering != Less,
+        CompareOp::Less => ordering == Less,
+        CompareOp::LessOrEqual => ordering != Greater,
+        CompareOp::Equal => ordering == Equal,
+        CompareOp::NotEqual => ordering != Equal,
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            ' ' | '\t' => {
+                i += 1;
+                continue;
+            }
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '&' if next == Some('&') => {
+                tokens.push(Token::And);
+                i += 1;
+            }
+            '|' if next == Some('|') => {
+                tokens.push(Token::Or);
+                i += 1;
+            }
+            '>' | '<' | '=' | '!' => {
+                let op = match (c, next) {
+                    ('>', Some('=')) => Some(CompareOp::GreaterOrEqual),
+                    ('<', Some('=')) => Some(CompareOp::LessOrEqual),
+                    ('=', Some('=')) => Some(CompareOp::Equal),
+                    ('!', Some('=')) => Some(CompareOp::NotEqual),
+                    _ => None,
+                };
+                match (op, c) {
+                    (Some(op), _) => {
+                        tokens.push(Token::Compare(op));
+                        i += 1;
+                    }
+                    (None, '>') => tokens.push(Token::Compare(CompareOp::Greater)),
+                    (None, '<') => tokens.push(Token::Compare(CompareOp::Less)),
+                    // Одиночное = тоже означает равенство
+                    (None, '=') => tokens.push(Token::Compare(CompareOp::Equal)),
+                    _ => tokens.push(Token::Not),
+                }
+            }
+            '"' | '\'' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&ch| ch == c)
+                    .ok_or_else(|| format!("unterminated string in filter '{}'", source))?;
+                tokens.push(Token::Text(chars[i + 1..i + 1 + end].iter().collect()));
+                i += end + 1;
+            }
+            c if c.is_ascii_digit()
+                || c == '.'
+                || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) =>
+            {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let number = text
+                    .parse()
+                    .map_err(|_| format!("invalid number '{}' in filter", text))?;
+                tokens.push(Token::Number(number));
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push(Token::Field(chars[start..i].iter().collect()));
+                continue;
+            }
+            _ => return Err(format!("unexpected '{}' in filter '{}'", c, source)),
+        }
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.position += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.position += 1;
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("missing ')' in filter".to_string()),
+                }
+            }
+            _ => {
+                let left = self.operand()?;
+                let op = match self.next() {
+                    Some(Token::Compare(op)) => op,
+                    other => return Err(format!("expected comparison, found {}", describe(other))),
+                };
+                let right = self.operand()?;
+                Ok(Expr::Compare(left, op, right))
+            }
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Field(name)) => Ok(Operand::Field(name)),
+            Some(Token::Number(number)) => Ok(Operand::Number(number)),
+            Some(Token::Text(text)) => Ok(Operand::Text(text)),
+            other => Err(format!(
+                "expected field, number or string, found {}",
+                describe(other)
+            )),
+        }
+    }
+}
+
+fn describe(token: Option<Token>) -> String {
+    match token {
+        None => "end of filter".to_string(),
+        Some(Token::Field(name)) => format!("'{}'", name),
+        Some(Token::Number(number)) => number.to_string(),
+        Some(Token::Text(text)) => format!("\"{}\"", text),
+        Some(Token::Compare(_)) => "comparison".to_string(),
+        Some(Token::And) => "'&&'".to_string(),
+        Some(Token::Or) => "'||'".to_string(),
+        Some(Token::Not) => "'!'".to_string(),
+        Some(Token::Open) => "'('".to_string(),
+        Some(Token::Close) => "')'".to_string(),
+    }
+}
@@ -0,0 +1,272 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Как часто печатается суммарная пропускная способность
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+// Сколько после STOP дочитываются датаграммы, уже отправленные сервером
+const DRAIN_TIME: Duration = Duration::from_millis(200);
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(200);
+
+// Параметры нагрузочного теста: по команде STREAM на виртуального клиента,
+// у каждого свой UDP порт
+pub struct LoadTest {
+    pub server_addr: String,
+    pub ping_addr: String,
+    pub host: IpAddr,
+    // Ключ для AUTH перед STREAM каждого клиента
+    pub api_key: Option<String>,
+    pub commands: Vec<(u16, String)>,
+    pub ping_interval: Duration,
+    // None - до Ctrl+C
+    pub duration: Option<Duration>,
+}
+
+// Общие счетчики всех виртуальных клиентов
+#[derive(Default)]
+struct Totals {
+    connected: AtomicUsize,
+    failed: AtomicUsize,
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    // Сколько котировок, по STATS, сервер отправил клиентам
+    server_sent: AtomicU64,
+    // Время ответа на STREAM и на PING по TCP
+    latencies: Mutex<Vec<Duration>>,
+}
+
+impl LoadTest {
+    pub fn run(self, running: Arc<AtomicBool>) {
+        let totals = Arc::new(Totals::default());
+        let ping_interval = self.ping_interval;
+        let started = Instant::now();
+
+        let handles: Vec<_> = self
+            .commands
+            .into_iter()
+            .map(|(port, command)| {
+                let totals = totals.clone();
+                let running = running.clone();
+                let server_addr = self.server_addr.clone();
+                let ping_addr = self.ping_addr.clone();
+                let host = self.host;
+                let api_key = self.api_key.clone();
+                thread::spawn(move || {
+                    let client = VirtualClient {
+                        server_addr: &server_addr,
+                        ping_addr: &ping_addr,
+                        host,
+                        api_key: api_key.as_deref(),
+                        port,
+                        command: &command,
+                        ping_interval,
+                        totals: &totals,
+                    };
+                    if let Err(e) = client.run(&running) {
+                        totals.failed.fetch_add(1, Ordering::Relaxed);
+                        tracing::warn!("Virtual client on UDP port {} failed: {}", port, e);
+                    }
+                })
+            })
+            .collect();
+
+        let mut last_messages = 0;
+        let mut last_bytes = 0;
+        let mut last_report = Instant::now();
+        while running.load(Ordering::SeqCst) && !handles.iter().all(|h| h.is_finished()) {
+            if self
+                .duration
+                .is_some_and(|limit| started.elapsed() >= limit)
+            {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+            let elapsed = last_report.elapsed();
+            if elapsed < REPORT_INTERVAL {
+                continue;
+            }
+            let messages = totals.messages.load(Ordering::Relaxed);
+            let bytes = totals.bytes.load(Ordering::Relaxed);
+            println!(
+                "{:>6.1}s  clients {:>5} (failed {})  {:>10.0} msg/s  {:>8.2} MB/s",
+                started.elapsed().as_secs_f64(),
+                totals.connected.load(Ordering::Relaxed),
+                totals.failed.load(Ordering::Relaxed),
+                (messages - last_messages) as f64 / elapsed.as_secs_f64(),
+                (bytes - last_bytes) as f64 / elapsed.as_secs_f64() / 1_000_000.0
+            );
+            last_messages = messages;
+            last_bytes = bytes;
+            last_report = Instant::now();
+        }
+
+        running.store(false, Ordering::SeqCst);
+        for handle in handles {
+            let _ = handle.join();
+        }
+        print_summary(&totals, started.elapsed());
+    }
+}
+
+struct VirtualClient<'a> {
+    server_addr: &'a str,
+    ping_addr: &'a str,
+    host: IpAddr,
+    api_key: Option<&'a str>,
+    port: u16,
+    command: &'a str,
+    ping_interval: Duration,
+    totals: &'a Totals,
+}
+
+impl VirtualClient<'_> {
+    fn run(&self, running: &AtomicBool) -> io::Result<()> {
+        // Сокет открывается до STREAM, чтобы не потерять первые котировки
+        let socket = UdpSocket::bind(SocketAddr::new(self.host, self.port))?;
+        socket.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+
+        let mut stream = TcpStream::connect(self.server_addr)?;
+        let mut buf = [0; 4096];
+        let n = stream.read(&mut buf)?;
+        tracing::trace!("Greeting: {}", String::from_utf8_lossy(&buf[..n]));
+
+        if let Some(key) = self.api_key {
+            let response = self.exchange(&mut stream, &format!("AUTH {}\n", key))?;
+            if !response.starts_with("AUTH_OK") {
+                return Err(io::Error::other(format!("AUTH rejected: {}", response)));
+            }
+        }
+        let response = self.exchange(&mut stream, self.command)?;
+        if !response.contains("STREAMING_STARTED") {
+            return Err(io::Error::other(format!("STREAM rejected: {}", response)));
+        }
+        let id = self.exchange(&mut stream, "ID\n")?;
+        let token = id
+            .strip_prefix("ID ")
+            .ok_or_else(|| io::Error::other(format!("unexpected response to ID: {}", id)))?
+            .to_string();
+        self.totals.connected.fetch_add(1, Ordering::Relaxed);
+
+        let result = self.receive(&socket, &mut stream, &token, running);
+        self.totals.connected.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+
+    fn receive(
+        &self,
+        socket: &UdpSocket,
+        stream: &mut TcpStream,
+        token: &str,
+        running: &AtomicBool,
+    ) -> io::Result<()> {
+        let ping = format!("PING {}", token);
+        let mut last_ping: Option<Instant> = None;
+        let mut buf = [0; 65536];
+        while running.load(Ordering::SeqCst) {
+            if last_ping.is_none_or(|at| at.elapsed() >= self.ping_interval) {
+                socket.send_to(ping.as_bytes(), self.ping_addr)?;
+                // Задержка ответа сервера - по PING через управляющее соединение
+                let response = self.exchange(stream, "PING\n")?;
+                if !response.starts_with("PONG") {
+                    return Err(io::Error::other(format!(
+                        "unexpected response to PING: {}",
+                        response
+                    )));
+                }
+                last_ping = Some(Instant::now());
+            }
+            self.receive_one(socket, &mut buf);
+        }
+
+        // Сколько сервер отправил этому клиенту - для оценки потерь
+        let stats = self.exchange(stream, "STATS\n")?;
+        let sent = serde_json::from_str::<serde_json::Value>(&stats)
+            .ok()
+            .and_then(|json| json.get("quotes_sent").and_then(|s| s.as_u64()));
+        stream.write_all(b"STOP\n")?;
+        let drain_until = Instant::now() + DRAIN_TIME;
+        while Instant::now() < drain_until {
+            self.receive_one(socket, &mut buf);
+        }
+        if let Some(sent) = sent {
+            self.totals.server_sent.fetch_add(sent, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn receive_one(&self, socket: &UdpSocket, buf: &mut [u8]) {
+        if let Ok(size) = socket.recv(buf) {
+            if &buf[..size] == b"PONG" {
+                return;
+            }
+            self.totals.messages.fetch_add(1, Ordering::Relaxed);
+            self.totals.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        }
+    }
+
+    // Команда с ответом одной строкой; время ответа попадает в статистику
+    fn exchange(&self, stream: &mut TcpStream, command: &str) -> io::Result<String> {
+        let started = Instant::now();
+        stream.write_all(command.as_bytes())?;
+        let mut buf = [0; 4096];
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "server closed the connection",
+            ));
+        }
+        self.totals
+            .latencies
+            .lock()
+            .unwrap()
+            .push(started.elapsed());
+        Ok(String::from_utf8_lossy(&buf[..n]).trim().to_string())
+    }
+}
+
+fn print_summary(totals: &Totals, elapsed: Duration) {
+    let messages = totals.messages.load(Ordering::Relaxed);
+    let bytes = totals.bytes.load(Ordering::Relaxed);
+    let sent = totals.server_sent.load(Ordering::Relaxed);
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+
+    println!("\n=== Load Test Summary ===");
+    println!("Duration: {:.1} seconds", elapsed.as_secs_f64());
+    println!("Failed clients: {}", totals.failed.load(Ordering::Relaxed));
+    println!("Messages received: {}", messages);
+    println!(
+        "Throughput: {:.0} msg/s, {:.2} MB/s",
+        messages as f64 / seconds,
+        bytes as f64 / seconds / 1_000_000.0
+    );
+    if sent > 0 {
+        // Отправленное между STATS и STOP тоже принимается, поэтому оценка снизу
+        let lost = sent.saturating_sub(messages);
+        println!(
+            "Loss: {} of {} sent ({:.2}%)",
+            lost,
+            sent,
+            lost as f64 / sent as f64 * 100.0
+        );
+    }
+
+    let mut latencies = totals.latencies.lock().unwrap();
+    if !latencies.is_empty() {
+        latencies.sort();
+        let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+        let average = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+        println!(
+            "Server response latency: min {:?}, avg {:?}, p50 {:?}, p99 {:?}, max {:?} ({} requests)",
+            latencies[0],
+            average,
+            percentile(0.5),
+            percentile(0.99),
+            latencies[latencies.len() - 1],
+            latencies.len()
+        );
+    }
+}
@@ -1,22 +1,74 @@
-use clap::Parser;
-use log::{debug, error, info, trace, warn};
+use clap::{CommandFactory, Parser};
+use quote_common::arg_config::{ArgSources, merge_args};
+use quote_common::line_reader::{Line, LineReader};
+use quote_common::models::{ErrorCode, ErrorResponse, HelloResponse, PROTOCOL_VERSION};
+use quote_common::{LogFilter, LogFormat, logging};
 use std::io::{Read, Write, stdin};
-use std::net::{TcpStream, UdpSocket};
-use std::sync::Arc;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
+use tracing::{debug, error, info, trace, warn};
+
+mod alerts;
+mod bars;
+mod capture;
+mod filter;
+mod load_test;
+mod parquet;
+mod sink;
+mod sqlite;
+mod stats;
+mod summary;
+mod tui;
+mod ws_relay;
 
 // Константы для конфигурации
 const DEFAULT_UDP_PORT: u16 = 55555;
 const DEFAULT_SERVER_PING_PORT: u16 = 34254;
 const DEFAULT_PING_INTERVAL: u64 = 2;
 const DEFAULT_DURATION: u64 = 0;
-const LOCALHOST: &str = "127.0.0.1";
+// UDP сокеты слушают все интерфейсы, чтобы принимать поток с удаленного сервера
+const DEFAULT_MAX_RECONNECTS: u32 = 10;
+const DEFAULT_OUTPUT_FLUSH_MS: u64 = 1000;
+const DEFAULT_PARQUET_FLUSH_SECS: u64 = 10;
+const DEFAULT_RECORD_MAX_MB: u64 = 100;
+const DEFAULT_RECORD_ROTATE_SECS: u64 = 3600;
+const DEFAULT_STATS_WINDOW_SECS: u64 = 60;
+const DEFAULT_UDP_GRACE_SECS: u64 = 5;
+// Загружается без --config, если лежит в текущем каталоге
+const DEFAULT_CONFIG: &str = "client.toml";
+// В режиме --machine в stdout идут только записи котировок
+static MACHINE_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+// Служебный вывод для человека: баннеры, статистика, подсказки. С --machine
+// уходит в stderr.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if MACHINE_OUTPUT.load(Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+// Код выхода, если котировки перестали приходить (--idle-timeout)
+const EXIT_IDLE_TIMEOUT: i32 = 3;
+// Пауза перед первой попыткой переподключения; дальше удваивается до максимума
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+// Как часто поток наблюдения за соединением проверяет сигнал остановки
+const CONNECTION_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Client configuration file; command-line flags override its values [default: client.toml, if present]
+    #[arg(long)]
+    config: Option<String>,
+
     /// TCP server address
     #[arg(short = 's', long, default_value = "LOCALHOST:8080")]
     server_addr: String,
@@ -29,6 +81,14 @@ struct Args {
     #[arg(long, default_value_t = DEFAULT_SERVER_PING_PORT)]
     server_ping_port: u16,
 
+    /// Address the server sends UDP quotes to (default: the local address used to reach the server)
+    #[arg(long)]
+    advertise_addr: Option<IpAddr>,
+
+    /// Local address for UDP sockets (default: all interfaces of the server's address family, dual-stack for IPv6)
+    #[arg(long)]
+    bind_addr: Option<IpAddr>,
+
     /// Ticker file path (alternative to --tickers)
     #[arg(short = 'f', long)]
     ticker_file: Option<String>,
@@ -41,48 +101,204 @@ struct Args {
     #[arg(long, default_value_t = DEFAULT_PING_INTERVAL)]
     ping_interval: u64,
 
-    /// Output format (simple, json, detailed, line)
+    /// Data to stream: trades, quotes (bid/ask) or both
+    #[arg(long, default_value = "trades")]
+    feed: String,
+
+    /// Also stream Level-2 order book depth updates
+    #[arg(long, default_value_t = false)]
+    depth: bool,
+
+    /// Ask the server to skip intermediate quotes when delivery falls behind
+    #[arg(long, default_value_t = false)]
+    conflate: bool,
+
+    /// Receive at most N trades (and bid/ask quotes) per ticker per second
+    #[arg(long)]
+    rate: Option<u32>,
+
+    /// Receive only every N-th trade (and bid/ask quote) of each ticker
+    #[arg(long)]
+    every: Option<u32>,
+
+    /// Receive one server-built OHLCV bar per ticker per interval (1s, 1m or 5m) instead of every trade
+    #[arg(long, conflicts_with = "bars")]
+    server_bars: Option<String>,
+
+    /// Server-side policy when this client's queue is full: block, drop-oldest, drop-newest or disconnect (default: the server's --overflow)
+    #[arg(long)]
+    overflow: Option<String>,
+
+    /// Resume an existing session (token printed at connect) instead of starting a new stream
+    #[arg(long)]
+    session: Option<String>,
+
+    /// API key sent as AUTH <key> before streaming (servers started with --api-keys)
+    #[arg(long)]
+    api_key: Option<String>,
+
+    /// Reconnect attempts after the server connection is lost (0 = exit instead)
+    #[arg(long, default_value_t = DEFAULT_MAX_RECONNECTS)]
+    max_reconnects: u32,
+
+    /// Show a live table of tickers (keys: q quit, s sort, p pause) instead of printing each quote
+    #[arg(long, default_value_t = false)]
+    tui: bool,
+
+    /// Aggregate trades into OHLCV bars of this interval (e.g. 30s, 1m, 5m) and print a line per closed bar instead of each quote
+    #[arg(long, conflicts_with = "tui")]
+    bars: Option<String>,
+
+    /// Show and record only trades and bid/ask quotes matching this expression, e.g. "price>100 && volume>2000"
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// Every N seconds print last price, change since start, message count and rate per ticker (biggest movers first) instead of each quote
+    #[arg(long, conflicts_with_all = ["tui", "bars"], value_parser = clap::value_parser!(u64).range(1..))]
+    summary: Option<u64>,
+
+    /// Price alert rule such as AAPL>250 or TSLA<=100 (repeatable); each fires once
+    #[arg(long = "alert")]
+    alerts: Vec<String>,
+
+    /// Shell command run when an alert fires (gets ALERT_RULE, ALERT_TICKER, ALERT_PRICE, ALERT_TIMESTAMP)
+    #[arg(long)]
+    alert_command: Option<String>,
+
+    /// Program run when an alert fires, with the rule, ticker, price and timestamp appended as arguments
+    #[arg(long)]
+    on_alert_exec: Option<String>,
+
+    /// POST a JSON payload of each fired alert to this http:// URL
+    #[arg(long)]
+    on_alert_webhook: Option<String>,
+
+    /// Output format (simple, json, detailed, line, csv)
     #[arg(long, default_value = "line")]
     output_format: String,
 
+    /// Append every received trade and bid/ask quote to this CSV file
+    #[arg(long)]
+    output_file: Option<String>,
+
+    /// Flush the output file every N milliseconds (0 = after every row)
+    #[arg(long, default_value_t = DEFAULT_OUTPUT_FLUSH_MS)]
+    output_flush_ms: u64,
+
+    /// Record received trades and bid/ask quotes as raw JSON lines to rotating files in this directory
+    #[arg(long)]
+    record_dir: Option<String>,
+
+    /// Start a new record file after N megabytes (0 = no size limit)
+    #[arg(long, default_value_t = DEFAULT_RECORD_MAX_MB)]
+    record_max_mb: u64,
+
+    /// Start a new record file every N seconds (0 = no time limit)
+    #[arg(long, default_value_t = DEFAULT_RECORD_ROTATE_SECS)]
+    record_rotate_secs: u64,
+
+    /// Write every received trade and bid/ask quote to this Parquet file
+    #[arg(long)]
+    parquet: Option<String>,
+
+    /// Write buffered rows to the Parquet file as a row group every N seconds
+    #[arg(long, default_value_t = DEFAULT_PARQUET_FLUSH_SECS)]
+    parquet_flush_secs: u64,
+
+    /// Insert every received trade and bid/ask quote into this SQLite database (a table per ticker)
+    #[arg(long)]
+    sqlite: Option<String>,
+
+    /// Commit buffered SQLite inserts every N milliseconds
+    #[arg(long, default_value_t = DEFAULT_OUTPUT_FLUSH_MS)]
+    sqlite_flush_ms: u64,
+
+    /// sqlite3 command-line tool used to write the database
+    #[arg(long, default_value = "sqlite3")]
+    sqlite_command: String,
+
+    /// Capacity-test the server: open N connections streaming to UDP ports starting at --udp-port and report throughput, loss and latency
+    #[arg(long)]
+    load_test: Option<u16>,
+
+    /// Re-publish every received JSON message to WebSocket clients (e.g. a browser) connecting to this address
+    #[arg(long)]
+    ws_relay: Option<String>,
+
+    /// Capture every received UDP datagram with its arrival time to this file (replay it with --replay)
+    #[arg(long)]
+    record: Option<String>,
+
+    /// Replay a session captured with --record through the usual output instead of connecting to the server
+    #[arg(long, conflicts_with_all = ["record", "load_test", "session"])]
+    replay: Option<String>,
+
+    /// Replay speed multiplier (1.0 = original timing, 0 = as fast as possible)
+    #[arg(long, default_value_t = 1.0)]
+    replay_speed: f64,
+
+    /// Window in seconds for rolling VWAP, volatility and min/max per ticker
+    #[arg(long, default_value_t = DEFAULT_STATS_WINDOW_SECS, value_parser = clap::value_parser!(u64).range(1..))]
+    stats_window: u64,
+
+    /// Write the final session summary with per-ticker rolling statistics to this JSON file
+    #[arg(long)]
+    summary_json: Option<String>,
+
     /// Run duration in seconds (0 for unlimited)
     #[arg(short = 'd', long, default_value_t = DEFAULT_DURATION)]
     duration: u64,
 
-    /// Log level (error, warn, info, debug, trace)
+    /// Warn if no UDP datagram arrives within N seconds after the stream starts (0 = never)
+    #[arg(long, default_value_t = DEFAULT_UDP_GRACE_SECS)]
+    udp_grace_secs: u64,
+
+    /// Stop after receiving N trades and bid/ask quotes
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_quotes: Option<u64>,
+
+    /// Exit with code 3 if no trades or bid/ask quotes arrive for S seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    idle_timeout: Option<u64>,
+
+    /// Log level (error, warn, info, debug, trace); RUST_LOG overrides it with a full filter
     #[arg(long, default_value = "info")]
     log_level: String,
 
+    /// Log output format (text, json)
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
+
     /// Enable colored output
     #[arg(long, default_value_t = true)]
     color: bool,
 
+    /// Print only quote records to stdout, one JSON object per line; banners, statistics and prompts go to stderr
+    #[arg(long, visible_alias = "quiet", conflicts_with_all = ["tui", "summary", "load_test"])]
+    machine: bool,
+
+    /// Disable colored prices and log formatting (e.g. when piping the output)
+    #[arg(long, default_value_t = false)]
+    no_color: bool,
+
     /// Show timestamp in output
     #[arg(long, default_value_t = false)]
     show_timestamp: bool,
 }
 
-fn setup_logging(level: &str, color: bool) {
-    use env_logger::Env;
-
-    // Создаем специальное окружение с нужным уровнем логирования
-    let env = Env::default().filter_or("RUST_LOG", format!("quote_client={}", level));
-
-    let mut builder = env_logger::Builder::from_env(env);
-
-    // Настраиваем формат
-    builder
-        .format_timestamp(Some(env_logger::TimestampPrecision::Millis))
-        .format_level(true)
-        .format_target(false)
-        .format_module_path(false);
-
-    if color {
-        builder.format_indent(Some(4));
-    }
+fn setup_logging(level: &str, format: LogFormat, color: bool) {
+    // --log-level относится к библиотеке и самой программе; RUST_LOG заменяет
+    // фильтр целиком: RUST_LOG=warn,quote_common::tcp_server=debug
+    let default = format!("quote_common={level},quote_client={level}");
+    let filter = match LogFilter::from_env_or(&default) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Invalid log filter: {}", e);
+            LogFilter::parse("quote_common=info,quote_client=info").expect("valid default filter")
+        }
+    };
 
-    // Инициализируем логгер
-    if let Err(e) = builder.try_init() {
+    if let Err(e) = logging::init(filter, format, color) {
         eprintln!("Failed to initialize logger: {}", e);
         eprintln!("Logging disabled. Using fallback to stdout.");
     }
@@ -164,33 +380,81 @@ fn load_tickers(args: &Args) -> Result<Vec<String>, Box<dyn std::error::Error>>
     Ok(tickers)
 }
 
-fn parse_json_quote(json_str: &str) -> Result<(String, f64, u32, u64), Box<dyn std::error::Error>> {
-    #[derive(serde::Deserialize)]
-    struct Quote {
-        ticker: String,
-        price: f64,
-        volume: u32,
-        timestamp: u64,
+#[derive(serde::Deserialize)]
+struct Quote {
+    ticker: String,
+    price: f64,
+    // Нет у валютных пар и индексов
+    #[serde(default)]
+    volume: Option<u32>,
+    timestamp: u64,
+    #[serde(default)]
+    asset_class: Option<String>,
+}
+
+impl Quote {
+    // Валютные пары котируются с 5 знаками, остальное - с 2
+    fn decimals(&self) -> usize {
+        price_decimals(self.asset_class.as_deref())
     }
+}
+
+fn price_decimals(asset_class: Option<&str>) -> usize {
+    match asset_class {
+        Some("fx") => 5,
+        _ => 2,
+    }
+}
+
+fn parse_json_quote(json_str: &str) -> Result<Quote, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_str(json_str)?)
+}
+
+// Строка CSV для вывода в терминал; заголовок печатается при старте
+fn format_csv(data: &str) -> String {
+    serde_json::from_str(data)
+        .ok()
+        .and_then(|json| sink::csv_row(&json))
+        .unwrap_or_else(|| data.to_string())
+}
+
+// Цвет цены по направлению тика: зеленый - выше прошлой сделки тикера, красный - ниже
+fn price_color(previous: Option<f64>, price: f64) -> Option<&'static str> {
+    match previous {
+        Some(previous) if price > previous => Some("\x1b[32m"),
+        Some(previous) if price < previous => Some("\x1b[31m"),
+        _ => None,
+    }
+}
 
-    let quote: Quote = serde_json::from_str(json_str)?;
-    Ok((quote.ticker, quote.price, quote.volume, quote.timestamp))
+fn paint(text: String, color: Option<&str>) -> String {
+    match color {
+        Some(color) => format!("{}{}\x1b[0m", color, text),
+        None => text,
+    }
 }
 
-fn format_quote(data: &str, format: &str, show_timestamp: bool) -> String {
+// color окрашивает цену в форматах line и detailed
+fn format_quote(data: &str, format: &str, show_timestamp: bool, color: Option<&str>) -> String {
     match format {
         "json" => {
             // Уже в JSON формате, просто возвращаем как есть
             data.to_string()
         }
+        "csv" => format_csv(data),
         "simple" => {
             // Пытаемся парсить JSON и конвертировать в простой формат
             match parse_json_quote(data) {
-                Ok((ticker, price, volume, timestamp)) => {
+                Ok(quote) => {
+                    let decimals = quote.decimals();
+                    let volume = quote.volume.unwrap_or(0);
                     if show_timestamp {
-                        format!("{}|{:.2}|{}|{}", ticker, price, volume, timestamp)
+                        format!(
+                            "{}|{:.*}|{}|{}",
+                            quote.ticker, decimals, quote.price, volume, quote.timestamp
+                        )
                     } else {
-                        format!("{}|{:.2}|{}", ticker, price, volume)
+                        format!("{}|{:.*}|{}", quote.ticker, decimals, quote.price, volume)
                     }
                 }
                 Err(_) => {
@@ -201,14 +465,28 @@ fn format_quote(data: &str, format: &str, show_timestamp: bool) -> String {
         }
         "detailed" => {
             match parse_json_quote(data) {
-                Ok((ticker, price, volume, timestamp)) => {
+                Ok(quote) => {
                     // Простой формат без chrono
-                    let seconds = timestamp / 1000;
-                    let millis = timestamp % 1000;
-                    format!(
-                        "[{}.{:03}] {}: ${:.2} (volume: {})",
-                        seconds, millis, ticker, price, volume
-                    )
+                    let seconds = quote.timestamp / 1000;
+                    let millis = quote.timestamp % 1000;
+                    let decimals = quote.decimals();
+                    match quote.volume {
+                        Some(volume) => format!(
+                            "[{}.{:03}] {}: {} (volume: {})",
+                            seconds,
+                            millis,
+                            quote.ticker,
+                            paint(format!("${:.*}", decimals, quote.price), color),
+                            volume
+                        ),
+                        None => format!(
+                            "[{}.{:03}] {}: {}",
+                            seconds,
+                            millis,
+                            quote.ticker,
+                            paint(format!("{:.*}", decimals, quote.price), color)
+                        ),
+                    }
                 }
                 Err(_) => {
                     format!("[Parse Error] {}", data)
@@ -216,16 +494,29 @@ fn format_quote(data: &str, format: &str, show_timestamp: bool) -> String {
             }
         }
         "line" => match parse_json_quote(data) {
-            Ok((ticker, price, volume, timestamp)) => {
+            Ok(quote) => {
+                let decimals = quote.decimals();
+                let line = match quote.volume {
+                    Some(volume) => {
+                        format!(
+                            "{} {} ({})",
+                            quote.ticker,
+                            paint(format!("${:.*}", decimals, quote.price), color),
+                            volume
+                        )
+                    }
+                    None => format!(
+                        "{} {}",
+                        quote.ticker,
+                        paint(format!("{:.*}", decimals, quote.price), color)
+                    ),
+                };
                 if show_timestamp {
-                    let seconds = timestamp / 1000;
-                    let millis = timestamp % 1000;
-                    format!(
-                        "[{}.{:03}] {} ${:.2} ({})",
-                        seconds, millis, ticker, price, volume
-                    )
+                    let seconds = quote.timestamp / 1000;
+                    let millis = quote.timestamp % 1000;
+                    format!("[{}.{:03}] {}", seconds, millis, line)
                 } else {
-                    format!("{} ${:.2} ({})", ticker, price, volume)
+                    line
                 }
             }
             Err(_) => {
@@ -236,121 +527,560 @@ fn format_quote(data: &str, format: &str, show_timestamp: bool) -> String {
     }
 }
 
-fn check_user_input(running: &AtomicBool) {
-    let mut input = String::new();
-    if stdin().read_line(&mut input).is_ok() {
-        let input = input.trim().to_lowercase();
-        if input == "quit" || input == "exit" || input == "q" {
-            info!("User requested shutdown...");
-            running.store(false, Ordering::SeqCst);
+// Форматирование котировки bid/ask в том же стиле, что и сделки
+fn format_bid_ask(data: &str, format: &str, show_timestamp: bool) -> String {
+    #[derive(serde::Deserialize)]
+    struct BidAsk {
+        ticker: String,
+        bid: f64,
+        bid_size: u32,
+        ask: f64,
+        ask_size: u32,
+        timestamp: u64,
+        #[serde(default)]
+        asset_class: Option<String>,
+    }
+
+    let quote: BidAsk = match serde_json::from_str(data) {
+        Ok(quote) => quote,
+        Err(_) => return format!("[Parse Error] {}", data),
+    };
+    let decimals = price_decimals(quote.asset_class.as_deref());
+
+    let seconds = quote.timestamp / 1000;
+    let millis = quote.timestamp % 1000;
+
+    match format {
+        "json" => data.to_string(),
+        "csv" => format_csv(data),
+        "simple" => {
+            if show_timestamp {
+                format!(
+                    "{}|{:.*}|{}|{:.*}|{}|{}",
+                    quote.ticker,
+                    decimals,
+                    quote.bid,
+                    quote.bid_size,
+                    decimals,
+                    quote.ask,
+                    quote.ask_size,
+                    quote.timestamp
+                )
+            } else {
+                format!(
+                    "{}|{:.*}|{}|{:.*}|{}",
+                    quote.ticker,
+                    decimals,
+                    quote.bid,
+                    quote.bid_size,
+                    decimals,
+                    quote.ask,
+                    quote.ask_size
+                )
+            }
+        }
+        "detailed" => format!(
+            "[{}.{:03}] {}: bid ${:.*} x {} / ask ${:.*} x {}",
+            seconds,
+            millis,
+            quote.ticker,
+            decimals,
+            quote.bid,
+            quote.bid_size,
+            decimals,
+            quote.ask,
+            quote.ask_size
+        ),
+        _ => {
+            if show_timestamp {
+                format!(
+                    "[{}.{:03}] {} {:.*}/{:.*} ({}x{})",
+                    seconds,
+                    millis,
+                    quote.ticker,
+                    decimals,
+                    quote.bid,
+                    decimals,
+                    quote.ask,
+                    quote.bid_size,
+                    quote.ask_size
+                )
+            } else {
+                format!(
+                    "{} {:.*}/{:.*} ({}x{})",
+                    quote.ticker,
+                    decimals,
+                    quote.bid,
+                    decimals,
+                    quote.ask,
+                    quote.bid_size,
+                    quote.ask_size
+                )
+            }
         }
     }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+// Адрес сервера с учетом разрешения имени
+fn resolve_server(args: &Args) -> std::io::Result<SocketAddr> {
+    args.server_addr.to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("cannot resolve {}", args.server_addr),
+        )
+    })
+}
 
-    // Проверка: хотя бы один источник тикеров должен быть указан
-    if args.ticker_file.is_none()
-        && args.tickers.is_none()
-        && !std::path::Path::new("tickers.txt").exists()
-    {
-        eprintln!("ERROR: No tickers specified!");
-        eprintln!("Use one of:");
-        eprintln!("  --tickers AAPL,TSLA,MSFT");
-        eprintln!("  --ticker-file my_tickers.txt");
-        eprintln!("  Or create tickers.txt file");
-        std::process::exit(1);
+// Адрес, на который сервер шлет котировки: --advertise-addr или адрес
+// интерфейса, через который идет маршрут к серверу. UDP connect ничего не
+// отправляет, только выбирает маршрут.
+fn advertise_ip(args: &Args, server: SocketAddr) -> std::io::Result<IpAddr> {
+    if let Some(ip) = args.advertise_addr {
+        return Ok(ip);
     }
+    let bind_addr = if server.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let probe = UdpSocket::bind(bind_addr)?;
+    probe.connect(server)?;
+    Ok(probe.local_addr()?.ip())
+}
 
-    // Инициализация логирования
-    setup_logging(&args.log_level, args.color);
+// Локальный адрес UDP сокетов: --bind-addr или все интерфейсы семейства
+// удаленного адреса ([::] принимает и IPv4)
+fn local_ip(args: &Args, remote: IpAddr) -> IpAddr {
+    args.bind_addr.unwrap_or(if remote.is_ipv6() {
+        Ipv6Addr::UNSPECIFIED.into()
+    } else {
+        Ipv4Addr::UNSPECIFIED.into()
+    })
+}
 
-    // Принудительно выводим критически важные сообщения
-    println!("=== Quote Client Starting ===");
-    println!("Server: {}", args.server_addr);
-    println!("UDP Port: {}", args.udp_port);
-    println!("Output Format: {}", args.output_format);
-    if args.show_timestamp {
-        println!("Timestamp: enabled");
+// Команда STREAM с тикерами и параметрами потока из командной строки
+fn stream_command(args: &Args, tickers: &[String], host: IpAddr, udp_port: u16) -> String {
+    format!(
+        "STREAM udp://{} {} {}{}{}{}{}{}{}\n",
+        SocketAddr::new(host, udp_port),
+        tickers.join(","),
+        args.feed.to_uppercase(),
+        if args.depth { " DEPTH" } else { "" },
+        if args.conflate { " CONFLATE" } else { "" },
+        args.rate
+            .map(|rate| format!(" RATE {}/s", rate))
+            .unwrap_or_default(),
+        args.every
+            .map(|every| format!(" EVERY {}", every))
+            .unwrap_or_default(),
+        args.overflow
+            .as_ref()
+            .map(|policy| format!(" OVERFLOW {}", policy))
+            .unwrap_or_default(),
+        args.server_bars
+            .as_ref()
+            .map(|interval| format!(" BARS {}", interval))
+            .unwrap_or_default()
+    )
+}
+
+fn read_greeting(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut buf = [0; 4096];
+    let n = stream.read(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf[..n]).to_string())
+}
+
+// Возможности сервера, без которых заданный поток не получить
+fn required_capabilities(args: &Args) -> Vec<String> {
+    let mut required = vec!["json", "udp"];
+    if !args.feed.eq_ignore_ascii_case("trades") {
+        required.push("quotes");
+    }
+    if args.depth {
+        required.push("depth");
+    }
+    if args.conflate {
+        required.push("conflate");
+    }
+    if args.rate.is_some() {
+        required.push("rate");
+    }
+    if args.every.is_some() {
+        required.push("every");
     }
-    println!("=============================");
+    if args.overflow.is_some() {
+        required.push("overflow");
+    }
+    if args.server_bars.is_some() {
+        required.push("bar-stream");
+    }
+    if args.session.is_some() {
+        required.push("resume");
+    }
+    if args.api_key.is_some() {
+        required.push("auth");
+    }
+    required.into_iter().map(str::to_string).collect()
+}
 
-    info!("Starting Quote Client...");
-    info!("Configuration:");
-    info!("  Server address: {}", args.server_addr);
-    info!("  UDP receive port: {}", args.udp_port);
-    info!("  Server ping port: {}", args.server_ping_port);
-    info!("  Ping interval: {}s", args.ping_interval);
-    info!("  Output format: {}", args.output_format);
-    if args.duration > 0 {
-        info!("  Duration: {} seconds", args.duration);
+// HELLO после приветствия. None - сервер HELLO не знает (ERR), тогда
+// клиент работает как раньше, без проверки возможностей.
+fn negotiate(
+    stream: &mut TcpStream,
+    capabilities: &[String],
+) -> std::io::Result<Option<HelloResponse>> {
+    let request = format!("HELLO {} {}\n", PROTOCOL_VERSION, capabilities.join(","));
+    stream.write_all(request.as_bytes())?;
+    // Остаток приветствия, если оно пришло не одним пакетом, пропускается
+    let mut lines = LineReader::new(&*stream);
+    loop {
+        let line = match lines.next_line()? {
+            Some(Line::Command(line)) => line,
+            Some(Line::TooLong) => continue,
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "server closed the connection during HELLO",
+                ));
+            }
+        };
+        if let Some(hello) = HelloResponse::parse(&line) {
+            return Ok(Some(hello));
+        }
+        if line.starts_with("ERR") {
+            // Старый сервер отвечает на неизвестную команду двумя строками
+            lines.next_line()?;
+            return Ok(None);
+        }
     }
-    info!("  Log level: {}", args.log_level);
-    info!("  Colored output: {}", args.color);
-    info!("Type 'quit' and press Enter to stop");
+}
 
-    // Загрузка тикеров
-    let tickers = load_tickers(&args)?;
-    println!("Loaded {} tickers: {}", tickers.len(), tickers.join(", "));
-    info!("Loaded {} tickers: {}", tickers.len(), tickers.join(", "));
+// AUTH по ключу; Err - ответ сервера, если ключ не принят
+fn authenticate(stream: &mut TcpStream, key: &str) -> Result<(), String> {
+    stream
+        .write_all(format!("AUTH {}\n", key).as_bytes())
+        .map_err(|e| e.to_string())?;
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).map_err(|e| e.to_string())?;
+    let response = String::from_utf8_lossy(&buf[..n]).trim().to_string();
+    match response.strip_prefix("AUTH_OK ") {
+        Some(name) => {
+            info!("Authenticated with API key {}", name);
+            Ok(())
+        }
+        None => Err(response),
+    }
+}
+
+// Запуск (или продолжение) потока и запрос токена сессии, которым
+// подписываются UDP ping. Err - ответ сервера, если поток не начался.
+fn request_stream(stream: &mut TcpStream, command: &str) -> Result<String, String> {
+    let mut buf = [0; 1024];
+    let mut exchange = |request: &str| -> Result<String, String> {
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let n = stream.read(&mut buf).map_err(|e| e.to_string())?;
+        Ok(String::from_utf8_lossy(&buf[..n]).trim().to_string())
+    };
+
+    status!("Sent command: {}", command.trim());
+    info!("Sent command: {}", command.trim());
+    let response = exchange(command)?;
+    status!("Server: {}", response);
+    info!("Server response: {}", response);
+
+    if !response.contains("STREAMING_STARTED") && !response.starts_with("RESUMED") {
+        return Err(response);
+    }
+
+    // После RESUME токен - продолженной сессии
+    let id_response = exchange("ID\n")?;
+    id_response
+        .strip_prefix("ID ")
+        .map(str::to_string)
+        .ok_or_else(|| format!("unexpected response to ID: {}", id_response))
+}
 
+// Идентификатор запуска сервера из строки INSTANCE приветствия
+fn greeting_instance(greeting: &str) -> Option<String> {
+    greeting
+        .lines()
+        .find_map(|line| line.strip_prefix("INSTANCE "))
+        .map(|instance| instance.trim().to_string())
+}
+
+struct Reconnected {
+    stream: TcpStream,
+    session_id: String,
+    // Нет у серверов, не присылающих INSTANCE
+    instance: Option<String>,
+    // Сработал RESUME прежней сессии, а не новый STREAM
+    resumed: bool,
+}
+
+// Переподключение с экспоненциальной паузой. Команды пробуются по порядку:
+// сначала RESUME прежней сессии (сервер мог сохранить ее), затем новый STREAM.
+fn reconnect(
+    server_addr: &str,
+    api_key: Option<&str>,
+    commands: &[String],
+    max_attempts: u32,
+    running: &AtomicBool,
+) -> Option<Reconnected> {
+    let mut delay = INITIAL_RECONNECT_DELAY;
+    for attempt in 1..=max_attempts {
+        status!(
+            "Reconnecting in {}s (attempt {}/{})...",
+            delay.as_secs(),
+            attempt,
+            max_attempts
+        );
+        info!(
+            "Reconnecting to {} in {:?} (attempt {}/{})",
+            server_addr, delay, attempt, max_attempts
+        );
+
+        // Ждем небольшими шагами, чтобы 'quit' срабатывал сразу
+        let deadline = std::time::Instant::now() + delay;
+        while std::time::Instant::now() < deadline {
+            if !running.load(Ordering::SeqCst) {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+
+        let mut stream = match TcpStream::connect(server_addr) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Reconnect to {} failed: {}", server_addr, e);
+                continue;
+            }
+        };
+        let instance = match read_greeting(&mut stream) {
+            Ok(greeting) => {
+                debug!("Server greeting: {}", greeting);
+                greeting_instance(&greeting)
+            }
+            Err(e) => {
+                warn!("Reconnect to {} failed: {}", server_addr, e);
+                continue;
+            }
+        };
+        if let Some(key) = api_key
+            && let Err(response) = authenticate(&mut stream, key)
+        {
+            warn!("AUTH rejected: {}", response);
+            continue;
+        }
+        for (index, command) in commands.iter().enumerate() {
+            match request_stream(&mut stream, command) {
+                Ok(session_id) => {
+                    status!("Reconnected to {} as session {}", server_addr, session_id);
+                    info!("Reconnected to {} as session {}", server_addr, session_id);
+                    return Some(Reconnected {
+                        stream,
+                        session_id,
+                        instance,
+                        resumed: index == 0,
+                    });
+                }
+                Err(response) => warn!("{} rejected: {}", command.trim(), response),
+            }
+        }
+    }
+    None
+}
+
+// Нагрузочный тест вместо обычного приема: виртуальные клиенты на портах
+// --udp-port, --udp-port + 1, ...
+fn run_load_test(
+    args: &Args,
+    tickers: &[String],
+    clients: u16,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if clients == 0 || args.udp_port as u32 + clients as u32 > u16::MAX as u32 + 1 {
+        return Err(format!(
+            "--load-test {} does not fit UDP ports starting at {}",
+            clients, args.udp_port
+        )
+        .into());
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.store(false, Ordering::SeqCst))?;
+    }
+
+    status!(
+        "Load test: {} clients on UDP ports {}-{}, press Ctrl+C to stop",
+        clients,
+        args.udp_port,
+        args.udp_port as u32 + clients as u32 - 1
+    );
+    info!("Starting load test with {} clients", clients);
+    let server = resolve_server(args)?;
+    let host = advertise_ip(args, server)?;
+    let commands = (0..clients)
+        .map(|i| {
+            let port = args.udp_port + i;
+            (port, stream_command(args, tickers, host, port))
+        })
+        .collect();
+    load_test::LoadTest {
+        server_addr: args.server_addr.clone(),
+        ping_addr: SocketAddr::new(server.ip(), args.server_ping_port).to_string(),
+        host: local_ip(args, host),
+        api_key: args.api_key.clone(),
+        commands,
+        ping_interval: Duration::from_secs(args.ping_interval),
+        duration: (args.duration > 0).then(|| Duration::from_secs(args.duration)),
+    }
+    .run(running);
+    Ok(())
+}
+
+// Соединение, токен сессии, команда STREAM для переподключения
+// и идентификатор запуска сервера
+type OpenedStream = (TcpStream, String, String, Option<String>);
+// После переподключения соединение подменяется
+type SharedConnection = Arc<Mutex<TcpStream>>;
+
+// Подключение к серверу и запуск потока. None - сервер отказал в STREAM/RESUME.
+fn open_stream(
+    args: &Args,
+    tickers: &[String],
+) -> Result<Option<OpenedStream>, Box<dyn std::error::Error>> {
     // Подключаемся к TCP серверу
-    println!("Connecting to server {}...", args.server_addr);
+    status!("Connecting to server {}...", args.server_addr);
     info!("Connecting to server {}...", args.server_addr);
     let mut tcp_stream = TcpStream::connect(&args.server_addr)?;
-    println!("Connected successfully to TCP server");
+    status!("Connected successfully to TCP server");
     info!("Connected successfully to TCP server");
 
     // Читаем приветственное сообщение
-    let mut buf = [0; 1024];
-    let n = tcp_stream.read(&mut buf)?;
-    let greeting = String::from_utf8_lossy(&buf[..n]);
-    println!("{}", greeting);
+    let greeting = read_greeting(&mut tcp_stream)?;
+    status!("{}", greeting);
     debug!("Server greeting: {}", greeting);
 
-    // Отправляем команду STREAM
-    let stream_command = format!(
-        "STREAM udp://{}:{} {}\n",
-        LOCALHOST, // Используем константу
-        args.udp_port,
-        tickers.join(",")
-    );
-
-    tcp_stream.write_all(stream_command.as_bytes())?;
-    println!("Sent command: {}", stream_command.trim());
-    info!("Sent command: {}", stream_command.trim());
+    // Токен сессии для переподключения без остановки потока
+    if let Some(token) = greeting
+        .lines()
+        .find_map(|line| line.strip_prefix("SESSION "))
+    {
+        status!(
+            "Session token: {} (reconnect with --session {})",
+            token,
+            token
+        );
+        info!("Session token: {}", token);
+    }
 
-    // Читаем ответ
-    let n = tcp_stream.read(&mut buf)?;
-    let response = String::from_utf8_lossy(&buf[..n]).trim().to_string();
-    println!("Server: {}", response);
-    info!("Server response: {}", response);
+    let required = required_capabilities(args);
+    match negotiate(&mut tcp_stream, &required)? {
+        Some(hello) => {
+            debug!("Server capabilities: {:?}", hello.capabilities);
+            let missing: Vec<&String> = required
+                .iter()
+                .filter(|name| !hello.accepted.contains(name))
+                .collect();
+            if !missing.is_empty() {
+                let missing = missing
+                    .iter()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                eprintln!("Server does not support: {}", missing);
+                error!("Server does not support: {}", missing);
+                return Ok(None);
+            }
+            info!("Negotiated protocol version {}", hello.version);
+        }
+        None => info!("Server does not support HELLO, skipping capability check"),
+    }
 
-    if !response.contains("STREAMING_STARTED") {
-        eprintln!("Failed to start streaming. Server response: {}", response);
-        error!("Failed to start streaming. Server response: {}", response);
-        return Ok(());
+    if let Some(key) = &args.api_key {
+        if let Err(response) = authenticate(&mut tcp_stream, key) {
+            eprintln!("Authentication failed. Server response: {}", response);
+            error!("Authentication failed. Server response: {}", response);
+            return Ok(None);
+        }
+        status!("Authenticated with API key");
     }
 
-    // Создаем UDP сокет для получения данных
-    let udp_socket = UdpSocket::bind(format!("{}:{}", LOCALHOST, args.udp_port))?;
-    udp_socket.set_read_timeout(Some(Duration::from_millis(1000)))?;
-    println!("UDP socket bound to {}:{}", LOCALHOST, args.udp_port);
-    info!("UDP socket bound to {}:{}", LOCALHOST, args.udp_port);
+    // Отправляем команду STREAM или продолжаем прежнюю сессию
+    let host = advertise_ip(args, tcp_stream.peer_addr()?)?;
+    status!(
+        "Receiving quotes at {}",
+        SocketAddr::new(host, args.udp_port)
+    );
+    info!(
+        "Receiving quotes at {}",
+        SocketAddr::new(host, args.udp_port)
+    );
+    let new_stream_command = stream_command(args, tickers, host, args.udp_port);
+    let first_command = match &args.session {
+        Some(token) => format!("RESUME {}\n", token),
+        None => new_stream_command.clone(),
+    };
 
-    // Флаг для контроля работы потоков
-    let running = Arc::new(AtomicBool::new(true));
+    let mut result = request_stream(&mut tcp_stream, &first_command);
+    // Сессия уже истекла на сервере - начинаем новый поток
+    if args.session.is_some()
+        && let Err(response) = &result
+        && ErrorResponse::parse(response).is_some_and(|e| e.is(ErrorCode::UnknownSession))
+    {
+        status!("Session is no longer known to the server, starting a new stream");
+        warn!("Session is no longer known to the server, starting a new stream");
+        result = request_stream(&mut tcp_stream, &new_stream_command);
+    }
+    let session_id = match result {
+        Ok(session_id) => session_id,
+        Err(response) => {
+            eprintln!("Failed to start streaming. Server response: {}", response);
+            error!("Failed to start streaming. Server response: {}", response);
+            return Ok(None);
+        }
+    };
+    debug!("Pinging as session {}", session_id);
+
+    Ok(Some((
+        tcp_stream,
+        session_id,
+        new_stream_command,
+        greeting_instance(&greeting),
+    )))
+}
+
+// Потоки живой сессии: ping сервера и слежение за TCP соединением
+fn spawn_session_threads(
+    args: &Args,
+    tcp_stream: TcpStream,
+    session_id: String,
+    new_stream_command: String,
+    instance: Option<String>,
+    running: &Arc<AtomicBool>,
+) -> std::io::Result<(SharedConnection, Vec<thread::JoinHandle<()>>)> {
+    // Ping идет на тот же хост, что и TCP соединение
+    let server_ip = tcp_stream.peer_addr()?.ip();
+    // После переподключения соединение и токен сессии меняются
+    let reader = tcp_stream.try_clone()?;
+    let connection = Arc::new(Mutex::new(tcp_stream));
+    let session_id = Arc::new(Mutex::new(session_id));
+    let server_ping_addr = SocketAddr::new(server_ip, args.server_ping_port);
+    let ping_bind_addr = SocketAddr::new(local_ip(args, server_ip), 0);
 
     // Запускаем поток для отправки PING сообщений
     let ping_thread = {
         let running = running.clone();
-        let server_ping_addr = format!("{}:{}", LOCALHOST, args.server_ping_port);
         let ping_interval = args.ping_interval;
+        let session_id = session_id.clone();
 
         thread::spawn(move || {
             // Простая реализация ping - пробуем создать сокет, если не получается - выходим
-            let ping_socket = match UdpSocket::bind(format!("{}:0", LOCALHOST)) {
+            let ping_socket = match UdpSocket::bind(ping_bind_addr) {
                 Ok(socket) => {
                     debug!("Ping socket created successfully");
                     socket
@@ -366,7 +1096,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             debug!("Starting ping thread, interval: {}s", ping_interval);
 
             while running.load(Ordering::SeqCst) {
-                match ping_socket.send_to(b"PING", &server_ping_addr) {
+                let ping_message = format!("PING {}", session_id.lock().unwrap());
+                match ping_socket.send_to(ping_message.as_bytes(), server_ping_addr) {
                     Ok(_) => {
                         ping_count += 1;
                         if ping_count == 1 {
@@ -386,11 +1117,367 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         })
     };
 
+    // Поток следит за TCP соединением и переподключается, если сервер пропал
+    let watch_thread = {
+        let running = running.clone();
+        let connection = connection.clone();
+        let session_id = session_id.clone();
+        let server_addr = args.server_addr.clone();
+        let api_key = args.api_key.clone();
+        let max_reconnects = args.max_reconnects;
+
+        thread::spawn(move || {
+            let mut reader = reader;
+            let mut instance = instance;
+            if let Err(e) = reader.set_read_timeout(Some(CONNECTION_POLL_INTERVAL)) {
+                warn!("Failed to set TCP read timeout: {}", e);
+            }
+            let mut buf = [0; 1024];
+
+            while running.load(Ordering::SeqCst) {
+                let lost = match reader.read(&mut buf) {
+                    Ok(0) => true,
+                    Ok(n) => {
+                        let text = String::from_utf8_lossy(&buf[..n]);
+                        debug!("Server: {}", text.trim());
+                        // Сервер отключает клиента, не успевающего забирать поток;
+                        // следом соединение закрывается
+                        for reason in text
+                            .lines()
+                            .filter_map(|line| line.trim().strip_prefix("SLOW_CONSUMER "))
+                        {
+                            status!("\nServer disconnected us as a slow consumer: {}", reason);
+                            warn!("Disconnected as a slow consumer: {}", reason);
+                        }
+                        // Администратор выгнал сессию (KICK): переподключение
+                        // отменило бы его решение
+                        if text.lines().any(|line| line.trim().starts_with("KICKED ")) {
+                            status!("\nDisconnected by the server administrator (KICK)");
+                            warn!("Kicked by the server administrator");
+                            running.store(false, Ordering::SeqCst);
+                            break;
+                        }
+                        text.lines()
+                            .any(|line| line.trim() == "SERVER_SHUTTING_DOWN")
+                    }
+                    Err(ref e)
+                        if e.kind() == std::io::ErrorKind::WouldBlock
+                            || e.kind() == std::io::ErrorKind::TimedOut =>
+                    {
+                        false
+                    }
+                    Err(e) => {
+                        warn!("TCP connection error: {}", e);
+                        true
+                    }
+                };
+                if !lost {
+                    continue;
+                }
+
+                status!("\nConnection to server {} lost", server_addr);
+                warn!("Connection to server {} lost", server_addr);
+                if max_reconnects == 0 {
+                    running.store(false, Ordering::SeqCst);
+                    break;
+                }
+
+                let resume_command = format!("RESUME {}\n", session_id.lock().unwrap());
+                let commands = [resume_command, new_stream_command.clone()];
+                let Some(reconnected) = reconnect(
+                    &server_addr,
+                    api_key.as_deref(),
+                    &commands,
+                    max_reconnects,
+                    &running,
+                ) else {
+                    if running.load(Ordering::SeqCst) {
+                        eprintln!("Giving up after {} reconnect attempts", max_reconnects);
+                        error!("Giving up after {} reconnect attempts", max_reconnects);
+                        running.store(false, Ordering::SeqCst);
+                    }
+                    break;
+                };
+
+                match reconnected.stream.try_clone() {
+                    Ok(clone) => reader = clone,
+                    Err(e) => {
+                        error!("Failed to clone TCP stream: {}", e);
+                        running.store(false, Ordering::SeqCst);
+                        break;
+                    }
+                }
+                if let Err(e) = reader.set_read_timeout(Some(CONNECTION_POLL_INTERVAL)) {
+                    warn!("Failed to set TCP read timeout: {}", e);
+                }
+                // Сервер с другим INSTANCE перезапущен; прежний поток к нам
+                // не вернется, пока не восстановлен RESUME или новой подпиской
+                match (&instance, &reconnected.instance) {
+                    (Some(before), Some(after)) if before != after => {
+                        let how = if reconnected.resumed {
+                            "session restored"
+                        } else {
+                            "resubscribed"
+                        };
+                        status!("Server restarted ({} -> {}), {}", before, after, how);
+                        warn!("Server restarted ({} -> {}), {}", before, after, how);
+                    }
+                    _ if !reconnected.resumed => {
+                        status!("Server lost the session, resubscribed");
+                        warn!("Server lost the session, resubscribed");
+                    }
+                    _ => {}
+                }
+                instance = reconnected.instance;
+                *connection.lock().unwrap() = reconnected.stream;
+                *session_id.lock().unwrap() = reconnected.session_id;
+            }
+            debug!("Connection watcher stopped");
+        })
+    };
+
+    Ok((connection, vec![ping_thread, watch_thread]))
+}
+
+fn check_user_input(running: &AtomicBool) {
+    let mut input = String::new();
+    if stdin().read_line(&mut input).is_ok() {
+        let input = input.trim().to_lowercase();
+        if input == "quit" || input == "exit" || input == "q" {
+            info!("User requested shutdown...");
+            running.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Параметры из client.toml идут перед флагами командной строки
+    let sources = ArgSources {
+        default_config: DEFAULT_CONFIG,
+        env_prefix: None,
+    };
+    let argv = match merge_args(&Args::command(), std::env::args_os().collect(), &sources) {
+        Ok(argv) => argv,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut args = Args::parse_from(argv);
+    if args.machine {
+        MACHINE_OUTPUT.store(true, Ordering::Relaxed);
+        args.output_format = "json".to_string();
+        args.no_color = true;
+    }
+
+    // Проверка: хотя бы один источник тикеров должен быть указан
+    if args.ticker_file.is_none()
+        && args.tickers.is_none()
+        && !std::path::Path::new("tickers.txt").exists()
+    {
+        eprintln!("ERROR: No tickers specified!");
+        eprintln!("Use one of:");
+        eprintln!("  --tickers AAPL,TSLA,MSFT");
+        eprintln!("  --ticker-file my_tickers.txt");
+        eprintln!("  Or create tickers.txt file");
+        std::process::exit(1);
+    }
+
+    // Инициализация логирования
+    setup_logging(
+        &args.log_level,
+        args.log_format,
+        args.color && !args.no_color,
+    );
+
+    // Принудительно выводим критически важные сообщения
+    status!("=== Quote Client Starting ===");
+    status!("Server: {}", args.server_addr);
+    status!("UDP Port: {}", args.udp_port);
+    status!("Output Format: {}", args.output_format);
+    if args.show_timestamp {
+        status!("Timestamp: enabled");
+    }
+    status!("=============================");
+
+    info!("Starting Quote Client...");
+    info!("Configuration:");
+    if let Some(path) = &args.config {
+        info!("  Configuration file: {}", path);
+    }
+    info!("  Server address: {}", args.server_addr);
+    info!("  UDP receive port: {}", args.udp_port);
+    info!("  Server ping port: {}", args.server_ping_port);
+    info!("  Ping interval: {}s", args.ping_interval);
+    info!("  Output format: {}", args.output_format);
+    if args.duration > 0 {
+        info!("  Duration: {} seconds", args.duration);
+    }
+    info!("  Log level: {}", args.log_level);
+    info!("  Colored output: {}", args.color && !args.no_color);
+    info!("Type 'quit' and press Enter to stop");
+
+    let mut bar_aggregator = match &args.bars {
+        Some(value) => match bars::parse_interval(value) {
+            Ok(interval) => Some(bars::BarAggregator::new(interval, value)),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let quote_filter = match &args.filter {
+        Some(source) => match filter::Filter::parse(source) {
+            Ok(filter) => Some(filter),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let mut rules = Vec::new();
+    for rule in &args.alerts {
+        match alerts::AlertRule::parse(rule) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Загрузка тикеров
+    let tickers = load_tickers(&args)?;
+    status!("Loaded {} tickers: {}", tickers.len(), tickers.join(", "));
+    info!("Loaded {} tickers: {}", tickers.len(), tickers.join(", "));
+
+    for rule in &rules {
+        if !tickers.contains(&rule.ticker) {
+            warn!("Alert {} is for a ticker that is not streamed", rule);
+        }
+    }
+    let webhook = match &args.on_alert_webhook {
+        Some(url) => match alerts::Webhook::parse(url) {
+            Ok(webhook) => Some(webhook),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let hooks = alerts::AlertHooks {
+        shell: args.alert_command.clone(),
+        exec: args
+            .on_alert_exec
+            .as_ref()
+            .map(|command| command.split_whitespace().map(str::to_string).collect()),
+        webhook,
+    };
+    let mut alert_engine = alerts::AlertEngine::new(rules, hooks);
+
+    if let Some(clients) = args.load_test {
+        run_load_test(&args, &tickers, clients)?;
+        return Ok(());
+    }
+
+    // При --replay сервер не нужен: датаграммы читаются из файла записи
+    let server = match &args.replay {
+        Some(_) => None,
+        None => match open_stream(&args, &tickers)? {
+            Some(server) => Some(server),
+            None => return Ok(()),
+        },
+    };
+
+    // Дашборд перерисовывается и без новых котировок
+    let read_timeout = if args.tui {
+        tui::REFRESH_INTERVAL
+    } else {
+        Duration::from_millis(1000)
+    };
+    let mut feed = match &args.replay {
+        Some(path) => {
+            let replay = capture::CaptureReplay::open(path, args.replay_speed, read_timeout)?;
+            status!("Replaying {} at {}x speed", path, args.replay_speed);
+            info!("Replaying {} at {}x speed", path, args.replay_speed);
+            capture::Feed::Replay(replay)
+        }
+        None => {
+            // Создаем UDP сокет для получения данных
+            let remote = match args.advertise_addr {
+                Some(ip) => ip,
+                None => resolve_server(&args)?.ip(),
+            };
+            let local_addr = SocketAddr::new(local_ip(&args, remote), args.udp_port);
+            let udp_socket = UdpSocket::bind(local_addr)?;
+            udp_socket.set_read_timeout(Some(read_timeout))?;
+            status!("UDP socket bound to {}", local_addr);
+            info!("UDP socket bound to {}", local_addr);
+            capture::Feed::Udp(udp_socket)
+        }
+    };
+    let mut capture = match &args.record {
+        Some(path) => {
+            let capture =
+                capture::CaptureWriter::create(path, Duration::from_millis(args.output_flush_ms))?;
+            status!("Capturing the session to {}", path);
+            info!("Capturing the session to {}", path);
+            Some(capture)
+        }
+        None => None,
+    };
+
+    // Флаг для контроля работы потоков
+    let running = Arc::new(AtomicBool::new(true));
+
+    // При записи в файл Ctrl+C завершает клиента штатно: буферы сбрасываются,
+    // последняя транзакция SQLite завершается, а Parquet файл получает
+    // метаданные, без которых он не читается
+    if args.output_file.is_some()
+        || args.record.is_some()
+        || args.record_dir.is_some()
+        || args.parquet.is_some()
+        || args.sqlite.is_some()
+    {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.store(false, Ordering::SeqCst))?;
+    }
+
+    let (connection, session_threads) = match server {
+        Some((tcp_stream, session_id, new_stream_command, instance)) => {
+            let (connection, threads) = spawn_session_threads(
+                &args,
+                tcp_stream,
+                session_id,
+                new_stream_command,
+                instance,
+                &running,
+            )?;
+            (Some(connection), threads)
+        }
+        None => (None, Vec::new()),
+    };
+
+    // Клавиши управления дашбордом
+    let controls = Arc::new(tui::Controls::default());
+
     // Запускаем поток для проверки пользовательского ввода
     let input_thread = {
         let running = running.clone();
+        let controls = controls.clone();
+        let tui_mode = args.tui;
         thread::spawn(move || {
-            println!("Type 'quit' and press Enter to stop");
+            if tui_mode {
+                tui::read_keys(&controls, &running);
+                info!("Input thread stopped");
+                return;
+            }
+            status!("Type 'quit' and press Enter to stop");
             info!("Input thread started. Type 'quit' to stop.");
             while running.load(Ordering::SeqCst) {
                 check_user_input(&running);
@@ -401,19 +1488,86 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Главный цикл получения котировок
-    println!("\nReceiving quotes (each ticker on new line)...");
+    status!("\nReceiving quotes (each ticker on new line)...");
     info!(
         "Starting to receive quotes with format: {}",
         args.output_format
     );
-    let mut quote_count = 0;
+    let mut quote_count: u64 = 0;
     let mut non_quote_messages = 0;
+    let mut filtered_out = 0;
     let start_time = std::time::Instant::now();
 
+    // Файл CSV пишется независимо от формата вывода в терминал
+    let mut csv_sink = match &args.output_file {
+        Some(path) => {
+            let sink = sink::CsvSink::create(path, Duration::from_millis(args.output_flush_ms))?;
+            status!("Writing quotes to {}", path);
+            info!("Writing quotes to {}", path);
+            Some(sink)
+        }
+        None => None,
+    };
+    let mut recorder = match &args.record_dir {
+        Some(dir) => {
+            let recorder = sink::JsonlRecorder::create(
+                dir,
+                args.record_max_mb * 1024 * 1024,
+                Duration::from_secs(args.record_rotate_secs),
+                Duration::from_millis(args.output_flush_ms),
+            )?;
+            status!("Recording quotes to {}", dir);
+            info!("Recording quotes to {}", dir);
+            Some(recorder)
+        }
+        None => None,
+    };
+    let mut parquet_sink = match &args.parquet {
+        Some(path) => {
+            let sink =
+                parquet::ParquetSink::create(path, Duration::from_secs(args.parquet_flush_secs))?;
+            status!("Writing quotes to {}", path);
+            info!("Writing quotes to {}", path);
+            Some(sink)
+        }
+        None => None,
+    };
+    let mut sqlite_sink = match &args.sqlite {
+        Some(path) => {
+            let sink = sqlite::SqliteSink::create(
+                path,
+                &args.sqlite_command,
+                Duration::from_millis(args.sqlite_flush_ms),
+            )?;
+            status!("Writing quotes to {}", path);
+            info!("Writing quotes to {}", path);
+            Some(sink)
+        }
+        None => None,
+    };
+    let mut ws_relay = match &args.ws_relay {
+        Some(addr) => {
+            let relay = ws_relay::WsRelay::start(addr)?;
+            status!("WebSocket relay listening on ws://{}", addr);
+            info!("WebSocket relay listening on {}", addr);
+            Some(relay)
+        }
+        None => None,
+    };
+    // В терминал в формате csv идут только строки таблицы
+    let csv_output = args.output_format == "csv";
+    if csv_output && !args.tui {
+        println!("{}", sink::CSV_HEADER);
+    }
+
     // Для статистики по тикерам
     let mut ticker_stats: std::collections::HashMap<String, usize> =
         std::collections::HashMap::new();
     let mut last_stats_time = start_time;
+    // Последняя цена тикера для цвета направления тика
+    let mut last_prices: std::collections::HashMap<String, f64> = std::collections::HashMap::new();
+    let use_color = args.color && !args.no_color;
+    let mut rolling_stats = stats::RollingStats::new(Duration::from_secs(args.stats_window));
     const STATS_INTERVAL: Duration = Duration::from_secs(5);
 
     // Если указана длительность, устанавливаем таймер
@@ -422,21 +1576,126 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     } else {
         None
     };
+    // Для --idle-timeout: когда в последний раз вырос счетчик котировок
+    let idle_timeout = args.idle_timeout.map(Duration::from_secs);
+    let mut last_quote_time = start_time;
+    let mut last_seen_count = 0;
+    let mut exit_code = 0;
+    // Поток начался, а датаграмм нет: вероятно, UDP режет файрвол или NAT.
+    // Доставки по TCP сервер пока не умеет, поэтому только предупреждаем.
+    let udp_grace = (connection.is_some() && args.udp_grace_secs > 0)
+        .then(|| Duration::from_secs(args.udp_grace_secs));
+    let mut udp_warned = false;
+
+    // В режиме --tui таблица заменяет построчный вывод
+    let screen = if args.tui {
+        Some(tui::Screen::enter()?)
+    } else {
+        None
+    };
+    let mut dashboard = args.tui.then(|| tui::Dashboard::new(&tickers));
+    let mut summary = args
+        .summary
+        .map(|secs| summary::Summary::new(Duration::from_secs(secs), &tickers));
+    let mut stdout = std::io::stdout();
 
     'main_loop: while running.load(Ordering::SeqCst) {
         // Проверяем таймер, если установлен
         if let Some(end) = end_time
             && std::time::Instant::now() >= end
         {
-            println!("\nDuration limit reached, stopping...");
+            status!("\nDuration limit reached, stopping...");
             info!("Duration limit reached, stopping...");
             running.store(false, Ordering::SeqCst);
             break 'main_loop;
         }
+        if let Some(max) = args.max_quotes
+            && quote_count >= max
+        {
+            status!("\nReceived {} quotes, stopping...", quote_count);
+            info!("Quote limit of {} reached, stopping...", max);
+            running.store(false, Ordering::SeqCst);
+            break 'main_loop;
+        }
+        if quote_count != last_seen_count {
+            last_seen_count = quote_count;
+            last_quote_time = std::time::Instant::now();
+        } else if let Some(timeout) = idle_timeout
+            && last_quote_time.elapsed() >= timeout
+        {
+            eprintln!(
+                "\nNo quotes received for {} seconds, stopping...",
+                timeout.as_secs()
+            );
+            error!(
+                "No quotes received for {} seconds, stopping...",
+                timeout.as_secs()
+            );
+            exit_code = EXIT_IDLE_TIMEOUT;
+            running.store(false, Ordering::SeqCst);
+            break 'main_loop;
+        }
+
+        if let Some(grace) = udp_grace
+            && !udp_warned
+            && quote_count + non_quote_messages + filtered_out == 0
+            && start_time.elapsed() >= grace
+        {
+            udp_warned = true;
+            warn!(
+                "No UDP datagrams received on port {} within {} seconds: UDP may be blocked by a firewall, or the server cannot reach the advertised address (see --advertise-addr)",
+                args.udp_port,
+                grace.as_secs()
+            );
+        }
+
+        if let Some(dashboard) = &mut dashboard
+            && let Err(e) = dashboard.maybe_render(&controls, &mut stdout)
+        {
+            warn!("Failed to draw dashboard: {}", e);
+        }
+        if let Some(summary) = &mut summary {
+            summary.maybe_print();
+        }
+        if let Some(aggregator) = &mut bar_aggregator {
+            for bar in aggregator.close_expired() {
+                println!("{}", bar.format(&args.output_format));
+            }
+        }
+        if let Some(sink) = &mut csv_sink
+            && let Err(e) = sink.flush_if_due()
+        {
+            error!("Failed to write output file: {}", e);
+        }
+        if let Some(capture) = &mut capture
+            && let Err(e) = capture.flush_if_due()
+        {
+            error!("Failed to write capture file: {}", e);
+        }
+        if let Some(recorder) = &mut recorder
+            && let Err(e) = recorder.flush_if_due()
+        {
+            error!("Failed to write record file: {}", e);
+        }
+        if let Some(sink) = &mut parquet_sink
+            && let Err(e) = sink.flush_if_due()
+        {
+            error!("Failed to write Parquet file: {}", e);
+        }
+        if let Some(sink) = &mut sqlite_sink
+            && let Err(e) = sink.flush_if_due()
+        {
+            error!("Failed to write SQLite database: {}", e);
+        }
 
         let mut buf = [0; 4096];
-        match udp_socket.recv_from(&mut buf) {
+        match feed.recv_from(&mut buf) {
             Ok((size, addr)) => {
+                if let Some(capture) = &mut capture
+                    && let Err(e) = capture.write(&buf[..size])
+                {
+                    error!("Failed to write capture file: {}", e);
+                }
                 let message = String::from_utf8_lossy(&buf[..size]);
 
                 // ФИЛЬТРАЦИЯ: принимаем только JSON котировки, игнорируем служебные сообщения
@@ -455,9 +1714,44 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // Пытаемся распарсить как JSON
                 match serde_json::from_str::<serde_json::Value>(&message) {
                     Ok(json) => {
-                        if json.get("ticker").is_some()
+                        // Фильтр отсеивает сделки и котировки bid/ask до вывода и записи
+                        if let Some(filter) = &quote_filter
+                            && matches!(
+                                json.get("type").and_then(|t| t.as_str()),
+                                None | Some("quote")
+                            )
+                            && !filter.matches(&json)
+                        {
+                            filtered_out += 1;
+                            continue;
+                        }
+                        if let Some(relay) = &mut ws_relay {
+                            relay.publish(&message);
+                        }
+                        if let Some(sink) = &mut csv_sink
+                            && let Err(e) = sink.record(&json)
+                        {
+                            error!("Failed to write output file: {}", e);
+                        }
+                        if let Some(recorder) = &mut recorder
+                            && let Err(e) = recorder.record(&message, &json)
+                        {
+                            error!("Failed to write record file: {}", e);
+                        }
+                        if let Some(sink) = &mut parquet_sink
+                            && let Err(e) = sink.record(&json)
+                        {
+                            error!("Failed to write Parquet file: {}", e);
+                        }
+                        if let Some(sink) = &mut sqlite_sink
+                            && let Err(e) = sink.record(&json)
+                        {
+                            error!("Failed to write SQLite database: {}", e);
+                        }
+                        // Сделка - единственное сообщение без поля type; объема может не быть
+                        if json.get("type").is_none()
+                            && json.get("ticker").is_some()
                             && json.get("price").is_some()
-                            && json.get("volume").is_some()
                             && json.get("timestamp").is_some()
                         {
                             if let Some(ticker_value) = json.get("ticker") {
@@ -465,12 +1759,89 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     let ticker_upper = ticker_str.to_uppercase();
                                     if tickers.contains(&ticker_upper) {
                                         // Это валидная котировка для запрошенного тикера
-                                        let formatted = format_quote(
-                                            &message,
-                                            &args.output_format,
-                                            args.show_timestamp,
-                                        );
-                                        println!("{}", formatted);
+                                        let parsed = parse_json_quote(&message).ok();
+                                        if let Some(quote) = &parsed {
+                                            rolling_stats.record(
+                                                &ticker_upper,
+                                                quote.price,
+                                                quote.volume,
+                                                quote.timestamp,
+                                                quote.decimals(),
+                                            );
+                                        }
+                                        if !alert_engine.is_empty()
+                                            && let Some(quote) = &parsed
+                                        {
+                                            for alert in alert_engine.check(
+                                                &ticker_upper,
+                                                quote.price,
+                                                quote.timestamp,
+                                            ) {
+                                                let text = alert.message(quote.decimals());
+                                                // Таблицу --tui не перерисовываем поверх: только лог
+                                                if dashboard.is_none() {
+                                                    if use_color {
+                                                        status!(
+                                                            "\x1b[1;33m*** {} ***\x1b[0m",
+                                                            text
+                                                        );
+                                                    } else {
+                                                        status!("*** {} ***", text);
+                                                    }
+                                                }
+                                                warn!("{}", text);
+                                                alert_engine.run_hooks(&alert);
+                                            }
+                                        }
+                                        if let Some(dashboard) = &mut dashboard {
+                                            if let Ok(quote) = parse_json_quote(&message) {
+                                                dashboard.record_trade(
+                                                    &ticker_upper,
+                                                    quote.price,
+                                                    quote.volume,
+                                                    quote.decimals(),
+                                                );
+                                            }
+                                            dashboard.record_message(&ticker_upper);
+                                        } else if let Some(summary) = &mut summary {
+                                            if let Ok(quote) = parse_json_quote(&message) {
+                                                summary.record_trade(
+                                                    &ticker_upper,
+                                                    quote.price,
+                                                    quote.decimals(),
+                                                );
+                                            }
+                                            summary.record_message(&ticker_upper);
+                                        } else if let Some(aggregator) = &mut bar_aggregator {
+                                            // Вместо каждой сделки - строка закрытого бара
+                                            if let Ok(quote) = parse_json_quote(&message)
+                                                && let Some(bar) = aggregator.on_trade(
+                                                    &ticker_upper,
+                                                    quote.price,
+                                                    quote.volume,
+                                                    quote.timestamp,
+                                                    quote.decimals(),
+                                                )
+                                            {
+                                                println!("{}", bar.format(&args.output_format));
+                                            }
+                                        } else {
+                                            let color = match &parsed {
+                                                Some(quote) if use_color => price_color(
+                                                    last_prices
+                                                        .insert(ticker_upper.clone(), quote.price),
+                                                    quote.price,
+                                                ),
+                                                _ => None,
+                                            };
+                                            let formatted = format_quote(
+                                                &message,
+                                                &args.output_format,
+                                                args.show_timestamp,
+                                                color,
+                                            );
+                                            println!("{}", formatted);
+                                        }
                                         quote_count += 1;
 
                                         // Собираем статистику по тикерам
@@ -480,14 +1851,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                         if quote_count == 1 {
                                             info!("First quote received: {}", ticker_str);
                                         }
-                                        if quote_count % 10 == 0 {
+                                        if quote_count.is_multiple_of(10) {
                                             debug!("Received {} quotes from {}", quote_count, addr);
                                         }
 
                                         // Показываем статистику каждые STATS_INTERVAL
                                         let now = std::time::Instant::now();
-                                        if now.duration_since(last_stats_time) >= STATS_INTERVAL {
-                                            println!(
+                                        if dashboard.is_none()
+                                            && bar_aggregator.is_none()
+                                            && summary.is_none()
+                                            && !csv_output
+                                            && now.duration_since(last_stats_time) >= STATS_INTERVAL
+                                        {
+                                            status!(
                                                 "\n--- Statistics (last {} seconds) ---",
                                                 STATS_INTERVAL.as_secs()
                                             );
@@ -496,10 +1872,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                             stats_vec.sort_by(|a, b| b.1.cmp(a.1)); // Сортировка по убыванию
 
                                             for (ticker, count) in stats_vec {
-                                                println!("  {}: {} quotes", ticker, count);
+                                                match rolling_stats.get(ticker) {
+                                                    Some(window) => status!(
+                                                        "  {}: {} quotes  {}",
+                                                        ticker,
+                                                        count,
+                                                        window.describe()
+                                                    ),
+                                                    None => {
+                                                        status!("  {}: {} quotes", ticker, count)
+                                                    }
+                                                }
                                             }
-                                            println!("  Total: {} quotes", quote_count);
-                                            println!("--------------------------------");
+                                            status!("  Total: {} quotes", quote_count);
+                                            status!("--------------------------------");
 
                                             ticker_stats.clear();
                                             last_stats_time = now;
@@ -520,6 +1906,81 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 warn!("JSON missing ticker field from {}: {}", addr, message);
                                 non_quote_messages += 1;
                             }
+                        } else if let Some(dashboard) = &mut dashboard {
+                            // В таблице котировки bid/ask, стакан и статусы учитываются в частоте
+                            if let Some(ticker) = json.get("ticker").and_then(|t| t.as_str()) {
+                                dashboard.record_message(&ticker.to_uppercase());
+                            }
+                            non_quote_messages += 1;
+                        } else if json.get("type").and_then(|t| t.as_str()) == Some("quote") {
+                            // Котировка bid/ask; в режимах баров и сводки только учитывается
+                            if let Some(summary) = &mut summary {
+                                if let Some(ticker) = json.get("ticker").and_then(|t| t.as_str()) {
+                                    summary.record_message(&ticker.to_uppercase());
+                                }
+                            } else if bar_aggregator.is_none() {
+                                println!(
+                                    "{}",
+                                    format_bid_ask(
+                                        &message,
+                                        &args.output_format,
+                                        args.show_timestamp
+                                    )
+                                );
+                            }
+                            quote_count += 1;
+                        } else if json.get("type").and_then(|t| t.as_str()) == Some("depth") {
+                            // Обновление стакана
+                            if args.output_format == "json" {
+                                println!("{}", message);
+                            } else if !csv_output {
+                                let ticker =
+                                    json.get("ticker").and_then(|t| t.as_str()).unwrap_or("?");
+                                let seq = json.get("seq").and_then(|s| s.as_u64()).unwrap_or(0);
+                                let snapshot = json.get("snapshot").and_then(|s| s.as_bool());
+                                let levels = json
+                                    .get("levels")
+                                    .and_then(|l| l.as_array())
+                                    .map_or(0, |l| l.len());
+                                status!(
+                                    "{} depth #{}: {} {} levels",
+                                    ticker,
+                                    seq,
+                                    if snapshot == Some(true) {
+                                        "snapshot"
+                                    } else {
+                                        "update"
+                                    },
+                                    levels
+                                );
+                            }
+                            non_quote_messages += 1;
+                        } else if json.get("type").and_then(|t| t.as_str()) == Some("status") {
+                            // Служебное событие о состоянии торгов
+                            let ticker = json.get("ticker").and_then(|t| t.as_str()).unwrap_or("?");
+                            let status = json.get("status").and_then(|s| s.as_str()).unwrap_or("?");
+                            if !csv_output {
+                                status!("*** {} market status: {}", ticker, status.to_uppercase());
+                            }
+                            info!("Market status for {}: {}", ticker, status);
+                            non_quote_messages += 1;
+                        } else if json.get("type").and_then(|t| t.as_str()) == Some("bar") {
+                            // Бар, собранный сервером (--server-bars)
+                            match serde_json::from_value::<bars::Bar>(json.clone()) {
+                                Ok(mut bar) => {
+                                    if args.output_format == "json" {
+                                        println!("{}", message);
+                                    } else if !csv_output {
+                                        bar.decimals = price_decimals(None);
+                                        println!("{}", bar.format(&args.output_format));
+                                    }
+                                    quote_count += 1;
+                                }
+                                Err(e) => {
+                                    debug!("Invalid bar from {}: {} ({})", addr, message, e);
+                                    non_quote_messages += 1;
+                                }
+                            }
                         } else {
                             debug!("Received non-quote JSON from {}: {}", addr, message);
                             non_quote_messages += 1;
@@ -541,6 +2002,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // Таймаут - нормально, продолжаем ждать
                 thread::sleep(Duration::from_millis(50));
             }
+            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                status!("\nReplay finished");
+                info!("Replay finished");
+                break 'main_loop;
+            }
             Err(e) => {
                 // Другие ошибки - логируем
                 error!("UDP receive error: {}", e);
@@ -549,25 +2015,77 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // Возвращаем обычный экран до итоговой статистики
+    drop(screen);
+
+    if let Some(sink) = &mut csv_sink {
+        match sink.flush() {
+            Ok(()) => info!("Wrote {} rows to the output file", sink.rows()),
+            Err(e) => error!("Failed to write output file: {}", e),
+        }
+    }
+    if let Some(relay) = &ws_relay {
+        info!(
+            "Relayed {} messages over WebSocket ({} clients connected)",
+            relay.published(),
+            relay.clients()
+        );
+    }
+    if let Some(capture) = &mut capture {
+        match capture.flush() {
+            Ok(()) => info!("Captured {} datagrams", capture.datagrams()),
+            Err(e) => error!("Failed to write capture file: {}", e),
+        }
+    }
+    if let Some(recorder) = &mut recorder {
+        match recorder.flush() {
+            Ok(()) => info!("Recorded {} messages", recorder.rows()),
+            Err(e) => error!("Failed to write record file: {}", e),
+        }
+    }
+    // Parquet читается только с метаданными, которые пишутся в конце файла
+    if let Some(sink) = &mut parquet_sink {
+        match sink.finish() {
+            Ok(()) => info!("Wrote {} rows to the Parquet file", sink.rows()),
+            Err(e) => error!("Failed to finish Parquet file: {}", e),
+        }
+    }
+    if let Some(sink) = &mut sqlite_sink {
+        match sink.finish() {
+            Ok(()) => info!("Inserted {} rows into the SQLite database", sink.rows()),
+            Err(e) => error!("Failed to finish SQLite database: {}", e),
+        }
+    }
+
     // Останавливаем потоки
     info!("Stopping threads...");
     running.store(false, Ordering::SeqCst);
 
     // Ждем завершения потоков
-    let _ = ping_thread.join();
-    let _ = input_thread.join();
+    for handle in session_threads {
+        let _ = handle.join();
+    }
+    // Поток ввода может ждать строку из stdin (конец --duration или записи):
+    // его не ждем, он завершится вместе с процессом
+    if input_thread.is_finished() {
+        let _ = input_thread.join();
+    }
     info!("All threads stopped");
 
     // Отправляем команду STOP
-    println!("\nSending STOP command to server...");
-    info!("Sending STOP command to server...");
-    if tcp_stream.write_all(b"STOP\n").is_err() {
-        println!("Failed to send STOP (connection may be closed)");
-        warn!("Failed to send STOP (connection may be closed)");
-    } else {
-        let _ = tcp_stream.read(&mut buf);
-        println!("STOP command sent successfully");
-        info!("STOP command sent successfully");
+    if let Some(connection) = &connection {
+        status!("\nSending STOP command to server...");
+        info!("Sending STOP command to server...");
+        let mut tcp_stream = connection.lock().unwrap();
+        if tcp_stream.write_all(b"STOP\n").is_err() {
+            status!("Failed to send STOP (connection may be closed)");
+            warn!("Failed to send STOP (connection may be closed)");
+        } else {
+            let mut buf = [0; 1024];
+            let _ = tcp_stream.read(&mut buf);
+            status!("STOP command sent successfully");
+            info!("STOP command sent successfully");
+        }
     }
 
     // Выводим итоговую статистику
@@ -578,27 +2096,72 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         0.0
     };
 
-    println!("\n=== Session Summary ===");
-    println!("Total quotes received: {}", quote_count);
-    println!("Non-quote messages filtered: {}", non_quote_messages);
-    println!("Total UDP messages: {}", quote_count + non_quote_messages);
-    println!("Session duration: {:.1} seconds", elapsed);
-    println!("Average rate: {:.1} quotes/sec", quotes_per_sec);
+    status!("\n=== Session Summary ===");
+    status!("Total quotes received: {}", quote_count);
+    status!("Non-quote messages filtered: {}", non_quote_messages);
+    if quote_filter.is_some() {
+        status!("Quotes rejected by --filter: {}", filtered_out);
+    }
+    status!(
+        "Total UDP messages: {}",
+        quote_count + non_quote_messages + filtered_out
+    );
+    status!("Session duration: {:.1} seconds", elapsed);
+    status!("Average rate: {:.1} quotes/sec", quotes_per_sec);
+
+    let window_stats = rolling_stats.all();
+    if !window_stats.is_empty() {
+        status!(
+            "Rolling statistics (last {} seconds of trades):",
+            rolling_stats.window().as_secs()
+        );
+        for (ticker, window) in &window_stats {
+            status!("  {}: {}", ticker, window.describe());
+        }
+    }
 
     if non_quote_messages > 0 {
         let filter_percent =
             (non_quote_messages as f64 / (quote_count + non_quote_messages) as f64) * 100.0;
-        println!(
+        status!(
             "Filter efficiency: {:.1}% messages filtered",
             filter_percent
         );
     }
 
-    println!("Client stopped successfully!");
+    if let Some(path) = &args.summary_json {
+        let tickers: serde_json::Map<String, serde_json::Value> = window_stats
+            .iter()
+            .map(|(ticker, window)| {
+                (
+                    ticker.clone(),
+                    serde_json::to_value(window).unwrap_or_default(),
+                )
+            })
+            .collect();
+        let summary = serde_json::json!({
+            "quotes_received": quote_count,
+            "non_quote_messages": non_quote_messages,
+            "filtered_out": filtered_out,
+            "duration_secs": elapsed,
+            "quotes_per_sec": quotes_per_sec,
+            "stats_window_secs": args.stats_window,
+            "tickers": tickers,
+        });
+        match std::fs::write(path, format!("{:#}\n", summary)) {
+            Ok(()) => status!("Session summary written to {}", path),
+            Err(e) => error!("Failed to write session summary to {}: {}", path, e),
+        }
+    }
+
+    status!("Client stopped successfully!");
 
     info!(
         "Client shutdown complete. Quotes: {}, Filtered: {}",
         quote_count, non_quote_messages
     );
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
     Ok(())
 }
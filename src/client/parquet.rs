@@ -0,0 +1,459 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Минимальная запись Parquet без внешних зависимостей: одна страница данных
+// на колонку в группе строк, кодирование PLAIN, без сжатия. Этого подмножества
+// формата достаточно стандартным читателям (pandas, duckdb, pyarrow).
+
+const MAGIC: &[u8] = b"PAR1";
+// Группа строк пишется не реже, чем набирается столько строк
+const MAX_ROW_GROUP_ROWS: usize = 100_000;
+
+// Физические типы и аннотации из parquet.thrift
+const TYPE_INT64: i32 = 2;
+const TYPE_DOUBLE: i32 = 5;
+const TYPE_BYTE_ARRAY: i32 = 6;
+const REPETITION_REQUIRED: i32 = 0;
+const REPETITION_OPTIONAL: i32 = 1;
+const CONVERTED_UTF8: i32 = 0;
+const CONVERTED_TIMESTAMP_MILLIS: i32 = 9;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_UNCOMPRESSED: i32 = 0;
+const PAGE_DATA: i32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Int64,
+    Double,
+    Utf8,
+    TimestampMillis,
+}
+
+impl Kind {
+    fn physical_type(self) -> i32 {
+        match self {
+            Kind::Int64 | Kind::TimestampMillis => TYPE_INT64,
+            Kind::Double => TYPE_DOUBLE,
+            Kind::Utf8 => TYPE_BYTE_ARRAY,
+        }
+    }
+
+    fn converted_type(self) -> Option<i32> {
+        match self {
+            Kind::Utf8 => Some(CONVERTED_UTF8),
+            Kind::TimestampMillis => Some(CONVERTED_TIMESTAMP_MILLIS),
+            _ => None,
+        }
+    }
+}
+
+// Колонки те же, что у CSV: сделки и котировки bid/ask в одной таблице
+const COLUMNS: [(&str, Kind, bool); 11] = [
+    ("received_ms", Kind::TimestampMillis, true),
+    ("type", Kind::Utf8, true),
+    ("ticker", Kind::Utf8, true),
+    ("price", Kind::Double, false),
+    ("volume", Kind::Int64, false),
+    ("bid", Kind::Double, false),
+    ("bid_size", Kind::Int64, false),
+    ("ask", Kind::Double, false),
+    ("ask_size", Kind::Int64, false),
+    ("timestamp", Kind::TimestampMillis, true),
+    ("asset_class", Kind::Utf8, false),
+];
+
+#[derive(Debug, Clone)]
+enum Value {
+    Int(i64),
+    Double(f64),
+    Text(String),
+}
+
+// Накопленные значения колонки; None - пустое значение необязательной колонки
+#[derive(Default)]
+struct ColumnBuffer {
+    values: Vec<Option<Value>>,
+}
+
+struct ColumnChunkMeta {
+    offset: u64,
+    num_values: i64,
+    size: i64,
+}
+
+struct RowGroupMeta {
+    columns: Vec<ColumnChunkMeta>,
+    num_rows: i64,
+    total_size: i64,
+}
+
+// Запись принятых котировок в Parquet файл группами строк. Файл становится
+// читаемым после finish(): в конце пишутся метаданные всех групп.
+pub struct ParquetSink {
+    writer: BufWriter<File>,
+    offset: u64,
+    columns: Vec<ColumnBuffer>,
+    buffered_rows: usize,
+    row_groups: Vec<RowGroupMeta>,
+    total_rows: i64,
+    // Как часто накопленные строки сбрасываются в новую группу
+    flush_every: Duration,
+    last_flush: Instant,
+    finished: bool,
+}
+
+impl ParquetSink {
+    pub fn create(path: &str, flush_every: Duration) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(ParquetSink {
+            writer,
+            offset: MAGIC.len() as u64,
+            columns: COLUMNS.iter().map(|_| ColumnBuffer::default()).collect(),
+            buffered_rows: 0,
+            row_groups: Vec::new(),
+            total_rows: 0,
+            flush_every,
+            last_flush: Instant::now(),
+            finished: false,
+        })
+    }
+
+    pub fn record(&mut self, json: &serde_json::Value) -> io::Result<()> {
+        let kind = match json.get("type").and_then(|t| t.as_str()) {
+            None => "trade",
+            Some("quote") => "quote",
+            Some(_) => return Ok(()),
+        };
+        let Some(ticker) = json.get("ticker").and_then(|t| t.as_str()) else {
+            return Ok(());
+        };
+
+        let received_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        for (index, (name, column_kind, required)) in COLUMNS.iter().enumerate() {
+            let value = match *name {
+                "received_ms" => Some(Value::Int(received_ms)),
+                "type" => Some(Value::Text(kind.to_string())),
+                "ticker" => Some(Value::Text(ticker.to_string())),
+                _ => json_value(json.get(*name), *column_kind),
+            };
+            // Обязательная колонка без значения (нет timestamp) - ноль
+            let value = match value {
+                None if *required => Some(Value::Int(0)),
+                value => value,
+            };
+            self.columns[index].values.push(value);
+        }
+        self.buffered_rows += 1;
+
+        if self.buffered_rows >= MAX_ROW_GROUP_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Вызывается и без новых котировок, чтобы группы писались по времени
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() >= self.flush_every {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn rows(&self) -> i64 {
+        self.total_rows + self.buffered_rows as i64
+    }
+
+    // Запись накопленных строк отдельной группой
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        if self.buffered_rows == 0 {
+            return self.writer.flush();
+        }
+
+        let mut chunks = Vec::with_capacity(COLUMNS.len());
+        let mut total_size = 0;
+        for (index, (_, kind, required)) in COLUMNS.iter().enumerate() {
+            let values = std::mem::take(&mut self.columns[index].values);
+            let page = encode_page(&values, *kind, *required);
+            let header = page_header(values.len(), page.len());
+
+            let offset = self.offset;
+            self.writer.write_all(&header)?;
+            self.writer.write_all(&page)?;
+            let size = (header.len() + page.len()) as u64;
+            self.offset += size;
+            total_size += size as i64;
+            chunks.push(ColumnChunkMeta {
+                offset,
+                num_values: values.len() as i64,
+                size: size as i64,
+            });
+        }
+
+        self.row_groups.push(RowGroupMeta {
+            columns: chunks,
+            num_rows: self.buffered_rows as i64,
+            total_size,
+        });
+        self.total_rows += self.buffered_rows as i64;
+        self.buffered_rows = 0;
+        self.writer.flush()
+    }
+
+    // Последняя группа и метаданные файла
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.flush()?;
+        let metadata = self.file_metadata();
+        self.writer.write_all(&metadata)?;
+        self.writer
+            .write_all(&(metadata.len() as u32).to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    fn file_metadata(&self) -> Vec<u8> {
+        let mut out = Compact::default();
+        out.i32_field(1, 1);
+
+        // Схема: корень и по элементу на колонку
+        out.list_field(2, COLUMNS.len() + 1);
+        out.begin_struct();
+        out.binary_field(4, b"schema");
+        out.i32_field(5, COLUMNS.len() as i32);
+        out.end_struct();
+        for (name, kind, required) in COLUMNS {
+            out.begin_struct();
+            out.i32_field(1, kind.physical_type());
+            let repetition = if required {
+                REPETITION_REQUIRED
+            } else {
+                REPETITION_OPTIONAL
+            };
+            out.i32_field(3, repetition);
+            out.binary_field(4, name.as_bytes());
+            if let Some(converted) = kind.converted_type() {
+                out.i32_field(6, converted);
+            }
+            out.end_struct();
+        }
+
+        out.i64_field(3, self.total_rows);
+
+        out.list_field(4, self.row_groups.len());
+        for group in &self.row_groups {
+            out.begin_struct();
+            out.list_field(1, group.columns.len());
+            for ((name, kind, _), chunk) in COLUMNS.iter().zip(&group.columns) {
+                out.begin_struct();
+                out.i64_field(2, chunk.offset as i64);
+                out.struct_field(3);
+                out.i32_field(1, kind.physical_type());
+                out.i32_list_field(2, &[ENCODING_PLAIN, ENCODING_RLE]);
+                out.binary_list_field(3, &[name.as_bytes()]);
+                out.i32_field(4, CODEC_UNCOMPRESSED);
+                out.i64_field(5, chunk.num_values);
+                out.i64_field(6, chunk.size);
+                out.i64_field(7, chunk.size);
+                out.i64_field(9, chunk.offset as i64);
+                out.end_struct();
+                out.end_struct();
+            }
+            out.i64_field(2, group.total_size);
+            out.i64_field(3, group.num_rows);
+            out.end_struct();
+        }
+
+        out.binary_field(6, b"quote-client");
+        out.end_struct();
+        out.bytes
+    }
+}
+
+impl Drop for ParquetSink {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+fn json_value(value: Option<&serde_json::Value>, kind: Kind) -> Option<Value> {
+    let value = value?;
+    match kind {
+        Kind::Int64 | Kind::TimestampMillis => value.as_i64().map(Value::Int),
+        Kind::Double => value.as_f64().map(Value::Double),
+        Kind::Utf8 => value.as_str().map(|text| Value::Text(text.to_string())),
+    }
+}
+
+// Страница данных v1: уровни определения (для необязательных колонок) и значения PLAIN
+fn encode_page(values: &[Option<Value>], kind: Kind, required: bool) -> Vec<u8> {
+    let mut page = Vec::new();
+    if !required {
+        let levels = definition_levels(values);
+        page.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+        page.extend_from_slice(&levels);
+    }
+    for value in values.iter().flatten() {
+        match (value, kind) {
+            (Value::Int(v), Kind::Int64 | Kind::TimestampMillis) => {
+                page.extend_from_slice(&v.to_le_bytes())
+            }
+            (Value::Double(v), Kind::Double) => page.extend_from_slice(&v.to_le_bytes()),
+            (Value::Text(text), Kind::Utf8) => {
+                page.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                page.extend_from_slice(text.as_bytes());
+            }
+            _ => unreachable!("value does not match column type"),
+        }
+    }
+    page
+}
+
+// Уровни определения (0 - пусто, 1 - есть значение) сериями RLE с шириной 1 бит
+fn definition_levels(values: &[Option<Value>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut index = 0;
+    while index < values.len() {
+        let present = values[index].is_some();
+        let run = values[index..]
+            .iter()
+            .take_while(|value| value.is_some() == present)
+            .count();
+        write_varint(&mut out, (run as u64) << 1);
+        out.push(present as u8);
+        index += run;
+    }
+    out
+}
+
+fn page_header(num_values: usize, page_size: usize) -> Vec<u8> {
+    let mut out = Compact::default();
+    out.i32_field(1, PAGE_DATA);
+    out.i32_field(2, page_size as i32);
+    out.i32_field(3, page_size as i32);
+    out.struct_field(5);
+    out.i32_field(1, num_values as i32);
+    out.i32_field(2, ENCODING_PLAIN);
+    out.i32_field(3, ENCODING_RLE);
+    out.i32_field(4, ENCODING_RLE);
+    out.end_struct();
+    out.end_struct();
+    out.bytes
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Компактный протокол Thrift, которым кодируются метаданные Parquet
+#[derive(Default)]
+struct Compact {
+    bytes: Vec<u8>,
+    // Номер последнего поля в каждой открытой структуре
+    last_field: Vec<i16>,
+    current: i16,
+}
+
+const COMPACT_I32: u8 = 5;
+const COMPACT_I64: u8 = 6;
+const COMPACT_BINARY: u8 = 8;
+const COMPACT_LIST: u8 = 9;
+const COMPACT_STRUCT: u8 = 12;
+
+impl Compact {
+    fn field_header(&mut self, id: i16, field_type: u8) {
+        let delta = id - self.current;
+        if (1..=15).contains(&delta) {
+            self.bytes.push(((delta as u8) << 4) | field_type);
+        } else {
+            self.bytes.push(field_type);
+            self.zigzag(id as i64);
+        }
+        self.current = id;
+    }
+
+    fn zigzag(&mut self, value: i64) {
+        write_varint(&mut self.bytes, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn i32_field(&mut self, id: i16, value: i32) {
+        self.field_header(id, COMPACT_I32);
+        self.zigzag(value as i64);
+    }
+
+    fn i64_field(&mut self, id: i16, value: i64) {
+        self.field_header(id, COMPACT_I64);
+        self.zigzag(value);
+    }
+
+    fn binary_field(&mut self, id: i16, value: &[u8]) {
+        self.field_header(id, COMPACT_BINARY);
+        self.binary(value);
+    }
+
+    fn binary(&mut self, value: &[u8]) {
+        write_varint(&mut self.bytes, value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn list_header(&mut self, len: usize, element_type: u8) {
+        if len < 15 {
+            self.bytes.push(((len as u8) << 4) | element_type);
+        } else {
+            self.bytes.push(0xF0 | element_type);
+            write_varint(&mut self.bytes, len as u64);
+        }
+    }
+
+    // Заголовок списка структур; элементы пишутся begin_struct/end_struct
+    fn list_field(&mut self, id: i16, len: usize) {
+        self.field_header(id, COMPACT_LIST);
+        self.list_header(len, COMPACT_STRUCT);
+    }
+
+    fn i32_list_field(&mut self, id: i16, values: &[i32]) {
+        self.field_header(id, COMPACT_LIST);
+        self.list_header(values.len(), COMPACT_I32);
+        for value in values {
+            self.zigzag(*value as i64);
+        }
+    }
+
+    fn binary_list_field(&mut self, id: i16, values: &[&[u8]]) {
+        self.field_header(id, COMPACT_LIST);
+        self.list_header(values.len(), COMPACT_BINARY);
+        for value in values {
+            self.binary(value);
+        }
+    }
+
+    // Вложенная структура как поле; закрывается end_struct
+    fn struct_field(&mut self, id: i16) {
+        self.field_header(id, COMPACT_STRUCT);
+        self.begin_struct();
+    }
+
+    // Начало структуры (элемента списка или поля)
+    fn begin_struct(&mut self) {
+        self.last_field.push(self.current);
+        self.current = 0;
+    }
+
+    fn end_struct(&mut self) {
+        self.bytes.push(0);
+        self.current = self.last_field.pop().unwrap_or(0);
+    }
+}
@@ -0,0 +1,209 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Расширение файлов записи, как у --record сервера: их читает --replay
+const RECORD_EXTENSION: &str = "jsonl";
+
+// Колонки CSV: сделки и котировки bid/ask в одной таблице, лишние поля пустые
+pub const CSV_HEADER: &str =
+    "received_ms,type,ticker,price,volume,bid,bid_size,ask,ask_size,timestamp,asset_class";
+
+// Строка CSV для сделки или котировки bid/ask; прочие сообщения не пишутся
+pub fn csv_row(json: &serde_json::Value) -> Option<String> {
+    let kind = match json.get("type").and_then(|t| t.as_str()) {
+        None => "trade",
+        Some("quote") => "quote",
+        Some(_) => return None,
+    };
+    json.get("ticker")?;
+
+    let mut fields = vec![received_ms().to_string(), kind.to_string()];
+    for key in [
+        "ticker",
+        "price",
+        "volume",
+        "bid",
+        "bid_size",
+        "ask",
+        "ask_size",
+        "timestamp",
+        "asset_class",
+    ] {
+        let field = match json.get(key) {
+            None | Some(serde_json::Value::Null) => String::new(),
+            Some(serde_json::Value::String(text)) => text.clone(),
+            Some(value) => value.to_string(),
+        };
+        fields.push(csv_field(&field));
+    }
+    Some(fields.join(","))
+}
+
+// Поле в кавычках, если в нем есть разделитель, кавычка или перевод строки
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn received_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+// Запись принятых котировок в CSV файл независимо от вывода в терминал.
+// Существующий файл дописывается, заголовок пишется только в пустой файл.
+pub struct CsvSink {
+    writer: BufWriter<File>,
+    // Как часто буфер сбрасывается на диск; ноль - после каждой строки
+    flush_every: Duration,
+    last_flush: Instant,
+    rows: u64,
+}
+
+impl CsvSink {
+    pub fn create(path: &str, flush_every: Duration) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_empty = file.metadata()?.len() == 0;
+        let mut writer = BufWriter::new(file);
+        if is_empty {
+            writeln!(writer, "{}", CSV_HEADER)?;
+            writer.flush()?;
+        }
+        Ok(CsvSink {
+            writer,
+            flush_every,
+            last_flush: Instant::now(),
+            rows: 0,
+        })
+    }
+
+    pub fn record(&mut self, json: &serde_json::Value) -> io::Result<()> {
+        let Some(row) = csv_row(json) else {
+            return Ok(());
+        };
+        writeln!(self.writer, "{}", row)?;
+        self.rows += 1;
+        self.flush_if_due()
+    }
+
+    // Вызывается и без новых котировок, чтобы файл не отставал от потока
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() >= self.flush_every {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+}
+
+// Запись принятых сделок и котировок bid/ask как есть, по JSON в строке, в
+// каталог с ротацией файлов по размеру и времени. Имена файлов те же, что у
+// --record сервера, так что каталог можно передать в --replay.
+pub struct JsonlRecorder {
+    dir: PathBuf,
+    writer: BufWriter<File>,
+    // 0 - без ограничения
+    max_bytes: u64,
+    rotate_every: Duration,
+    opened_at: Instant,
+    written: u64,
+    flush_every: Duration,
+    last_flush: Instant,
+    rows: u64,
+}
+
+impl JsonlRecorder {
+    pub fn create(
+        dir: &str,
+        max_bytes: u64,
+        rotate_every: Duration,
+        flush_every: Duration,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let dir = PathBuf::from(dir);
+        let writer = open_record_file(&dir)?;
+        Ok(JsonlRecorder {
+            dir,
+            writer,
+            max_bytes,
+            rotate_every,
+            opened_at: Instant::now(),
+            written: 0,
+            flush_every,
+            last_flush: Instant::now(),
+            rows: 0,
+        })
+    }
+
+    pub fn record(&mut self, message: &str, json: &serde_json::Value) -> io::Result<()> {
+        match json.get("type").and_then(|t| t.as_str()) {
+            None | Some("quote") => {}
+            Some(_) => return Ok(()),
+        }
+
+        let line = message.trim();
+        let too_big = self.max_bytes > 0
+            && self.written > 0
+            && self.written + line.len() as u64 + 1 > self.max_bytes;
+        let too_old = !self.rotate_every.is_zero() && self.opened_at.elapsed() >= self.rotate_every;
+        if too_big || too_old {
+            self.rotate()?;
+        }
+
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.written += line.len() as u64 + 1;
+        self.rows += 1;
+        self.flush_if_due()
+    }
+
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() >= self.flush_every {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer = open_record_file(&self.dir)?;
+        self.opened_at = Instant::now();
+        self.written = 0;
+        Ok(())
+    }
+}
+
+// Имя файла содержит время открытия, поэтому файлы сортируются хронологически
+fn open_record_file(dir: &std::path::Path) -> io::Result<BufWriter<File>> {
+    let name = format!(
+        "quotes-{}.{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S%.3f"),
+        RECORD_EXTENSION
+    );
+    let path = dir.join(name);
+    tracing::info!("Recording quotes to {}", path.display());
+    Ok(BufWriter::new(File::create(path)?))
+}
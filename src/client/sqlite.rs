@@ -0,0 +1,175 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Транзакция закрывается не реже, чем набирается столько строк
+const MAX_BATCH_ROWS: usize = 1000;
+
+// Колонки таблицы тикера и их типы SQLite
+const COLUMNS: [(&str, &str); 9] = [
+    ("type", "TEXT"),
+    ("price", "REAL"),
+    ("volume", "INTEGER"),
+    ("bid", "REAL"),
+    ("bid_size", "INTEGER"),
+    ("ask", "REAL"),
+    ("ask_size", "INTEGER"),
+    ("timestamp", "INTEGER"),
+    ("asset_class", "TEXT"),
+];
+
+// Запись принятых котировок в SQLite, по таблице на тикер. Отдельной
+// библиотеки нет: SQL передается утилите sqlite3 через stdin, строки
+// группируются в транзакции по времени и числу.
+pub struct SqliteSink {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    tables: HashSet<String>,
+    batch: String,
+    batch_rows: usize,
+    rows: u64,
+    flush_every: Duration,
+    last_flush: Instant,
+}
+
+impl SqliteSink {
+    pub fn create(path: &str, sqlite_command: &str, flush_every: Duration) -> io::Result<Self> {
+        let mut command = Command::new(sqlite_command);
+        command
+            .arg("-batch")
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null());
+        // Своя группа процессов: Ctrl+C в терминале не прерывает запись,
+        // клиент сам завершает последнюю транзакцию
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command.spawn().map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot run {}: {}", sqlite_command, e))
+        })?;
+        let mut stdin = child.stdin.take();
+        if let Some(stdin) = &mut stdin {
+            // WAL позволяет читать базу запросами, пока идет запись
+            stdin.write_all(b"PRAGMA journal_mode=WAL;\n")?;
+        }
+        Ok(SqliteSink {
+            child,
+            stdin,
+            tables: HashSet::new(),
+            batch: String::new(),
+            batch_rows: 0,
+            rows: 0,
+            flush_every,
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, json: &serde_json::Value) -> io::Result<()> {
+        let kind = match json.get("type").and_then(|t| t.as_str()) {
+            None => "trade",
+            Some("quote") => "quote",
+            Some(_) => return Ok(()),
+        };
+        let Some(ticker) = json.get("ticker").and_then(|t| t.as_str()) else {
+            return Ok(());
+        };
+
+        let table = identifier(ticker);
+        if self.tables.insert(ticker.to_string()) {
+            let columns: Vec<String> = COLUMNS
+                .iter()
+                .map(|(name, sql_type)| format!("{} {}", name, sql_type))
+                .collect();
+            self.batch.push_str(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (received_ms INTEGER NOT NULL, {});\n",
+                table,
+                columns.join(", ")
+            ));
+        }
+
+        let received_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let mut values = vec![received_ms.to_string(), literal(kind)];
+        for (name, _) in COLUMNS.iter().skip(1) {
+            values.push(match json.get(*name) {
+                None | Some(serde_json::Value::Null) => "NULL".to_string(),
+                Some(serde_json::Value::String(text)) => literal(text),
+                Some(serde_json::Value::Number(number)) => number.to_string(),
+                Some(other) => literal(&other.to_string()),
+            });
+        }
+        let names: Vec<&str> = COLUMNS.iter().map(|(name, _)| *name).collect();
+        self.batch.push_str(&format!(
+            "INSERT INTO {} (received_ms, {}) VALUES ({});\n",
+            table,
+            names.join(", "),
+            values.join(", ")
+        ));
+        self.batch_rows += 1;
+        self.rows += 1;
+
+        if self.batch_rows >= MAX_BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Вызывается и без новых котировок, чтобы база не отставала от потока
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() >= self.flush_every {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Накопленные строки одной транзакцией
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let Some(stdin) = &mut self.stdin else {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "sqlite3 closed"));
+        };
+        let batch = std::mem::take(&mut self.batch);
+        self.batch_rows = 0;
+        stdin.write_all(b"BEGIN;\n")?;
+        stdin.write_all(batch.as_bytes())?;
+        stdin.write_all(b"COMMIT;\n")?;
+        stdin.flush()
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    // Последняя транзакция и ожидание, пока sqlite3 запишет ее и завершится
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.flush()?;
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("sqlite3 exited with {}", status)));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        if self.stdin.is_some() {
+            let _ = self.finish();
+        }
+    }
+}
+
+// Имя таблицы в кавычках: в тикерах бывают точки и дефисы
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
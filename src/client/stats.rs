@@ -0,0 +1,129 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+// Статистика тикера за скользящее окно по меткам времени сделок
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WindowStats {
+    pub trades: usize,
+    // Для инструментов без объема (валюты, индексы) - средняя цена
+    pub vwap: f64,
+    // Стандартное отклонение логарифмических доходностей между сделками, %
+    pub volatility_pct: f64,
+    pub min: f64,
+    pub max: f64,
+    #[serde(skip)]
+    pub decimals: usize,
+}
+
+impl WindowStats {
+    pub fn describe(&self) -> String {
+        format!(
+            "VWAP {:.*}  vol {:.3}%  min {:.*}  max {:.*}",
+            self.decimals,
+            self.vwap,
+            self.volatility_pct,
+            self.decimals,
+            self.min,
+            self.decimals,
+            self.max
+        )
+    }
+}
+
+#[derive(Default)]
+struct Series {
+    // (метка времени, цена, объем)
+    trades: VecDeque<(u64, f64, u64)>,
+    decimals: usize,
+}
+
+pub struct RollingStats {
+    window_ms: u64,
+    series: HashMap<String, Series>,
+}
+
+impl RollingStats {
+    pub fn new(window: Duration) -> Self {
+        RollingStats {
+            window_ms: window.as_millis() as u64,
+            series: HashMap::new(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_millis(self.window_ms)
+    }
+
+    pub fn record(
+        &mut self,
+        ticker: &str,
+        price: f64,
+        volume: Option<u32>,
+        timestamp: u64,
+        decimals: usize,
+    ) {
+        let series = self.series.entry(ticker.to_string()).or_default();
+        series
+            .trades
+            .push_back((timestamp, price, volume.unwrap_or(0) as u64));
+        series.decimals = decimals;
+        let oldest = timestamp.saturating_sub(self.window_ms);
+        while series.trades.front().is_some_and(|&(ts, _, _)| ts < oldest) {
+            series.trades.pop_front();
+        }
+    }
+
+    pub fn get(&self, ticker: &str) -> Option<WindowStats> {
+        let series = self.series.get(ticker)?;
+        let trades = &series.trades;
+        if trades.is_empty() {
+            return None;
+        }
+
+        let volume: u64 = trades.iter().map(|&(_, _, volume)| volume).sum();
+        let vwap = if volume > 0 {
+            trades
+                .iter()
+                .map(|&(_, price, volume)| price * volume as f64)
+                .sum::<f64>()
+                / volume as f64
+        } else {
+            trades.iter().map(|&(_, price, _)| price).sum::<f64>() / trades.len() as f64
+        };
+
+        let returns: Vec<f64> = trades
+            .iter()
+            .zip(trades.iter().skip(1))
+            .filter(|(previous, _)| previous.1 > 0.0)
+            .map(|(previous, current)| (current.1 / previous.1).ln())
+            .collect();
+        let volatility_pct = if returns.len() > 1 {
+            let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+            let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>()
+                / (returns.len() - 1) as f64;
+            variance.sqrt() * 100.0
+        } else {
+            0.0
+        };
+
+        let prices = trades.iter().map(|&(_, price, _)| price);
+        Some(WindowStats {
+            trades: trades.len(),
+            vwap,
+            volatility_pct,
+            min: prices.clone().fold(f64::INFINITY, f64::min),
+            max: prices.fold(f64::NEG_INFINITY, f64::max),
+            decimals: series.decimals,
+        })
+    }
+
+    // Все тикеры со сделками в окне, по алфавиту
+    pub fn all(&self) -> Vec<(String, WindowStats)> {
+        let mut tickers: Vec<&String> = self.series.keys().collect();
+        tickers.sort();
+        tickers
+            .into_iter()
+            .filter_map(|ticker| Some((ticker.clone(), self.get(ticker)?)))
+            .collect()
+    }
+}
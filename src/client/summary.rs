@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct TickerSummary {
+    first_price: Option<f64>,
+    last_price: Option<f64>,
+    decimals: usize,
+    messages: u64,
+    interval_messages: u64,
+}
+
+impl TickerSummary {
+    fn change_pct(&self) -> Option<f64> {
+        match (self.first_price, self.last_price) {
+            (Some(first), Some(last)) if first > 0.0 => Some((last - first) / first * 100.0),
+            _ => None,
+        }
+    }
+}
+
+// Периодическая сводка по тикерам (--summary): последняя цена, изменение с
+// начала сессии, число и частота сообщений; сверху - сильнее всего изменившиеся
+pub struct Summary {
+    interval: Duration,
+    tickers: HashMap<String, TickerSummary>,
+    last_print: Instant,
+}
+
+impl Summary {
+    pub fn new(interval: Duration, tickers: &[String]) -> Self {
+        Summary {
+            interval,
+            tickers: tickers
+                .iter()
+                .map(|ticker| (ticker.clone(), TickerSummary::default()))
+                .collect(),
+            last_print: Instant::now(),
+        }
+    }
+
+    pub fn record_trade(&mut self, ticker: &str, price: f64, decimals: usize) {
+        let summary = self.tickers.entry(ticker.to_string()).or_default();
+        summary.first_price.get_or_insert(price);
+        summary.last_price = Some(price);
+        summary.decimals = decimals;
+    }
+
+    // Сделки и котировки bid/ask тикера учитываются в числе сообщений
+    pub fn record_message(&mut self, ticker: &str) {
+        let summary = self.tickers.entry(ticker.to_string()).or_default();
+        summary.messages += 1;
+        summary.interval_messages += 1;
+    }
+
+    pub fn maybe_print(&mut self) {
+        let elapsed = self.last_print.elapsed();
+        if elapsed < self.interval {
+            return;
+        }
+        self.last_print = Instant::now();
+
+        let mut rows: Vec<(&String, &TickerSummary)> = self.tickers.iter().collect();
+        rows.sort_by(|a, b| {
+            let a_move = a.1.change_pct().map_or(-1.0, f64::abs);
+            let b_move = b.1.change_pct().map_or(-1.0, f64::abs);
+            b_move.total_cmp(&a_move).then_with(|| a.0.cmp(b.0))
+        });
+
+        println!(
+            "\n--- Summary (every {} seconds, biggest movers first) ---",
+            self.interval.as_secs()
+        );
+        println!(
+            "  {:<10} {:>14} {:>9} {:>10} {:>8}",
+            "TICKER", "LAST", "CHANGE %", "MESSAGES", "MSG/S"
+        );
+        for (ticker, summary) in rows {
+            let last = summary.last_price.map_or("-".to_string(), |price| {
+                format!("{:.*}", summary.decimals, price)
+            });
+            let change = summary
+                .change_pct()
+                .map_or("-".to_string(), |change| format!("{:+.2}%", change));
+            println!(
+                "  {:<10} {:>14} {:>9} {:>10} {:>8.1}",
+                ticker,
+                last,
+                change,
+                summary.messages,
+                summary.interval_messages as f64 / elapsed.as_secs_f64()
+            );
+        }
+        println!("--------------------------------");
+
+        for summary in self.tickers.values_mut() {
+            summary.interval_messages = 0;
+        }
+    }
+}
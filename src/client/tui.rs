@@ -0,0 +1,295 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Как часто перерисовывается таблица
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+// Окно, за которое считается частота сообщений тикера
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+// Колонки, по которым сортируется таблица (клавиша 's' переключает по кругу)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortColumn {
+    Ticker,
+    Change,
+    Volume,
+    Rate,
+}
+
+const SORT_COLUMNS: [SortColumn; 4] = [
+    SortColumn::Ticker,
+    SortColumn::Change,
+    SortColumn::Volume,
+    SortColumn::Rate,
+];
+
+impl SortColumn {
+    fn label(self) -> &'static str {
+        match self {
+            SortColumn::Ticker => "ticker",
+            SortColumn::Change => "change",
+            SortColumn::Volume => "volume",
+            SortColumn::Rate => "msg/s",
+        }
+    }
+}
+
+// Управление с клавиатуры, общее для потока ввода и главного цикла
+#[derive(Debug, Default)]
+pub struct Controls {
+    paused: AtomicBool,
+    sort: AtomicUsize,
+}
+
+impl Controls {
+    // Обработка клавиши; false - пользователь вышел
+    pub fn handle_key(&self, key: u8) -> bool {
+        match key.to_ascii_lowercase() {
+            b'q' => return false,
+            b's' => {
+                let next = (self.sort.load(Ordering::Relaxed) + 1) % SORT_COLUMNS.len();
+                self.sort.store(next, Ordering::Relaxed);
+            }
+            b'p' | b' ' => {
+                self.paused.fetch_xor(true, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn sort_column(&self) -> SortColumn {
+        SORT_COLUMNS[self.sort.load(Ordering::Relaxed) % SORT_COLUMNS.len()]
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TickerRow {
+    first_price: Option<f64>,
+    last_price: Option<f64>,
+    // Направление последнего изменения цены: 1, -1 или 0
+    direction: i8,
+    volume: u64,
+    decimals: usize,
+    window_messages: u64,
+    rate: f64,
+}
+
+impl TickerRow {
+    fn change_pct(&self) -> f64 {
+        match (self.first_price, self.last_price) {
+            (Some(first), Some(last)) if first > 0.0 => (last - first) / first * 100.0,
+            _ => 0.0,
+        }
+    }
+}
+
+// Таблица подписанных тикеров вместо построчного вывода котировок
+pub struct Dashboard {
+    rows: HashMap<String, TickerRow>,
+    window_start: Instant,
+    last_render: Option<Instant>,
+    total_messages: u64,
+}
+
+impl Dashboard {
+    pub fn new(tickers: &[String]) -> Self {
+        Dashboard {
+            rows: tickers
+                .iter()
+                .map(|ticker| (ticker.clone(), TickerRow::default()))
+                .collect(),
+            window_start: Instant::now(),
+            last_render: None,
+            total_messages: 0,
+        }
+    }
+
+    pub fn record_trade(&mut self, ticker: &str, price: f64, volume: Option<u32>, decimals: usize) {
+        let row = self.rows.entry(ticker.to_string()).or_default();
+        row.first_price.get_or_insert(price);
+        if let Some(last) = row.last_price {
+            row.direction = if price > last {
+                1
+            } else if price < last {
+                -1
+            } else {
+                row.direction
+            };
+        }
+        row.last_price = Some(price);
+        row.volume += volume.unwrap_or(0) as u64;
+        row.decimals = decimals;
+    }
+
+    // Любое сообщение тикера (сделка, котировка, стакан) учитывается в частоте
+    pub fn record_message(&mut self, ticker: &str) {
+        self.rows
+            .entry(ticker.to_string())
+            .or_default()
+            .window_messages += 1;
+        self.total_messages += 1;
+    }
+
+    // Перерисовка, если подошло время; на паузе таблица не обновляется
+    pub fn maybe_render(&mut self, controls: &Controls, out: &mut impl Write) -> io::Result<()> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= RATE_WINDOW {
+            for row in self.rows.values_mut() {
+                row.rate = row.window_messages as f64 / elapsed.as_secs_f64();
+                row.window_messages = 0;
+            }
+            self.window_start = now;
+        }
+
+        if self
+            .last_render
+            .is_some_and(|last| now.duration_since(last) < REFRESH_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.last_render = Some(now);
+        if controls.is_paused() {
+            // Отметка паузы в строке статуса, таблица остается прежней
+            write!(
+                out,
+                "\x1b[2;1H\x1b[2K\x1b[7m PAUSED \x1b[0m  press 'p' to resume"
+            )?;
+            return out.flush();
+        }
+        self.render(controls.sort_column(), out)
+    }
+
+    fn render(&self, sort: SortColumn, out: &mut impl Write) -> io::Result<()> {
+        let mut rows: Vec<(&String, &TickerRow)> = self.rows.iter().collect();
+        match sort {
+            SortColumn::Ticker => rows.sort_by(|a, b| a.0.cmp(b.0)),
+            SortColumn::Change => {
+                rows.sort_by(|a, b| b.1.change_pct().total_cmp(&a.1.change_pct()))
+            }
+            SortColumn::Volume => rows.sort_by_key(|row| Reverse(row.1.volume)),
+            SortColumn::Rate => rows.sort_by(|a, b| b.1.rate.total_cmp(&a.1.rate)),
+        }
+
+        let mut screen = String::new();
+        screen.push_str("\x1b[H\x1b[2J");
+        screen.push_str(&format!(
+            "Quote Client - {} tickers, {} messages  |  q: quit  s: sort ({})  p: pause\r\n\r\n",
+            self.rows.len(),
+            self.total_messages,
+            sort.label()
+        ));
+        screen.push_str(&format!(
+            "\x1b[1m{:<10} {:>14} {:>12} {:>9}   {:>12} {:>8}\x1b[0m\r\n",
+            "TICKER", "LAST", "CHANGE", "CHANGE %", "VOLUME", "MSG/S"
+        ));
+
+        for (ticker, row) in rows {
+            let Some(last) = row.last_price else {
+                screen.push_str(&format!("{:<10} {:>14}\r\n", ticker, "-"));
+                continue;
+            };
+            let change = last - row.first_price.unwrap_or(last);
+            let (color, arrow) = match row.direction {
+                1 => ("\x1b[32m", "▲"),
+                -1 => ("\x1b[31m", "▼"),
+                _ => ("", " "),
+            };
+            screen.push_str(&format!(
+                "{:<10} {}{:>14.*} {:>+12.*} {:>+8.2}% {}\x1b[0m {:>12} {:>8.1}\r\n",
+                ticker,
+                color,
+                row.decimals,
+                last,
+                row.decimals,
+                change,
+                row.change_pct(),
+                arrow,
+                row.volume,
+                row.rate
+            ));
+        }
+
+        out.write_all(screen.as_bytes())?;
+        out.flush()
+    }
+}
+
+// Альтернативный экран терминала на время работы дашборда. На Linux терминал
+// переводится в посимвольный режим, чтобы клавиши срабатывали без Enter.
+pub struct Screen {
+    #[cfg(target_os = "linux")]
+    original: Option<libc::termios>,
+}
+
+impl Screen {
+    pub fn enter() -> io::Result<Self> {
+        let screen = Screen {
+            #[cfg(target_os = "linux")]
+            original: enable_raw_mode(),
+        };
+        let mut out = io::stdout();
+        // Альтернативный буфер и скрытый курсор
+        out.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        out.flush()?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        let _ = out.write_all(b"\x1b[?25h\x1b[?1049l");
+        let _ = out.flush();
+        #[cfg(target_os = "linux")]
+        if let Some(original) = &self.original {
+            // SAFETY: восстанавливаем настройки, полученные от tcgetattr
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn enable_raw_mode() -> Option<libc::termios> {
+    // SAFETY: termios заполняется tcgetattr до использования
+    unsafe {
+        let mut original: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+            // stdin не терминал: клавиши читаются построчно
+            return None;
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+            return None;
+        }
+        Some(original)
+    }
+}
+
+// Чтение клавиш до выхода пользователя или закрытия stdin
+pub fn read_keys(controls: &Controls, running: &AtomicBool) {
+    let mut stdin = io::stdin();
+    let mut key = [0u8; 1];
+    while running.load(Ordering::SeqCst) {
+        match stdin.read(&mut key) {
+            Ok(1) => {
+                if !controls.handle_key(key[0]) {
+                    running.store(false, Ordering::SeqCst);
+                }
+            }
+            _ => break,
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Сколько кадров может ждать отправки медленному браузеру; лишние отбрасываются
+const CLIENT_QUEUE: usize = 1024;
+// Из RFC 6455: добавляется к Sec-WebSocket-Key перед хешированием
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// Управляющие кадры от браузера не длиннее 125 байт, данные ему не нужны
+const MAX_INCOMING_FRAME: u64 = 64 * 1024;
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+type Clients = Arc<Mutex<Vec<SyncSender<Arc<[u8]>>>>>;
+
+// Ретрансляция принятых котировок браузерам по WebSocket: клиент работает как
+// мост между UDP потоком и страницей. Кадр собирается один раз для всех.
+pub struct WsRelay {
+    clients: Clients,
+    published: u64,
+}
+
+impl WsRelay {
+    pub fn start(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+        {
+            let clients = clients.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            let clients = clients.clone();
+                            thread::spawn(move || {
+                                let peer = stream.peer_addr().ok();
+                                if let Err(e) = serve(stream, &clients) {
+                                    tracing::debug!("WebSocket client {:?} closed: {}", peer, e);
+                                }
+                            });
+                        }
+                        Err(e) => tracing::warn!("WebSocket accept failed: {}", e),
+                    }
+                }
+            });
+        }
+        Ok(WsRelay {
+            clients,
+            published: 0,
+        })
+    }
+
+    pub fn publish(&mut self, message: &str) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let frame: Arc<[u8]> = frame(OPCODE_TEXT, message.trim().as_bytes()).into();
+        clients.retain(|client| match client.try_send(frame.clone()) {
+            Ok(()) => true,
+            // Браузер не успевает: кадр пропускается, соединение остается
+            Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+        self.published += 1;
+    }
+
+    pub fn published(&self) -> u64 {
+        self.published
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+// Рукопожатие, затем отдельный поток пишет кадры, а этот читает входящие
+fn serve(stream: TcpStream, clients: &Clients) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let mut key = None;
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("sec-websocket-key")
+        {
+            key = Some(value.trim().to_string());
+        }
+    }
+    let Some(key) = key else {
+        writer.write_all(
+            b"HTTP/1.1 426 Upgrade Required\r\nUpgrade: websocket\r\nContent-Length: 0\r\n\r\n",
+        )?;
+        return Ok(());
+    };
+    let accept = base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()));
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )?;
+    tracing::info!("WebSocket client connected: {}", request_line.trim());
+
+    let (sender, receiver) = sync_channel(CLIENT_QUEUE);
+    clients.lock().unwrap().push(sender.clone());
+    thread::spawn(move || write_frames(writer, receiver));
+
+    // Браузер присылает кадры в маске; отвечаем на ping и close
+    loop {
+        let (opcode, payload) = read_frame(&mut reader)?;
+        match opcode {
+            OPCODE_CLOSE => {
+                let _ = sender.send(frame(OPCODE_CLOSE, &payload).into());
+                return Ok(());
+            }
+            OPCODE_PING => {
+                let _ = sender.send(frame(OPCODE_PONG, &payload).into());
+            }
+            _ => {}
+        }
+    }
+}
+
+fn write_frames(mut writer: TcpStream, receiver: Receiver<Arc<[u8]>>) {
+    for frame in receiver {
+        let closing = frame.first() == Some(&(0x80 | OPCODE_CLOSE));
+        if writer.write_all(&frame).is_err() || closing {
+            break;
+        }
+    }
+    let _ = writer.shutdown(std::net::Shutdown::Both);
+}
+
+fn read_frame(reader: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+    let mut len = (header[1] & 0x7F) as u64;
+    if len == 126 {
+        let mut ext = [0; 2];
+        reader.read_exact(&mut ext)?;
+        len = u16::from_be_bytes(ext) as u64;
+    } else if len == 127 {
+        let mut ext = [0; 8];
+        reader.read_exact(&mut ext)?;
+        len = u64::from_be_bytes(ext);
+    }
+    if len > MAX_INCOMING_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    let mut mask = [0; 4];
+    if masked {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    if masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Ok((opcode, payload))
+}
+
+// Кадр сервера: последний фрагмент, без маски
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// SHA-1 нужен только для Sec-WebSocket-Accept
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                chunk[i * 4],
+                chunk[i * 4 + 1],
+                chunk[i * 4 + 2],
+                chunk[i * 4 + 3],
+            ]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (i, state) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&state.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(triple >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -1,14 +1,118 @@
-use crate::models::ClientConfig;
-use log::{debug, error, info, trace, warn};
+use crate::access_list::AccessList;
+use crate::clock;
+use crate::metrics::ServerMetrics;
+use crate::models::{ClientConfig, StreamOptions};
+use crate::observer::{LogObserver, ServerObserver};
+use crate::udp_sender::DeliveryStats;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::UdpSocket;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
 use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, trace, warn};
+
+// Как сервер узнает, что клиент жив
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Liveness {
+    // UDP ping на порт обработчика ping
+    #[default]
+    Udp,
+    // Открытое TCP соединение (и PING по нему); для клиентов за NAT/файрволом,
+    // которые не могут слать UDP на порт ping
+    Tcp,
+}
+
+impl Liveness {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "udp" => Some(Liveness::Udp),
+            "tcp" => Some(Liveness::Tcp),
+            _ => None,
+        }
+    }
+}
+
+// Запись о клиенте в файле подписок (--persist-clients)
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedClient {
+    client_id: String,
+    udp_addr: String,
+    tickers: Vec<String>,
+    #[serde(default)]
+    options: StreamOptions,
+}
+
+// Действие при удалении клиента (STOP, KICK, таймаут ping): отписка от тикеров
+pub type RemoveHook = Arc<dyn Fn(&str, &ClientConfig) + Send + Sync>;
+// Уведомление отключенного медленного потребителя; получает client_id и причину
+pub type SlowConsumerHook = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+// Ждать подтверждения, что клиент медленный, столько секунд по умолчанию
+pub const DEFAULT_SLOW_CONSUMER_SECS: u64 = 5;
+
+// Пороги медленного потребителя: клиента, чья очередь дольше sustain_secs
+// держится выше max_queue событий или отстает больше чем на max_lag_ms,
+// сервер отключает. 0 - порог не проверяется.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowConsumerLimits {
+    pub max_queue: u64,
+    pub max_lag_ms: u64,
+    pub sustain_secs: u64,
+}
+
+impl Default for SlowConsumerLimits {
+    fn default() -> Self {
+        SlowConsumerLimits {
+            max_queue: 0,
+            max_lag_ms: 0,
+            sustain_secs: DEFAULT_SLOW_CONSUMER_SECS,
+        }
+    }
+}
+
+impl SlowConsumerLimits {
+    pub fn is_enabled(&self) -> bool {
+        self.max_queue > 0 || self.max_lag_ms > 0
+    }
+
+    // Какой порог превышен сейчас; None - очередь в норме
+    fn exceeded(&self, stats: &DeliveryStats) -> Option<String> {
+        if self.max_queue > 0 && stats.queue_len > self.max_queue {
+            return Some(format!(
+                "queue {} events over {}",
+                stats.queue_len, self.max_queue
+            ));
+        }
+        if self.max_lag_ms > 0 && stats.queue_lag_ms > self.max_lag_ms {
+            return Some(format!(
+                "lag {} ms over {} ms",
+                stats.queue_lag_ms, self.max_lag_ms
+            ));
+        }
+        None
+    }
+}
 
 pub struct ClientManager {
     clients: Arc<Mutex<HashMap<String, ClientConfig>>>,
-    ping_timeout_secs: u64,
+    on_remove: Option<RemoveHook>,
+    // Получает удаление клиентов по таймауту; меняется и после запуска сервера
+    observer: Arc<Mutex<Arc<dyn ServerObserver>>>,
+    // Удаления по таймауту ping считаются для /metrics
+    metrics: Arc<ServerMetrics>,
+    // Меняются при перезагрузке конфигурации
+    ping_timeout_secs: Arc<AtomicU64>,
+    slow_consumer: Arc<Mutex<SlowConsumerLimits>>,
+    on_slow_consumer: Option<SlowConsumerHook>,
+    // Файл, в котором подписки переживают перезапуск сервера
+    persist: Option<Arc<SessionFile>>,
 }
 
 impl ClientManager {
@@ -19,185 +123,606 @@ impl ClientManager {
         );
         ClientManager {
             clients: Arc::new(Mutex::new(HashMap::new())),
-            ping_timeout_secs,
+            on_remove: None,
+            observer: Arc::new(Mutex::new(Arc::new(LogObserver))),
+            metrics: Arc::new(ServerMetrics::new()),
+            ping_timeout_secs: Arc::new(AtomicU64::new(ping_timeout_secs)),
+            slow_consumer: Arc::new(Mutex::new(SlowConsumerLimits::default())),
+            on_slow_consumer: None,
+            persist: None,
+        }
+    }
+
+    pub fn with_on_remove(
+        mut self,
+        hook: impl Fn(&str, &ClientConfig) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_remove = Some(Arc::new(hook));
+        self
+    }
+
+    // Вызывается для каждого клиента, отключенного как медленный потребитель
+    pub fn with_on_slow_consumer(
+        mut self,
+        hook: impl Fn(&str, &str) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_slow_consumer = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn with_observer(self, observer: Arc<dyn ServerObserver>) -> Self {
+        self.set_observer(observer);
+        self
+    }
+
+    pub fn set_observer(&self, observer: Arc<dyn ServerObserver>) {
+        *self.observer.lock().unwrap() = observer;
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<ServerMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_persistence(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        info!("Persisting client subscriptions to {}", path.display());
+        self.persist = Some(Arc::new(SessionFile::new(path)));
+        self
+    }
+
+    pub fn ping_timeout_secs(&self) -> u64 {
+        self.ping_timeout_secs.load(Ordering::Relaxed)
+    }
+
+    // Новый таймаут действует со следующей проверки устаревших клиентов
+    pub fn set_ping_timeout_secs(&self, secs: u64) {
+        self.ping_timeout_secs.store(secs, Ordering::Relaxed);
+    }
+
+    pub fn slow_consumer_limits(&self) -> SlowConsumerLimits {
+        *self.slow_consumer.lock().unwrap()
+    }
+
+    // Новые пороги действуют со следующей проверки; отсчет уже начатых
+    // превышений сохраняется
+    pub fn set_slow_consumer_limits(&self, limits: SlowConsumerLimits) {
+        if limits.is_enabled() {
+            info!(
+                max_queue = limits.max_queue,
+                max_lag_ms = limits.max_lag_ms,
+                sustain_secs = limits.sustain_secs,
+                "Slow consumer detection enabled"
+            );
+        }
+        *self.slow_consumer.lock().unwrap() = limits;
+    }
+
+    // Подписки, сохраненные до перезапуска. Время ping у них свежее: клиенту
+    // дается обычный таймаут, чтобы снова начать слать ping.
+    pub fn load_persisted(&self) -> Vec<(String, ClientConfig)> {
+        let Some(path) = self.persist.as_ref().map(|file| &file.path) else {
+            return Vec::new();
+        };
+        if !path.exists() {
+            return Vec::new();
+        }
+
+        let loaded = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                serde_json::from_str::<Vec<PersistedClient>>(&content).map_err(|e| e.to_string())
+            });
+        match loaded {
+            Ok(entries) => entries
+                .into_iter()
+                .map(|entry| {
+                    let config = ClientConfig::new(entry.udp_addr, entry.tickers)
+                        .with_options(entry.options);
+                    (entry.client_id, config)
+                })
+                .collect(),
+            Err(e) => {
+                error!(
+                    "Failed to load persisted clients from {}: {}",
+                    path.display(),
+                    e
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    // Снимок списка для файла подписок, пока список заблокирован
+    fn snapshot(&self, clients: &HashMap<String, ClientConfig>) -> Option<Snapshot> {
+        self.persist.as_ref().map(|file| file.snapshot(clients))
+    }
+
+    // Запись снимка уже после снятия блокировки списка
+    fn persist(&self, snapshot: Option<Snapshot>) {
+        if let (Some(file), Some(snapshot)) = (&self.persist, snapshot) {
+            file.write(snapshot);
         }
     }
 
     // Добавление нового клиента
     pub fn add_client(&self, client_id: String, config: ClientConfig) {
         info!(
-            "Adding new client: {} -> UDP: {}, Tickers: {}",
-            client_id,
-            config.udp_addr,
-            config.tickers.join(", ")
-        );
-        let mut clients = self.clients.lock().unwrap();
-        let old_count = clients.len();
-        clients.insert(client_id, config);
-        info!(
-            "Client added. Total clients: {} (was: {})",
-            clients.len(),
-            old_count
+            %client_id,
+            udp_addr = %config.udp_addr,
+            tickers = %config.tickers.join(","),
+            "Adding new client"
         );
+        let snapshot = {
+            let mut clients = self.clients.lock().unwrap();
+            let old_count = clients.len();
+            // Повторный STREAM той же сессии заменяет прежний поток
+            if let Some(old) = clients.insert(client_id.clone(), config) {
+                info!(%client_id, "Client restarted its stream");
+                release(&client_id, &old, self.on_remove.as_ref());
+            }
+            info!(total = clients.len(), was = old_count, "Client added");
+            self.snapshot(&clients)
+        };
+        self.persist(snapshot);
     }
 
-    // Удаление клиента
+    // Удаление клиента: UdpSender останавливается, подписки снимаются
     pub fn remove_client(&self, client_id: &str) -> Option<ClientConfig> {
         let mut clients = self.clients.lock().unwrap();
         if let Some(config) = clients.remove(client_id) {
-            info!(
-                "Removed client: {}. Active clients: {}",
-                client_id,
-                clients.len()
-            );
+            info!(%client_id, active = clients.len(), "Removed client");
+            let snapshot = self.snapshot(&clients);
+            release(client_id, &config, self.on_remove.as_ref());
+            drop(clients);
+            self.persist(snapshot);
             Some(config)
         } else {
-            warn!("Attempted to remove non-existent client: {}", client_id);
+            warn!(%client_id, "Attempted to remove non-existent client");
             None
         }
     }
 
+    pub fn get_client(&self, client_id: &str) -> Option<ClientConfig> {
+        self.clients.lock().unwrap().get(client_id).cloned()
+    }
+
+    // Снимок всех клиентов, отсортированный по id
+    pub fn list_clients(&self) -> Vec<(String, ClientConfig)> {
+        let clients = self.clients.lock().unwrap();
+        let mut list: Vec<(String, ClientConfig)> = clients
+            .iter()
+            .map(|(id, config)| (id.clone(), config.clone()))
+            .collect();
+        list.sort_by(|a, b| a.0.cmp(&b.0));
+        list
+    }
+
+    // Принудительное отключение клиента. Возвращает false для неизвестного клиента.
+    pub fn kick(&self, client_id: &str) -> bool {
+        match self.remove_client(client_id) {
+            Some(config) => {
+                warn!(
+                    %client_id,
+                    udp_addr = %config.udp_addr,
+                    "Client kicked, UDP stream stopped"
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Остановка UDP потоков всех клиентов при завершении сервера. Записи
+    // (и файл подписок) сохраняются, чтобы потоки восстановились после перезапуска.
+    pub fn stop_all_senders(&self) {
+        let clients = self.clients.lock().unwrap();
+        for config in clients.values() {
+            config.sender.stop();
+        }
+        info!("Stopped UDP streams of {} clients", clients.len());
+    }
+
+    // Токен сессии, выдаваемый клиенту при подключении. По нему клиент
+    // после переподключения продолжает существующую подписку.
+    pub fn new_session_token() -> String {
+        format!("{:032x}", rand::random::<u128>())
+    }
+
+    // Продолжение сессии после переподключения: подписка и UDP поток не меняются,
+    // обновляется только время последнего ping
+    pub fn resume(&self, client_id: &str) -> Option<ClientConfig> {
+        let mut clients = self.clients.lock().unwrap();
+        let config = clients.get_mut(client_id)?;
+        config.update_ping();
+        info!(%client_id, "Client session resumed");
+        Some(config.clone())
+    }
+
     // Обновление времени последнего ping
     pub fn update_ping(&self, client_id: &str) -> bool {
         let mut clients = self.clients.lock().unwrap();
         if let Some(config) = clients.get_mut(client_id) {
             config.update_ping();
-            debug!("Updated ping for client: {}", client_id);
+            debug!(%client_id, "Updated ping");
             true
         } else {
-            debug!("Ping update failed: client {} not found", client_id);
+            debug!(%client_id, "Ping update failed: client not found");
             false
         }
     }
 
-    // Запуск обработчика ping сообщений
-    pub fn start_ping_handler(&self, udp_port: u16) {
-        info!("Starting ping handler on UDP port {}", udp_port);
+    // Продление сессии без записи в лог (для TCP liveness)
+    pub fn touch(&self, client_id: &str) {
+        if let Some(config) = self.clients.lock().unwrap().get_mut(client_id) {
+            config.update_ping();
+        }
+    }
+
+    // Запуск обработчика ping сообщений задачами tokio; вызывается внутри
+    // среды выполнения сервера. Сокет привязывается сразу, чтобы ошибка
+    // занятого порта вернулась вызывающему; задачи работают до
+    // PingHandler::shutdown.
+    pub fn start_ping_handler(
+        &self,
+        addr: SocketAddr,
+        access_list: Arc<AccessList>,
+    ) -> io::Result<PingHandler> {
+        info!("Starting ping handler on UDP {}", addr);
+
+        let udp_socket = std::net::UdpSocket::bind(addr)?;
+        udp_socket.set_nonblocking(true)?;
+        let udp_socket = UdpSocket::from_std(udp_socket)?;
+        let local_addr = udp_socket.local_addr()?;
+        info!("Ping handler listening on UDP {}", local_addr);
+
+        let (stop, stopped) = watch::channel(false);
+
+        let receiver = tokio::spawn(receive_pings(
+            udp_socket,
+            self.clients.clone(),
+            access_list,
+            stopped.clone(),
+        ));
+
+        let reaper = Reaper {
+            clients: self.clients.clone(),
+            ping_timeout_secs: self.ping_timeout_secs.clone(),
+            slow_consumer: self.slow_consumer.clone(),
+            persist: self.persist.clone(),
+            on_remove: self.on_remove.clone(),
+            on_slow_consumer: self.on_slow_consumer.clone(),
+            observer: self.observer.clone(),
+            metrics: self.metrics.clone(),
+            slow_since: HashMap::new(),
+        };
+        let reaper = tokio::spawn(reaper.run(stopped));
+
+        Ok(PingHandler {
+            local_addr,
+            stop,
+            receiver,
+            reaper,
+        })
+    }
+}
+
+// Проверка клиентов без ping раз в столько времени
+const STALE_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+const STATS_EVERY_SWEEPS: u64 = 10;
+
+// Почему клиент удаляется при очередной проверке
+enum Removal {
+    // Нет ping дольше таймаута
+    Stale,
+    // Медленный потребитель: причина для журнала и для самого клиента
+    SlowConsumer(String),
+}
 
-        let clients = self.clients.clone();
-        let ping_timeout = self.ping_timeout_secs;
+// Задача удаления клиентов: без ping, отключенных генератором по политике
+// переполнения disconnect и медленных потребителей
+struct Reaper {
+    clients: Arc<Mutex<HashMap<String, ClientConfig>>>,
+    ping_timeout_secs: Arc<AtomicU64>,
+    slow_consumer: Arc<Mutex<SlowConsumerLimits>>,
+    persist: Option<Arc<SessionFile>>,
+    on_remove: Option<RemoveHook>,
+    on_slow_consumer: Option<SlowConsumerHook>,
+    observer: Arc<Mutex<Arc<dyn ServerObserver>>>,
+    metrics: Arc<ServerMetrics>,
+    // С какого момента (мс часов процесса) очередь клиента превышает пороги
+    slow_since: HashMap<String, u64>,
+}
 
-        thread::spawn(move || {
-            let udp_socket = match UdpSocket::bind(format!("127.0.0.1:{}", udp_port)) {
-                Ok(socket) => {
-                    info!("Ping handler listening on UDP port {}", udp_port);
-                    socket
+impl Reaper {
+    // Таймер в реальном времени: на ручных часах проверка не должна
+    // вставать, таймауты сессий при этом считаются по часам процесса.
+    // Проверка пишет файл подписок и вызывает хуки, поэтому идет вне
+    // потоков среды выполнения.
+    async fn run(mut self, mut stopped: watch::Receiver<bool>) {
+        let mut sweeps: u64 = 0;
+        let start = tokio::time::Instant::now() + STALE_SWEEP_INTERVAL;
+        let mut timer = tokio::time::interval_at(start, STALE_SWEEP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = stopped.wait_for(|stopped| *stopped) => break,
+                _ = timer.tick() => {}
+            }
+            sweeps += 1;
+            let sweep = tokio::task::spawn_blocking(move || {
+                self.sweep();
+                // Логируем статистику каждые 10 проверок
+                if sweeps.is_multiple_of(STATS_EVERY_SWEEPS) {
+                    log_delivery_stats(&self.clients.lock().unwrap());
                 }
+                self
+            });
+            match sweep.await {
+                Ok(reaper) => self = reaper,
                 Err(e) => {
-                    error!("Failed to bind UDP socket for ping handler: {}", e);
+                    error!(error = %e, "Stale client sweep failed, reaper stopped");
                     return;
                 }
-            };
-
-            if let Err(e) = udp_socket.set_read_timeout(Some(Duration::from_millis(500))) {
-                error!("Failed to set UDP socket timeout: {}", e);
-                return;
             }
+        }
+        debug!("Stale client reaper stopped");
+    }
 
-            let mut buf = [0; 1024];
-            let mut stats_cycles = 0;
-
-            info!("Ping handler thread started");
-
-            loop {
-                stats_cycles += 1;
-
-                match udp_socket.recv_from(&mut buf) {
-                    Ok((size, addr)) => {
-                        let message = String::from_utf8_lossy(&buf[..size]);
-                        if message.trim() == "PING" {
-                            debug!("Received PING from {}", addr);
-
-                            let client_id = format!("{}", addr);
-
-                            let mut clients_lock = clients.lock().unwrap();
-                            if let Some(config) = clients_lock.get_mut(&client_id) {
-                                config.update_ping();
-                                // Отправляем PONG обратно
-                                if let Err(e) = udp_socket.send_to(b"PONG", addr) {
-                                    error!("Failed to send PONG to {}: {}", addr, e);
-                                } else {
-                                    trace!("Sent PONG to {}", addr);
-                                }
-                            } else {
-                                // Если клиент не найден, возможно он только что подключился
-                                // с другим ID, ищем по части адреса
-                                let addr_ip = addr.ip().to_string();
-                                let mut found = false;
-                                for (id, config) in clients_lock.iter_mut() {
-                                    if id.contains(&addr_ip) {
-                                        config.update_ping();
-                                        if let Err(e) = udp_socket.send_to(b"PONG", addr) {
-                                            error!("Failed to send PONG to {}: {}", addr, e);
-                                        }
-                                        debug!(
-                                            "Matched PING from {} to existing client {}",
-                                            addr, id
-                                        );
-                                        found = true;
-                                        break;
-                                    }
-                                }
-
-                                if !found {
-                                    debug!("PING from unknown client: {}", addr);
-                                }
+    fn sweep(&mut self) {
+        let now = clock::now_millis();
+        let ping_timeout = self.ping_timeout_secs.load(Ordering::Relaxed);
+        let limits = *self.slow_consumer.lock().unwrap();
+        let removals: Vec<(String, Removal)> = {
+            let clients = self.clients.lock().unwrap();
+            let slow_since = &mut self.slow_since;
+            slow_since.retain(|id, _| clients.contains_key(id));
+            clients
+                .iter()
+                .filter_map(|(id, config)| {
+                    if config.sender.overflow_stats().is_disconnected() {
+                        let reason = "queue overflow".to_string();
+                        return Some((id.clone(), Removal::SlowConsumer(reason)));
+                    }
+                    match limits.exceeded(&config.sender.stats()) {
+                        Some(reason) => {
+                            let since = *slow_since.entry(id.clone()).or_insert(now);
+                            if now.saturating_sub(since) >= limits.sustain_secs * 1000 {
+                                return Some((id.clone(), Removal::SlowConsumer(reason)));
                             }
-                        } else {
-                            debug!("Received non-PING message from {}: {}", addr, message);
+                        }
+                        None => {
+                            slow_since.remove(id);
                         }
                     }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        // Таймаут - продолжаем проверку
+                    config
+                        .is_stale(ping_timeout)
+                        .then(|| (id.clone(), Removal::Stale))
+                })
+                .collect()
+        };
+
+        if removals.is_empty() {
+            debug!("No stale clients found");
+            return;
+        }
+        warn!(count = removals.len(), "Found stale clients");
+
+        let observer = self.observer.lock().unwrap().clone();
+        let mut slow_consumers = Vec::new();
+        let snapshot = {
+            let mut clients = self.clients.lock().unwrap();
+            for (client_id, removal) in removals {
+                let Some(config) = clients.remove(&client_id) else {
+                    continue;
+                };
+                self.slow_since.remove(&client_id);
+                match removal {
+                    Removal::Stale => {
+                        observer.on_client_stale(&client_id, &config);
+                        self.metrics.record_stale();
                     }
-                    Err(e) => {
-                        error!("Error receiving ping: {}", e);
-                        thread::sleep(Duration::from_secs(1));
+                    Removal::SlowConsumer(reason) => {
+                        observer.on_slow_consumer(&client_id, &config, &reason);
+                        self.metrics.record_slow_consumer();
+                        slow_consumers.push((client_id.clone(), reason));
                     }
                 }
+                release(&client_id, &config, self.on_remove.as_ref());
+            }
+            info!("Active clients after cleanup: {}", clients.len());
+            self.persist.as_ref().map(|file| file.snapshot(&clients))
+        };
+        if let (Some(file), Some(snapshot)) = (&self.persist, snapshot) {
+            file.write(snapshot);
+        }
 
-                // Периодически проверяем устаревших клиентов
-                if stats_cycles % 10 == 0 {
-                    // Каждую секунду (10 * 100ms)
-                    let stale_clients: Vec<String> = {
-                        let clients_lock = clients.lock().unwrap();
-                        clients_lock
-                            .iter()
-                            .filter(|(_, config)| config.is_stale(ping_timeout))
-                            .map(|(id, _)| id.clone())
-                            .collect()
+        // Клиенту сообщают о причине уже после снятия блокировки списка
+        if let Some(hook) = &self.on_slow_consumer {
+            for (client_id, reason) in slow_consumers {
+                hook(&client_id, &reason);
+            }
+        }
+    }
+}
+
+// Работающий обработчик ping: задача приема ping и задача, по таймеру
+// удаляющая клиентов без ping
+pub struct PingHandler {
+    local_addr: SocketAddr,
+    // Сигнал остановки обеим задачам
+    stop: watch::Sender<bool>,
+    receiver: JoinHandle<()>,
+    reaper: JoinHandle<()>,
+}
+
+impl PingHandler {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // Остановка обеих задач и ожидание их завершения
+    pub async fn shutdown(self) {
+        self.stop.send_replace(true);
+        let _ = self.receiver.await;
+        let _ = self.reaper.await;
+        info!("Ping handler stopped");
+    }
+}
+
+async fn receive_pings(
+    udp_socket: UdpSocket,
+    clients: Arc<Mutex<HashMap<String, ClientConfig>>>,
+    access_list: Arc<AccessList>,
+    mut stopped: watch::Receiver<bool>,
+) {
+    let mut buf = [0; 1024];
+    info!("Ping handler task started");
+
+    loop {
+        let received = tokio::select! {
+            _ = stopped.wait_for(|stopped| *stopped) => break,
+            received = udp_socket.recv_from(&mut buf) => received,
+        };
+        match received {
+            Ok((size, addr)) => {
+                let message = String::from_utf8_lossy(&buf[..size]);
+                if !access_list.is_allowed(addr.ip()) {
+                    debug!(peer = %addr, "Ignoring datagram denied by access list");
+                } else if let Some(token) = parse_ping(&message) {
+                    debug!(peer = %addr, client_id = token, "Received PING");
+
+                    // Ping приходит с произвольного порта, поэтому клиент
+                    // подписывает его токеном сессии (команда ID)
+                    let found = match clients.lock().unwrap().get_mut(token) {
+                        Some(config) => {
+                            config.update_ping();
+                            true
+                        }
+                        None => false,
                     };
 
-                    if !stale_clients.is_empty() {
-                        warn!(
-                            "Found {} stale clients: {:?}",
-                            stale_clients.len(),
-                            stale_clients
-                        );
-
-                        let mut clients_lock = clients.lock().unwrap();
-                        for client_id in stale_clients {
-                            if let Some(config) = clients_lock.remove(&client_id) {
-                                warn!(
-                                    "Removed stale client: {} (UDP: {})",
-                                    client_id, config.udp_addr
-                                );
-                            }
+                    if found {
+                        // Отправляем PONG обратно
+                        if let Err(e) = udp_socket.send_to(b"PONG", addr).await {
+                            error!(peer = %addr, error = %e, "Failed to send PONG");
+                        } else {
+                            trace!(peer = %addr, "Sent PONG");
                         }
-                        info!("Active clients after cleanup: {}", clients_lock.len());
                     } else {
-                        debug!("No stale clients found");
-                    }
-
-                    // Логируем статистику каждые 10 секунд
-                    if stats_cycles % 100 == 0 {
-                        let clients_count = clients.lock().unwrap().len();
-                        info!("Ping handler status: {} active clients", clients_count);
+                        debug!(peer = %addr, client_id = token, "PING for unknown session");
                     }
+                } else {
+                    debug!("Received non-PING message from {}: {}", addr, message);
                 }
+            }
+            Err(e) => {
+                error!("Error receiving ping: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+fn log_delivery_stats(clients: &HashMap<String, ClientConfig>) {
+    info!("Ping handler status: {} active clients", clients.len());
+    for (id, config) in clients.iter() {
+        let stats = config.sender.stats();
+        info!(
+            client_id = %id,
+            quotes = stats.quotes_sent,
+            bytes = stats.bytes_sent,
+            errors = stats.send_errors,
+            queue = stats.queue_len,
+            lag_ms = stats.queue_lag_ms,
+            dropped = stats.queue_dropped,
+            "Client delivery stats"
+        );
+    }
+}
+
+// Файл подписок (--persist-clients). Снимок списка клиентов берется под его
+// блокировкой, а пишется на диск уже без нее, чтобы STREAM, STOP и проверка
+// клиентов не ждали диск. Снимки нумеруются в порядке изменений списка:
+// запоздавший старый снимок не затирает уже записанный новый.
+struct SessionFile {
+    path: PathBuf,
+    next_version: AtomicU64,
+    // Номер последнего записанного снимка; держится на время записи
+    written: Mutex<u64>,
+}
+
+struct Snapshot {
+    version: u64,
+    entries: Vec<PersistedClient>,
+}
 
-                thread::sleep(Duration::from_millis(100));
+impl SessionFile {
+    fn new(path: PathBuf) -> Self {
+        SessionFile {
+            path,
+            next_version: AtomicU64::new(1),
+            written: Mutex::new(0),
+        }
+    }
+
+    fn snapshot(&self, clients: &HashMap<String, ClientConfig>) -> Snapshot {
+        let mut entries: Vec<PersistedClient> = clients
+            .iter()
+            .map(|(id, config)| PersistedClient {
+                client_id: id.clone(),
+                udp_addr: config.udp_addr.clone(),
+                tickers: config.tickers.clone(),
+                options: config.options.clone(),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        Snapshot {
+            version: self.next_version.fetch_add(1, Ordering::SeqCst),
+            entries,
+        }
+    }
+
+    // Запись через временный файл, чтобы при падении не остался обрезанный JSON
+    fn write(&self, snapshot: Snapshot) {
+        let mut written = self.written.lock().unwrap();
+        if snapshot.version < *written {
+            return;
+        }
+        let path = &self.path;
+        let result = serde_json::to_string_pretty(&snapshot.entries)
+            .map_err(std::io::Error::other)
+            .and_then(|json| {
+                let tmp = path.with_extension("tmp");
+                fs::write(&tmp, json)?;
+                fs::rename(&tmp, path)
+            });
+        match result {
+            Ok(()) => {
+                *written = snapshot.version;
+                debug!(
+                    "Saved {} clients to {}",
+                    snapshot.entries.len(),
+                    path.display()
+                );
             }
-        });
+            Err(e) => error!("Failed to save clients to {}: {}", path.display(), e),
+        }
+    }
+}
+
+// Токен из сообщения "PING <token>"; ping без токена не принимается
+fn parse_ping(message: &str) -> Option<&str> {
+    let mut parts = message.split_whitespace();
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("PING"), Some(token), None) => Some(token),
+        _ => None,
+    }
+}
+
+// Освобождение ресурсов удаленного клиента: остановка UDP потока и отписка
+fn release(client_id: &str, config: &ClientConfig, on_remove: Option<&RemoveHook>) {
+    config.sender.stop();
+    if let Some(hook) = on_remove {
+        hook(client_id, config);
     }
 }
@@ -0,0 +1,157 @@
+use chrono::{DateTime, Local, TimeZone};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Шаг опроса ручных часов при ожидании: время на них двигает только тест
+const MOCK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// Источник времени сервера: метки котировок, интервалы генерации и таймауты ping
+pub trait Clock: Send + Sync {
+    // Текущее время в миллисекундах от эпохи Unix
+    fn now_millis(&self) -> u64;
+
+    // Сколько реального времени ждать, чтобы на этих часах прошло duration
+    fn real_duration(&self, duration: Duration) -> Duration;
+}
+
+// Обычные системные часы
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        system_millis()
+    }
+
+    fn real_duration(&self, duration: Duration) -> Duration {
+        duration
+    }
+}
+
+// Ускоренное (или замедленное) время: стартует с текущего момента
+// и идет в scale раз быстрее реального
+#[derive(Debug, Clone, Copy)]
+pub struct ScaledClock {
+    origin_millis: u64,
+    started: Instant,
+    scale: f64,
+}
+
+impl ScaledClock {
+    pub fn new(scale: f64) -> Result<Self, String> {
+        if !(scale > 0.0 && scale.is_finite()) {
+            return Err(format!("Time scale must be positive, got {}", scale));
+        }
+        Ok(ScaledClock {
+            origin_millis: system_millis(),
+            started: Instant::now(),
+            scale,
+        })
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+}
+
+impl Clock for ScaledClock {
+    fn now_millis(&self) -> u64 {
+        let elapsed = self.started.elapsed().as_secs_f64() * 1000.0 * self.scale;
+        self.origin_millis + elapsed as u64
+    }
+
+    fn real_duration(&self, duration: Duration) -> Duration {
+        duration.div_f64(self.scale)
+    }
+}
+
+// Ручные часы для тестов: время стоит, пока его не сдвинут через advance
+#[derive(Debug, Default)]
+pub struct MockClock {
+    millis: AtomicU64,
+}
+
+impl MockClock {
+    pub fn new(start_millis: u64) -> Self {
+        MockClock {
+            millis: AtomicU64::new(start_millis),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.millis
+            .fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    // Время только идет вперед, как и через advance: более раннее значение
+    // игнорируется, чтобы возраст ping и другие сроки не уходили в минус
+    pub fn set_millis(&self, millis: u64) {
+        self.millis.fetch_max(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+
+    fn real_duration(&self, _duration: Duration) -> Duration {
+        MOCK_POLL_INTERVAL
+    }
+}
+
+// Часы процесса; None - системные
+static CLOCK: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+
+// Замена часов всего процесса. Вызывается до старта генератора и сервера.
+pub fn set_clock(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap() = Some(clock);
+}
+
+// Возврат к системным часам
+pub fn reset_clock() {
+    *CLOCK.write().unwrap() = None;
+}
+
+pub fn now_millis() -> u64 {
+    match CLOCK.read().unwrap().as_ref() {
+        Some(clock) => clock.now_millis(),
+        None => system_millis(),
+    }
+}
+
+// Текущее локальное время по часам процесса (для расписания сессии и имен файлов)
+pub fn now_local() -> DateTime<Local> {
+    Local
+        .timestamp_millis_opt(now_millis() as i64)
+        .single()
+        .unwrap_or_else(Local::now)
+}
+
+pub(crate) fn real_duration(duration: Duration) -> Duration {
+    match CLOCK.read().unwrap().as_ref() {
+        Some(clock) => clock.real_duration(duration),
+        None => duration,
+    }
+}
+
+// Сон, пока на часах процесса не пройдет duration
+pub fn sleep(duration: Duration) {
+    let deadline = now_millis() + duration.as_millis() as u64;
+    loop {
+        let now = now_millis();
+        if now >= deadline {
+            break;
+        }
+        thread::sleep(real_duration(Duration::from_millis(deadline - now)));
+    }
+}
+
+fn system_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
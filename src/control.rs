@@ -0,0 +1,487 @@
+use crate::bars::Bar;
+use crate::generator::ParamsSnapshot;
+use crate::line_reader::frame;
+use crate::models::{Command, CommandError, HelloResponse, StockQuote};
+use crate::reload::ReloadReport;
+use crate::udp_sender::DeliveryStats;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+// Возможность HELLO, переключающая соединение на команды и ответы в JSON
+pub const JSON_CONTROL: &str = "json-control";
+// Возможность HELLO, переключающая соединение на кадры с длиной
+pub const FRAMED: &str = "framed";
+
+// Кодирование команд и ответов на TCP соединении
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlEncoding {
+    // Строки протокола: STREAM udp://... AAPL -> STREAMING_STARTED
+    #[default]
+    Text,
+    // Одна строка JSON на запрос и на ответ
+    Json,
+}
+
+// Режим соединения, выбранный последним HELLO: кодирование и кадры
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ControlMode {
+    pub encoding: ControlEncoding,
+    // Каждая команда и каждый ответ - кадр: 4 байта длины (big-endian) и содержимое
+    pub framed: bool,
+}
+
+impl ControlMode {
+    pub fn negotiated(accepted: &[String]) -> Self {
+        let accepts = |name: &str| accepted.iter().any(|accepted| accepted == name);
+        ControlMode {
+            encoding: if accepts(JSON_CONTROL) {
+                ControlEncoding::Json
+            } else {
+                ControlEncoding::Text
+            },
+            framed: accepts(FRAMED),
+        }
+    }
+
+    // Байты ответа для отправки: строки как есть или один кадр без
+    // завершающего перевода строки
+    pub fn wrap(&self, response: &str) -> Vec<u8> {
+        if self.framed {
+            frame(response.strip_suffix('\n').unwrap_or(response).as_bytes())
+        } else {
+            response.as_bytes().to_vec()
+        }
+    }
+
+    pub fn reply(&self, reply: &Reply) -> Vec<u8> {
+        self.wrap(&reply.encode(self.encoding))
+    }
+
+    pub fn error(&self, error: &CommandError) -> Vec<u8> {
+        self.wrap(&encode_error(error, self.encoding))
+    }
+}
+
+// Ответ на успешную команду
+#[derive(Debug, Clone)]
+pub enum Reply {
+    StreamingStarted,
+    Bars(Vec<Bar>),
+    Snapshot(Vec<StockQuote>),
+    ShockApplied {
+        ticker: String,
+        price: f64,
+    },
+    AdminOk,
+    AuthOk {
+        name: String,
+    },
+    TickerAdded(String),
+    TickerRemoved(String),
+    ParamsUpdated(String),
+    Resumed {
+        udp_addr: String,
+        tickers: Vec<String>,
+    },
+    Clients(Vec<Value>),
+    Kicked(String),
+    Params(Vec<ParamsSnapshot>),
+    ReloadStatus(Option<ReloadReport>),
+    Stats(DeliveryStats),
+    Hello(HelloResponse),
+    Tickers(Vec<String>),
+    Id(String),
+    Pong,
+    StreamingStopped,
+    Help(&'static str),
+}
+
+impl Reply {
+    // Имя ответа в JSON совпадает с первым словом текстового ответа
+    // (для многострочных - с командой)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reply::StreamingStarted => "STREAMING_STARTED",
+            Reply::Bars(_) => "BARS",
+            Reply::Snapshot(_) => "SNAPSHOT",
+            Reply::ShockApplied { .. } => "SHOCK_APPLIED",
+            Reply::AdminOk => "ADMIN_OK",
+            Reply::AuthOk { .. } => "AUTH_OK",
+            Reply::TickerAdded(_) => "TICKER_ADDED",
+            Reply::TickerRemoved(_) => "TICKER_REMOVED",
+            Reply::ParamsUpdated(_) => "PARAMS_UPDATED",
+            Reply::Resumed { .. } => "RESUMED",
+            Reply::Clients(_) => "CLIENTS",
+            Reply::Kicked(_) => "KICKED",
+            Reply::Params(_) => "PARAMS",
+            Reply::ReloadStatus(_) => "RELOAD_STATUS",
+            Reply::Stats(_) => "STATS",
+            Reply::Hello(_) => "HELLO",
+            Reply::Tickers(_) => "TICKERS",
+            Reply::Id(_) => "ID",
+            Reply::Pong => "PONG",
+            Reply::StreamingStopped => "STREAMING_STOPPED",
+            Reply::Help(_) => "HELP",
+        }
+    }
+
+    pub fn encode(&self, encoding: ControlEncoding) -> String {
+        match encoding {
+            ControlEncoding::Text => self.to_text(),
+            ControlEncoding::Json => self.to_json(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        match self {
+            Reply::Bars(bars) => {
+                let mut response = String::new();
+                for bar in bars {
+                    response.push_str(&bar.to_json());
+                    response.push('\n');
+                }
+                response.push_str("BARS_END\n");
+                response
+            }
+            Reply::Snapshot(quotes) => {
+                let mut response = String::new();
+                for quote in quotes {
+                    response.push_str(&quote.to_json());
+                    response.push('\n');
+                }
+                response.push_str("SNAPSHOT_END\n");
+                response
+            }
+            Reply::ShockApplied { ticker, price } => {
+                format!("SHOCK_APPLIED {} {:.2}\n", ticker, price)
+            }
+            Reply::AuthOk { name } => format!("AUTH_OK {}\n", name),
+            Reply::TickerAdded(ticker)
+            | Reply::TickerRemoved(ticker)
+            | Reply::ParamsUpdated(ticker) => format!("{} {}\n", self.name(), ticker),
+            Reply::Resumed { udp_addr, tickers } => {
+                format!("RESUMED {} {}\n", udp_addr, tickers.join(","))
+            }
+            Reply::Clients(clients) => {
+                let mut response = String::new();
+                for client in clients {
+                    response.push_str(&client.to_string());
+                    response.push('\n');
+                }
+                response.push_str("CLIENTS_END\n");
+                response
+            }
+            Reply::Kicked(client_id) => format!("KICKED {}\n", client_id),
+            Reply::Params(params) => {
+                let mut response = String::new();
+                for ticker in params {
+                    response.push_str(&serde_json::to_string(ticker).unwrap_or_default());
+                    response.push('\n');
+                }
+                response.push_str("PARAMS_END\n");
+                response
+            }
+            Reply::ReloadStatus(report) => {
+                format!("{}\n", serde_json::to_string(report).unwrap_or_default())
+            }
+            Reply::Stats(stats) => {
+                format!("{}\n", serde_json::to_string(stats).unwrap_or_default())
+            }
+            Reply::Hello(hello) => hello.to_line(),
+            Reply::Tickers(tickers) => format!("TICKERS {}\n", tickers.join(",")),
+            Reply::Id(token) => format!("ID {}\n", token),
+            Reply::Help(text) => text.to_string(),
+            Reply::StreamingStarted | Reply::AdminOk | Reply::Pong | Reply::StreamingStopped => {
+                format!("{}\n", self.name())
+            }
+        }
+    }
+
+    // {"ok":true,"reply":"<имя>", ...поля ответа}
+    pub fn to_json(&self) -> String {
+        let fields = match self {
+            Reply::Bars(bars) => json!({ "bars": bars }),
+            Reply::Snapshot(quotes) => json!({ "quotes": quotes }),
+            Reply::ShockApplied { ticker, price } => json!({ "ticker": ticker, "price": price }),
+            Reply::AuthOk { name } => json!({ "name": name }),
+            Reply::TickerAdded(ticker)
+            | Reply::TickerRemoved(ticker)
+            | Reply::ParamsUpdated(ticker) => json!({ "ticker": ticker }),
+            Reply::Resumed { udp_addr, tickers } => {
+                json!({ "udp_addr": udp_addr, "tickers": tickers })
+            }
+            Reply::Clients(clients) => json!({ "clients": clients }),
+            Reply::Kicked(client_id) => json!({ "client_id": client_id }),
+            Reply::Params(params) => json!({ "params": params }),
+            Reply::ReloadStatus(report) => json!({ "reload": report }),
+            Reply::Stats(stats) => json!({ "stats": stats }),
+            Reply::Hello(hello) => serde_json::to_value(hello).unwrap_or_default(),
+            Reply::Tickers(tickers) => json!({ "tickers": tickers }),
+            Reply::Id(token) => json!({ "session": token }),
+            Reply::Help(text) => json!({ "help": text }),
+            Reply::StreamingStarted | Reply::AdminOk | Reply::Pong | Reply::StreamingStopped => {
+                json!({})
+            }
+        };
+        let mut object = Map::new();
+        object.insert("ok".to_string(), Value::Bool(true));
+        object.insert("reply".to_string(), Value::from(self.name()));
+        if let Value::Object(fields) = fields {
+            object.extend(fields);
+        }
+        format!("{}\n", Value::Object(object))
+    }
+}
+
+pub fn encode_error(error: &CommandError, encoding: ControlEncoding) -> String {
+    match encoding {
+        ControlEncoding::Text => format!("{}\n", error),
+        ControlEncoding::Json => {
+            let code = error.code();
+            let response = json!({
+                "ok": false,
+                "error": {
+                    "status": code.status(),
+                    "code": code.name(),
+                    "detail": error.detail(),
+                },
+            });
+            format!("{}\n", response)
+        }
+    }
+}
+
+// Команда в JSON режиме: {"cmd":"STREAM","udp_addr":"udp://...","tickers":["AAPL"]}.
+// Запрос переводится в строку текстового протокола и разбирается тем же
+// Command::parse, поэтому проверки аргументов в обоих режимах одинаковые.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "SCREAMING_SNAKE_CASE", deny_unknown_fields)]
+enum JsonCommand {
+    Stream {
+        udp_addr: String,
+        tickers: Vec<String>,
+        feed: Option<String>,
+        #[serde(default)]
+        depth: bool,
+        #[serde(default)]
+        conflate: bool,
+        rate: Option<u32>,
+        every: Option<u32>,
+        overflow: Option<String>,
+        bars: Option<String>,
+    },
+    Bars {
+        ticker: String,
+        interval: String,
+        count: Option<usize>,
+    },
+    Snapshot {
+        #[serde(default)]
+        tickers: Vec<String>,
+    },
+    Shock {
+        ticker: String,
+        percent: f64,
+        seconds: Option<u64>,
+    },
+    Admin {
+        token: String,
+    },
+    Auth {
+        key: String,
+    },
+    AddTicker {
+        ticker: String,
+        price: Option<f64>,
+        volume: Option<u32>,
+    },
+    RemoveTicker {
+        ticker: String,
+    },
+    Set {
+        ticker: String,
+        params: Map<String, Value>,
+    },
+    Resume {
+        token: String,
+    },
+    Clients,
+    Kick {
+        client_id: String,
+    },
+    Params {
+        ticker: Option<String>,
+    },
+    ReloadStatus,
+    Hello {
+        version: u32,
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
+    Stats,
+    List,
+    Id,
+    Ping,
+    Stop,
+    Help,
+}
+
+// Слово командной строки: без пробелов, иначе строка разберется иначе
+fn word(value: &str) -> Result<&str, CommandError> {
+    if value.is_empty() || value.contains(char::is_whitespace) {
+        return Err(CommandError::InvalidFormat(format!(
+            "invalid value {:?}",
+            value
+        )));
+    }
+    Ok(value)
+}
+
+fn words(values: &[String]) -> Result<String, CommandError> {
+    let values = values
+        .iter()
+        .map(|value| word(value))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(values.join(","))
+}
+
+impl JsonCommand {
+    fn to_line(&self) -> Result<String, CommandError> {
+        let line = match self {
+            JsonCommand::Stream {
+                udp_addr,
+                tickers,
+                feed,
+                depth,
+                conflate,
+                rate,
+                every,
+                overflow,
+                bars,
+            } => {
+                let mut line = format!("STREAM {} {}", word(udp_addr)?, words(tickers)?);
+                if let Some(feed) = feed {
+                    line.push(' ');
+                    line.push_str(word(feed)?);
+                }
+                if *depth {
+                    line.push_str(" DEPTH");
+                }
+                if *conflate {
+                    line.push_str(" CONFLATE");
+                }
+                if let Some(rate) = rate {
+                    line.push_str(&format!(" RATE {}/s", rate));
+                }
+                if let Some(every) = every {
+                    line.push_str(&format!(" EVERY {}", every));
+                }
+                if let Some(overflow) = overflow {
+                    line.push_str(" OVERFLOW ");
+                    line.push_str(word(overflow)?);
+                }
+                if let Some(bars) = bars {
+                    line.push_str(" BARS ");
+                    line.push_str(word(bars)?);
+                }
+                line
+            }
+            JsonCommand::Bars {
+                ticker,
+                interval,
+                count,
+            } => {
+                let mut line = format!("BARS {} {}", word(ticker)?, word(interval)?);
+                if let Some(count) = count {
+                    line.push_str(&format!(" {}", count));
+                }
+                line
+            }
+            JsonCommand::Snapshot { tickers } if tickers.is_empty() => "SNAPSHOT".to_string(),
+            JsonCommand::Snapshot { tickers } => format!("SNAPSHOT {}", words(tickers)?),
+            JsonCommand::Shock {
+                ticker,
+                percent,
+                seconds,
+            } => {
+                let mut line = format!("SHOCK {} {:+}%", word(ticker)?, percent);
+                if let Some(seconds) = seconds {
+                    line.push_str(&format!(" {}", seconds));
+                }
+                line
+            }
+            JsonCommand::Admin { token } => format!("ADMIN {}", word(token)?),
+            JsonCommand::Auth { key } => format!("AUTH {}", word(key)?),
+            JsonCommand::AddTicker {
+                ticker,
+                price,
+                volume,
+            } => match (price, volume) {
+                (None, Some(_)) => {
+                    return Err(CommandError::InvalidFormat(
+                        "ADD_TICKER volume requires price".to_string(),
+                    ));
+                }
+                _ => {
+                    let mut line = format!("ADD_TICKER {}", word(ticker)?);
+                    if let Some(price) = price {
+                        line.push_str(&format!(" {}", price));
+                    }
+                    if let Some(volume) = volume {
+                        line.push_str(&format!(" {}", volume));
+                    }
+                    line
+                }
+            },
+            JsonCommand::RemoveTicker { ticker } => format!("REMOVE_TICKER {}", word(ticker)?),
+            JsonCommand::Set { ticker, params } => {
+                let mut line = format!("SET {}", word(ticker)?);
+                for (key, value) in params {
+                    let value = match value {
+                        Value::Number(number) => number.to_string(),
+                        _ => {
+                            return Err(CommandError::InvalidFormat(format!(
+                                "{} must be a number",
+                                key
+                            )));
+                        }
+                    };
+                    line.push_str(&format!(" {}={}", word(key)?, value));
+                }
+                line
+            }
+            JsonCommand::Resume { token } => format!("RESUME {}", word(token)?),
+            JsonCommand::Clients => "CLIENTS".to_string(),
+            JsonCommand::Kick { client_id } => format!("KICK {}", word(client_id)?),
+            JsonCommand::Params { ticker } => match ticker {
+                Some(ticker) => format!("PARAMS {}", word(ticker)?),
+                None => "PARAMS".to_string(),
+            },
+            JsonCommand::Hello {
+                version,
+                capabilities,
+            } => format!("HELLO {} {}", version, words(capabilities)?),
+            JsonCommand::ReloadStatus => "RELOAD_STATUS".to_string(),
+            JsonCommand::Stats => "STATS".to_string(),
+            JsonCommand::List => "LIST".to_string(),
+            JsonCommand::Id => "ID".to_string(),
+            JsonCommand::Ping => "PING".to_string(),
+            JsonCommand::Stop => "STOP".to_string(),
+            JsonCommand::Help => "HELP".to_string(),
+        };
+        Ok(line)
+    }
+}
+
+pub fn parse_json_command(line: &str) -> Result<Command, CommandError> {
+    let request: JsonCommand =
+        serde_json::from_str(line).map_err(|e| CommandError::InvalidFormat(e.to_string()))?;
+    Command::parse(&request.to_line()?)
+}
+
+pub fn parse_command(line: &str, encoding: ControlEncoding) -> Result<Command, CommandError> {
+    match encoding {
+        ControlEncoding::Text => Command::parse(line),
+        ControlEncoding::Json => parse_json_command(line),
+    }
+}
@@ -0,0 +1,233 @@
+use crate::error::QuoteError;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+use tracing::{debug, warn};
+
+// Запуск сервера как фоновой службы: отсоединение от терминала, PID файл,
+// SIGHUP для перезагрузки конфигурации и уведомление systemd (sd_notify)
+// о готовности и остановке
+
+// Процесс в фоне. Родитель, запустивший сервер, ждет ready() и выходит с
+// кодом 0; если потомок завершился раньше (например, порт занят) - с кодом 1.
+pub struct Daemon {
+    #[cfg(target_os = "linux")]
+    ready_fd: Option<libc::c_int>,
+}
+
+impl Daemon {
+    // Уход в фон двойным fork с setsid. stdin - /dev/null, stdout и stderr -
+    // output (дописывается) или /dev/null. Рабочий каталог не меняется, чтобы
+    // относительные пути конфигурации оставались верными. Вызывать до запуска
+    // потоков: после fork в потомке остается только вызвавший поток.
+    #[cfg(target_os = "linux")]
+    pub fn detach(output: Option<&Path>) -> Result<Self, QuoteError> {
+        use std::os::fd::AsRawFd;
+
+        // Файлы открываются до fork, чтобы ошибка дошла до терминала
+        let null = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+        let output = match output {
+            Some(path) => open_log(path)?,
+            None => null.try_clone()?,
+        };
+
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let (read_fd, write_fd) = (fds[0], fds[1]);
+
+        match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error().into()),
+            0 => {}
+            _ => {
+                // Родитель: ждем байт готовности или закрытия канала
+                unsafe { libc::close(write_fd) };
+                let mut byte = 0u8;
+                let read = unsafe { libc::read(read_fd, (&mut byte as *mut u8).cast(), 1) };
+                if read == 1 {
+                    std::process::exit(0);
+                }
+                eprintln!("Server failed to start in the background, see its log");
+                std::process::exit(1);
+            }
+        }
+
+        unsafe { libc::close(read_fd) };
+        if unsafe { libc::setsid() } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // Второй fork: процесс не лидер сессии и не получит управляющий терминал
+        match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error().into()),
+            0 => {}
+            _ => unsafe { libc::_exit(0) },
+        }
+
+        for (file, target) in [(&null, 0), (&output, 1), (&output, 2)] {
+            if unsafe { libc::dup2(file.as_raw_fd(), target) } == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+        Ok(Daemon {
+            ready_fd: Some(write_fd),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn detach(_output: Option<&Path>) -> Result<Self, QuoteError> {
+        Err(QuoteError::Config(
+            "daemon mode is only supported on Linux".to_string(),
+        ))
+    }
+
+    // Сервер запущен: процесс, запустивший демона, завершается успешно
+    pub fn ready(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(fd) = self.ready_fd.take() {
+            let byte = 1u8;
+            unsafe {
+                libc::write(fd, (&byte as *const u8).cast(), 1);
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+// Файл журнала, который дописывается и переживает перезапуски
+pub fn open_log(path: &Path) -> Result<File, QuoteError> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| QuoteError::Config(format!("cannot open log file {}: {}", path.display(), e)))
+}
+
+// PID файл процесса; удаляется при завершении
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    // Запись PID текущего процесса. Файл с PID еще работающего процесса -
+    // ошибка: второй экземпляр не запускается поверх первого.
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self, QuoteError> {
+        let path = path.into();
+        if let Ok(contents) = fs::read_to_string(&path)
+            && let Ok(pid) = contents.trim().parse::<u32>()
+            && pid != std::process::id()
+            && process_alive(pid)
+        {
+            return Err(QuoteError::Config(format!(
+                "{} belongs to running process {}",
+                path.display(),
+                pid
+            )));
+        }
+        fs::write(&path, format!("{}\n", std::process::id())).map_err(|e| {
+            QuoteError::Config(format!("cannot write PID file {}: {}", path.display(), e))
+        })?;
+        debug!(path = %path.display(), "Wrote PID file");
+        Ok(PidFile { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), error = %e, "Failed to remove PID file");
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Сигнал 0 только проверяет существование; EPERM - процесс чужой, но жив
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+// Как часто поток SIGHUP проверяет, пришел ли сигнал
+#[cfg(target_os = "linux")]
+const HANGUP_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[cfg(target_os = "linux")]
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+#[cfg(target_os = "linux")]
+extern "C" fn on_hangup_signal(_signal: libc::c_int) {
+    HANGUP.store(true, Ordering::SeqCst);
+}
+
+// handler на каждый SIGHUP (перезагрузка конфигурации). Обработчик сигнала
+// только ставит флаг, handler вызывается из отдельного потока, где можно
+// брать блокировки и писать в журнал. Несколько сигналов подряд могут слиться в один.
+#[cfg(target_os = "linux")]
+pub fn on_hangup(handler: impl Fn() + Send + 'static) -> io::Result<()> {
+    let action = on_hangup_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGHUP, action) } == libc::SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(HANGUP_POLL_INTERVAL);
+            if HANGUP.swap(false, Ordering::SeqCst) {
+                debug!("Received SIGHUP");
+                handler();
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn on_hangup(_handler: impl Fn() + Send + 'static) -> io::Result<()> {
+    Ok(())
+}
+
+// Сообщение systemd для Type=notify: READY=1, STOPPING=1, STATUS=...
+// Без NOTIFY_SOCKET (сервер запущен не systemd) ничего не делает и
+// возвращает false.
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) -> io::Result<bool> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let Some(socket_path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let socket_path = socket_path.to_string_lossy();
+    // "@name" - сокет в абстрактном пространстве имен
+    let addr = match socket_path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+        None => SocketAddr::from_pathname(socket_path.as_ref())?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    debug!(state, "Sent sd_notify");
+    Ok(true)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) -> io::Result<bool> {
+    Ok(false)
+}
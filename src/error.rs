@@ -0,0 +1,22 @@
+use crate::models::CommandError;
+use std::io;
+use thiserror::Error;
+
+// Ошибки библиотеки: сервер, генератор и отправка UDP возвращают их вместо
+// io::Error и строк, чтобы вызывающий мог отличить неверную настройку от
+// сбоя сети
+#[derive(Error, Debug)]
+pub enum QuoteError {
+    // Неверные параметры сервера, файла тикеров или записи
+    #[error("configuration error: {0}")]
+    Config(String),
+    // Неверная команда или адрес клиента
+    #[error(transparent)]
+    Protocol(#[from] CommandError),
+    // Сокеты: привязка портов, прием соединений, отправка
+    #[error("transport error: {0}")]
+    Transport(#[from] io::Error),
+    // Генератор не смог загрузить тикеры или историю
+    #[error("generator error: {0}")]
+    Generator(String),
+}
@@ -1,222 +1,2173 @@
-use crate::models::StockQuote;
-use crossbeam_channel::{Receiver, Sender, unbounded};
-use log::{debug, info, trace, warn};
-use rand::Rng;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::thread;
+use crate::bars::{Bar, BarAggregator, BarInterval};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::clock;
+use crate::error::QuoteError;
+use crate::generator_handle::{GeneratorControl, GeneratorHandle};
+use crate::metrics::{ServerMetrics, TickerSubscriptions};
+use crate::models::{
+    AssetClass, BidAskQuote, DepthUpdate, FeedMessage, MarketStatus, ParamUpdate, QuoteEvent,
+    StatusEvent, StockQuote, StreamOptions, current_millis,
+};
+use crate::orderbook::{OrderBook, round_price, round_to_tick};
+use crate::replay::ReplaySource;
+use crate::session::SessionSchedule;
+use crate::subscription::{ClientFeed, FeedReceiver, Subscription, SubscriptionId};
+use crate::ticker_config::{self, IndexSpec, TickerSpec, VolumeProfile};
+use crossbeam_channel::{Receiver, Sender, TrySendError, bounded, unbounded};
+use rand::distributions::{Bernoulli, Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, info, trace, trace_span, warn};
+
+// Параметры ценовой модели отдельного тикера
+#[derive(Debug, Clone)]
+struct TickerParams {
+    asset_class: AssetClass,
+    // Для валютных пар волатильность задается в пипсах размера pip_size
+    volatility: f64,
+    pip_size: f64,
+    drift: f64,
+    // Шаг цены: публикуемые цены округляются до него
+    tick_size: f64,
+    volume_profile: Option<VolumeProfile>,
+    spread_bps: f64,
+    interval_ms: Option<u64>,
+}
+
+// Текущие цена и параметры модели тикера для команды PARAMS
+#[derive(Debug, Clone, Serialize)]
+pub struct ParamsSnapshot {
+    pub ticker: String,
+    pub asset_class: AssetClass,
+    pub price: f64,
+    pub volatility: f64,
+    pub drift: f64,
+    pub spread_bps: f64,
+    pub tick_size: f64,
+    pub base_volume: u32,
+    // None - общий интервал генерации сервера
+    pub interval_ms: Option<u64>,
+    pub subscribers: usize,
+    pub halted: bool,
+}
+
+impl TickerParams {
+    // Нижняя граница цены: валютная пара может стоить меньше 1.0
+    fn min_price(&self) -> f64 {
+        match self.asset_class {
+            AssetClass::Equity => 1.0,
+            AssetClass::Fx => self.pip_size,
+        }
+    }
+}
+
+type TickerSpecs = Vec<(String, TickerSpec)>;
+
+// Изменение списка тикеров во время работы
+enum UniverseChange {
+    // Полная замена списка (перезагрузка файла тикеров)
+    Reload(TickerSpecs),
+    // Тикер добавлен или изменился его интервал: генератору нужно обновить свой список
+    Refresh,
+    Remove(String),
+}
+
+// Распределение интервалов между котировками тикера
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrivalProcess {
+    // Фиксированный интервал
+    #[default]
+    Fixed,
+    // Пуассоновский поток: интервалы из экспоненциального распределения
+    // со средним, равным интервалу тикера
+    Poisson,
+}
+
+impl ArrivalProcess {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "fixed" => Some(ArrivalProcess::Fixed),
+            "poisson" => Some(ArrivalProcess::Poisson),
+            _ => None,
+        }
+    }
+}
+
+// Что делать с событием, когда очередь подписчика заполнена
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+    // Ждать, пока подписчик освободит место (тормозит генерацию для всех)
+    Block,
+    // Вытеснить самое старое событие из очереди
+    #[default]
+    DropOldest,
+    // Отбросить новое событие
+    DropNewest,
+    // Снять всю подписку: клиент отключается, вместо того чтобы терять события
+    Disconnect,
+}
+
+impl OverflowPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "block" => Some(OverflowPolicy::Block),
+            "drop-oldest" => Some(OverflowPolicy::DropOldest),
+            "drop-newest" => Some(OverflowPolicy::DropNewest),
+            "disconnect" => Some(OverflowPolicy::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+// Переполнения очередей одной подписки по всем ее тикерам; общий с
+// отправителем клиента, через него потери попадают в статистику доставки
+#[derive(Debug, Default)]
+pub struct OverflowStats {
+    dropped: AtomicU64,
+    // Подписка снята политикой disconnect
+    disconnected: AtomicBool,
+}
+
+impl OverflowStats {
+    // Событий, вытесненных или отброшенных из переполненных очередей
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+}
+
+// Куда подписчик отправляет события тикера
+#[derive(Clone)]
+enum FeedSink {
+    // Свой канал тикера для FeedReceiver внутри процесса. Копия приемного
+    // конца, через которую drop-oldest вытесняет старые события, держится
+    // только при этой политике: с ней канал не закрывается сам и подписчик
+    // удаляется при отписке.
+    Local {
+        tx: Sender<FeedMessage>,
+        rx: Option<Receiver<FeedMessage>>,
+    },
+    // Общая очередь всех тикеров клиента, которую разбирает его UDP задача
+    Queue(mpsc::Sender<FeedMessage>),
+    // Та же очередь при drop-oldest: кольцо broadcast само вытесняет самое
+    // старое событие, когда заполнено
+    Ring {
+        tx: broadcast::Sender<FeedMessage>,
+        capacity: usize,
+    },
+}
+
+impl FeedSink {
+    // Канал тикера и его приемный конец для FeedReceiver
+    fn local(capacity: usize, policy: OverflowPolicy) -> (Self, Receiver<FeedMessage>) {
+        let (tx, rx) = bounded(capacity);
+        let own_rx = (policy == OverflowPolicy::DropOldest).then(|| rx.clone());
+        (FeedSink::Local { tx, rx: own_rx }, rx)
+    }
+
+    // События в собственной очереди тикера. Общая очередь клиента не делится
+    // по тикерам: она видна в quote_client_queue_depth.
+    fn queued(&self) -> usize {
+        match self {
+            FeedSink::Local { tx, .. } => tx.len(),
+            FeedSink::Queue(_) | FeedSink::Ring { .. } => 0,
+        }
+    }
+
+    // Очередь клиента на все его тикеры: по capacity событий на тикер
+    fn client(capacity: usize, tickers: usize, policy: OverflowPolicy) -> (Self, ClientFeed) {
+        let capacity = capacity.saturating_mul(tickers.max(1));
+        if policy == OverflowPolicy::DropOldest {
+            // Кольцо выделяется целиком сразу, поэтому его размер ограничен
+            let capacity = capacity.min(MAX_RING_CAPACITY).next_power_of_two();
+            let (tx, rx) = broadcast::channel(capacity);
+            (FeedSink::Ring { tx, capacity }, ClientFeed::Ring(rx))
+        } else {
+            let (tx, rx) = mpsc::channel(capacity.min(MAX_QUEUE_CAPACITY));
+            (FeedSink::Queue(tx), ClientFeed::Queue(rx))
+        }
+    }
+}
+
+// Подписчик тикера: отправляющий конец ограниченного канала клиента
+struct Subscriber {
+    // Подписка, по которой подписчик снимается через unsubscribe
+    subscription: SubscriptionId,
+    sink: FeedSink,
+    // Своя политика клиента или политика сервера
+    policy: OverflowPolicy,
+    // Подписчик закрытых баров этого интервала вместо сделок и котировок
+    bars: Option<BarInterval>,
+    dropped: u64,
+    // Общий учет потерь подписки по всем ее тикерам. Учет принадлежит
+    // потребителю (отправителю клиента или приемным концам FeedReceiver):
+    // когда потребитель удален, каналы подписки закрыты.
+    stats: Weak<OverflowStats>,
+}
+
+impl Subscriber {
+    fn new(
+        subscription: SubscriptionId,
+        stats: &Arc<OverflowStats>,
+        sink: FeedSink,
+        policy: OverflowPolicy,
+        bars: Option<BarInterval>,
+    ) -> Self {
+        Subscriber {
+            subscription,
+            sink,
+            policy,
+            bars,
+            dropped: 0,
+            stats: Arc::downgrade(stats),
+        }
+    }
+
+    // Подписчику баров - только бары его интервала и статусы, остальным -
+    // все, кроме баров
+    fn wants(&self, event: &QuoteEvent) -> bool {
+        match (event, self.bars) {
+            (QuoteEvent::Status(_), _) => true,
+            (QuoteEvent::Bar(bar), Some(interval)) => bar.interval == interval.name(),
+            (QuoteEvent::Bar(_), None) => false,
+            (_, bars) => bars.is_none(),
+        }
+    }
+
+    // Потребитель подписки удален вместе с приемными концами ее каналов.
+    // У drop-oldest своя копия приемного конца, поэтому по каналу это не видно.
+    fn is_closed(&self) -> bool {
+        self.stats.strong_count() == 0
+    }
+
+    // Отправка по политике переполнения. false - подписчик отключился
+    // или отключен политикой disconnect.
+    fn deliver(&mut self, ticker: &str, message: FeedMessage) -> bool {
+        let policy = self.policy;
+        let mut message = message;
+        loop {
+            let rejected = match (&self.sink, policy) {
+                (FeedSink::Ring { tx, capacity }, _) => {
+                    // В заполненном кольце новое событие займет место самого старого
+                    let full = tx.len() >= *capacity;
+                    if tx.send(message).is_err() {
+                        return false;
+                    }
+                    if !full {
+                        return true;
+                    }
+                    self.record_drop(ticker);
+                    return true;
+                }
+                (FeedSink::Local { tx, .. }, OverflowPolicy::Block) => {
+                    return tx.send(message).is_ok();
+                }
+                // Генератор и обработчики команд не работают внутри задач
+                // tokio, поэтому здесь можно ждать места в очереди
+                (FeedSink::Queue(tx), OverflowPolicy::Block) => {
+                    return tx.blocking_send(message).is_ok();
+                }
+                (FeedSink::Local { tx, .. }, _) => match tx.try_send(message) {
+                    Ok(()) => return true,
+                    Err(TrySendError::Disconnected(_)) => return false,
+                    Err(TrySendError::Full(rejected)) => rejected,
+                },
+                (FeedSink::Queue(tx), _) => match tx.try_send(message) {
+                    Ok(()) => return true,
+                    Err(mpsc::error::TrySendError::Closed(_)) => return false,
+                    Err(mpsc::error::TrySendError::Full(rejected)) => rejected,
+                },
+            };
+
+            if !self.record_drop(ticker) {
+                return false;
+            }
+            match &self.sink {
+                // Освобождаем место от самого старого события и повторяем
+                FeedSink::Local { rx: Some(rx), .. } if policy == OverflowPolicy::DropOldest => {
+                    let _ = rx.try_recv();
+                    message = rejected;
+                }
+                _ => return true,
+            }
+        }
+    }
+
+    // Учет потерянного события. false - политика disconnect отключает подписчика.
+    fn record_drop(&mut self, ticker: &str) -> bool {
+        let policy = self.policy;
+        self.dropped += 1;
+        let stats = self.stats.upgrade();
+        if let Some(stats) = &stats {
+            stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        if policy == OverflowPolicy::Disconnect {
+            warn!(%ticker, subscription = %self.subscription, "Disconnecting slow subscriber");
+            if let Some(stats) = &stats {
+                stats.disconnected.store(true, Ordering::Relaxed);
+            }
+            return false;
+        }
+        if self.dropped == 1 || self.dropped.is_multiple_of(1000) {
+            warn!(%ticker, dropped = self.dropped, ?policy, "Slow subscriber");
+        }
+        true
+    }
+}
+
+// Состояние ценовой модели тикера
+struct TickerState {
+    price: f64,
+    base_volume: u32,
+    params: TickerParams,
+    // None, если симуляция глубины выключена
+    order_book: Option<OrderBook>,
+    // Ценовой коридор, если для тикера задан price_band
+    breaker: Option<CircuitBreaker>,
+    // Окончание повышенной волатильности после рыночного шока (мс)
+    shock_until: Option<u64>,
+    // Последние сделка и bid/ask: их сразу получает новый подписчик
+    last_trade: Option<StockQuote>,
+    last_bid_ask: Option<BidAskQuote>,
+    // Последние сделки в порядке времени, не больше history_size генератора
+    history: VecDeque<StockQuote>,
+}
+
+impl TickerState {
+    // Сделка становится последней известной и попадает в историю тикера
+    fn record_trade(&mut self, quote: &StockQuote, history_size: usize) {
+        if history_size > 0 {
+            while self.history.len() >= history_size {
+                self.history.pop_front();
+            }
+            self.history.push_back(quote.clone());
+        }
+        self.last_trade = Some(quote.clone());
+    }
+}
+
+// Тикер генератора. Модель и подписчики под разными блокировками: генерация
+// котировки не ждет рассылку, а подписка на один тикер - генерацию остальных.
+struct TickerEntry {
+    state: Mutex<TickerState>,
+    // Отдельный генератор случайных чисел тикера, пока его не забрал поток
+    // генерации: дальше он живет в TickerSampler этого потока
+    rng: Mutex<Option<StdRng>>,
+    subscribers: Mutex<Vec<Subscriber>>,
+    // Число подписчиков для проверок в цикле генерации без блокировки списка
+    subscriber_count: AtomicUsize,
+}
+
+impl TickerEntry {
+    fn has_subscribers(&self) -> bool {
+        self.subscriber_count.load(Ordering::Relaxed) > 0
+    }
+}
+
+// Тикер в локальном состоянии потока генерации: расписание котировок и
+// случайные величины, без поиска в карте тикеров на каждой котировке
+struct TickerSlot {
+    ticker: String,
+    entry: Arc<TickerEntry>,
+    interval: Duration,
+    next_fire: u64,
+    // Пропущенные срабатывания без подписчиков
+    idle_skips: u32,
+    // Индексы, в которые входит тикер; пересобираются вместе со списком тикеров
+    indices: Vec<Arc<TickerEntry>>,
+    sampler: TickerSampler,
+}
+
+// RNG тикера и распределения его случайных величин. Принадлежит одному потоку
+// генерации и используется вне блокировок; распределения, зависящие от
+// параметров тикера, пересоздаются только при изменении этих параметров.
+struct TickerSampler {
+    // Цена, объем и гэп открытия
+    rng: StdRng,
+    // Интервалы, размеры bid/ask и стакан: сколько их выбирается, зависит от
+    // подписчиков и idle_slowdown, поэтому они не сдвигают путь цены при том же --seed
+    aux_rng: StdRng,
+    unit: Uniform<f64>,
+    volume_noise: Uniform<f64>,
+    volume_spike: Bernoulli,
+    // Изменение цены для волатильности из первого элемента
+    price_change: Option<(f64, Uniform<f64>)>,
+    // Размер заявки bid/ask в лотах для базового числа лотов из первого элемента
+    order_size: Option<(u32, Uniform<u32>)>,
+}
+
+impl TickerSampler {
+    fn new(rng: StdRng, aux_rng: StdRng) -> Self {
+        TickerSampler {
+            rng,
+            aux_rng,
+            unit: Uniform::new(0.0, 1.0),
+            volume_noise: Uniform::new(-2.0, 2.0),
+            volume_spike: Bernoulli::new(VOLUME_SPIKE_PROBABILITY)
+                .expect("spike probability is within 0..=1"),
+            price_change: None,
+            order_size: None,
+        }
+    }
+
+    // Множитель среднего интервала для пуассоновского потока.
+    // 1 - U лежит в (0, 1], поэтому логарифм конечен.
+    fn arrival_factor(&mut self) -> f64 {
+        -(1.0 - self.unit.sample(&mut self.aux_rng)).ln()
+    }
+
+    fn price_change(&mut self, volatility: f64) -> f64 {
+        if volatility <= 0.0 {
+            return 0.0;
+        }
+        let dist = match self.price_change {
+            Some((cached, dist)) if cached == volatility => dist,
+            _ => {
+                let dist = Uniform::new(-volatility, volatility);
+                self.price_change = Some((volatility, dist));
+                dist
+            }
+        };
+        dist.sample(&mut self.rng)
+    }
+
+    fn order_size(&mut self, lots: u32) -> u32 {
+        let dist = match self.order_size {
+            Some((cached, dist)) if cached == lots => dist,
+            _ => {
+                let dist = Uniform::new_inclusive(1, 10 * lots);
+                self.order_size = Some((lots, dist));
+                dist
+            }
+        };
+        dist.sample(&mut self.aux_rng)
+    }
+}
+
+// Число частей агрегатора баров
+const BAR_SHARDS: usize = 16;
+
+// Бары, разбитые на части по хешу тикера: потоки генерации с разными
+// тикерами не ждут друг друга на одной блокировке
+struct BarShards {
+    shards: Vec<Mutex<BarAggregator>>,
+}
+
+impl BarShards {
+    fn new(count: usize) -> Self {
+        BarShards {
+            shards: (0..count.max(1))
+                .map(|_| Mutex::new(BarAggregator::new(BarInterval::ALL.to_vec())))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, ticker: &str) -> &Mutex<BarAggregator> {
+        &self.shards[(ticker_hash(ticker) % self.shards.len() as u64) as usize]
+    }
+
+    fn on_trade(&self, quote: &StockQuote) -> Vec<Bar> {
+        self.shard(&quote.ticker).lock().unwrap().on_trade(quote)
+    }
+
+    fn close_expired(&self, now: u64) -> Vec<Bar> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.lock().unwrap().close_expired(now))
+            .collect()
+    }
+
+    fn recent(&self, ticker: &str, interval: BarInterval, count: usize) -> Vec<Bar> {
+        self.shard(ticker)
+            .lock()
+            .unwrap()
+            .recent(ticker, interval, count)
+    }
+
+    // Тикеры подписки раскладываются по своим частям с общим каналом
+    fn subscribe(&self, tickers: Vec<String>, interval: BarInterval) -> Receiver<Bar> {
+        let (tx, rx) = unbounded();
+        let mut by_shard: HashMap<usize, Vec<String>> = HashMap::new();
+        for ticker in tickers {
+            let shard = (ticker_hash(&ticker) % self.shards.len() as u64) as usize;
+            by_shard.entry(shard).or_default().push(ticker);
+        }
+        for (shard, tickers) in by_shard {
+            self.shards[shard]
+                .lock()
+                .unwrap()
+                .add_subscriber(tickers, interval, tx.clone());
+        }
+        rx
+    }
+}
+
+// Общее состояние потоков генерации: версию списка тикеров и фазу сессии
+// меняет поток 0, остальные подхватывают их в начале цикла
+struct WorkerSync {
+    universe_version: AtomicU64,
+    session_status: Mutex<Option<MarketStatus>>,
+}
+
+// Емкость очереди подписчика по умолчанию, событий
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+// Меньше нельзя: при подписке в очередь сразу кладутся статус, последние
+// сделка и bid/ask и снимок стакана
+const MIN_CHANNEL_CAPACITY: usize = 16;
+// Предел общей очереди клиента на все тикеры, событий; кольцо drop-oldest
+// выделяется сразу целиком и ограничено сильнее
+const MAX_QUEUE_CAPACITY: usize = 1 << 20;
+const MAX_RING_CAPACITY: usize = 1 << 16;
+// Сделок в истории тикера по умолчанию
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+// Период сборки закрытых подписок по умолчанию, с
+pub const DEFAULT_SUBSCRIPTION_GC_SECS: u64 = 30;
 
 #[derive(Clone)]
 pub struct QuoteGenerator {
-    ticker_prices: Arc<Mutex<HashMap<String, f64>>>,
-    base_volumes: Arc<Mutex<HashMap<String, u32>>>,
-    volatility: f64,
-    // Храним senders для каждого тикера отдельно
-    ticker_senders: Arc<Mutex<HashMap<String, Vec<Sender<StockQuote>>>>>,
+    // Тикеры с их состоянием и подписчиками. Карта блокируется на запись только
+    // при добавлении и удалении тикеров, остальные операции берут ее на чтение
+    // и работают с блокировками отдельного тикера.
+    entries: Arc<RwLock<HashMap<String, Arc<TickerEntry>>>>,
+    // Ограничение очереди подписчика и поведение при ее заполнении
+    channel_capacity: usize,
+    overflow: OverflowPolicy,
+    // Волатильность по умолчанию, интервал и idle_slowdown; меняются на лету
+    defaults: Arc<Mutex<GenerationDefaults>>,
+    seed: Option<u64>,
+    session: Option<SessionSchedule>,
+    arrival: ArrivalProcess,
+    // Глубина стакана; 0 - симуляция стакана выключена
+    depth_levels: usize,
+    // Число потоков генерации
+    workers: usize,
+    // OHLCV бары, общие для всех клиентов
+    bars: Arc<BarShards>,
+    // Синтетические индексы: цена не генерируется, а считается по составляющим
+    indices: Arc<RwLock<HashMap<String, IndexSpec>>>,
+    // Изменения списка тикеров, которые генератор применит в начале следующего цикла
+    pending_changes: Arc<Mutex<Vec<UniverseChange>>>,
+    // Тикеры каждой подписки: по ним unsubscribe находит ее подписчиков
+    subscriptions: Arc<Mutex<HashMap<SubscriptionId, Vec<String>>>>,
+    // Сколько последних сделок каждого тикера держать в памяти; 0 - без истории
+    history_size: usize,
+    // Как часто искать закрытые каналы подписчиков; ноль - не искать
+    subscription_gc: Duration,
+    // Исторические данные: при наличии воспроизводятся вместо генерации
+    replay: Option<Arc<ReplaySource>>,
+    // Счетчики сделок и событий для /metrics
+    metrics: Arc<ServerMetrics>,
 }
 
+// Параметры генерации, которые можно сменить без перезапуска сервера
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GenerationDefaults {
+    // Для тикеров, у которых волатильность не задана явно
+    pub volatility: f64,
+    // Общий интервал котировок; у тикера может быть свой interval_ms
+    pub interval_ms: u64,
+    // Тикеры без подписчиков генерируются в столько раз реже (0 - не генерируются)
+    pub idle_slowdown: u32,
+}
+
+// На премаркете котировки генерируются раз в столько циклов
+const PRE_MARKET_SLOWDOWN: u32 = 5;
+// Спред bid/ask по умолчанию, базисных пунктов
+const DEFAULT_SPREAD_BPS: f64 = 5.0;
+// Шаг цены для тикеров без явного tick_size
+const DEFAULT_TICK_SIZE: f64 = 0.01;
+// Валютные пары по умолчанию: котировка с 5 знаками, пипс 0.0001,
+// изменение до 5 пипсов за шаг и спред 1 б.п.
+const DEFAULT_FX_TICK_SIZE: f64 = 0.00001;
+const DEFAULT_PIP_SIZE: f64 = 0.0001;
+const DEFAULT_FX_VOLATILITY_PIPS: f64 = 5.0;
+const DEFAULT_FX_SPREAD_BPS: f64 = 1.0;
+// Во сколько раз реже генерируются котировки тикеров без подписчиков
+pub const DEFAULT_IDLE_SLOWDOWN: u32 = 10;
+// Во сколько раз растет волатильность тикера после рыночного шока
+const SHOCK_VOLATILITY_FACTOR: f64 = 3.0;
+// Доля сделок с тройным объемом
+const VOLUME_SPIKE_PROBABILITY: f64 = 0.05;
+
 impl QuoteGenerator {
     pub fn new(tickers: Vec<String>, volatility: f64) -> Self {
-        let mut ticker_prices = HashMap::new();
-        let mut base_volumes = HashMap::new();
-        let mut ticker_senders = HashMap::new();
+        let specs = tickers
+            .into_iter()
+            .map(|ticker| (ticker, TickerSpec::default()))
+            .collect();
+        Self::with_specs(specs, volatility, None)
+    }
 
-        let mut rng = rand::thread_rng();
+    // Создание генератора с индивидуальными параметрами тикеров.
+    // volatility используется для тикеров, у которых она не задана явно.
+    // При заданном seed ценовые ряды воспроизводятся от запуска к запуску.
+    pub fn with_specs(
+        specs: Vec<(String, TickerSpec)>,
+        volatility: f64,
+        seed: Option<u64>,
+    ) -> Self {
+        if let Some(seed) = seed {
+            info!("Using deterministic generation with seed {}", seed);
+        }
 
-        // Инициализируем начальные цены и senders для каждого тикера
-        for ticker in tickers {
-            let ticker_upper = ticker.to_uppercase();
-            let initial_price = rng.gen_range(50.0..1000.0);
-            ticker_prices.insert(ticker_upper.clone(), initial_price);
-            ticker_senders.insert(ticker_upper.clone(), Vec::new());
-
-            let base_volume = match ticker_upper.as_str() {
-                "AAPL" | "MSFT" | "GOOGL" => 5000,
-                "TSLA" | "AMZN" | "NVDA" => 3000,
-                "META" | "JPM" | "JNJ" => 2000,
-                _ => 1000,
-            };
-            base_volumes.insert(ticker_upper, base_volume);
+        let generator = QuoteGenerator {
+            entries: Arc::new(RwLock::new(HashMap::new())),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            overflow: OverflowPolicy::default(),
+            defaults: Arc::new(Mutex::new(GenerationDefaults {
+                volatility,
+                interval_ms: 0,
+                idle_slowdown: DEFAULT_IDLE_SLOWDOWN,
+            })),
+            seed,
+            session: None,
+            arrival: ArrivalProcess::Fixed,
+            depth_levels: 0,
+            workers: 1,
+            bars: Arc::new(BarShards::new(BAR_SHARDS)),
+            indices: Arc::new(RwLock::new(HashMap::new())),
+            pending_changes: Arc::new(Mutex::new(Vec::new())),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            history_size: DEFAULT_HISTORY_SIZE,
+            subscription_gc: Duration::from_secs(DEFAULT_SUBSCRIPTION_GC_SECS),
+            replay: None,
+            metrics: Arc::new(ServerMetrics::new()),
+        };
+
+        // Инициализируем начальные цены и senders для каждого тикера.
+        // Индексы - после обычных тикеров, их значение считается по составляющим.
+        let mut specs = specs;
+        specs.sort_by_key(|(_, spec)| spec.index.is_some());
+        for (ticker, spec) in specs {
+            generator.insert_ticker(&ticker.to_uppercase(), &spec);
         }
 
         debug!(
             "Initialized quote generator with {} tickers",
-            ticker_prices.len()
+            generator.entries.read().unwrap().len()
         );
 
-        QuoteGenerator {
-            ticker_prices: Arc::new(Mutex::new(ticker_prices)),
-            base_volumes: Arc::new(Mutex::new(base_volumes)),
-            volatility,
-            ticker_senders: Arc::new(Mutex::new(ticker_senders)),
+        generator
+    }
+
+    // Добавление тикера со всем его состоянием и пустым списком подписчиков
+    fn insert_ticker(&self, ticker: &str, spec: &TickerSpec) {
+        let mut rng = ticker_rng(ticker, self.seed);
+        let index_level = spec.index.as_ref().and_then(|index| {
+            self.indices
+                .write()
+                .unwrap()
+                .insert(ticker.to_string(), index.clone());
+            self.index_level(index)
+        });
+        let price_range = match spec.asset_class.unwrap_or_default() {
+            AssetClass::Equity => 50.0..1000.0,
+            AssetClass::Fx => 0.5..2.0,
+        };
+        let initial_price = index_level
+            .or(spec.initial_price)
+            .unwrap_or_else(|| rng.gen_range(price_range));
+        let params = self.ticker_params_from(spec);
+
+        let state = TickerState {
+            price: initial_price,
+            base_volume: base_volume_for(ticker, spec),
+            order_book: (self.depth_levels > 0)
+                .then(|| OrderBook::new(self.depth_levels, params.tick_size)),
+            breaker: spec.price_band.map(CircuitBreaker::new),
+            shock_until: None,
+            last_trade: None,
+            last_bid_ask: None,
+            history: VecDeque::new(),
+            params,
+        };
+        let entry = TickerEntry {
+            state: Mutex::new(state),
+            rng: Mutex::new(Some(rng)),
+            subscribers: Mutex::new(Vec::new()),
+            subscriber_count: AtomicUsize::new(0),
+        };
+        self.entries
+            .write()
+            .unwrap()
+            .insert(ticker.to_string(), Arc::new(entry));
+    }
+
+    // Тикер без удержания карты: вызывающий берет блокировки самого тикера
+    fn entry(&self, ticker: &str) -> Option<Arc<TickerEntry>> {
+        self.entries.read().unwrap().get(ticker).cloned()
+    }
+
+    // Доступ к состоянию тикера; None для неизвестного тикера
+    fn with_state<R>(&self, ticker: &str, f: impl FnOnce(&mut TickerState) -> R) -> Option<R> {
+        let entry = self.entry(ticker)?;
+        let mut state = entry.state.lock().unwrap();
+        Some(f(&mut state))
+    }
+
+    // Значение индекса по текущим ценам его составляющих
+    fn index_level(&self, index: &IndexSpec) -> Option<f64> {
+        let prices: HashMap<String, f64> = index
+            .constituents
+            .keys()
+            .filter_map(|ticker| Some((ticker.clone(), self.with_state(ticker, |s| s.price)?)))
+            .collect();
+        index.level(&prices)
+    }
+
+    fn ticker_params_from(&self, spec: &TickerSpec) -> TickerParams {
+        let asset_class = spec.asset_class.unwrap_or_default();
+        let (volatility, tick_size, spread_bps) = match asset_class {
+            AssetClass::Equity => (
+                self.defaults.lock().unwrap().volatility,
+                DEFAULT_TICK_SIZE,
+                DEFAULT_SPREAD_BPS,
+            ),
+            AssetClass::Fx => (
+                DEFAULT_FX_VOLATILITY_PIPS,
+                DEFAULT_FX_TICK_SIZE,
+                DEFAULT_FX_SPREAD_BPS,
+            ),
+        };
+        TickerParams {
+            asset_class,
+            volatility: spec.volatility.unwrap_or(volatility),
+            pip_size: spec.pip_size.unwrap_or(DEFAULT_PIP_SIZE),
+            drift: spec.drift.unwrap_or(0.0),
+            tick_size: spec.tick_size.unwrap_or(tick_size),
+            volume_profile: spec.volume_profile,
+            spread_bps: spec.spread_bps.unwrap_or(spread_bps),
+            interval_ms: spec.interval_ms,
         }
     }
 
-    // Создание нового ресивера для клиента для конкретных тикеров
-    // Возвращает Vec<Receiver<StockQuote>> - по одному ресиверу на каждый тикер
-    pub fn subscribe_to_tickers(&self, tickers: Vec<String>) -> Vec<Receiver<StockQuote>> {
-        let mut receivers = Vec::new();
+    // Включение симуляции стакана глубиной levels уровней с каждой стороны
+    pub fn with_depth(mut self, levels: usize) -> Self {
+        self.depth_levels = levels;
+        if levels > 0 {
+            info!("Order book simulation enabled: {} levels per side", levels);
+            for entry in self.entries.read().unwrap().values() {
+                let mut state = entry.state.lock().unwrap();
+                state.order_book = Some(OrderBook::new(levels, state.params.tick_size));
+            }
+        }
+        self
+    }
 
-        {
-            let mut ticker_senders = self.ticker_senders.lock().unwrap();
-
-            for ticker in tickers {
-                let ticker_upper = ticker.to_uppercase();
-
-                if let Some(sender_list) = ticker_senders.get_mut(&ticker_upper) {
-                    let (tx, rx) = unbounded();
-                    sender_list.push(tx);
-                    receivers.push(rx);
-                    debug!("Client subscribed to ticker: {}", ticker_upper);
-                } else {
-                    warn!(
-                        "Client tried to subscribe to non-existent ticker: {}",
-                        ticker_upper
-                    );
+    pub fn depth_enabled(&self) -> bool {
+        self.depth_levels > 0
+    }
+
+    // Включение расписания торговой сессии
+    pub fn with_session(mut self, schedule: SessionSchedule) -> Self {
+        info!(
+            "Market session: open {}, close {}, pre-market {}",
+            schedule.open,
+            schedule.close,
+            schedule
+                .pre_market
+                .map_or("disabled".to_string(), |t| t.to_string())
+        );
+        self.session = Some(schedule);
+        self
+    }
+
+    pub fn with_arrival(mut self, arrival: ArrivalProcess) -> Self {
+        if arrival == ArrivalProcess::Poisson {
+            info!("Using Poisson arrival process for quote timing");
+        }
+        self.arrival = arrival;
+        self
+    }
+
+    // Частота фоновой генерации тикеров без подписчиков: в slowdown раз реже
+    // обычной, 0 - котировки для них не генерируются вовсе
+    pub fn with_idle_slowdown(self, slowdown: u32) -> Self {
+        self.defaults.lock().unwrap().idle_slowdown = slowdown;
+        self
+    }
+
+    pub fn defaults(&self) -> GenerationDefaults {
+        *self.defaults.lock().unwrap()
+    }
+
+    // Новые параметры по умолчанию во время работы: интервал и idle_slowdown
+    // действуют со следующего цикла, волатильность - для тикеров без явной
+    // волатильности после следующей request_reload
+    pub fn set_defaults(&self, defaults: GenerationDefaults) {
+        *self.defaults.lock().unwrap() = defaults;
+        self.pending_changes
+            .lock()
+            .unwrap()
+            .push(UniverseChange::Refresh);
+    }
+
+    // Очереди подписчиков ограничены capacity событиями; policy решает,
+    // что делать, когда клиент не успевает их забирать
+    pub fn with_channel_capacity(mut self, capacity: usize, policy: OverflowPolicy) -> Self {
+        if capacity < MIN_CHANNEL_CAPACITY {
+            warn!(
+                "Channel capacity {} is too small, using {}",
+                capacity, MIN_CHANNEL_CAPACITY
+            );
+        }
+        self.channel_capacity = capacity.max(MIN_CHANNEL_CAPACITY);
+        self.overflow = policy;
+        info!(
+            "Subscriber queues: {} events, overflow policy {:?}",
+            self.channel_capacity, policy
+        );
+        self
+    }
+
+    // Генерация в workers потоках, между которыми тикеры делятся по хешу имени:
+    // для тысяч тикеров, которые один поток не успевает обойти за интервал
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    // Глубина истории сделок каждого тикера, см. history(); 0 выключает ее
+    pub fn with_history(mut self, size: usize) -> Self {
+        self.history_size = size;
+        self
+    }
+
+    // Период фоновой сборки закрытых подписок; Duration::ZERO выключает ее,
+    // и закрытые каналы убираются только при неудачной отправке
+    pub fn with_subscription_gc(mut self, interval: Duration) -> Self {
+        self.subscription_gc = interval;
+        self
+    }
+
+    // Общий реестр счетчиков сервера
+    pub fn with_metrics(mut self, metrics: Arc<ServerMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    // Подписчики и глубина их очередей по тикерам, в алфавитном порядке
+    pub fn subscription_stats(&self) -> Vec<TickerSubscriptions> {
+        let entries = self.entries.read().unwrap();
+        let mut stats: Vec<TickerSubscriptions> = entries
+            .iter()
+            .map(|(ticker, entry)| {
+                let senders = entry.subscribers.lock().unwrap();
+                TickerSubscriptions {
+                    ticker: ticker.clone(),
+                    subscribers: senders.len(),
+                    queued: senders.iter().map(|sender| sender.sink.queued()).sum(),
                 }
-            }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+        stats
+    }
+
+    fn is_index(&self, ticker: &str) -> bool {
+        self.indices.read().unwrap().contains_key(ticker)
+    }
+
+    // Записи индексов, в которые входит тикер
+    fn index_entries(&self, ticker: &str) -> Vec<Arc<TickerEntry>> {
+        let names: Vec<String> = self
+            .indices
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, index)| index.constituents.contains_key(ticker))
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.iter().filter_map(|name| self.entry(name)).collect()
+    }
+
+    // Тикеры, котировки которых генерируются (все, кроме индексов)
+    fn traded_tickers(&self) -> Vec<String> {
+        let mut tickers = self.tickers();
+        tickers.retain(|ticker| !self.is_index(ticker));
+        tickers
+    }
+
+    // Часть тикеров потока генерации worker. Тикер закреплен за потоком по хешу
+    // имени и не переходит к другому при изменении списка тикеров.
+    fn worker_tickers(&self, worker: usize) -> Vec<String> {
+        let mut tickers = self.traded_tickers();
+        if self.workers > 1 {
+            let workers = self.workers as u64;
+            tickers.retain(|ticker| ticker_hash(ticker) % workers == worker as u64);
         }
+        tickers
+    }
 
-        receivers
+    // Сделки тикеров для потребителей внутри процесса; подписка снимается,
+    // когда Subscription удаляется
+    pub fn subscribe(&self, tickers: Vec<String>) -> Subscription {
+        Subscription::new(self, tickers)
     }
 
-    // Отписка клиента от тикеров
-    pub fn unsubscribe_from_tickers(&self, tickers: Vec<String>) {
-        let ticker_senders = self.ticker_senders.lock().unwrap();
+    // Создание нового ресивера для клиента для конкретных тикеров
+    // Возвращает id подписки и по одному ресиверу на каждый тикер;
+    // подписка действует до unsubscribe(id) или пока жив хоть один ресивер
+    pub fn subscribe_to_tickers(
+        &self,
+        tickers: Vec<String>,
+    ) -> (SubscriptionId, Vec<FeedReceiver>) {
+        let owner = Arc::new(OverflowStats::default());
+        let policy = self.overflow;
+        let mut receivers = Vec::new();
+        let id = self.add_subscribers(&owner, policy, None, tickers, || {
+            let (sink, receiver) = FeedSink::local(self.channel_capacity, policy);
+            receivers.push(FeedReceiver::new(receiver, owner.clone()));
+            sink
+        });
+        (id, receivers)
+    }
+
+    // Подписка клиента: события всех тикеров идут в одну очередь для его
+    // UDP задачи, потери из нее учитываются в stats.
+    // Политика переполнения из options заменяет серверную для этой подписки,
+    // с options.bars клиент получает закрытые бары вместо сделок и котировок.
+    pub fn subscribe_client(
+        &self,
+        client_id: &str,
+        tickers: Vec<String>,
+        stats: &Arc<OverflowStats>,
+        options: &StreamOptions,
+    ) -> (SubscriptionId, ClientFeed) {
+        let policy = options.overflow.unwrap_or(self.overflow);
+        let (sink, feed) = FeedSink::client(self.channel_capacity, tickers.len(), policy);
+        let id = self.add_subscribers(stats, policy, options.bars, tickers, || sink.clone());
+        debug!(%client_id, subscription = %id, "Client subscribed");
+        (id, feed)
+    }
+
+    // Подписчики тикеров; sink вызывается для каждого найденного тикера
+    fn add_subscribers(
+        &self,
+        stats: &Arc<OverflowStats>,
+        policy: OverflowPolicy,
+        bars: Option<BarInterval>,
+        tickers: Vec<String>,
+        mut sink: impl FnMut() -> FeedSink,
+    ) -> SubscriptionId {
+        let id = SubscriptionId::next();
+        let mut subscribed = Vec::new();
+        let session_status = self.session.as_ref().map(|s| s.current_status());
 
         for ticker in tickers {
             let ticker_upper = ticker.to_uppercase();
 
-            if let Some(sender_list) = ticker_senders.get(&ticker_upper) {
-                debug!(
-                    "Cleaning up subscriptions for ticker: {} ({} senders)",
-                    ticker_upper,
-                    sender_list.len()
+            let Some(entry) = self.entry(&ticker_upper) else {
+                warn!(
+                    ticker = %ticker_upper,
+                    "Client tried to subscribe to non-existent ticker"
                 );
+                continue;
+            };
+            let mut subscriber = Subscriber::new(id, stats, sink(), policy, bars);
+            // Подписчик баров сразу получает последний закрытый бар
+            let last_bar = match bars {
+                Some(interval) => self.recent_bars(&ticker_upper, interval, 1).pop(),
+                None => None,
+            };
+            // Состояние держится до добавления подписчика, чтобы между снимком
+            // стакана и первым обновлением не вклинилось другое
+            let state = entry.state.lock().unwrap();
+            // Сразу сообщаем текущую фазу сессии или остановку торгов
+            let halted = state.breaker.as_ref().is_some_and(|b| b.is_halted());
+            let status = if halted {
+                Some(MarketStatus::Halted)
+            } else {
+                session_status
+            };
+            if let Some(status) = status {
+                let event = StatusEvent::new(ticker_upper.clone(), status);
+                let status = FeedMessage::new(QuoteEvent::Status(event));
+                subscriber.deliver(&ticker_upper, status);
+            }
+            // Последние известные цены: клиент не ждет первой котировки до интервала
+            let last = [
+                state.last_trade.clone().map(QuoteEvent::Trade),
+                state.last_bid_ask.clone().map(QuoteEvent::Quote),
+                last_bar.map(QuoteEvent::Bar),
+            ];
+            for event in last.into_iter().flatten() {
+                if subscriber.wants(&event) {
+                    subscriber.deliver(&ticker_upper, FeedMessage::new(event));
+                }
+            }
+            // Снимок стакана, от которого клиент применяет инкрементальные обновления
+            if let Some(book) = &state.order_book
+                && bars.is_none()
+            {
+                let snapshot = FeedMessage::new(QuoteEvent::Depth(book.snapshot(&ticker_upper)));
+                subscriber.deliver(&ticker_upper, snapshot);
             }
+            let mut senders = entry.subscribers.lock().unwrap();
+            senders.push(subscriber);
+            entry
+                .subscriber_count
+                .store(senders.len(), Ordering::Relaxed);
+            debug!(ticker = %ticker_upper, subscription = %id, "Client subscribed to ticker");
+            subscribed.push(ticker_upper);
         }
+
+        self.subscriptions.lock().unwrap().insert(id, subscribed);
+        id
     }
 
-    // Запуск генерации котировок в отдельном потоке
-    pub fn start(self, interval_ms: u64) {
-        info!("Starting quote generator with interval {}ms", interval_ms);
+    // Снятие подписки: ее подписчики сразу удаляются из тикеров, каналы
+    // закрываются, и генератор перестает отправлять в них события.
+    // false - подписка уже снята или неизвестна.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let Some(tickers) = self.subscriptions.lock().unwrap().remove(&id) else {
+            return false;
+        };
+        for ticker in &tickers {
+            // Тикер мог быть удален вместе со своими подписчиками
+            let Some(entry) = self.entry(ticker) else {
+                continue;
+            };
+            let mut senders = entry.subscribers.lock().unwrap();
+            senders.retain(|sender| sender.subscription != id);
+            entry
+                .subscriber_count
+                .store(senders.len(), Ordering::Relaxed);
+            debug!(%ticker, subscription = %id, left = senders.len(), "Unsubscribed");
+        }
+        true
+    }
 
-        thread::spawn(move || {
-            let tickers: Vec<String> = {
-                let prices = self.ticker_prices.lock().unwrap();
-                prices.keys().cloned().collect()
+    // Генератор, воспроизводящий исторические котировки (CSV, JSONL или каталог записи)
+    pub fn from_replay(filename: &str, speed: f64) -> Result<Self, QuoteError> {
+        if speed <= 0.0 {
+            return Err(QuoteError::Config(format!(
+                "Replay speed must be positive, got {}",
+                speed
+            )));
+        }
+        let source = ReplaySource::from_file(filename, speed).map_err(|e| {
+            QuoteError::Generator(format!("cannot load replay from {}: {}", filename, e))
+        })?;
+        let specs = source
+            .tickers()
+            .into_iter()
+            .map(|ticker| {
+                let spec = TickerSpec {
+                    initial_price: source.first_price(&ticker),
+                    ..TickerSpec::default()
+                };
+                (ticker, spec)
+            })
+            .collect();
+
+        let mut generator = Self::with_specs(specs, 0.0, None);
+        generator.replay = Some(Arc::new(source));
+        Ok(generator)
+    }
+
+    // Запуск генерации котировок в отдельном потоке (или в потоках with_workers)
+    // Возвращает хэндл для паузы, продолжения и остановки генерации
+    pub fn start(self, interval_ms: u64) -> GeneratorHandle {
+        let control = Arc::new(GeneratorControl::default());
+        let thread_control = control.clone();
+
+        let gc = self.spawn_subscription_gc(&control);
+
+        if let Some(replay) = self.replay.clone() {
+            let thread = thread::spawn(move || self.run_replay(&replay, &thread_control));
+            let mut threads = vec![thread];
+            threads.extend(gc);
+            return GeneratorHandle::with_threads(control, threads);
+        }
+
+        info!(
+            "Starting quote generator with interval {}ms, {} worker(s)",
+            interval_ms, self.workers
+        );
+        self.defaults.lock().unwrap().interval_ms = interval_ms;
+
+        let sync = Arc::new(WorkerSync {
+            universe_version: AtomicU64::new(0),
+            session_status: Mutex::new(self.session.as_ref().map(|s| s.current_status())),
+        });
+        let threads = (0..self.workers)
+            .map(|worker| {
+                let generator = self.clone();
+                let control = control.clone();
+                let sync = sync.clone();
+                thread::spawn(move || generator.run_worker(worker, interval_ms, &control, &sync))
+            })
+            .chain(gc)
+            .collect();
+        GeneratorHandle::with_threads(control, threads)
+    }
+
+    // Поток сборки закрытых подписок; останавливается вместе с генератором
+    fn spawn_subscription_gc(&self, control: &Arc<GeneratorControl>) -> Option<JoinHandle<()>> {
+        if self.subscription_gc.is_zero() {
+            return None;
+        }
+        let generator = self.clone();
+        let control = control.clone();
+        Some(thread::spawn(move || {
+            // На паузе сон прерывается сразу, поэтому сначала ждем продолжения
+            while control.wait_while_paused().is_some() && control.sleep(generator.subscription_gc)
+            {
+                generator.collect_subscriptions();
+            }
+        }))
+    }
+
+    // Сборка мусора подписок: подписчики с закрытыми каналами удаляются из
+    // тикеров, не дожидаясь неудачной отправки (у тихого тикера ее может не
+    // быть долго), а подписки без живых подписчиков - из списка подписок.
+    // Возвращает число удаленных подписчиков и подписок.
+    fn collect_subscriptions(&self) -> (usize, usize) {
+        // Список подписок блокируется на весь обход: подписка регистрируется
+        // после добавления своих подписчиков и не пропадет посередине
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let entries: Vec<_> = self.entries.read().unwrap().values().cloned().collect();
+        let mut live = HashSet::new();
+        let mut collected = 0;
+        for entry in entries {
+            let mut senders = entry.subscribers.lock().unwrap();
+            let before = senders.len();
+            senders.retain(|sender| !sender.is_closed());
+            collected += before - senders.len();
+            entry
+                .subscriber_count
+                .store(senders.len(), Ordering::Relaxed);
+            live.extend(senders.iter().map(|sender| sender.subscription));
+        }
+        let before = subscriptions.len();
+        subscriptions.retain(|id, _| live.contains(id));
+        let released = before - subscriptions.len();
+        drop(subscriptions);
+
+        if collected > 0 || released > 0 {
+            debug!(collected, released, "Collected closed subscriptions");
+        }
+        self.metrics.record_collected(collected, released);
+        (collected, released)
+    }
+
+    // Цикл потока генерации worker над его частью тикеров. Поток 0 кроме того
+    // применяет изменения списка тикеров, следит за фазой сессии, закрывает бары
+    // и пишет статистику.
+    fn run_worker(
+        &self,
+        worker: usize,
+        interval_ms: u64,
+        control: &GeneratorControl,
+        sync: &WorkerSync,
+    ) {
+        let coordinator = worker == 0;
+        let mut version = sync.universe_version.load(Ordering::SeqCst);
+        let mut tickers = self.tickers();
+
+        // Время следующей котировки каждого тикера; у тикера может быть свой интервал
+        let mut default_interval = Duration::from_millis(interval_ms);
+        let started = clock::now_millis();
+        let mut slots = self.worker_slots(worker, default_interval, Vec::new(), started);
+        // Для пуассоновского потока первые котировки тоже разнесены во времени
+        for slot in &mut slots {
+            slot.next_fire = started + self.next_gap(slot, slot.interval).as_millis() as u64;
+        }
+
+        let mut iteration = 0;
+        // Циклы, в которых котировки опоздали больше чем на общий интервал
+        let mut late_cycles: u64 = 0;
+        let mut session_status = *sync.session_status.lock().unwrap();
+        info!(
+            worker,
+            "Quote generator thread started for {} tickers",
+            slots.len()
+        );
+        if coordinator && let Some(status) = session_status {
+            info!("Market session status at start: {}", status);
+        }
+        self.metrics
+            .update_worker(worker, |gauges| gauges.tickers = slots.len());
+
+        loop {
+            if control.wait_while_paused().is_none() {
+                break;
+            }
+            iteration += 1;
+            trace!(worker, "Generation iteration {} started", iteration);
+
+            // Изменения списка тикеров применяются между циклами генерации: их
+            // применяет поток 0, остальные по новой версии перестраивают свою часть
+            if coordinator && self.apply_pending_changes() {
+                sync.universe_version.fetch_add(1, Ordering::SeqCst);
+            }
+            let current = sync.universe_version.load(Ordering::SeqCst);
+            if current != version {
+                version = current;
+                tickers = self.tickers();
+                default_interval = Duration::from_millis(self.defaults().interval_ms);
+                slots = self.worker_slots(worker, default_interval, slots, clock::now_millis());
+                self.metrics
+                    .update_worker(worker, |gauges| gauges.tickers = slots.len());
+            }
+
+            // Смену фазы торговой сессии замечает и рассылает поток 0; гэп
+            // открытия каждый поток применяет к своим тикерам своими RNG
+            if !coordinator {
+                let status = *sync.session_status.lock().unwrap();
+                if status != session_status
+                    && status == Some(MarketStatus::Open)
+                    && let Some(schedule) = &self.session
+                {
+                    self.apply_opening_gap(&mut slots, schedule.opening_gap);
+                }
+                session_status = status;
+            } else if let Some(schedule) = &self.session {
+                let status = schedule.current_status();
+                if session_status != Some(status) {
+                    info!(
+                        "Market session changed: {} -> {}",
+                        session_status.map_or("-".to_string(), |s| s.to_string()),
+                        status
+                    );
+                    if status == MarketStatus::Open {
+                        self.apply_opening_gap(&mut slots, schedule.opening_gap);
+                    }
+                    for ticker in &tickers {
+                        let event = StatusEvent::new(ticker.clone(), status);
+                        self.broadcast(ticker, QuoteEvent::Status(event));
+                    }
+                    session_status = Some(status);
+                    *sync.session_status.lock().unwrap() = session_status;
+                }
+            }
+
+            // Вне основной сессии котировок нет, на премаркете - редкие и "широкие"
+            let (generate, volatility_factor, volume_factor, slowdown) = match session_status {
+                Some(MarketStatus::Closed) => (false, 1.0, 1.0, 1),
+                Some(MarketStatus::PreMarket) => (true, 2.0, 0.1, PRE_MARKET_SLOWDOWN),
+                _ => (true, 1.0, 1.0, 1),
             };
+            let now = clock::now_millis();
 
-            let mut iteration = 0;
-            info!(
-                "Quote generator thread started for {} tickers",
-                tickers.len()
-            );
+            if generate {
+                // Положение внутри торгового дня для профиля объема
+                let day_fraction = match &self.session {
+                    Some(schedule) => schedule.current_day_fraction(),
+                    None => SessionSchedule::regular_hours().current_day_fraction(),
+                };
 
-            loop {
-                iteration += 1;
-                trace!("Generation iteration {} started", iteration);
-
-                // Генерируем котировки для ВСЕХ тикеров
-                for ticker in &tickers {
-                    let (price, volume) = {
-                        let mut prices = self.ticker_prices.lock().unwrap();
-                        let base_volumes = self.base_volumes.lock().unwrap();
-
-                        let last_price = prices.get_mut(ticker).unwrap();
-                        let mut rng = rand::thread_rng();
-                        let change = rng.gen_range(-self.volatility..self.volatility);
-                        *last_price *= 1.0 + change;
-
-                        if *last_price < 1.0 {
-                            *last_price = 1.0;
-                        }
+                let idle_slowdown = self.defaults().idle_slowdown;
+                // Наибольшее опоздание котировки этого цикла от ее расписания
+                let mut skew = 0;
+                for slot in &mut slots {
+                    let due = slot.next_fire;
+                    if due > now {
+                        continue;
+                    }
+                    skew = skew.max(now - due);
+                    let ticker = slot.ticker.as_str();
+                    let _span = trace_span!("ticker", %ticker).entered();
+                    let gap = self.next_gap(slot, slot.interval * slowdown);
+                    slot.next_fire = now + gap.as_millis() as u64;
 
-                        let base_volume = base_volumes.get(ticker).copied().unwrap_or(1000);
-                        let std_dev = (base_volume as f64 * 0.3) as u32;
-                        let normal_sample = rng.gen_range(-2.0..2.0);
-                        let volume_f64 = base_volume as f64 + normal_sample * std_dev as f64;
+                    // Тикеры без подписчиков идут в фоновом режиме: редкие котировки
+                    // только для движения цены и баров, без bid/ask, стакана и рассылки
+                    // Составляющие индекса с подписчиками тоже генерируются в полном темпе
+                    let background = !slot.entry.has_subscribers()
+                        && !slot.indices.iter().any(|index| index.has_subscribers());
+                    if background {
+                        slot.idle_skips += 1;
+                        if idle_slowdown == 0 || slot.idle_skips < idle_slowdown {
+                            continue;
+                        }
+                        slot.idle_skips = 0;
+                    }
 
-                        let volume = if rng.gen_bool(0.05) {
-                            (volume_f64.max(100.0) as u32) * 3
-                        } else {
-                            volume_f64.max(100.0) as u32
-                        };
+                    // Пока торги остановлены коридором, котировок по тикеру нет
+                    if self.poll_halt(slot, session_status) {
+                        continue;
+                    }
 
-                        (*last_price, volume)
-                    };
+                    let volatility_factor = volatility_factor * self.shock_factor(slot);
+                    let quote =
+                        self.next_quote(slot, volatility_factor, volume_factor, day_fraction);
+                    let history_size = self.history_size;
+                    slot.entry
+                        .state
+                        .lock()
+                        .unwrap()
+                        .record_trade(&quote, history_size);
+                    self.metrics.record_trade();
+                    let (price, volume) = (quote.price, quote.volume);
+                    let breached = self.check_price_band(slot, price, quote.timestamp);
+                    let closed = self.bars.on_trade(&quote);
+                    self.broadcast_bars(closed);
+                    if !slot.indices.is_empty() {
+                        self.update_indices(&slot.ticker);
+                    }
+                    if background {
+                        trace!(price, "Background quote");
+                        continue;
+                    }
 
-                    let quote = StockQuote::new(ticker.clone(), price, volume);
+                    let bid_ask = self.next_bid_ask(slot, price, volatility_factor);
+                    let depth = self.update_order_book(slot, &bid_ask);
 
                     // Отправляем котировку только подписанным клиентам для этого тикера
-                    {
-                        let mut ticker_senders = self.ticker_senders.lock().unwrap();
-
-                        if let Some(senders) = ticker_senders.get_mut(ticker) {
-                            // Удаляем отключившихся клиентов
-                            senders.retain(|sender| {
-                                if sender.send(quote.clone()).is_err() {
-                                    trace!("Removing disconnected sender for ticker {}", ticker);
-                                    false
-                                } else {
-                                    true
-                                }
-                            });
-
-                            trace!(
-                                "Generated quote for {}: price={:.2}, volume={} (sent to {} clients)",
-                                ticker,
-                                price,
-                                volume,
-                                senders.len()
-                            );
+                    let (entry, ticker) = (&slot.entry, slot.ticker.as_str());
+                    let receivers = self.broadcast_to(entry, ticker, QuoteEvent::Trade(quote));
+                    self.broadcast_to(entry, ticker, QuoteEvent::Quote(bid_ask));
+                    if let Some(depth) = depth {
+                        self.broadcast_to(entry, ticker, QuoteEvent::Depth(depth));
+                    }
+                    if breached {
+                        let event = StatusEvent::new(ticker.to_string(), MarketStatus::Halted);
+                        self.broadcast_to(entry, ticker, QuoteEvent::Status(event));
+                    }
+                    trace!(price, volume, receivers, "Generated quote");
+                }
+
+                // Поток не успевает обойти свои тикеры за интервал: их стоит
+                // разделить между большим числом потоков (--generator-workers)
+                self.metrics
+                    .update_worker(worker, |gauges| gauges.skew_ms = skew);
+                if skew > default_interval.as_millis() as u64 {
+                    late_cycles += 1;
+                    self.metrics.record_late_cycle();
+                    if late_cycles == 1 || late_cycles.is_multiple_of(100) {
+                        warn!(
+                            worker,
+                            skew_ms = skew,
+                            tickers = slots.len(),
+                            late_cycles,
+                            "Quote generation is behind the configured interval"
+                        );
+                    }
+                }
+            }
+
+            if coordinator {
+                // Закрываем бары с истекшим интервалом
+                let closed = self.bars.close_expired(current_millis());
+                self.broadcast_bars(closed);
+            }
+
+            if coordinator && iteration % 100 == 0 {
+                // Статистика по подпискам
+                let entries = self.entries.read().unwrap();
+                let mut total_clients = 0;
+                let mut active_tickers = 0;
+
+                for entry in entries.values() {
+                    let subscribers = entry.subscriber_count.load(Ordering::Relaxed);
+                    if subscribers > 0 {
+                        active_tickers += 1;
+                        total_clients += subscribers;
+                    }
+                }
+
+                info!(
+                    "Completed {} cycles, {} active tickers, total active clients: {}",
+                    iteration, active_tickers, total_clients
+                );
+            }
+
+            // Спим до ближайшей котировки, но не дольше общего интервала,
+            // чтобы вовремя замечать смену фазы сессии и закрывать бары
+            let now = clock::now_millis();
+            let latest = now + default_interval.as_millis() as u64;
+            let wake = slots
+                .iter()
+                .map(|slot| slot.next_fire)
+                .min()
+                .map_or(latest, |t| t.min(latest));
+            if !control.sleep(Duration::from_millis(wake.saturating_sub(now))) {
+                break;
+            }
+        }
+
+        info!(
+            worker,
+            "Quote generator thread stopped after {} cycles", iteration
+        );
+    }
+
+    // Воспроизведение исторических котировок с исходными интервалами между ними
+    fn run_replay(&self, replay: &ReplaySource, control: &GeneratorControl) {
+        info!(
+            "Starting replay of {} quotes at x{} speed",
+            replay.quotes.len(),
+            replay.speed
+        );
+
+        let mut started = clock::now_millis();
+        let first_timestamp = replay.quotes.first().map_or(0, |q| q.timestamp);
+
+        for (i, quote) in replay.quotes.iter().enumerate() {
+            let offset_ms =
+                (quote.timestamp.saturating_sub(first_timestamp) as f64 / replay.speed) as u64;
+
+            // Время на паузе сдвигает шкалу воспроизведения
+            loop {
+                match control.wait_while_paused() {
+                    Some(paused) => started += paused.as_millis() as u64,
+                    None => {
+                        info!("Replay stopped after {} quotes", i);
+                        return;
+                    }
+                }
+                let due = started + offset_ms;
+                let now = clock::now_millis();
+                if due <= now {
+                    break;
+                }
+                if !control.sleep(Duration::from_millis(due - now)) {
+                    info!("Replay stopped after {} quotes", i);
+                    return;
+                }
+            }
+
+            self.with_state(&quote.ticker, |state| {
+                state.price = quote.price;
+                state.record_trade(quote, self.history_size);
+            });
+
+            // Бары закрываются по времени воспроизведения, а не по часам сервера
+            let mut closed = self.bars.on_trade(quote);
+            closed.extend(self.bars.close_expired(quote.timestamp));
+            self.broadcast_bars(closed);
+
+            self.metrics.record_trade();
+            let receivers = self.broadcast(&quote.ticker, QuoteEvent::Trade(quote.clone()));
+            trace!(
+                ticker = %quote.ticker,
+                seq = i + 1,
+                price = quote.price,
+                receivers,
+                "Replayed quote"
+            );
+        }
+
+        info!(
+            "Replay finished: {} quotes in {:.1}s",
+            replay.quotes.len(),
+            clock::now_millis().saturating_sub(started) as f64 / 1000.0
+        );
+    }
+
+    // Тикеры потока worker после изменения списка. Оставшиеся сохраняют
+    // расписание и RNG из previous, новые забирают RNG своего тикера и
+    // генерируются сразу (в момент now).
+    fn worker_slots(
+        &self,
+        worker: usize,
+        default_interval: Duration,
+        previous: Vec<TickerSlot>,
+        now: u64,
+    ) -> Vec<TickerSlot> {
+        let mut previous: HashMap<String, TickerSlot> = previous
+            .into_iter()
+            .map(|slot| (slot.ticker.clone(), slot))
+            .collect();
+        self.worker_tickers(worker)
+            .into_iter()
+            .filter_map(|ticker| {
+                let entry = self.entry(&ticker)?;
+                let interval = entry
+                    .state
+                    .lock()
+                    .unwrap()
+                    .params
+                    .interval_ms
+                    .map_or(default_interval, Duration::from_millis);
+                let indices = self.index_entries(&ticker);
+                let slot = match previous.remove(&ticker) {
+                    // Тикер мог быть удален и добавлен заново с новым состоянием
+                    Some(slot) if Arc::ptr_eq(&slot.entry, &entry) => TickerSlot {
+                        interval,
+                        indices,
+                        ..slot
+                    },
+                    _ => {
+                        let rng = entry.rng.lock().unwrap().take();
+                        let rng = rng.unwrap_or_else(|| ticker_rng(&ticker, self.seed));
+                        let aux_rng =
+                            ticker_rng(&ticker, self.seed.map(|seed| seed ^ AUX_RNG_SALT));
+                        TickerSlot {
+                            ticker,
+                            entry,
+                            interval,
+                            next_fire: now,
+                            idle_skips: 0,
+                            indices,
+                            sampler: TickerSampler::new(rng, aux_rng),
                         }
                     }
+                };
+                Some(slot)
+            })
+            .collect()
+    }
+
+    // Новый список тикеров без перезапуска сервера. Применяется генератором
+    // в начале следующего цикла: новые тикеры добавляются, параметры оставшихся
+    // обновляются, подписчики удаленных тикеров получают статус delisted.
+    pub fn request_reload(&self, specs: Vec<(String, TickerSpec)>) {
+        self.pending_changes
+            .lock()
+            .unwrap()
+            .push(UniverseChange::Reload(specs));
+    }
+
+    // Добавление тикера во время работы. Возвращает false, если тикер уже есть.
+    pub fn add_ticker(&self, ticker: &str, spec: TickerSpec) -> bool {
+        let ticker_upper = ticker.to_uppercase();
+        if self.has_ticker(&ticker_upper) {
+            return false;
+        }
+
+        info!(ticker = %ticker_upper, "Ticker listed");
+        self.insert_ticker(&ticker_upper, &spec);
+        self.pending_changes
+            .lock()
+            .unwrap()
+            .push(UniverseChange::Refresh);
+        true
+    }
+
+    // Удаление тикера во время работы; выполняется генератором в начале следующего цикла.
+    // Возвращает false для неизвестного тикера.
+    pub fn remove_ticker(&self, ticker: &str) -> bool {
+        let ticker_upper = ticker.to_uppercase();
+        if !self.has_ticker(&ticker_upper) {
+            return false;
+        }
+
+        self.pending_changes
+            .lock()
+            .unwrap()
+            .push(UniverseChange::Remove(ticker_upper));
+        true
+    }
+
+    // Параметры тикера или всех тикеров (ticker = None) в алфавитном порядке
+    pub fn params_snapshot(&self, ticker: Option<&str>) -> Vec<ParamsSnapshot> {
+        let tickers = match ticker {
+            Some(ticker) => vec![ticker.to_uppercase()],
+            None => self.tickers(),
+        };
+        tickers
+            .into_iter()
+            .filter_map(|ticker| {
+                let entry = self.entry(&ticker)?;
+                let state = entry.state.lock().unwrap();
+                let params = &state.params;
+                Some(ParamsSnapshot {
+                    asset_class: params.asset_class,
+                    price: state.price,
+                    volatility: params.volatility,
+                    drift: params.drift,
+                    spread_bps: params.spread_bps,
+                    tick_size: params.tick_size,
+                    base_volume: state.base_volume,
+                    interval_ms: params.interval_ms,
+                    subscribers: entry.subscriber_count.load(Ordering::Relaxed),
+                    halted: state.breaker.as_ref().is_some_and(|b| b.is_halted()),
+                    ticker,
+                })
+            })
+            .collect()
+    }
+
+    // Изменение параметров модели тикера во время работы.
+    // Возвращает false для неизвестного тикера.
+    pub fn update_params(&self, ticker: &str, updates: &[ParamUpdate]) -> bool {
+        let ticker_upper = ticker.to_uppercase();
+        let updated = self.with_state(&ticker_upper, |state| {
+            let mut refresh = false;
+            for update in updates {
+                match *update {
+                    ParamUpdate::Volatility(v) => state.params.volatility = v,
+                    ParamUpdate::Drift(v) => state.params.drift = v,
+                    ParamUpdate::SpreadBps(v) => state.params.spread_bps = v,
+                    ParamUpdate::BaseVolume(v) => state.base_volume = v,
+                    ParamUpdate::IntervalMs(v) => {
+                        state.params.interval_ms = Some(v);
+                        refresh = true;
+                    }
+                }
+            }
+            refresh
+        });
+        let Some(refresh) = updated else {
+            return false;
+        };
+        info!(ticker = %ticker_upper, ?updates, "Updated parameters");
+
+        if refresh {
+            self.pending_changes
+                .lock()
+                .unwrap()
+                .push(UniverseChange::Refresh);
+        }
+        true
+    }
+
+    fn apply_pending_changes(&self) -> bool {
+        let changes: Vec<UniverseChange> = self.pending_changes.lock().unwrap().drain(..).collect();
+        if changes.is_empty() {
+            return false;
+        }
+
+        for change in changes {
+            match change {
+                UniverseChange::Reload(specs) => self.apply_reload(specs),
+                UniverseChange::Refresh => {}
+                UniverseChange::Remove(ticker) => {
+                    if self.has_ticker(&ticker) {
+                        self.delist_ticker(&ticker);
+                    }
                 }
+            }
+        }
+        true
+    }
+
+    fn apply_reload(&self, specs: TickerSpecs) {
+        let specs: HashMap<String, TickerSpec> = specs
+            .into_iter()
+            .map(|(ticker, spec)| (ticker.to_uppercase(), spec))
+            .collect();
+        let current = self.tickers();
+
+        for ticker in &current {
+            if !specs.contains_key(ticker) {
+                self.delist_ticker(ticker);
+            }
+        }
 
-                if iteration % 100 == 0 {
-                    // Статистика по подпискам
-                    let ticker_senders = self.ticker_senders.lock().unwrap();
-                    let mut total_clients = 0;
-                    let mut active_tickers = 0;
+        // Индексы - после обычных тикеров, их значение считается по составляющим
+        let mut ordered: Vec<(&String, &TickerSpec)> = specs.iter().collect();
+        ordered.sort_by_key(|(_, spec)| spec.index.is_some());
 
-                    for (_ticker, senders) in ticker_senders.iter() {
-                        if !senders.is_empty() {
-                            active_tickers += 1;
-                            total_clients += senders.len();
+        let mut added = 0;
+        for (ticker, spec) in ordered {
+            if current.contains(ticker) {
+                match &spec.index {
+                    Some(index) => {
+                        self.indices
+                            .write()
+                            .unwrap()
+                            .insert(ticker.clone(), index.clone());
+                    }
+                    None => {
+                        self.indices.write().unwrap().remove(ticker);
+                    }
+                }
+                let params = self.ticker_params_from(spec);
+                self.with_state(ticker, |state| {
+                    state.params = params;
+                    state.base_volume = base_volume_for(ticker, spec);
+                    match spec.price_band {
+                        Some(band) => {
+                            state
+                                .breaker
+                                .get_or_insert_with(|| CircuitBreaker::new(band));
                         }
+                        None => state.breaker = None,
                     }
+                });
+            } else {
+                info!(%ticker, "Ticker listed");
+                self.insert_ticker(ticker, spec);
+                added += 1;
+            }
+        }
 
-                    info!(
-                        "Completed {} cycles, {} active tickers, total active clients: {}",
-                        iteration, active_tickers, total_clients
-                    );
+        info!(
+            "Ticker universe reloaded: {} tickers ({} added, {} removed)",
+            specs.len(),
+            added,
+            current.iter().filter(|t| !specs.contains_key(*t)).count()
+        );
+    }
+
+    // Удаление тикера: подписчики получают статус delisted, после чего их каналы закрываются
+    fn delist_ticker(&self, ticker: &str) {
+        info!(%ticker, "Ticker delisted");
+        let event = StatusEvent::new(ticker.to_string(), MarketStatus::Delisted);
+        self.broadcast(ticker, QuoteEvent::Status(event));
+
+        // Тикер удаляется из карты целиком: каналы подписчиков закрываются,
+        // когда отпущена последняя ссылка на него
+        self.entries.write().unwrap().remove(ticker);
+        self.indices.write().unwrap().remove(ticker);
+    }
+
+    // Отслеживание изменений файла тикеров: при изменении времени модификации
+    // файл перечитывается и список тикеров перезагружается
+    pub fn watch_ticker_file(&self, filename: &str, poll_interval: Duration) {
+        let generator = self.clone();
+        let filename = filename.to_string();
+        info!("Watching ticker file {} for changes", filename);
+
+        thread::spawn(move || {
+            let modified = |name: &str| std::fs::metadata(name).and_then(|m| m.modified()).ok();
+            let mut last_modified = modified(&filename);
+
+            loop {
+                thread::sleep(poll_interval);
+                let current = modified(&filename);
+                if current.is_none() || current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+
+                match ticker_config::load_ticker_specs(&filename) {
+                    Ok(specs) => {
+                        info!(
+                            "Ticker file {} changed, reloading {} tickers",
+                            filename,
+                            specs.len()
+                        );
+                        generator.request_reload(specs);
+                    }
+                    Err(e) => warn!("Failed to reload ticker file {}: {}", filename, e),
+                }
+            }
+        });
+    }
+
+    // Интервал до следующей котировки тикера со средним mean
+    fn next_gap(&self, slot: &mut TickerSlot, mean: Duration) -> Duration {
+        match self.arrival {
+            ArrivalProcess::Fixed => mean,
+            ArrivalProcess::Poisson => mean.mul_f64(slot.sampler.arrival_factor()),
+        }
+    }
+
+    // Генерация следующей котировки тикера
+    fn next_quote(
+        &self,
+        slot: &mut TickerSlot,
+        volatility_factor: f64,
+        volume_factor: f64,
+        day_fraction: Option<f64>,
+    ) -> StockQuote {
+        let TickerSlot {
+            ticker,
+            entry,
+            sampler,
+            ..
+        } = slot;
+        let (price, volume) = {
+            let mut state = entry.state.lock().unwrap();
+            let TickerState {
+                price: last_price,
+                base_volume,
+                params,
+                ..
+            } = &mut *state;
+            let change = sampler.price_change(params.volatility * volatility_factor);
+            // Валютная пара меняется на число пипсов, а не на долю цены
+            match params.asset_class {
+                AssetClass::Equity => *last_price *= 1.0 + params.drift + change,
+                AssetClass::Fx => {
+                    *last_price = *last_price * (1.0 + params.drift) + change * params.pip_size
                 }
+            }
+
+            if *last_price < params.min_price() {
+                *last_price = params.min_price();
+            }
+
+            if params.asset_class == AssetClass::Fx {
+                let price = round_price(*last_price, params.tick_size);
+                return StockQuote::new(ticker.clone(), price, 0).with_asset_class(AssetClass::Fx);
+            }
+
+            // Вне сессии профиль дает "полуденный" объем
+            let profile_factor = params
+                .volume_profile
+                .map_or(1.0, |profile| match day_fraction {
+                    Some(x) => profile.factor(x),
+                    None => profile.midday,
+                });
+
+            let base_volume = *base_volume;
+            let std_dev = (base_volume as f64 * 0.3) as u32;
+            let normal_sample = sampler.volume_noise.sample(&mut sampler.rng);
+            let volume_f64 = (base_volume as f64 + normal_sample * std_dev as f64)
+                * volume_factor
+                * profile_factor;
+
+            let volume = if sampler.volume_spike.sample(&mut sampler.rng) {
+                (volume_f64.max(100.0) as u32) * 3
+            } else {
+                volume_f64.max(100.0) as u32
+            };
+
+            // Внутренняя цена не округляется, иначе изменения меньше половины шага
+            // "залипали" бы; до шага округляется только публикуемая котировка
+            (round_price(*last_price, params.tick_size), volume)
+        };
+
+        StockQuote::new(ticker.clone(), price, volume)
+    }
+
+    // Лучшие цены bid/ask вокруг текущей цены. На премаркете спред шире.
+    fn next_bid_ask(&self, slot: &mut TickerSlot, mid: f64, spread_factor: f64) -> BidAskQuote {
+        let mut state = slot.entry.state.lock().unwrap();
+        let TickerState {
+            base_volume,
+            params,
+            ..
+        } = &*state;
+
+        let tick_size = params.tick_size;
+        let half_spread =
+            (mid * params.spread_bps / 10_000.0 * spread_factor / 2.0).max(tick_size / 2.0);
+        let bid_ticks = ((mid - half_spread) / tick_size).floor() as i64;
+        let ask_ticks = (((mid + half_spread) / tick_size).ceil() as i64).max(bid_ticks + 1);
+        let bid = round_to_tick(bid_ticks, tick_size);
+        let ask = round_to_tick(ask_ticks, tick_size);
+
+        // Размеры заявок кратны лоту в 100 акций и зависят от базового объема
+        let lots = (*base_volume / 1000).max(1);
+        let bid_size = slot.sampler.order_size(lots) * 100;
+        let ask_size = slot.sampler.order_size(lots) * 100;
+
+        let bid_ask = BidAskQuote::new(slot.ticker.clone(), bid, bid_size, ask, ask_size)
+            .with_asset_class(params.asset_class);
+        state.last_bid_ask = Some(bid_ask.clone());
+        bid_ask
+    }
+
+    // Обновление стакана вокруг новой котировки bid/ask
+    fn update_order_book(
+        &self,
+        slot: &mut TickerSlot,
+        bid_ask: &BidAskQuote,
+    ) -> Option<DepthUpdate> {
+        let mut state = slot.entry.state.lock().unwrap();
+        let book = state.order_book.as_mut()?;
+
+        Some(book.update(
+            &slot.ticker,
+            (bid_ask.bid, bid_ask.bid_size),
+            (bid_ask.ask, bid_ask.ask_size),
+            &mut slot.sampler.aux_rng,
+        ))
+    }
+
+    // Гэп цен своих тикеров потока при открытии сессии
+    fn apply_opening_gap(&self, slots: &mut [TickerSlot], max_gap: f64) {
+        if max_gap <= 0.0 {
+            return;
+        }
+
+        for slot in slots {
+            let gap = slot.sampler.rng.gen_range(-max_gap..max_gap);
+            let mut state = slot.entry.state.lock().unwrap();
+            state.price = (state.price * (1.0 + gap)).max(state.params.min_price());
+            debug!(ticker = %slot.ticker, gap_pct = gap * 100.0, "Opening gap");
+        }
+    }
+
+    // Рыночный шок: мгновенное изменение цены на percent процентов и повышенная
+    // волатильность на duration. Возвращает новую цену или None для неизвестного тикера.
+    pub fn apply_shock(&self, ticker: &str, percent: f64, duration: Duration) -> Option<f64> {
+        let ticker_upper = ticker.to_uppercase();
+        let until = current_millis() + duration.as_millis() as u64;
+        let new_price = self.with_state(&ticker_upper, |state| {
+            state.price = (state.price * (1.0 + percent / 100.0)).max(state.params.min_price());
+            state.shock_until = Some(until);
+            round_price(state.price, state.params.tick_size)
+        })?;
+
+        info!(
+            ticker = %ticker_upper,
+            percent,
+            new_price,
+            duration_secs = duration.as_secs(),
+            "Market shock"
+        );
+        Some(new_price)
+    }
+
+    // Пересчет индексов, в которые входит тикер, и рассылка их новых значений
+    fn update_indices(&self, ticker: &str) {
+        let indices: Vec<(String, IndexSpec)> = {
+            let indices = self.indices.read().unwrap();
+            if indices.is_empty() {
+                return;
+            }
+            indices
+                .iter()
+                .filter(|(_, index)| index.constituents.contains_key(ticker))
+                .map(|(name, index)| (name.clone(), index.clone()))
+                .collect()
+        };
+
+        for (name, index) in indices {
+            let Some(level) = self.index_level(&index) else {
+                continue;
+            };
+            let Some(tick_size) = self.with_state(&name, |state| {
+                state.price = level;
+                state.params.tick_size
+            }) else {
+                continue;
+            };
+            // У индекса нет собственного объема
+            let quote = StockQuote::new(name.clone(), round_price(level, tick_size), 0);
+            self.with_state(&name, |state| state.record_trade(&quote, self.history_size));
+            let closed = self.bars.on_trade(&quote);
+            self.broadcast_bars(closed);
+            self.broadcast(&name, QuoteEvent::Trade(quote));
+        }
+    }
+
+    // Множитель волатильности тикера с учетом активного шока
+    fn shock_factor(&self, slot: &TickerSlot) -> f64 {
+        let mut state = slot.entry.state.lock().unwrap();
+        match state.shock_until {
+            Some(until) if current_millis() < until => SHOCK_VOLATILITY_FACTOR,
+            Some(_) => {
+                state.shock_until = None;
+                debug!(ticker = %slot.ticker, "Market shock expired");
+                1.0
+            }
+            None => 1.0,
+        }
+    }
+
+    // Проверка остановки торгов тикера. По истечении остановки подписчики получают
+    // статус возобновления (текущую фазу сессии). Возвращает true, пока торги стоят.
+    fn poll_halt(&self, slot: &TickerSlot, session_status: Option<MarketStatus>) -> bool {
+        let resumed = {
+            let mut state = slot.entry.state.lock().unwrap();
+            state
+                .breaker
+                .as_mut()
+                .filter(|breaker| breaker.is_halted())
+                .map(|breaker| breaker.poll_resume(current_millis()))
+        };
+        match resumed {
+            None => return false,
+            Some(false) => return true,
+            Some(true) => {}
+        }
+
+        let ticker = slot.ticker.as_str();
+        info!(%ticker, "Trading resumed");
+        let status = session_status.unwrap_or(MarketStatus::Open);
+        let event = StatusEvent::new(ticker.to_string(), status);
+        self.broadcast_to(&slot.entry, ticker, QuoteEvent::Status(event));
+        false
+    }
+
+    // Учет цены в коридоре тикера. Возвращает true, если торги только что остановлены.
+    fn check_price_band(&self, slot: &TickerSlot, price: f64, timestamp: u64) -> bool {
+        let band = {
+            let mut state = slot.entry.state.lock().unwrap();
+            state.breaker.as_mut().map(|breaker| {
+                let reference = breaker.reference_price();
+                (breaker.on_price(price, timestamp), reference)
+            })
+        };
+        let Some((breached, reference)) = band else {
+            return false;
+        };
+        if breached {
+            warn!(
+                ticker = %slot.ticker,
+                price,
+                reference = reference.unwrap_or(price),
+                "Trading halted: price outside band"
+            );
+        }
+        breached
+    }
+
+    // Закрытые бары - подписчикам баров их тикеров (STREAM ... BARS)
+    fn broadcast_bars(&self, bars: Vec<Bar>) {
+        for bar in bars {
+            let ticker = bar.ticker.clone();
+            self.broadcast(&ticker, QuoteEvent::Bar(bar));
+        }
+    }
+
+    // Отправка события всем подписчикам тикера. Возвращает число получателей.
+    // Событие сериализуется один раз, подписчики получают общий буфер.
+    fn broadcast(&self, ticker: &str, event: QuoteEvent) -> usize {
+        match self.entry(ticker) {
+            Some(entry) => self.broadcast_to(&entry, ticker, event),
+            None => 0,
+        }
+    }
+
+    fn broadcast_to(&self, entry: &TickerEntry, ticker: &str, event: QuoteEvent) -> usize {
+        // Без подписчиков не сериализуем событие и не берем блокировку списка
+        if !entry.has_subscribers() {
+            return 0;
+        }
+        let mut senders = entry.subscribers.lock().unwrap();
 
-                thread::sleep(Duration::from_millis(interval_ms));
+        let message = FeedMessage::new(event);
+        let mut published = 0;
+        let mut dropped = 0;
+        // Подписки, отключенные политикой disconnect: их снимаем со всех тикеров
+        let mut overflowed = Vec::new();
+        // Удаляем отключившихся клиентов
+        senders.retain_mut(|sender| {
+            if !sender.wants(&message.event) {
+                return true;
             }
+            let before = sender.dropped;
+            let connected = sender.deliver(ticker, message.clone());
+            dropped += sender.dropped - before;
+            if !connected {
+                trace!(%ticker, "Removing disconnected sender");
+                // Отключен переполнением, а не закрытием канала
+                if sender.dropped > before {
+                    overflowed.push(sender.subscription);
+                }
+            } else {
+                published += 1;
+            }
+            connected
         });
+        entry
+            .subscriber_count
+            .store(senders.len(), Ordering::Relaxed);
+        drop(senders);
+        self.metrics.record_published(published);
+        if dropped > 0 {
+            self.metrics.record_dropped(dropped);
+        }
+        for id in overflowed {
+            self.metrics.record_overflow_disconnect();
+            self.unsubscribe(id);
+        }
+        published
+    }
+
+    // Последняя сделка тикера из кэша последних значений, без подписки на
+    // поток. None - тикера нет или по нему еще не было сделок.
+    pub fn last_quote(&self, ticker: &str) -> Option<StockQuote> {
+        self.with_state(&ticker.to_uppercase(), |state| state.last_trade.clone())
+            .flatten()
+    }
+
+    // Последние сделки всех тикеров, в алфавитном порядке
+    pub fn last_quotes(&self) -> Vec<StockQuote> {
+        self.tickers()
+            .iter()
+            .filter_map(|ticker| self.last_quote(ticker))
+            .collect()
+    }
+
+    // Сделки тикера из истории в памяти с from_ms по to_ms включительно, в
+    // порядке времени. История хранит только последние history_size сделок.
+    pub fn history(&self, ticker: &str, from_ms: u64, to_ms: u64) -> Vec<StockQuote> {
+        self.with_state(&ticker.to_uppercase(), |state| {
+            let start = state.history.partition_point(|q| q.timestamp < from_ms);
+            let end = state.history.partition_point(|q| q.timestamp <= to_ms);
+            state
+                .history
+                .range(start..end.max(start))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    // Последние закрытые бары тикера
+    pub fn recent_bars(&self, ticker: &str, interval: BarInterval, count: usize) -> Vec<Bar> {
+        self.bars.recent(&ticker.to_uppercase(), interval, count)
+    }
+
+    // Подписка на закрытые бары тикеров
+    pub fn subscribe_bars(&self, tickers: Vec<String>, interval: BarInterval) -> Receiver<Bar> {
+        let tickers = tickers.iter().map(|t| t.to_uppercase()).collect();
+        self.bars.subscribe(tickers, interval)
+    }
+
+    // Все тикеры генератора в алфавитном порядке
+    pub fn tickers(&self) -> Vec<String> {
+        let mut tickers: Vec<String> = self.entries.read().unwrap().keys().cloned().collect();
+        tickers.sort();
+        tickers
     }
 
     // Проверка существования тикера
     pub fn has_ticker(&self, ticker: &str) -> bool {
         let ticker_upper = ticker.to_uppercase();
-        let prices = self.ticker_prices.lock().unwrap();
-        prices.contains_key(&ticker_upper)
+        self.entries.read().unwrap().contains_key(&ticker_upper)
+    }
+
+    // Загрузка тикеров из файла: tickers.toml с параметрами или простой список
+    pub fn from_file(filename: &str, volatility: f64) -> Result<Self, QuoteError> {
+        Self::from_file_with_seed(filename, volatility, None)
     }
 
-    // Загрузка тикеров из файла
-    pub fn from_file(filename: &str, volatility: f64) -> std::io::Result<Self> {
+    pub fn from_file_with_seed(
+        filename: &str,
+        volatility: f64,
+        seed: Option<u64>,
+    ) -> Result<Self, QuoteError> {
         info!("Loading tickers from file: {}", filename);
-        let content = std::fs::read_to_string(filename)?;
-        let tickers: Vec<String> = content
-            .lines()
-            .map(|line| line.trim().to_uppercase())
-            .filter(|line| !line.is_empty())
-            .collect();
+        let specs = ticker_config::load_ticker_specs(filename).map_err(|e| {
+            QuoteError::Generator(format!("cannot load tickers from {}: {}", filename, e))
+        })?;
+
+        info!("Loaded {} tickers from {}", specs.len(), filename);
+        Ok(Self::with_specs(specs, volatility, seed))
+    }
+}
+
+// Базовый объем: из конфигурации или по "ликвидности" известных тикеров
+fn base_volume_for(ticker: &str, spec: &TickerSpec) -> u32 {
+    spec.base_volume.unwrap_or(match ticker {
+        "AAPL" | "MSFT" | "GOOGL" => 5000,
+        "TSLA" | "AMZN" | "NVDA" => 3000,
+        "META" | "JPM" | "JNJ" => 2000,
+        _ => 1000,
+    })
+}
+
+// Смешивается с --seed для второго потока случайных чисел тикера
+const AUX_RNG_SALT: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// RNG тикера: при заданном seed выводится из seed и имени тикера,
+// поэтому ряд тикера не зависит от состава и порядка остальных тикеров
+fn ticker_rng(ticker: &str, seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed ^ ticker_hash(ticker)),
+        None => StdRng::from_entropy(),
+    }
+}
 
-        info!("Loaded {} tickers from {}", tickers.len(), filename);
-        Ok(Self::new(tickers, volatility))
+// Хеш имени тикера (FNV-1a), одинаковый от запуска к запуску
+fn ticker_hash(ticker: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in ticker.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
     }
+    hash
 }
@@ -0,0 +1,100 @@
+use crate::clock;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::info;
+
+#[derive(Debug, Default)]
+struct ControlState {
+    paused: bool,
+    shutdown: bool,
+}
+
+// Общее состояние потока генерации и его управляющего хэндла.
+// Condvar будит поток сразу при паузе, продолжении или остановке.
+#[derive(Debug, Default)]
+pub(crate) struct GeneratorControl {
+    state: Mutex<ControlState>,
+    changed: Condvar,
+}
+
+impl GeneratorControl {
+    // Сон до duration по часам процесса с ранним пробуждением при изменении
+    // состояния. Возвращает false, если генератор остановлен.
+    pub(crate) fn sleep(&self, duration: Duration) -> bool {
+        let deadline = clock::now_millis() + duration.as_millis() as u64;
+        let mut state = self.state.lock().unwrap();
+        while !state.shutdown && !state.paused {
+            let now = clock::now_millis();
+            if now >= deadline {
+                break;
+            }
+            let timeout = clock::real_duration(Duration::from_millis(deadline - now));
+            state = self.changed.wait_timeout(state, timeout).unwrap().0;
+        }
+        !state.shutdown
+    }
+
+    // Ожидание снятия паузы. Возвращает время, проведенное на паузе,
+    // или None, если генератор остановлен.
+    pub(crate) fn wait_while_paused(&self) -> Option<Duration> {
+        let started = clock::now_millis();
+        let mut state = self.state.lock().unwrap();
+        while state.paused && !state.shutdown {
+            state = self.changed.wait(state).unwrap();
+        }
+        if state.shutdown {
+            None
+        } else {
+            Some(Duration::from_millis(
+                clock::now_millis().saturating_sub(started),
+            ))
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut ControlState)) {
+        change(&mut self.state.lock().unwrap());
+        self.changed.notify_all();
+    }
+}
+
+// Управление запущенным генератором: пауза, продолжение и остановка.
+// Удаление хэндла не останавливает генератор.
+pub struct GeneratorHandle {
+    control: Arc<GeneratorControl>,
+    // Потоки генерации; больше одного при with_workers
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl GeneratorHandle {
+    pub(crate) fn with_threads(
+        control: Arc<GeneratorControl>,
+        threads: Vec<JoinHandle<()>>,
+    ) -> Self {
+        GeneratorHandle { control, threads }
+    }
+
+    pub fn pause(&self) {
+        info!("Pausing quote generator");
+        self.control.update(|state| state.paused = true);
+    }
+
+    pub fn resume(&self) {
+        info!("Resuming quote generator");
+        self.control.update(|state| state.paused = false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.state.lock().unwrap().paused
+    }
+
+    // Остановка генератора с ожиданием завершения всех его потоков
+    pub fn shutdown(self) {
+        info!("Shutting down quote generator");
+        self.control.update(|state| state.shutdown = true);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+        info!("Quote generator stopped");
+    }
+}
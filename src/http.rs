@@ -0,0 +1,205 @@
+use crate::access_list::AccessList;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+// Минимальный HTTP/1.1 сервер для служебных страниц (/metrics, дашборд): по запросу
+// на соединение, без keep-alive и chunked
+// Пауза после ошибки приема (например, кончились дескрипторы)
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_millis(100);
+// На чтение всего запроса
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_HEADER_LINES: usize = 100;
+const MAX_BODY_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub peer: SocketAddr,
+    pub method: String,
+    // Путь без строки запроса
+    pub path: String,
+    pub query: String,
+    // Имена заголовков в нижнем регистре
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Response {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body.into())
+    }
+
+    pub fn json(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status, "application/json", body.into())
+    }
+
+    pub fn not_found() -> Self {
+        Self::text(404, "not found\n")
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            429 => "Too Many Requests",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            _ => "Error",
+        }
+    }
+
+    async fn write_to(&self, stream: &mut TcpStream) -> std::io::Result<()> {
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await
+    }
+}
+
+// Прием соединений до сигнала stop; каждый запрос обрабатывается в своей
+// задаче, а сам обработчик может блокироваться (блокировки, запись файлов)
+pub async fn serve<H>(
+    listener: TcpListener,
+    access_list: Arc<AccessList>,
+    mut stop: watch::Receiver<bool>,
+    handler: H,
+) where
+    H: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let accepted = tokio::select! {
+            _ = stop.wait_for(|stop| *stop) => break,
+            accepted = listener.accept() => accepted,
+        };
+        match accepted {
+            Ok((stream, peer)) => {
+                if !access_list.is_allowed(peer.ip().to_canonical()) {
+                    debug!(%peer, "HTTP connection denied by access list");
+                    continue;
+                }
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, peer, handler.as_ref()).await {
+                        debug!(%peer, error = %e, "HTTP request failed");
+                    }
+                });
+            }
+            Err(e) => {
+                warn!(error = %e, "Failed to accept HTTP connection");
+                tokio::time::sleep(ACCEPT_ERROR_PAUSE).await;
+            }
+        }
+    }
+}
+
+async fn handle<H>(mut stream: TcpStream, peer: SocketAddr, handler: &H) -> std::io::Result<()>
+where
+    H: Fn(&Request) -> Response,
+{
+    let request = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream, peer)).await;
+    let response = match request {
+        Ok(Ok(request)) => tokio::task::block_in_place(|| handler(&request)),
+        Ok(Err(response)) => response,
+        Err(_) => Response::text(400, "cannot read request\n"),
+    };
+    response.write_to(&mut stream).await
+}
+
+async fn read_request(stream: &mut TcpStream, peer: SocketAddr) -> Result<Request, Response> {
+    let bad_request = |detail: &str| Response::text(400, format!("{}\n", detail));
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .await
+        .map_err(|_| bad_request("cannot read request"))?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(bad_request("malformed request line"));
+    };
+    let method = method.to_uppercase();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+
+    let mut headers = Vec::new();
+    let mut content_length = 0;
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        reader
+            .read_line(&mut line)
+            .await
+            .map_err(|_| bad_request("cannot read headers"))?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim().to_lowercase(), value.trim().to_string());
+        if name == "content-length" {
+            content_length = value
+                .parse()
+                .map_err(|_| bad_request("invalid Content-Length"))?;
+        }
+        headers.push((name, value));
+    }
+    if content_length > MAX_BODY_LEN {
+        return Err(Response::text(413, "request body too large\n"));
+    }
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|_| bad_request("truncated body"))?;
+
+    Ok(Request {
+        peer,
+        method,
+        path,
+        query,
+        headers,
+        body,
+    })
+}
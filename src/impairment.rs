@@ -0,0 +1,138 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Дольше удержанная для перестановки датаграмма не ждет следующую
+const REORDER_MAX_HOLD: Duration = Duration::from_secs(1);
+
+// Датаграмма в линии задержки: время отправки, порядковый номер, данные
+type Scheduled = (Instant, u64, Arc<[u8]>);
+
+// Имитация плохой сети на исходящем UDP потоке всех клиентов
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkImpairment {
+    // Доля потерянных датаграмм, %
+    pub loss_pct: f64,
+    // Доля продублированных датаграмм, %
+    pub duplicate_pct: f64,
+    // Доля датаграмм, переставленных со следующей, %
+    pub reorder_pct: f64,
+    // Случайная задержка каждой датаграммы от 0 до jitter_ms
+    pub jitter_ms: u64,
+}
+
+impl NetworkImpairment {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("loss", self.loss_pct),
+            ("duplicate", self.duplicate_pct),
+            ("reorder", self.reorder_pct),
+        ] {
+            if !(0.0..=100.0).contains(&value) {
+                return Err(format!(
+                    "Network {} must be between 0 and 100%, got {}",
+                    name, value
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.loss_pct > 0.0
+            || self.duplicate_pct > 0.0
+            || self.reorder_pct > 0.0
+            || self.jitter_ms > 0
+    }
+}
+
+// Линия задержки одного клиента: датаграммы выходят из нее в момент due
+pub(crate) struct ImpairedLink {
+    config: NetworkImpairment,
+    rng: StdRng,
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    // Датаграмма, которая уйдет после следующей
+    held: Option<(Instant, Arc<[u8]>)>,
+    seq: u64,
+}
+
+impl ImpairedLink {
+    pub(crate) fn new(config: NetworkImpairment) -> Self {
+        ImpairedLink {
+            config,
+            rng: StdRng::from_entropy(),
+            queue: BinaryHeap::new(),
+            held: None,
+            seq: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, payload: Arc<[u8]>) {
+        if self.chance(self.config.loss_pct) {
+            return;
+        }
+        if self.chance(self.config.duplicate_pct) {
+            self.schedule(payload.clone());
+        }
+        if self.held.is_none() && self.chance(self.config.reorder_pct) {
+            self.held = Some((Instant::now(), payload));
+            return;
+        }
+        self.schedule(payload);
+        if let Some((_, held)) = self.held.take() {
+            self.schedule(held);
+        }
+    }
+
+    // Очередная датаграмма, время отправки которой наступило
+    pub(crate) fn pop_due(&mut self) -> Option<Arc<[u8]>> {
+        let now = Instant::now();
+        if let Some((since, _)) = &self.held
+            && now.duration_since(*since) >= REORDER_MAX_HOLD
+        {
+            let (_, held) = self.held.take()?;
+            self.schedule(held);
+        }
+        match self.queue.peek() {
+            Some(Reverse((due, _, _))) if *due <= now => {
+                self.queue.pop().map(|Reverse((_, _, payload))| payload)
+            }
+            _ => None,
+        }
+    }
+
+    // Сколько ждать до следующей датаграммы (None - очередь пуста)
+    pub(crate) fn next_due_in(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let queued = self
+            .queue
+            .peek()
+            .map(|Reverse((due, _, _))| due.saturating_duration_since(now));
+        let held = self
+            .held
+            .as_ref()
+            .map(|(since, _)| (*since + REORDER_MAX_HOLD).saturating_duration_since(now));
+        match (queued, held) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn schedule(&mut self, payload: Arc<[u8]>) {
+        let delay = if self.config.jitter_ms > 0 {
+            self.rng.gen_range(0..=self.config.jitter_ms)
+        } else {
+            0
+        };
+        let due = Instant::now() + Duration::from_millis(delay);
+        self.seq += 1;
+        self.queue.push(Reverse((due, self.seq, payload)));
+    }
+
+    fn chance(&mut self, pct: f64) -> bool {
+        pct > 0.0 && self.rng.gen_range(0.0..100.0) < pct
+    }
+}
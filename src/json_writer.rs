@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+
+// Запись JSON котировок прямо в байтовый буфер, без промежуточных String и
+// serde_json::Value. Вывод совпадает с serde_json: те же экранирование строк,
+// кратчайшая запись чисел с плавающей точкой и null вместо NaN и бесконечностей.
+pub(crate) struct JsonWriter<'a> {
+    out: &'a mut Vec<u8>,
+    first: bool,
+}
+
+impl<'a> JsonWriter<'a> {
+    // Открывает объект; поля добавляются по одному, close() закрывает его
+    pub fn object(out: &'a mut Vec<u8>) -> Self {
+        out.push(b'{');
+        JsonWriter { out, first: true }
+    }
+
+    fn key(&mut self, key: &str) -> &mut Vec<u8> {
+        if !self.first {
+            self.out.push(b',');
+        }
+        self.first = false;
+        write_str(self.out, key);
+        self.out.push(b':');
+        self.out
+    }
+
+    pub fn str(&mut self, key: &str, value: &str) -> &mut Self {
+        write_str(self.key(key), value);
+        self
+    }
+
+    pub fn u64(&mut self, key: &str, value: u64) -> &mut Self {
+        let mut buffer = itoa::Buffer::new();
+        self.key(key)
+            .extend_from_slice(buffer.format(value).as_bytes());
+        self
+    }
+
+    pub fn f64(&mut self, key: &str, value: f64) -> &mut Self {
+        write_f64(self.key(key), value);
+        self
+    }
+
+    pub fn bool(&mut self, key: &str, value: bool) -> &mut Self {
+        let literal: &[u8] = if value { b"true" } else { b"false" };
+        self.key(key).extend_from_slice(literal);
+        self
+    }
+
+    // Массив объектов: item пишет каждый элемент через свой JsonWriter
+    pub fn array<T>(
+        &mut self,
+        key: &str,
+        items: &[T],
+        mut item: impl FnMut(&mut JsonWriter<'_>, &T),
+    ) -> &mut Self {
+        let out = self.key(key);
+        out.push(b'[');
+        for (i, value) in items.iter().enumerate() {
+            if i > 0 {
+                out.push(b',');
+            }
+            let mut writer = JsonWriter::object(out);
+            item(&mut writer, value);
+            writer.close();
+        }
+        out.push(b']');
+        self
+    }
+
+    pub fn close(&mut self) {
+        self.out.push(b'}');
+    }
+}
+
+// Общий буфер потока: сериализация событий генератора не выделяет память,
+// пока буфер не вырастет до размера самого длинного события
+pub(crate) fn with_buffer<R>(f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+    thread_local! {
+        static BUFFER: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(256));
+    }
+    BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        buffer.clear();
+        f(&mut buffer)
+    })
+}
+
+fn write_f64(out: &mut Vec<u8>, value: f64) {
+    if value.is_finite() {
+        let mut buffer = zmij::Buffer::new();
+        out.extend_from_slice(buffer.format_finite(value).as_bytes());
+    } else {
+        out.extend_from_slice(b"null");
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    out.push(b'"');
+    let bytes = value.as_bytes();
+    let mut start = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        let escape: &[u8] = match byte {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x08 => b"\\b",
+            0x0c => b"\\f",
+            0x00..=0x1f => &[
+                b'\\',
+                b'u',
+                b'0',
+                b'0',
+                HEX[(byte >> 4) as usize],
+                HEX[(byte & 0xf) as usize],
+            ],
+            _ => continue,
+        };
+        out.extend_from_slice(&bytes[start..i]);
+        out.extend_from_slice(escape);
+        start = i + 1;
+    }
+    out.extend_from_slice(&bytes[start..]);
+    out.push(b'"');
+}
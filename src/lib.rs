@@ -1,11 +1,75 @@
+pub mod access_list;
+pub mod arg_config;
+pub mod auth;
+pub mod bars;
+pub mod circuit_breaker;
 pub mod client_manager;
+pub mod clock;
+pub mod control;
+pub mod daemon;
+pub mod error;
 pub mod generator;
+pub mod generator_handle;
+pub mod http;
+pub mod impairment;
+pub(crate) mod json_writer;
+pub mod line_reader;
+pub mod logging;
+pub mod metrics;
 pub mod models;
+pub mod observer;
+pub mod orderbook;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod rate_limiter;
+pub mod recorder;
+pub mod reload;
+pub mod replay;
+pub mod server_config;
+pub mod session;
+pub mod subscription;
 pub mod tcp_server;
+pub mod testing;
+pub mod ticker_config;
+pub mod toml_lite;
+pub mod udp_batch;
 pub mod udp_sender;
 
+pub use crate::access_list::{AccessList, Cidr};
+pub use crate::auth::{ApiKey, KeyStore};
+pub use crate::bars::{Bar, BarAggregator, BarInterval};
+pub use crate::circuit_breaker::CircuitBreaker;
 pub use crate::client_manager::ClientManager;
-pub use crate::generator::QuoteGenerator;
-pub use crate::models::{ClientConfig, Command, CommandError, StockQuote};
-pub use crate::tcp_server::TcpServer;
+pub use crate::clock::{Clock, MockClock, ScaledClock, SystemClock};
+pub use crate::control::{ControlEncoding, ControlMode, Reply};
+pub use crate::daemon::{Daemon, PidFile};
+pub use crate::error::QuoteError;
+pub use crate::generator::{ArrivalProcess, GenerationDefaults, QuoteGenerator};
+pub use crate::generator_handle::GeneratorHandle;
+pub use crate::impairment::NetworkImpairment;
+pub use crate::logging::{LogFilter, LogFormat, LogSubscriber};
+pub use crate::metrics::{Gauges, ServerMetrics, TickerSubscriptions, WorkerGauges};
+pub use crate::models::{
+    AssetClass, BidAskQuote, BookSide, ClientConfig, Command, CommandError, DepthLevel,
+    DepthUpdate, ErrorCode, ErrorResponse, FeedKind, FeedMessage, MarketStatus, ParamUpdate,
+    QuoteEvent, StatusEvent, StockQuote, StreamOptions,
+};
+pub use crate::observer::{LogObserver, ServerObserver};
+pub use crate::orderbook::OrderBook;
+#[cfg(feature = "otel")]
+pub use crate::otel::{OtelConfig, OtelObserver};
+pub use crate::rate_limiter::{RateLimiter, RateLimits};
+pub use crate::recorder::Recorder;
+pub use crate::reload::ReloadReport;
+pub use crate::replay::ReplaySource;
+pub use crate::server_config::{
+    GenerationConfig, RecordConfig, ServerConfig, TickerSource, TransportConfig,
+};
+pub use crate::session::SessionSchedule;
+#[cfg(feature = "stream")]
+pub use crate::subscription::Stream;
+pub use crate::subscription::{ClientFeed, FeedReceiver, Subscription, SubscriptionId};
+pub use crate::tcp_server::{ServerHandle, TcpServer};
+pub use crate::testing::{TestClient, TestServer, TestServerBuilder};
+pub use crate::ticker_config::{IndexSpec, PriceBand, TickerSpec, VolumeProfile};
 pub use crate::udp_sender::UdpSender;
@@ -0,0 +1,175 @@
+use std::io::{self, Read};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// Длиннее строки команды не бывают; такая строка отбрасывается целиком
+pub const MAX_LINE_LEN: usize = 4096;
+// Наибольшее содержимое кадра в режиме framed; больший кадр пропускается
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+// Длина кадра: u32 big-endian перед содержимым
+const FRAME_HEADER_LEN: usize = 4;
+// Сколько байт читается из соединения за раз
+const READ_CHUNK_LEN: usize = 4096;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line {
+    Command(String),
+    // Строка превысила MAX_LINE_LEN (кадр - MAX_FRAME_LEN); ее остаток пропускается
+    TooLong,
+}
+
+// Разбиение TCP потока на строки команд: одна команда может прийти несколькими
+// пакетами, а один пакет - нести несколько команд. Принимаются \n и \r\n,
+// пустые строки пропускаются. При ошибке чтения (в том числе таймауте)
+// недочитанная строка сохраняется до следующего вызова. Тот же буфер читается
+// и кадрами (next_frame), поэтому режим можно сменить посреди соединения.
+// Читает как из обычного потока, так и из соединения tokio.
+pub struct LineReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    // Пропускаем хвост слишком длинной строки
+    discarding: bool,
+    // Сколько байт слишком длинного кадра еще пропустить
+    skip: usize,
+}
+
+impl<R> LineReader<R> {
+    pub fn new(inner: R) -> Self {
+        LineReader {
+            inner,
+            buf: Vec::new(),
+            discarding: false,
+            skip: 0,
+        }
+    }
+
+    // Команда из уже прочитанных байт; None - нужно дочитать
+    fn buffered(&mut self, framed: bool) -> Option<Line> {
+        if framed {
+            self.buffered_frame()
+        } else {
+            self.buffered_line()
+        }
+    }
+
+    // Кадр: 4 байта длины и команда, в которой могут быть переводы строк.
+    // Пустые кадры пропускаются.
+    fn buffered_frame(&mut self) -> Option<Line> {
+        loop {
+            if self.skip > 0 {
+                let skipped = self.skip.min(self.buf.len());
+                self.buf.drain(..skipped);
+                self.skip -= skipped;
+            }
+            if self.skip > 0 || self.buf.len() < FRAME_HEADER_LEN {
+                return None;
+            }
+            let mut header = [0; FRAME_HEADER_LEN];
+            header.copy_from_slice(&self.buf[..FRAME_HEADER_LEN]);
+            let len = u32::from_be_bytes(header) as usize;
+            if len > MAX_FRAME_LEN {
+                self.buf.drain(..FRAME_HEADER_LEN);
+                self.skip = len;
+                return Some(Line::TooLong);
+            }
+            if self.buf.len() < FRAME_HEADER_LEN + len {
+                return None;
+            }
+            let frame: Vec<u8> = self.buf.drain(..FRAME_HEADER_LEN + len).collect();
+            if let Some(line) = command(&frame[FRAME_HEADER_LEN..]) {
+                return Some(line);
+            }
+        }
+    }
+
+    fn buffered_line(&mut self) -> Option<Line> {
+        loop {
+            if let Some(end) = self.buf.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..=end).collect();
+                if std::mem::take(&mut self.discarding) {
+                    continue;
+                }
+                if line.len() > MAX_LINE_LEN {
+                    return Some(Line::TooLong);
+                }
+                if let Some(line) = command(&line) {
+                    return Some(line);
+                }
+                continue;
+            }
+            if self.buf.len() > MAX_LINE_LEN {
+                self.buf.clear();
+                if !std::mem::replace(&mut self.discarding, true) {
+                    return Some(Line::TooLong);
+                }
+            }
+            return None;
+        }
+    }
+
+    // Соединение закрыто: последняя команда без перевода строки
+    // (printf STATS | nc) выполняется, недочитанный кадр без конца - нет
+    fn finish(&mut self, framed: bool) -> Option<Line> {
+        let rest = std::mem::take(&mut self.buf);
+        if framed || std::mem::take(&mut self.discarding) {
+            return None;
+        }
+        command(&rest)
+    }
+}
+
+impl<R: Read> LineReader<R> {
+    // Следующий кадр; None - соединение закрыто
+    pub fn next_frame(&mut self) -> io::Result<Option<Line>> {
+        self.next_command(true)
+    }
+
+    // Следующая строка; None - соединение закрыто
+    pub fn next_line(&mut self) -> io::Result<Option<Line>> {
+        self.next_command(false)
+    }
+
+    pub fn next_command(&mut self, framed: bool) -> io::Result<Option<Line>> {
+        loop {
+            if let Some(line) = self.buffered(framed) {
+                return Ok(Some(line));
+            }
+            let mut chunk = [0; READ_CHUNK_LEN];
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(self.finish(framed));
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    // То же для соединения tokio. Прерванное ожидание (таймаут, select!) не
+    // теряет байт: прочитанное сразу попадает в буфер.
+    pub async fn next_command_async(&mut self, framed: bool) -> io::Result<Option<Line>> {
+        loop {
+            if let Some(line) = self.buffered(framed) {
+                return Ok(Some(line));
+            }
+            let mut chunk = [0; READ_CHUNK_LEN];
+            let n = self.inner.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(self.finish(framed));
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+// Кадр для отправки: длина и содержимое
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn command(line: &[u8]) -> Option<Line> {
+    let text = String::from_utf8_lossy(line).trim().to_string();
+    (!text.is_empty()).then_some(Line::Command(text))
+}
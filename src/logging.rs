@@ -0,0 +1,473 @@
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+
+// Фильтр в синтаксисе RUST_LOG (как EnvFilter): "info",
+// "warn,quote_common::tcp_server=debug". Для цели события берется самая
+// длинная совпавшая директива, иначе уровень по умолчанию.
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    default: LevelFilter,
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut filter = LogFilter {
+            default: LevelFilter::ERROR,
+            directives: Vec::new(),
+        };
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    filter
+                        .directives
+                        .push((target.trim().to_string(), parse_level(level)?));
+                }
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        // Длинные цели проверяются первыми
+        filter
+            .directives
+            .sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Ok(filter)
+    }
+
+    // RUST_LOG, если задана, иначе default (обычно --log-level)
+    pub fn from_env_or(default: &str) -> Result<Self, String> {
+        match std::env::var("RUST_LOG") {
+            Ok(spec) if !spec.trim().is_empty() => {
+                Self::parse(&spec).map_err(|e| format!("RUST_LOG: {}", e))
+            }
+            _ => Self::parse(default),
+        }
+    }
+
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|(prefix, _)| {
+                target == prefix
+                    || target
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    pub fn enabled(&self, target: &str, level: &Level) -> bool {
+        *level <= self.level_for(target)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level.trim()).map_err(|_| format!("unknown log level: {}", level))
+}
+
+// Формат вывода: строка с полями key=value или JSON объект на событие
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "unknown log format: {} (expected text or json)",
+                other
+            )),
+        }
+    }
+}
+
+// Подписчик tracing: фильтрует по LogFilter и пишет события в stderr (или в
+// файл) вместе с полями и цепочкой открытых спанов (соединение, отправитель, тикер)
+pub struct LogSubscriber {
+    // Заменяется set_filter при перезагрузке конфигурации
+    filter: RwLock<LogFilter>,
+    format: LogFormat,
+    color: bool,
+    file: Option<Mutex<File>>,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+struct SpanData {
+    metadata: &'static Metadata<'static>,
+    fields: Map<String, Value>,
+    parent: Option<u64>,
+    refs: usize,
+}
+
+thread_local! {
+    // Спаны, в которые вошел текущий поток, от внешнего к внутреннему
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+impl LogSubscriber {
+    pub fn new(filter: LogFilter, format: LogFormat) -> Self {
+        LogSubscriber {
+            filter: RwLock::new(filter),
+            format,
+            color: false,
+            file: None,
+            spans: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    // Цветной уровень, если stderr - терминал
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color && self.file.is_none() && std::io::stderr().is_terminal();
+        self
+    }
+
+    // Запись в файл вместо stderr, без цвета
+    pub fn with_file(mut self, file: File) -> Self {
+        self.file = Some(Mutex::new(file));
+        self.color = false;
+        self
+    }
+
+    pub fn set_filter(&self, filter: LogFilter) {
+        *self.filter.write().unwrap() = filter;
+        // Решения, закэшированные в местах вызова, пересчитываются по новому фильтру
+        tracing::callsite::rebuild_interest_cache();
+    }
+
+    fn current(&self) -> Option<u64> {
+        ENTERED.with(|entered| entered.borrow().last().copied())
+    }
+
+    // Имена и поля спанов от корня до parent
+    fn span_chain(&self, parent: Option<u64>) -> Vec<(&'static str, Map<String, Value>)> {
+        let spans = self.spans.lock().unwrap();
+        let mut chain = Vec::new();
+        let mut next = parent;
+        while let Some(id) = next {
+            let Some(span) = spans.get(&id) else { break };
+            chain.push((span.metadata.name(), span.fields.clone()));
+            next = span.parent;
+        }
+        chain.reverse();
+        chain
+    }
+
+    fn release(&self, spans: &mut HashMap<u64, SpanData>, id: u64) -> bool {
+        let Some(span) = spans.get_mut(&id) else {
+            return false;
+        };
+        span.refs -= 1;
+        if span.refs > 0 {
+            return false;
+        }
+        let parent = spans.remove(&id).and_then(|span| span.parent);
+        if let Some(parent) = parent {
+            self.release(spans, parent);
+        }
+        true
+    }
+
+    fn format_text(
+        &self,
+        metadata: &Metadata<'_>,
+        message: &str,
+        fields: &Map<String, Value>,
+        chain: &[(&'static str, Map<String, Value>)],
+    ) -> String {
+        let level = format!("{:<5}", metadata.level().as_str());
+        let level = if self.color {
+            format!("\x1b[{}m{}\x1b[0m", level_color(metadata.level()), level)
+        } else {
+            level
+        };
+        let mut line = format!("[{} {}] ", timestamp(), level);
+        for (name, span_fields) in chain {
+            line.push_str(name);
+            if !span_fields.is_empty() {
+                line.push('{');
+                line.push_str(&text_fields(span_fields));
+                line.push('}');
+            }
+            line.push_str(": ");
+        }
+        line.push_str(message);
+        if !fields.is_empty() {
+            line.push(' ');
+            line.push_str(&text_fields(fields));
+        }
+        line
+    }
+
+    fn format_json(
+        &self,
+        metadata: &Metadata<'_>,
+        message: String,
+        fields: Map<String, Value>,
+        chain: Vec<(&'static str, Map<String, Value>)>,
+    ) -> String {
+        let mut object = Map::new();
+        object.insert("timestamp".to_string(), Value::String(timestamp()));
+        object.insert(
+            "level".to_string(),
+            Value::String(metadata.level().to_string()),
+        );
+        object.insert(
+            "target".to_string(),
+            Value::String(metadata.target().to_string()),
+        );
+        // Поля события отдельно, чтобы не пересекаться с ключами записи
+        let mut event_fields = Map::new();
+        event_fields.insert("message".to_string(), Value::String(message));
+        event_fields.extend(fields);
+        object.insert("fields".to_string(), Value::Object(event_fields));
+        if !chain.is_empty() {
+            let spans = chain
+                .into_iter()
+                .map(|(name, mut fields)| {
+                    fields.insert("name".to_string(), Value::String(name.to_string()));
+                    Value::Object(fields)
+                })
+                .collect();
+            object.insert("spans".to_string(), Value::Array(spans));
+        }
+        Value::Object(object).to_string()
+    }
+}
+
+impl Subscriber for LogSubscriber {
+    // Решение кэшируется в месте вызова; set_filter сбрасывает кэш
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.enabled(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.filter
+            .read()
+            .unwrap()
+            .enabled(metadata.target(), metadata.level())
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.filter.read().unwrap().max_level())
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let parent = if span.is_root() {
+            None
+        } else if span.is_contextual() {
+            self.current()
+        } else {
+            span.parent().map(Id::into_u64)
+        };
+        let mut fields = FieldVisitor::default();
+        span.record(&mut fields);
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut spans = self.spans.lock().unwrap();
+        // Родитель живет, пока жив хотя бы один дочерний спан
+        if let Some(parent) = parent.and_then(|parent| spans.get_mut(&parent)) {
+            parent.refs += 1;
+        }
+        spans.insert(
+            id,
+            SpanData {
+                metadata: span.metadata(),
+                fields: fields.fields,
+                parent,
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = FieldVisitor::default();
+        values.record(&mut fields);
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            span.fields.extend(fields.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let parent = if event.is_root() {
+            None
+        } else if event.is_contextual() {
+            self.current()
+        } else {
+            event.parent().map(Id::into_u64)
+        };
+        let mut fields = FieldVisitor::default();
+        event.record(&mut fields);
+        let chain = self.span_chain(parent);
+
+        let metadata = event.metadata();
+        let line = match self.format {
+            LogFormat::Text => self.format_text(metadata, &fields.message, &fields.fields, &chain),
+            LogFormat::Json => self.format_json(metadata, fields.message, fields.fields, chain),
+        };
+        match &self.file {
+            Some(file) => {
+                let _ = writeln!(file.lock().unwrap(), "{}", line);
+            }
+            None => {
+                let _ = writeln!(std::io::stderr().lock(), "{}", line);
+            }
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        let id = span.into_u64();
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(position) = entered.iter().rposition(|&entered| entered == id) {
+                entered.remove(position);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        self.release(&mut spans, span.into_u64())
+    }
+}
+
+// Установка подписчика для всего процесса; повторная установка - ошибка
+pub fn init(filter: LogFilter, format: LogFormat, color: bool) -> Result<(), String> {
+    install(LogSubscriber::new(filter, format).with_color(color))
+}
+
+pub fn install(subscriber: LogSubscriber) -> Result<(), String> {
+    tracing::subscriber::set_global_default(subscriber).map_err(|e| e.to_string())
+}
+
+// Новый фильтр для установленного LogSubscriber; false - установлен другой
+// подписчик или никакого
+pub fn set_filter(filter: LogFilter) -> bool {
+    tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<LogSubscriber>()
+            .map(|subscriber| subscriber.set_filter(filter.clone()))
+            .is_some()
+    })
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Map<String, Value>,
+}
+
+impl FieldVisitor {
+    fn insert(&mut self, field: &Field, value: Value) {
+        if field.name() == "message" {
+            self.message = match value {
+                Value::String(message) => message,
+                other => other.to_string(),
+            };
+        } else {
+            self.fields.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        let value = serde_json::Number::from_f64(value).map_or(Value::Null, Value::Number);
+        self.insert(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::String(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::String(format!("{:?}", value)));
+    }
+}
+
+// Поля как key=value; значения с пробелами и пустые - в кавычках
+fn text_fields(fields: &Map<String, Value>) -> String {
+    fields
+        .iter()
+        .map(|(key, value)| match value {
+            Value::String(s) if s.is_empty() || s.contains(char::is_whitespace) => {
+                format!("{}={:?}", key, s)
+            }
+            Value::String(s) => format!("{}={}", key, s),
+            other => format!("{}={}", key, other),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn timestamp() -> String {
+    chrono::Utc::now()
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string()
+}
+
+fn level_color(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 31,
+        Level::WARN => 33,
+        Level::INFO => 32,
+        Level::DEBUG => 34,
+        Level::TRACE => 35,
+    }
+}
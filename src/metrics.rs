@@ -0,0 +1,340 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Общий реестр счетчиков сервера: генератор, менеджер клиентов и отправители
+// увеличивают их, /metrics отдает в текстовом формате Prometheus.
+// Значения только растут; скорость считается на стороне Prometheus через rate().
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    trades_generated: AtomicU64,
+    events_published: AtomicU64,
+    events_dropped: AtomicU64,
+    datagrams_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    send_errors: AtomicU64,
+    stale_removals: AtomicU64,
+    connections_accepted: AtomicU64,
+    // Соединения, закрытые сразу: все потоки обработки заняты
+    connections_rejected_busy: AtomicU64,
+    // Подписки, снятые политикой переполнения disconnect
+    overflow_disconnects: AtomicU64,
+    // Клиенты, отключенные как медленные потребители
+    slow_consumers: AtomicU64,
+    // Закрытые каналы подписчиков и подписки, убранные периодической сборкой
+    subscribers_collected: AtomicU64,
+    subscriptions_collected: AtomicU64,
+    late_generation_cycles: AtomicU64,
+    // Мгновенные значения потоков генерации по номеру потока
+    workers: Mutex<Vec<WorkerGauges>>,
+}
+
+// Поток генерации: сколько тикеров за ним закреплено и насколько его последний
+// цикл отстал от расписания котировок
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkerGauges {
+    pub tickers: usize,
+    pub skew_ms: u64,
+}
+
+// Мгновенные значения, которые сервер собирает в момент запроса
+#[derive(Debug, Clone, Default)]
+pub struct Gauges {
+    pub tcp_connections: usize,
+    pub streaming_clients: usize,
+    pub tickers: Vec<TickerSubscriptions>,
+    // Событий в очередях клиента по client_id
+    pub client_queues: Vec<(String, u64)>,
+}
+
+// Подписчики тикера и события, ждущие в их очередях
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TickerSubscriptions {
+    pub ticker: String,
+    pub subscribers: usize,
+    pub queued: usize,
+}
+
+impl ServerMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_trade(&self) {
+        self.trades_generated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Событие положено в очереди receivers подписчиков
+    pub(crate) fn record_published(&self, receivers: usize) {
+        self.events_published
+            .fetch_add(receivers as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dropped(&self, events: u64) {
+        self.events_dropped.fetch_add(events, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_sent(&self, datagrams: usize, bytes: usize) {
+        self.datagrams_sent
+            .fetch_add(datagrams as u64, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_send_error(&self) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_stale(&self) {
+        self.stale_removals.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_connection(&self) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_busy_rejection(&self) {
+        self.connections_rejected_busy
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_overflow_disconnect(&self) {
+        self.overflow_disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_slow_consumer(&self) {
+        self.slow_consumers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_collected(&self, subscribers: usize, subscriptions: usize) {
+        self.subscribers_collected
+            .fetch_add(subscribers as u64, Ordering::Relaxed);
+        self.subscriptions_collected
+            .fetch_add(subscriptions as u64, Ordering::Relaxed);
+    }
+
+    // Цикл генерации, в котором котировка вышла позже своего времени больше
+    // чем на общий интервал
+    pub(crate) fn record_late_cycle(&self) {
+        self.late_generation_cycles.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn update_worker(&self, worker: usize, update: impl FnOnce(&mut WorkerGauges)) {
+        let mut workers = self.workers.lock().unwrap();
+        if workers.len() <= worker {
+            workers.resize(worker + 1, WorkerGauges::default());
+        }
+        update(&mut workers[worker]);
+    }
+
+    pub fn trades_generated(&self) -> u64 {
+        self.trades_generated.load(Ordering::Relaxed)
+    }
+
+    pub fn datagrams_sent(&self) -> u64 {
+        self.datagrams_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn send_errors(&self) -> u64 {
+        self.send_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn stale_removals(&self) -> u64 {
+        self.stale_removals.load(Ordering::Relaxed)
+    }
+
+    pub fn late_generation_cycles(&self) -> u64 {
+        self.late_generation_cycles.load(Ordering::Relaxed)
+    }
+
+    pub fn workers(&self) -> Vec<WorkerGauges> {
+        self.workers.lock().unwrap().clone()
+    }
+
+    // Текстовый формат Prometheus 0.0.4
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = Exposition::default();
+        let counter = |out: &mut Exposition, name, help, value: &AtomicU64| {
+            out.family(name, "counter", help);
+            out.sample(name, &[], value.load(Ordering::Relaxed));
+        };
+
+        counter(
+            &mut out,
+            "quote_trades_generated_total",
+            "Trades generated for all tickers",
+            &self.trades_generated,
+        );
+        counter(
+            &mut out,
+            "quote_events_published_total",
+            "Events put into subscriber queues",
+            &self.events_published,
+        );
+        counter(
+            &mut out,
+            "quote_events_dropped_total",
+            "Events dropped from full subscriber queues",
+            &self.events_dropped,
+        );
+        counter(
+            &mut out,
+            "quote_datagrams_sent_total",
+            "UDP datagrams sent to clients",
+            &self.datagrams_sent,
+        );
+        counter(
+            &mut out,
+            "quote_bytes_sent_total",
+            "UDP payload bytes sent to clients",
+            &self.bytes_sent,
+        );
+        counter(
+            &mut out,
+            "quote_udp_send_errors_total",
+            "Failed UDP sends and address resolutions",
+            &self.send_errors,
+        );
+        counter(
+            &mut out,
+            "quote_stale_clients_removed_total",
+            "Clients removed after ping timeout",
+            &self.stale_removals,
+        );
+        counter(
+            &mut out,
+            "quote_connections_accepted_total",
+            "Accepted TCP control connections",
+            &self.connections_accepted,
+        );
+        counter(
+            &mut out,
+            "quote_connections_rejected_busy_total",
+            "TCP connections rejected because the connection limit was reached",
+            &self.connections_rejected_busy,
+        );
+        counter(
+            &mut out,
+            "quote_slow_subscribers_disconnected_total",
+            "Subscriptions dropped by the disconnect overflow policy",
+            &self.overflow_disconnects,
+        );
+        counter(
+            &mut out,
+            "quote_slow_consumers_disconnected_total",
+            "Clients disconnected as slow consumers",
+            &self.slow_consumers,
+        );
+        counter(
+            &mut out,
+            "quote_subscribers_collected_total",
+            "Closed subscriber channels removed by the subscription sweep",
+            &self.subscribers_collected,
+        );
+        counter(
+            &mut out,
+            "quote_subscriptions_collected_total",
+            "Subscriptions without live channels removed by the subscription sweep",
+            &self.subscriptions_collected,
+        );
+        counter(
+            &mut out,
+            "quote_generation_late_cycles_total",
+            "Generation cycles that ran more than one interval behind schedule",
+            &self.late_generation_cycles,
+        );
+
+        out.family(
+            "quote_tcp_connections",
+            "gauge",
+            "Open TCP control connections",
+        );
+        out.sample("quote_tcp_connections", &[], gauges.tcp_connections);
+        out.family(
+            "quote_clients",
+            "gauge",
+            "Clients with an active UDP stream",
+        );
+        out.sample("quote_clients", &[], gauges.streaming_clients);
+
+        out.family(
+            "quote_ticker_subscribers",
+            "gauge",
+            "Subscribers per ticker",
+        );
+        for ticker in &gauges.tickers {
+            let labels = [("ticker", ticker.ticker.as_str())];
+            out.sample("quote_ticker_subscribers", &labels, ticker.subscribers);
+        }
+        out.family(
+            "quote_ticker_queue_depth",
+            "gauge",
+            "Events waiting in per-ticker queues of in-process subscribers",
+        );
+        for ticker in &gauges.tickers {
+            let labels = [("ticker", ticker.ticker.as_str())];
+            out.sample("quote_ticker_queue_depth", &labels, ticker.queued);
+        }
+        out.family(
+            "quote_client_queue_depth",
+            "gauge",
+            "Events waiting in the queues of a client",
+        );
+        for (client_id, queued) in &gauges.client_queues {
+            let labels = [("client_id", client_id.as_str())];
+            out.sample("quote_client_queue_depth", &labels, queued);
+        }
+
+        let workers = self.workers();
+        out.family(
+            "quote_generator_worker_tickers",
+            "gauge",
+            "Tickers assigned to a generation worker thread",
+        );
+        for (worker, stats) in workers.iter().enumerate() {
+            let worker = worker.to_string();
+            let labels = [("worker", worker.as_str())];
+            out.sample("quote_generator_worker_tickers", &labels, stats.tickers);
+        }
+        out.family(
+            "quote_generator_skew_ms",
+            "gauge",
+            "How far the last cycle of a generation worker ran behind schedule",
+        );
+        for (worker, stats) in workers.iter().enumerate() {
+            let worker = worker.to_string();
+            let labels = [("worker", worker.as_str())];
+            out.sample("quote_generator_skew_ms", &labels, stats.skew_ms);
+        }
+
+        out.0
+    }
+}
+
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                .collect();
+            let _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.0, " {}", value);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,42 +1,103 @@
+use crate::bars::{Bar, BarInterval};
+use crate::generator::OverflowPolicy;
+use crate::json_writer::{self, JsonWriter};
+use crate::subscription::SubscriptionId;
+use crate::udp_sender::SenderState;
 use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Instant;
 use thiserror::Error;
 
+// Класс инструмента: от него зависят точность цены и смысл объема
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetClass {
+    #[default]
+    Equity,
+    // Валютная пара: цена с 5 знаками, волатильность в пипсах, без объема
+    Fx,
+}
+
+impl AssetClass {
+    pub fn is_equity(&self) -> bool {
+        *self == AssetClass::Equity
+    }
+
+    // Значение в JSON (как у serde)
+    pub(crate) fn json_name(&self) -> &'static str {
+        match self {
+            AssetClass::Equity => "equity",
+            AssetClass::Fx => "fx",
+        }
+    }
+
+    // Число знаков после запятой в публикуемых ценах
+    pub fn price_decimals(&self) -> usize {
+        match self {
+            AssetClass::Equity => 2,
+            AssetClass::Fx => 5,
+        }
+    }
+}
+
+// Объем 0 означает, что у инструмента нет объема (валютные пары, индексы),
+// такое поле в JSON не передается
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StockQuote {
     pub ticker: String,
     pub price: f64,
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub volume: u32,
     pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "AssetClass::is_equity")]
+    pub asset_class: AssetClass,
 }
 
 impl StockQuote {
     pub fn new(ticker: String, price: f64, volume: u32) -> Self {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
+        let timestamp = current_millis();
 
         StockQuote {
             ticker,
             price,
             volume,
             timestamp,
+            asset_class: AssetClass::Equity,
         }
     }
 
+    pub fn with_asset_class(mut self, asset_class: AssetClass) -> Self {
+        self.asset_class = asset_class;
+        self
+    }
+
     // JSON формат (основной)
     pub fn to_json(&self) -> String {
-        serde_json::to_string(self).unwrap_or_else(|_| {
-            // Fallback на текстовый формат при ошибке сериализации
-            self.to_string()
-        })
+        json_string(|out| self.serialize_into(out))
     }
 
     // Байтовое представление (использует JSON)
     pub fn to_bytes(&self) -> Vec<u8> {
-        self.to_json().into_bytes()
+        let mut out = Vec::new();
+        self.serialize_into(&mut out);
+        out
+    }
+
+    // Дописывает JSON котировки в out без промежуточных строк; тот же вывод,
+    // что у serde, поэтому from_json читает его обратно
+    pub fn serialize_into(&self, out: &mut Vec<u8>) {
+        let mut json = JsonWriter::object(out);
+        json.str("ticker", &self.ticker).f64("price", self.price);
+        if self.volume != 0 {
+            json.u64("volume", self.volume.into());
+        }
+        json.u64("timestamp", self.timestamp);
+        if !self.asset_class.is_equity() {
+            json.str("asset_class", self.asset_class.json_name());
+        }
+        json.close();
     }
 
     // Парсинг из JSON
@@ -49,17 +110,312 @@ impl fmt::Display for StockQuote {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{}|{:.2}|{}|{}",
-            self.ticker, self.price, self.volume, self.timestamp
+            "{}|{:.*}|{}|{}",
+            self.ticker,
+            self.asset_class.price_decimals(),
+            self.price,
+            self.volume,
+            self.timestamp
         )
     }
 }
 
+// Котировка лучших цен покупки/продажи (bid/ask)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BidAskQuote {
+    pub ticker: String,
+    pub bid: f64,
+    pub bid_size: u32,
+    pub ask: f64,
+    pub ask_size: u32,
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "AssetClass::is_equity")]
+    pub asset_class: AssetClass,
+}
+
+impl BidAskQuote {
+    pub fn new(ticker: String, bid: f64, bid_size: u32, ask: f64, ask_size: u32) -> Self {
+        BidAskQuote {
+            ticker,
+            bid,
+            bid_size,
+            ask,
+            ask_size,
+            timestamp: current_millis(),
+            asset_class: AssetClass::Equity,
+        }
+    }
+
+    pub fn with_asset_class(mut self, asset_class: AssetClass) -> Self {
+        self.asset_class = asset_class;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookSide {
+    Bid,
+    Ask,
+}
+
+impl BookSide {
+    pub(crate) fn json_name(&self) -> &'static str {
+        match self {
+            BookSide::Bid => "bid",
+            BookSide::Ask => "ask",
+        }
+    }
+}
+
+// Уровень стакана; size = 0 означает удаление уровня
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthLevel {
+    pub side: BookSide,
+    pub price: f64,
+    pub size: u32,
+}
+
+// Обновление стакана (Level 2). snapshot = true - полный стакан,
+// иначе только изменившиеся уровни. seq растет на 1 с каждым обновлением тикера.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthUpdate {
+    pub ticker: String,
+    pub seq: u64,
+    pub snapshot: bool,
+    pub levels: Vec<DepthLevel>,
+    pub timestamp: u64,
+}
+
+impl DepthUpdate {
+    pub fn new(ticker: String, seq: u64, snapshot: bool, levels: Vec<DepthLevel>) -> Self {
+        DepthUpdate {
+            ticker,
+            seq,
+            snapshot,
+            levels,
+            timestamp: current_millis(),
+        }
+    }
+}
+
+// Состояние торгов по тикеру
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketStatus {
+    PreMarket,
+    Open,
+    Closed,
+    // Торги по тикеру остановлены ценовым коридором
+    Halted,
+    // Тикер удален из списка торгуемых
+    Delisted,
+}
+
+impl fmt::Display for MarketStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MarketStatus::PreMarket => "PRE_MARKET",
+            MarketStatus::Open => "OPEN",
+            MarketStatus::Closed => "CLOSED",
+            MarketStatus::Halted => "HALTED",
+            MarketStatus::Delisted => "DELISTED",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl MarketStatus {
+    pub(crate) fn json_name(&self) -> &'static str {
+        match self {
+            MarketStatus::PreMarket => "pre_market",
+            MarketStatus::Open => "open",
+            MarketStatus::Closed => "closed",
+            MarketStatus::Halted => "halted",
+            MarketStatus::Delisted => "delisted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusEvent {
+    pub ticker: String,
+    pub status: MarketStatus,
+    pub timestamp: u64,
+}
+
+impl StatusEvent {
+    pub fn new(ticker: String, status: MarketStatus) -> Self {
+        StatusEvent {
+            ticker,
+            status,
+            timestamp: current_millis(),
+        }
+    }
+}
+
+// Сообщение потока: котировка или служебное событие.
+// Котировки сериализуются как раньше, события - с полем "type".
+#[derive(Debug, Clone)]
+pub enum QuoteEvent {
+    Trade(StockQuote),
+    Quote(BidAskQuote),
+    Depth(DepthUpdate),
+    Status(StatusEvent),
+    // Закрытый OHLCV бар для клиентов, подписанных на бары (STREAM ... BARS)
+    Bar(Bar),
+}
+
+impl QuoteEvent {
+    pub fn ticker(&self) -> &str {
+        match self {
+            QuoteEvent::Trade(quote) => &quote.ticker,
+            QuoteEvent::Quote(quote) => &quote.ticker,
+            QuoteEvent::Depth(depth) => &depth.ticker,
+            QuoteEvent::Status(status) => &status.ticker,
+            QuoteEvent::Bar(bar) => &bar.ticker,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        json_string(|out| self.serialize_into(out))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.serialize_into(&mut out);
+        out
+    }
+
+    // Поля событий с "type" идут по алфавиту, как раньше при сборке через
+    // serde_json::Value
+    pub fn serialize_into(&self, out: &mut Vec<u8>) {
+        let mut json = match self {
+            QuoteEvent::Trade(quote) => return quote.serialize_into(out),
+            QuoteEvent::Quote(quote) => {
+                let mut json = JsonWriter::object(out);
+                json.f64("ask", quote.ask)
+                    .u64("ask_size", quote.ask_size.into());
+                if !quote.asset_class.is_equity() {
+                    json.str("asset_class", quote.asset_class.json_name());
+                }
+                json.f64("bid", quote.bid)
+                    .u64("bid_size", quote.bid_size.into())
+                    .str("ticker", &quote.ticker)
+                    .u64("timestamp", quote.timestamp)
+                    .str("type", "quote");
+                json
+            }
+            QuoteEvent::Depth(depth) => {
+                let mut json = JsonWriter::object(out);
+                json.array(
+                    "levels",
+                    &depth.levels,
+                    |level, DepthLevel { side, price, size }| {
+                        level
+                            .f64("price", *price)
+                            .str("side", side.json_name())
+                            .u64("size", (*size).into());
+                    },
+                )
+                .u64("seq", depth.seq)
+                .bool("snapshot", depth.snapshot)
+                .str("ticker", &depth.ticker)
+                .u64("timestamp", depth.timestamp)
+                .str("type", "depth");
+                json
+            }
+            QuoteEvent::Status(status) => {
+                let mut json = JsonWriter::object(out);
+                json.str("status", status.status.json_name())
+                    .str("ticker", &status.ticker)
+                    .u64("timestamp", status.timestamp)
+                    .str("type", "status");
+                json
+            }
+            QuoteEvent::Bar(bar) => {
+                let mut json = JsonWriter::object(out);
+                json.f64("close", bar.close)
+                    .u64("end", bar.end)
+                    .f64("high", bar.high)
+                    .str("interval", &bar.interval)
+                    .f64("low", bar.low)
+                    .f64("open", bar.open)
+                    .u64("start", bar.start)
+                    .str("ticker", &bar.ticker)
+                    .u64("trades", bar.trades.into())
+                    .str("type", "bar")
+                    .u64("volume", bar.volume);
+                json
+            }
+        };
+        json.close();
+    }
+
+    pub fn from_json(json_str: &str) -> Option<Self> {
+        let value: serde_json::Value = serde_json::from_str(json_str).ok()?;
+        match value.get("type").and_then(|t| t.as_str()) {
+            None => serde_json::from_value(value).ok().map(QuoteEvent::Trade),
+            Some("quote") => serde_json::from_value(value).ok().map(QuoteEvent::Quote),
+            Some("depth") => serde_json::from_value(value).ok().map(QuoteEvent::Depth),
+            Some("status") => serde_json::from_value(value).ok().map(QuoteEvent::Status),
+            Some("bar") => serde_json::from_value(value).ok().map(QuoteEvent::Bar),
+            Some(_) => None,
+        }
+    }
+}
+
+// Событие вместе с его JSON, сериализованным один раз в генераторе: буфер
+// общий для всех подписчиков тикера и отправляется клиентам без повторной сериализации
+#[derive(Debug, Clone)]
+pub struct FeedMessage {
+    pub event: QuoteEvent,
+    pub payload: Arc<[u8]>,
+    // Момент публикации: по нему видно, насколько клиент отстает от генератора
+    pub created: Instant,
+}
+
+impl FeedMessage {
+    pub fn new(event: QuoteEvent) -> Self {
+        // Сериализация в буфер потока генерации; выделяется только общий payload
+        let payload = json_writer::with_buffer(|buffer| {
+            event.serialize_into(buffer);
+            Arc::from(&buffer[..])
+        });
+        FeedMessage {
+            event,
+            payload,
+            created: Instant::now(),
+        }
+    }
+}
+
+fn json_string(serialize: impl FnOnce(&mut Vec<u8>)) -> String {
+    let mut out = Vec::new();
+    serialize(&mut out);
+    // JsonWriter пишет только целые UTF-8 строки и ASCII
+    String::from_utf8(out).unwrap_or_default()
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+// Метки времени берутся из часов процесса (см. clock)
+pub(crate) fn current_millis() -> u64 {
+    crate::clock::now_millis()
+}
+
 #[derive(Debug, Clone)]
 pub struct ClientConfig {
     pub udp_addr: String,
     pub tickers: Vec<String>,
+    pub options: StreamOptions,
     pub last_ping: u64,
+    // Состояние UdpSender клиента: через него поток останавливается извне
+    pub sender: Arc<SenderState>,
+    // Подписка в генераторе; снимается при удалении клиента
+    pub subscription: Option<SubscriptionId>,
 }
 
 impl ClientConfig {
@@ -67,53 +423,599 @@ impl ClientConfig {
         ClientConfig {
             udp_addr,
             tickers,
+            options: StreamOptions::default(),
             last_ping: Self::current_timestamp(),
+            sender: Arc::new(SenderState::default()),
+            subscription: None,
         }
     }
 
+    pub fn with_options(mut self, options: StreamOptions) -> Self {
+        self.options = options;
+        self
+    }
+
     pub fn update_ping(&mut self) {
         self.last_ping = Self::current_timestamp();
     }
 
     fn current_timestamp() -> u64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
+        current_millis() / 1000
+    }
+
+    // Секунды с последнего ping
+    pub fn ping_age_secs(&self) -> u64 {
+        Self::current_timestamp().saturating_sub(self.last_ping)
     }
 
     pub fn is_stale(&self, timeout_secs: u64) -> bool {
-        let now = Self::current_timestamp();
-        now - self.last_ping > timeout_secs
+        Self::current_timestamp().saturating_sub(self.last_ping) > timeout_secs
+    }
+}
+
+// Какие данные клиент хочет получать в потоке
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedKind {
+    #[default]
+    Trades,
+    Quotes,
+    Both,
+}
+
+impl fmt::Display for FeedKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FeedKind::Trades => "TRADES",
+            FeedKind::Quotes => "QUOTES",
+            FeedKind::Both => "BOTH",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Дополнительные параметры команды STREAM (после списка тикеров)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamOptions {
+    pub feed: FeedKind,
+    // Инкрементальные обновления стакана
+    pub depth: bool,
+    // При отставании клиента отправлять только последнюю котировку тикера
+    pub conflate: bool,
+    // Не больше стольких сделок (и котировок bid/ask) тикера в секунду (RATE N/s)
+    pub max_rate: Option<u32>,
+    // Только каждая N-я сделка (котировка bid/ask) тикера (EVERY N)
+    pub every: Option<u32>,
+    // Своя политика переполнения очередей клиента (OVERFLOW <policy>);
+    // None - политика сервера
+    pub overflow: Option<OverflowPolicy>,
+    // Вместо сделок и котировок - закрытые бары этого интервала (BARS <interval>)
+    pub bars: Option<BarInterval>,
+}
+
+// Целое положительное значение параметра STREAM
+fn positive_option(name: &str, value: &str) -> Result<u32, CommandError> {
+    value
+        .parse::<u32>()
+        .ok()
+        .filter(|value| *value > 0)
+        .ok_or_else(|| {
+            CommandError::InvalidFormat(format!(
+                "{} expects a positive number, got '{}'",
+                name, value
+            ))
+        })
+}
+
+impl StreamOptions {
+    pub fn parse(tokens: &[&str]) -> Result<Self, CommandError> {
+        let mut options = StreamOptions::default();
+        let mut tokens = tokens.iter();
+
+        while let Some(token) = tokens.next() {
+            match token.to_uppercase().as_str() {
+                "TRADES" => options.feed = FeedKind::Trades,
+                "QUOTES" => options.feed = FeedKind::Quotes,
+                "BOTH" => options.feed = FeedKind::Both,
+                "DEPTH" => options.depth = true,
+                "CONFLATE" => options.conflate = true,
+                "RATE" => {
+                    let value = tokens.next().copied().unwrap_or_default();
+                    let rate = value.strip_suffix("/s").unwrap_or(value);
+                    options.max_rate = Some(positive_option("RATE", rate)?);
+                }
+                "EVERY" => {
+                    let value = tokens.next().copied().unwrap_or_default();
+                    options.every = Some(positive_option("EVERY", value)?);
+                }
+                "OVERFLOW" => {
+                    let value = tokens.next().copied().unwrap_or_default();
+                    let policy = OverflowPolicy::parse(value).ok_or_else(|| {
+                        CommandError::InvalidFormat(format!(
+                            "OVERFLOW expects block, drop-oldest, drop-newest or disconnect, got '{}'",
+                            value
+                        ))
+                    })?;
+                    options.overflow = Some(policy);
+                }
+                "BARS" => {
+                    let value = tokens.next().copied().unwrap_or_default();
+                    let interval = BarInterval::parse(value).ok_or_else(|| {
+                        CommandError::InvalidFormat(format!(
+                            "BARS expects 1s, 1m or 5m, got '{}'",
+                            value
+                        ))
+                    })?;
+                    options.bars = Some(interval);
+                }
+                other => {
+                    return Err(CommandError::InvalidFormat(format!(
+                        "Unknown STREAM option: {}",
+                        other
+                    )));
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    // Нужно ли отправлять событие клиенту с такими параметрами
+    pub fn accepts(&self, event: &QuoteEvent) -> bool {
+        // Подписчику баров - только бары своего интервала и статусы
+        if let Some(interval) = self.bars {
+            return match event {
+                QuoteEvent::Bar(bar) => bar.interval == interval.name(),
+                QuoteEvent::Status(_) => true,
+                _ => false,
+            };
+        }
+        match event {
+            QuoteEvent::Trade(_) => self.feed != FeedKind::Quotes,
+            QuoteEvent::Quote(_) => self.feed != FeedKind::Trades,
+            QuoteEvent::Depth(_) => self.depth,
+            QuoteEvent::Status(_) => true,
+            QuoteEvent::Bar(_) => false,
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum Command {
     Stream {
+        // Адрес udp://host:port как его передал клиент: при разборе он только
+        // проверяется, имя хоста отправитель разрешает заново после ошибок
         udp_addr: String,
         tickers: Vec<String>,
+        options: StreamOptions,
+    },
+    Bars {
+        ticker: String,
+        interval: BarInterval,
+        count: usize,
+    },
+    // Пустой список - все тикеры
+    Snapshot {
+        tickers: Vec<String>,
+    },
+    Shock {
+        ticker: String,
+        percent: f64,
+        duration_secs: u64,
+    },
+    Admin {
+        token: String,
+    },
+    // Вход по API ключу из файла ключей
+    Auth {
+        key: String,
+    },
+    AddTicker {
+        ticker: String,
+        price: Option<f64>,
+        volume: Option<u32>,
+    },
+    RemoveTicker {
+        ticker: String,
+    },
+    // Изменение параметров модели тикера; ticker = "*" - для всех тикеров
+    Set {
+        ticker: String,
+        updates: Vec<ParamUpdate>,
+    },
+    // Продолжение сессии после переподключения по токену из приветствия
+    Resume {
+        token: String,
+    },
+    // Список подключенных клиентов (админ)
+    Clients,
+    // Отключение клиента с остановкой его UDP потока (админ)
+    Kick {
+        client_id: String,
+    },
+    // Цены и параметры модели тикера или всех тикеров (админ)
+    Params {
+        ticker: Option<String>,
     },
+    // Итог последней перезагрузки конфигурации (админ)
+    ReloadStatus,
+    // Согласование версии протокола и возможностей
+    Hello {
+        version: u32,
+        capabilities: Vec<String>,
+    },
+    // Статистика доставки текущей сессии
+    Stats,
+    // Список тикеров, доступных соединению
+    List,
+    // Токен текущей сессии: им клиент подписывает UDP ping (PING <token>)
+    Id,
     Ping,
     Stop,
     Help,
 }
 
+// Параметр ценовой модели, изменяемый командой SET
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamUpdate {
+    Volatility(f64),
+    Drift(f64),
+    SpreadBps(f64),
+    BaseVolume(u32),
+    IntervalMs(u64),
+}
+
+impl ParamUpdate {
+    // Разбор пары key=value
+    pub fn parse(pair: &str) -> Result<Self, CommandError> {
+        let invalid = || CommandError::InvalidFormat(format!("Invalid parameter: {}", pair));
+        let (key, value) = pair.split_once('=').ok_or_else(invalid)?;
+
+        let update = match key.to_lowercase().as_str() {
+            "volatility" => ParamUpdate::Volatility(value.parse().map_err(|_| invalid())?),
+            "drift" => ParamUpdate::Drift(value.parse().map_err(|_| invalid())?),
+            "spread_bps" => ParamUpdate::SpreadBps(value.parse().map_err(|_| invalid())?),
+            "base_volume" => ParamUpdate::BaseVolume(value.parse().map_err(|_| invalid())?),
+            "interval_ms" => ParamUpdate::IntervalMs(value.parse().map_err(|_| invalid())?),
+            _ => {
+                return Err(CommandError::InvalidFormat(format!(
+                    "Unknown parameter: {} (expected volatility, drift, spread_bps, base_volume or interval_ms)",
+                    key
+                )));
+            }
+        };
+
+        let valid = match update {
+            ParamUpdate::Volatility(v) | ParamUpdate::SpreadBps(v) => v.is_finite() && v >= 0.0,
+            ParamUpdate::Drift(v) => v.is_finite(),
+            ParamUpdate::BaseVolume(v) => v > 0,
+            ParamUpdate::IntervalMs(v) => v > 0,
+        };
+        if !valid {
+            return Err(invalid());
+        }
+        Ok(update)
+    }
+}
+
+// Версия протокола управления; HELLO договаривается о min(клиент, сервер)
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Возможности сервера из ответа на HELLO. Клиент без HELLO получает то же
+// поведение, что и раньше; новые возможности появляются здесь, и клиент
+// включает их, только увидев в списке.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    // Форматы датаграмм
+    pub formats: Vec<String>,
+    // Сжатие датаграмм; пусто - без сжатия
+    pub compression: Vec<String>,
+    // Транспорты потока котировок
+    pub transports: Vec<String>,
+    // Параметры и команды: quotes, depth, conflate, rate, every, resume, bars
+    pub features: Vec<String>,
+}
+
+impl Capabilities {
+    pub fn contains(&self, name: &str) -> bool {
+        [
+            &self.formats,
+            &self.compression,
+            &self.transports,
+            &self.features,
+        ]
+        .iter()
+        .any(|names| names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+    }
+}
+
+// Ответ сервера: HELLO {"version":1,"capabilities":{...},"accepted":[...]}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HelloResponse {
+    pub version: u32,
+    pub capabilities: Capabilities,
+    // Запрошенные клиентом возможности, которые сервер поддерживает
+    pub accepted: Vec<String>,
+}
+
+impl HelloResponse {
+    pub fn to_line(&self) -> String {
+        format!(
+            "HELLO {}\n",
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        serde_json::from_str(line.trim().strip_prefix("HELLO ")?).ok()
+    }
+}
+
+// Сколько баров возвращает BARS без явного count
+pub const DEFAULT_BARS_COUNT: usize = 10;
+// Длительность повышенной волатильности после SHOCK без явного значения, секунд
+pub const DEFAULT_SHOCK_SECS: u64 = 30;
+// Наибольший рост цены одним SHOCK, процентов (падение ограничено -100%)
+pub const MAX_SHOCK_PERCENT: f64 = 1000.0;
+
+// Коды ошибок протокола; строка ошибки - ERR <status> <NAME> [подробности].
+// Число - класс ошибки как в HTTP, имя - стабильный идентификатор, по которому
+// клиенту стоит ветвиться, подробности - для человека.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    InvalidCommand,
+    InvalidAddress,
+    NoTickers,
+    Unauthorized,
+    Forbidden,
+    UnknownTicker,
+    UnknownSession,
+    NotStreaming,
+    TickerExists,
+    LineTooLong,
+    RateLimited,
+    Internal,
+    NotSupported,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 13] = [
+        ErrorCode::InvalidCommand,
+        ErrorCode::InvalidAddress,
+        ErrorCode::NoTickers,
+        ErrorCode::Unauthorized,
+        ErrorCode::Forbidden,
+        ErrorCode::UnknownTicker,
+        ErrorCode::UnknownSession,
+        ErrorCode::NotStreaming,
+        ErrorCode::TickerExists,
+        ErrorCode::LineTooLong,
+        ErrorCode::RateLimited,
+        ErrorCode::Internal,
+        ErrorCode::NotSupported,
+    ];
+
+    pub fn status(self) -> u16 {
+        match self {
+            ErrorCode::InvalidCommand | ErrorCode::InvalidAddress | ErrorCode::NoTickers => 400,
+            ErrorCode::Unauthorized => 401,
+            ErrorCode::Forbidden => 403,
+            ErrorCode::UnknownTicker | ErrorCode::UnknownSession => 404,
+            ErrorCode::NotStreaming | ErrorCode::TickerExists => 409,
+            ErrorCode::LineTooLong => 413,
+            ErrorCode::RateLimited => 429,
+            ErrorCode::Internal => 500,
+            ErrorCode::NotSupported => 501,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidCommand => "INVALID_COMMAND",
+            ErrorCode::InvalidAddress => "INVALID_ADDRESS",
+            ErrorCode::NoTickers => "NO_TICKERS",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::UnknownTicker => "UNKNOWN_TICKER",
+            ErrorCode::UnknownSession => "UNKNOWN_SESSION",
+            ErrorCode::NotStreaming => "NOT_STREAMING",
+            ErrorCode::TickerExists => "TICKER_EXISTS",
+            ErrorCode::LineTooLong => "LINE_TOO_LONG",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::Internal => "INTERNAL",
+            ErrorCode::NotSupported => "NOT_SUPPORTED",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|code| code.name() == name)
+    }
+}
+
+// Префикс строки ошибки: ERR 404 UNKNOWN_TICKER
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ERR {} {}", self.status(), self.name())
+    }
+}
+
+// Ошибка из ответа сервера. Имя хранится строкой, чтобы клиент разбирал и
+// коды, которые появятся в более новых версиях сервера.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub status: u16,
+    pub name: String,
+    pub detail: String,
+}
+
+impl ErrorResponse {
+    // None - не строка ошибки или ошибка старого сервера без кода
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.trim().strip_prefix("ERR ")?.splitn(3, ' ');
+        let status = parts.next()?.parse().ok()?;
+        let name = parts.next()?;
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+            return None;
+        }
+        Some(ErrorResponse {
+            status,
+            name: name.to_string(),
+            detail: parts.next().unwrap_or_default().to_string(),
+        })
+    }
+
+    pub fn code(&self) -> Option<ErrorCode> {
+        ErrorCode::from_name(&self.name)
+    }
+
+    pub fn is(&self, code: ErrorCode) -> bool {
+        self.name == code.name()
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum CommandError {
-    #[error("ERR Invalid command format: {0}")]
+    #[error("{} {}", ErrorCode::InvalidCommand, .0)]
     InvalidFormat(String),
-    #[error("ERR Invalid UDP address: {0}")]
+    #[error("{} {}", ErrorCode::InvalidAddress, .0)]
     InvalidAddress(String),
-    #[error("ERR No tickers specified")]
+    #[error("{}", ErrorCode::NoTickers)]
     NoTickers,
-    #[error("ERR Invalid ticker: {0}")]
+    #[error("{} {}", ErrorCode::UnknownTicker, .0)]
     InvalidTicker(String),
-    #[error("ERR IO error: {0}")]
+    // Нет входа или неверный ключ/токен
+    #[error("{} {}", ErrorCode::Unauthorized, .0)]
+    Unauthorized(String),
+    // Вход выполнен, но прав на команду или тикер нет
+    #[error("{} {}", ErrorCode::Forbidden, .0)]
+    Forbidden(String),
+    #[error("{} {}", ErrorCode::UnknownSession, .0)]
+    UnknownSession(String),
+    #[error("{}", ErrorCode::NotStreaming)]
+    NotStreaming,
+    #[error("{} {}", ErrorCode::TickerExists, .0)]
+    TickerExists(String),
+    #[error("{} command longer than {} bytes", ErrorCode::LineTooLong, .0)]
+    LineTooLong(usize),
+    #[error("{} {}", ErrorCode::RateLimited, .0)]
+    RateLimited(String),
+    // Возможность выключена на этом сервере
+    #[error("{} {}", ErrorCode::NotSupported, .0)]
+    NotSupported(String),
+    #[error("{} {}", ErrorCode::Internal, .0)]
     IoError(#[from] std::io::Error),
 }
 
+impl CommandError {
+    // Подробности без префикса ERR <status> <NAME>
+    pub fn detail(&self) -> String {
+        let line = self.to_string();
+        let prefix = self.code().to_string();
+        line.strip_prefix(&prefix)
+            .unwrap_or(&line)
+            .trim_start()
+            .to_string()
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            CommandError::InvalidFormat(_) => ErrorCode::InvalidCommand,
+            CommandError::InvalidAddress(_) => ErrorCode::InvalidAddress,
+            CommandError::NoTickers => ErrorCode::NoTickers,
+            CommandError::InvalidTicker(_) => ErrorCode::UnknownTicker,
+            CommandError::Unauthorized(_) => ErrorCode::Unauthorized,
+            CommandError::Forbidden(_) => ErrorCode::Forbidden,
+            CommandError::UnknownSession(_) => ErrorCode::UnknownSession,
+            CommandError::NotStreaming => ErrorCode::NotStreaming,
+            CommandError::TickerExists(_) => ErrorCode::TickerExists,
+            CommandError::LineTooLong(_) => ErrorCode::LineTooLong,
+            CommandError::RateLimited(_) => ErrorCode::RateLimited,
+            CommandError::NotSupported(_) => ErrorCode::NotSupported,
+            CommandError::IoError(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+// Проверка адреса из STREAM: udp://host:port, имя хоста разрешается сразу,
+// чтобы клиент получил ошибку в ответ на команду, а не молчащий поток
+fn parse_udp_target(value: &str) -> Result<SocketAddr, CommandError> {
+    let Some(target) = value.strip_prefix("udp://") else {
+        return Err(CommandError::InvalidAddress(
+            "Address must start with udp://".to_string(),
+        ));
+    };
+    let addr = target
+        .to_socket_addrs()
+        .map_err(|e| CommandError::InvalidAddress(format!("{}: {}", target, e)))?
+        .next()
+        .ok_or_else(|| CommandError::InvalidAddress(format!("{} resolved to nothing", target)))?;
+    if addr.port() == 0 || addr.ip().is_unspecified() {
+        return Err(CommandError::InvalidAddress(format!(
+            "{} is not a valid destination",
+            target
+        )));
+    }
+    Ok(addr)
+}
+
 impl Command {
+    // Имя команды для журнала и метрик
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Stream { .. } => "STREAM",
+            Command::Bars { .. } => "BARS",
+            Command::Snapshot { .. } => "SNAPSHOT",
+            Command::Shock { .. } => "SHOCK",
+            Command::Admin { .. } => "ADMIN",
+            Command::Auth { .. } => "AUTH",
+            Command::AddTicker { .. } => "ADD_TICKER",
+            Command::RemoveTicker { .. } => "REMOVE_TICKER",
+            Command::Set { .. } => "SET",
+            Command::Resume { .. } => "RESUME",
+            Command::Clients => "CLIENTS",
+            Command::Kick { .. } => "KICK",
+            Command::Params { .. } => "PARAMS",
+            Command::ReloadStatus => "RELOAD_STATUS",
+            Command::Hello { .. } => "HELLO",
+            Command::Stats => "STATS",
+            Command::List => "LIST",
+            Command::Id => "ID",
+            Command::Ping => "PING",
+            Command::Stop => "STOP",
+            Command::Help => "HELP",
+        }
+    }
+
+    // Команды, требующие прав администратора
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Command::Shock { .. }
+                | Command::AddTicker { .. }
+                | Command::RemoveTicker { .. }
+                | Command::Set { .. }
+                | Command::Clients
+                | Command::Kick { .. }
+                | Command::Params { .. }
+                | Command::ReloadStatus
+        )
+    }
+
+    // Команды, которые принимает POST /api/command: административные и
+    // чтение последних цен
+    pub fn is_http(&self) -> bool {
+        self.is_admin() || matches!(self, Command::Snapshot { .. })
+    }
+
+    // Команды, доступные до AUTH при --require-auth
+    pub fn is_public(&self) -> bool {
+        matches!(
+            self,
+            Command::Auth { .. } | Command::Hello { .. } | Command::List | Command::Help
+        )
+    }
+
     pub fn parse(input: &str) -> Result<Self, CommandError> {
         let parts: Vec<&str> = input.split_whitespace().collect();
         if parts.is_empty() {
@@ -128,13 +1030,8 @@ impl Command {
                     ));
                 }
 
-                // Парсим UDP адрес
+                parse_udp_target(parts[1])?;
                 let udp_addr = parts[1].to_string();
-                if !udp_addr.starts_with("udp://") {
-                    return Err(CommandError::InvalidAddress(
-                        "Address must start with udp://".to_string(),
-                    ));
-                }
 
                 // Парсим тикеры - преобразуем в верхний регистр
                 if parts.len() < 3 {
@@ -151,8 +1048,214 @@ impl Command {
                     return Err(CommandError::NoTickers);
                 }
 
-                Ok(Command::Stream { udp_addr, tickers })
+                let options = StreamOptions::parse(&parts[3..])?;
+
+                Ok(Command::Stream {
+                    udp_addr,
+                    tickers,
+                    options,
+                })
+            }
+            "BARS" => {
+                if parts.len() < 3 {
+                    return Err(CommandError::InvalidFormat(
+                        "BARS requires ticker and interval (1s, 1m, 5m)".to_string(),
+                    ));
+                }
+
+                let ticker = parts[1].to_uppercase();
+                let interval = BarInterval::parse(parts[2]).ok_or_else(|| {
+                    CommandError::InvalidFormat(format!(
+                        "Invalid bar interval: {} (expected 1s, 1m or 5m)",
+                        parts[2]
+                    ))
+                })?;
+                let count = match parts.get(3) {
+                    Some(count) => count.parse().map_err(|_| {
+                        CommandError::InvalidFormat(format!("Invalid bar count: {}", count))
+                    })?,
+                    None => DEFAULT_BARS_COUNT,
+                };
+
+                Ok(Command::Bars {
+                    ticker,
+                    interval,
+                    count,
+                })
+            }
+            "SNAPSHOT" => {
+                let tickers = parts
+                    .get(1)
+                    .map(|list| {
+                        list.split(',')
+                            .map(|t| t.trim().to_uppercase())
+                            .filter(|t| !t.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Ok(Command::Snapshot { tickers })
+            }
+            "SHOCK" => {
+                if parts.len() < 3 {
+                    return Err(CommandError::InvalidFormat(
+                        "SHOCK requires ticker and price change, e.g. SHOCK AAPL -5%".to_string(),
+                    ));
+                }
+
+                let ticker = parts[1].to_uppercase();
+                let percent: f64 = parts[2].trim_end_matches('%').parse().map_err(|_| {
+                    CommandError::InvalidFormat(format!("Invalid price change: {}", parts[2]))
+                })?;
+                if !percent.is_finite() || percent <= -100.0 || percent > MAX_SHOCK_PERCENT {
+                    return Err(CommandError::InvalidFormat(format!(
+                        "Price change must be greater than -100% and at most +{}%",
+                        MAX_SHOCK_PERCENT
+                    )));
+                }
+                let duration_secs = match parts.get(3) {
+                    Some(secs) => secs.parse().map_err(|_| {
+                        CommandError::InvalidFormat(format!("Invalid shock duration: {}", secs))
+                    })?,
+                    None => DEFAULT_SHOCK_SECS,
+                };
+
+                Ok(Command::Shock {
+                    ticker,
+                    percent,
+                    duration_secs,
+                })
+            }
+            "ADMIN" => match parts.get(1) {
+                Some(token) => Ok(Command::Admin {
+                    token: token.to_string(),
+                }),
+                None => Err(CommandError::InvalidFormat(
+                    "ADMIN requires token".to_string(),
+                )),
+            },
+            "ADD_TICKER" => {
+                let ticker = parts.get(1).map(|t| t.to_uppercase()).ok_or_else(|| {
+                    CommandError::InvalidFormat("ADD_TICKER requires ticker".to_string())
+                })?;
+                let price = match parts.get(2) {
+                    Some(price) => match price.parse::<f64>() {
+                        Ok(price) if price > 0.0 => Some(price),
+                        _ => {
+                            return Err(CommandError::InvalidFormat(format!(
+                                "Invalid initial price: {}",
+                                price
+                            )));
+                        }
+                    },
+                    None => None,
+                };
+                let volume = match parts.get(3) {
+                    Some(volume) => Some(volume.parse().map_err(|_| {
+                        CommandError::InvalidFormat(format!("Invalid base volume: {}", volume))
+                    })?),
+                    None => None,
+                };
+
+                Ok(Command::AddTicker {
+                    ticker,
+                    price,
+                    volume,
+                })
+            }
+            "REMOVE_TICKER" => match parts.get(1) {
+                Some(ticker) => Ok(Command::RemoveTicker {
+                    ticker: ticker.to_uppercase(),
+                }),
+                None => Err(CommandError::InvalidFormat(
+                    "REMOVE_TICKER requires ticker".to_string(),
+                )),
+            },
+            "SET" => {
+                if parts.len() < 3 {
+                    return Err(CommandError::InvalidFormat(
+                        "SET requires ticker and key=value parameters".to_string(),
+                    ));
+                }
+                let updates = parts[2..]
+                    .iter()
+                    .map(|pair| ParamUpdate::parse(pair))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(Command::Set {
+                    ticker: parts[1].to_uppercase(),
+                    updates,
+                })
+            }
+            "SET_VOLATILITY" => {
+                if parts.len() < 3 {
+                    return Err(CommandError::InvalidFormat(
+                        "SET_VOLATILITY requires ticker and value".to_string(),
+                    ));
+                }
+                let update = ParamUpdate::parse(&format!("volatility={}", parts[2]))?;
+
+                Ok(Command::Set {
+                    ticker: parts[1].to_uppercase(),
+                    updates: vec![update],
+                })
+            }
+            "RESUME" => {
+                if parts.len() != 2 {
+                    return Err(CommandError::InvalidFormat(
+                        "RESUME requires a session token".to_string(),
+                    ));
+                }
+                Ok(Command::Resume {
+                    token: parts[1].to_string(),
+                })
+            }
+            "CLIENTS" => Ok(Command::Clients),
+            "KICK" => {
+                if parts.len() != 2 {
+                    return Err(CommandError::InvalidFormat(
+                        "KICK requires a client id".to_string(),
+                    ));
+                }
+                Ok(Command::Kick {
+                    client_id: parts[1].to_string(),
+                })
+            }
+            "PARAMS" => Ok(Command::Params {
+                ticker: parts.get(1).map(|ticker| ticker.to_uppercase()),
+            }),
+            "RELOAD_STATUS" => Ok(Command::ReloadStatus),
+            "HELLO" => {
+                let version = parts
+                    .get(1)
+                    .and_then(|version| version.parse::<u32>().ok())
+                    .filter(|&version| version > 0)
+                    .ok_or_else(|| {
+                        CommandError::InvalidFormat(
+                            "HELLO requires a protocol version >= 1".to_string(),
+                        )
+                    })?;
+                let capabilities = parts[2..]
+                    .iter()
+                    .flat_map(|list| list.split(','))
+                    .map(|name| name.trim().to_lowercase())
+                    .filter(|name| !name.is_empty())
+                    .collect();
+                Ok(Command::Hello {
+                    version,
+                    capabilities,
+                })
             }
+            "AUTH" => match parts.get(1) {
+                Some(key) => Ok(Command::Auth {
+                    key: key.to_string(),
+                }),
+                None => Err(CommandError::InvalidFormat(
+                    "AUTH requires API key".to_string(),
+                )),
+            },
+            "LIST" => Ok(Command::List),
+            "STATS" => Ok(Command::Stats),
+            "ID" => Ok(Command::Id),
             "PING" => Ok(Command::Ping),
             "STOP" => Ok(Command::Stop),
             "HELP" => Ok(Command::Help),
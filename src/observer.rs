@@ -0,0 +1,71 @@
+use crate::error::QuoteError;
+use crate::models::{ClientConfig, CommandError};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tracing::{info, trace, warn};
+
+// События жизненного цикла сервера для встраивающего кода: метрики, аудит,
+// проверки в тестах. Реализации по умолчанию пишут в журнал, поэтому
+// достаточно переопределить нужные методы. Методы вызываются из задач
+// соединений, UdpSender и обработчика ping и не должны надолго блокировать.
+pub trait ServerObserver: Send + Sync {
+    // Принято TCP соединение; client_id - выданный ему токен сессии
+    fn on_client_connected(&self, client_id: &str, peer: SocketAddr) {
+        info!(%client_id, %peer, "Client connected");
+    }
+
+    fn on_stream_started(&self, client_id: &str, config: &ClientConfig) {
+        info!(
+            %client_id,
+            udp_addr = %config.udp_addr,
+            tickers = %config.tickers.join(","),
+            "Started UDP streaming"
+        );
+    }
+
+    // На каждую отправленную датаграмму; sent - сколько всего отправлено клиенту
+    fn on_quote_sent(&self, client_id: &str, payload: &[u8], sent: u64) {
+        let _ = payload;
+        if sent.is_multiple_of(50) {
+            trace!(%client_id, seq = sent, "Quotes sent");
+        }
+    }
+
+    // Команда выполнена за elapsed; error - причина отказа
+    fn on_command(
+        &self,
+        client_id: &str,
+        command: &str,
+        elapsed: Duration,
+        error: Option<&CommandError>,
+    ) {
+        let _ = (client_id, command, elapsed, error);
+    }
+
+    // Пачка датаграмм ушла одним системным вызовом за elapsed
+    fn on_batch_sent(&self, client_id: &str, datagrams: usize, bytes: usize, elapsed: Duration) {
+        let _ = (client_id, datagrams, bytes, elapsed);
+    }
+
+    // Клиент удален по таймауту ping
+    fn on_client_stale(&self, client_id: &str, config: &ClientConfig) {
+        warn!(%client_id, udp_addr = %config.udp_addr, "Removed stale client");
+    }
+
+    // Клиент отключен как медленный потребитель: очередь дольше допустимого
+    // выше порогов или переполнилась при политике disconnect
+    fn on_slow_consumer(&self, client_id: &str, config: &ClientConfig, reason: &str) {
+        warn!(%client_id, udp_addr = %config.udp_addr, %reason, "Disconnected slow consumer");
+    }
+
+    // Ошибка команды клиента или запуска его UDP потока
+    fn on_error(&self, client_id: &str, error: &QuoteError) {
+        warn!(%client_id, %error, "Client error");
+    }
+}
+
+// Наблюдатель по умолчанию: только журнал
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogObserver;
+
+impl ServerObserver for LogObserver {}
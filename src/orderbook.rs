@@ -0,0 +1,179 @@
+use crate::models::{BookSide, DepthLevel, DepthUpdate};
+use rand::Rng;
+use rand::distributions::{Bernoulli, Distribution, Uniform};
+
+// Уменьшение размера заявок с каждым следующим уровнем стакана
+const SIZE_DECAY: f64 = 0.85;
+// Вероятность изменения размера на уровне за один цикл
+const CHURN_PROBABILITY: f64 = 0.3;
+const LOT_SIZE: u32 = 100;
+
+// Симуляция стакана заявок (Level 2) одного тикера.
+// Цены хранятся в шагах цены (tick), чтобы уровни можно было сравнивать точно.
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    levels: usize,
+    tick_size: f64,
+    seq: u64,
+    bids: Vec<(i64, u32)>,
+    asks: Vec<(i64, u32)>,
+    sizes: SizeDistributions,
+}
+
+// Распределения размеров заявок, создаются один раз на стакан
+#[derive(Debug, Clone, Copy)]
+struct SizeDistributions {
+    churn: Bernoulli,
+    // Множитель размера существующего уровня при изменении
+    resize: Uniform<f64>,
+    // Множитель размера нового уровня
+    fresh: Uniform<f64>,
+}
+
+impl SizeDistributions {
+    fn new() -> Self {
+        SizeDistributions {
+            churn: Bernoulli::new(CHURN_PROBABILITY).expect("churn probability is within 0..=1"),
+            resize: Uniform::new(0.7, 1.3),
+            fresh: Uniform::new(0.5, 1.5),
+        }
+    }
+}
+
+impl OrderBook {
+    pub fn new(levels: usize, tick_size: f64) -> Self {
+        OrderBook {
+            levels,
+            tick_size,
+            seq: 0,
+            bids: Vec::new(),
+            asks: Vec::new(),
+            sizes: SizeDistributions::new(),
+        }
+    }
+
+    // Перестроение стакана вокруг новых лучших цен.
+    // Возвращает инкрементальное обновление: изменившиеся уровни и удаленные (size = 0).
+    pub fn update(
+        &mut self,
+        ticker: &str,
+        best_bid: (f64, u32),
+        best_ask: (f64, u32),
+        rng: &mut impl Rng,
+    ) -> DepthUpdate {
+        let bid_tick = (best_bid.0 / self.tick_size).round() as i64;
+        let ask_tick = (best_ask.0 / self.tick_size).round() as i64;
+
+        let new_bids = self.rebuild_side(&self.bids, bid_tick, -1, best_bid.1, rng);
+        let new_asks = self.rebuild_side(&self.asks, ask_tick, 1, best_ask.1, rng);
+
+        let mut changes = Vec::new();
+        self.diff_side(BookSide::Bid, &self.bids, &new_bids, &mut changes);
+        self.diff_side(BookSide::Ask, &self.asks, &new_asks, &mut changes);
+
+        self.bids = new_bids;
+        self.asks = new_asks;
+        self.seq += 1;
+
+        DepthUpdate::new(ticker.to_string(), self.seq, false, changes)
+    }
+
+    // Полный снимок стакана для новых подписчиков
+    pub fn snapshot(&self, ticker: &str) -> DepthUpdate {
+        let mut levels = Vec::with_capacity(self.bids.len() + self.asks.len());
+        for &(tick, size) in &self.bids {
+            levels.push(self.level(BookSide::Bid, tick, size));
+        }
+        for &(tick, size) in &self.asks {
+            levels.push(self.level(BookSide::Ask, tick, size));
+        }
+        DepthUpdate::new(ticker.to_string(), self.seq, true, levels)
+    }
+
+    fn level(&self, side: BookSide, tick: i64, size: u32) -> DepthLevel {
+        DepthLevel {
+            side,
+            price: round_to_tick(tick, self.tick_size),
+            size,
+        }
+    }
+
+    // Уровни одной стороны: direction = -1 для bid (цены вниз), +1 для ask (вверх).
+    // На уже существующих уровнях размер сохраняется с вероятностью "оттока".
+    fn rebuild_side(
+        &self,
+        previous: &[(i64, u32)],
+        best_tick: i64,
+        direction: i64,
+        best_size: u32,
+        rng: &mut impl Rng,
+    ) -> Vec<(i64, u32)> {
+        let base_size = best_size.max(LOT_SIZE) as f64;
+        let sizes = &self.sizes;
+
+        (0..self.levels)
+            .map(|i| {
+                let tick = best_tick + direction * i as i64;
+                let size = if i == 0 {
+                    best_size.max(LOT_SIZE)
+                } else {
+                    match size_at(previous, tick, direction) {
+                        Some(size) if !sizes.churn.sample(rng) => size,
+                        Some(size) => round_lot(size as f64 * sizes.resize.sample(rng)),
+                        None => round_lot(
+                            base_size * SIZE_DECAY.powi(i as i32) * sizes.fresh.sample(rng),
+                        ),
+                    }
+                };
+                (tick, size)
+            })
+            .collect()
+    }
+
+    fn diff_side(
+        &self,
+        side: BookSide,
+        old: &[(i64, u32)],
+        new: &[(i64, u32)],
+        changes: &mut Vec<DepthLevel>,
+    ) {
+        let direction = match side {
+            BookSide::Bid => -1,
+            BookSide::Ask => 1,
+        };
+        for &(tick, size) in new {
+            if size_at(old, tick, direction) != Some(size) {
+                changes.push(self.level(side, tick, size));
+            }
+        }
+        for &(tick, _) in old {
+            if size_at(new, tick, direction).is_none() {
+                changes.push(self.level(side, tick, 0));
+            }
+        }
+    }
+}
+
+// Цена уровня без артефактов плавающей точки (859.5600000000001 -> 859.56)
+pub fn round_to_tick(ticks: i64, tick_size: f64) -> f64 {
+    let decimals = (-tick_size.log10()).ceil().max(0.0) as i32;
+    let factor = 10f64.powi(decimals);
+    (ticks as f64 * tick_size * factor).round() / factor
+}
+
+// Округление произвольной цены до ближайшего допустимого шага
+pub fn round_price(price: f64, tick_size: f64) -> f64 {
+    round_to_tick((price / tick_size).round() as i64, tick_size)
+}
+
+// Размер на уровне tick. Уровни стороны идут подряд, по шагу цены от лучшего
+// в сторону direction, поэтому уровень находится по смещению, без поиска.
+fn size_at(levels: &[(i64, u32)], tick: i64, direction: i64) -> Option<u32> {
+    let &(best, _) = levels.first()?;
+    let offset = usize::try_from((tick - best) * direction).ok()?;
+    levels.get(offset).map(|&(_, size)| size)
+}
+
+fn round_lot(size: f64) -> u32 {
+    ((size / LOT_SIZE as f64).round() as u32).max(1) * LOT_SIZE
+}
@@ -0,0 +1,492 @@
+use crate::error::QuoteError;
+use crate::models::{ClientConfig, CommandError};
+use crate::observer::{LogObserver, ServerObserver};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_OTLP_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_DELIVERY_SAMPLE: u64 = 100;
+// Спаны между выгрузками; лишние отбрасываются, а не копятся в памяти
+const MAX_PENDING_SPANS: usize = 4096;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(2);
+// Границы гистограмм, мс: от долей миллисекунды до секунды
+const DURATION_BOUNDS_MS: &[f64] = &[
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 1000.0,
+];
+
+// Куда и как часто выгружать трассы и метрики (OTLP/HTTP с JSON кодировкой,
+// порт 4318 коллектора, Jaeger или Tempo)
+#[derive(Debug, Clone)]
+pub struct OtelConfig {
+    // http://host:4318; пути /v1/traces и /v1/metrics добавляются сами
+    pub endpoint: String,
+    pub service_name: String,
+    pub export_interval: Duration,
+    // Спан доставки для каждой N-й пачки датаграмм; метрики считают все
+    pub delivery_sample: u64,
+}
+
+impl OtelConfig {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        OtelConfig {
+            endpoint: endpoint.into(),
+            service_name: "quote-server".to_string(),
+            export_interval: DEFAULT_OTLP_INTERVAL,
+            delivery_sample: DEFAULT_DELIVERY_SAMPLE,
+        }
+    }
+}
+
+// Наблюдатель сервера, превращающий события в спаны и метрики OTLP. Журнал
+// пишется как у LogObserver. Выгрузка идет фоновым потоком, поэтому вызовы
+// из потоков соединений и отправителей только накапливают данные.
+pub struct OtelObserver {
+    shared: Arc<Shared>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct Shared {
+    config: OtelConfig,
+    target: HttpTarget,
+    started_nanos: u64,
+    spans: Mutex<Vec<Value>>,
+    dropped_spans: AtomicU64,
+    metrics: Mutex<Metrics>,
+    batches: AtomicU64,
+    stop: AtomicBool,
+}
+
+impl OtelObserver {
+    pub fn start(config: OtelConfig) -> Result<Arc<Self>, QuoteError> {
+        let target = HttpTarget::parse(&config.endpoint).map_err(QuoteError::Config)?;
+        info!(
+            endpoint = %config.endpoint,
+            service = %config.service_name,
+            interval_ms = config.export_interval.as_millis() as u64,
+            "Exporting OpenTelemetry traces and metrics"
+        );
+        let shared = Arc::new(Shared {
+            config,
+            target,
+            started_nanos: unix_nanos(SystemTime::now()),
+            spans: Mutex::new(Vec::new()),
+            dropped_spans: AtomicU64::new(0),
+            metrics: Mutex::new(Metrics::default()),
+            batches: AtomicU64::new(0),
+            stop: AtomicBool::new(false),
+        });
+
+        let exporter = shared.clone();
+        let thread = thread::spawn(move || {
+            let tick = Duration::from_millis(100);
+            let mut waited = Duration::ZERO;
+            while !exporter.stop.load(Ordering::Relaxed) {
+                thread::sleep(tick);
+                waited += tick;
+                if waited >= exporter.config.export_interval {
+                    waited = Duration::ZERO;
+                    exporter.export();
+                }
+            }
+        });
+        Ok(Arc::new(OtelObserver {
+            shared,
+            thread: Mutex::new(Some(thread)),
+        }))
+    }
+
+    // Остановка фонового потока и последняя выгрузка, чтобы не потерять хвост
+    pub fn shutdown(&self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+        self.shared.export();
+    }
+
+    fn add_span(&self, span: Value) {
+        let mut spans = self.shared.spans.lock().unwrap();
+        if spans.len() < MAX_PENDING_SPANS {
+            spans.push(span);
+        } else {
+            self.shared.dropped_spans.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn count(&self, name: &'static str, attributes: Attributes, value: u64) {
+        self.shared
+            .metrics
+            .lock()
+            .unwrap()
+            .add(name, attributes, value);
+    }
+}
+
+impl ServerObserver for OtelObserver {
+    fn on_client_connected(&self, client_id: &str, peer: SocketAddr) {
+        LogObserver.on_client_connected(client_id, peer);
+        self.count("quote.clients.connected", Vec::new(), 1);
+    }
+
+    fn on_stream_started(&self, client_id: &str, config: &ClientConfig) {
+        LogObserver.on_stream_started(client_id, config);
+        self.count("quote.streams.started", Vec::new(), 1);
+    }
+
+    fn on_command(
+        &self,
+        client_id: &str,
+        command: &str,
+        elapsed: Duration,
+        error: Option<&CommandError>,
+    ) {
+        LogObserver.on_command(client_id, command, elapsed, error);
+        let status = error.map_or("OK", |e| e.code().name());
+        self.count(
+            "quote.commands",
+            vec![
+                ("command", command.to_string()),
+                ("status", status.to_string()),
+            ],
+            1,
+        );
+        self.shared.metrics.lock().unwrap().record(
+            "quote.command.duration",
+            vec![("command", command.to_string())],
+            elapsed,
+        );
+
+        let mut attributes = vec![
+            ("client.id", client_id.to_string()),
+            ("quote.command", command.to_string()),
+        ];
+        if let Some(e) = error {
+            attributes.push(("quote.error.code", e.code().name().to_string()));
+        }
+        // SERVER: команда клиента по управляющему соединению
+        self.add_span(span_json(
+            command,
+            2,
+            elapsed,
+            &attributes,
+            error.map(|e| e.to_string()),
+        ));
+    }
+
+    fn on_batch_sent(&self, client_id: &str, datagrams: usize, bytes: usize, elapsed: Duration) {
+        LogObserver.on_batch_sent(client_id, datagrams, bytes, elapsed);
+        {
+            let mut metrics = self.shared.metrics.lock().unwrap();
+            metrics.add("quote.datagrams.sent", Vec::new(), datagrams as u64);
+            metrics.add("quote.bytes.sent", Vec::new(), bytes as u64);
+            metrics.record("quote.delivery.duration", Vec::new(), elapsed);
+        }
+
+        let batch = self.shared.batches.fetch_add(1, Ordering::Relaxed);
+        let sample = self.shared.config.delivery_sample.max(1);
+        if batch.is_multiple_of(sample) {
+            // PRODUCER: котировки уходят получателю по UDP
+            self.add_span(span_json(
+                "deliver",
+                4,
+                elapsed,
+                &[
+                    ("client.id", client_id.to_string()),
+                    ("quote.datagrams", datagrams.to_string()),
+                    ("quote.bytes", bytes.to_string()),
+                ],
+                None,
+            ));
+        }
+    }
+
+    fn on_client_stale(&self, client_id: &str, config: &ClientConfig) {
+        LogObserver.on_client_stale(client_id, config);
+        self.count("quote.clients.stale", Vec::new(), 1);
+    }
+
+    fn on_slow_consumer(&self, client_id: &str, config: &ClientConfig, reason: &str) {
+        LogObserver.on_slow_consumer(client_id, config, reason);
+        self.count("quote.clients.slow", Vec::new(), 1);
+    }
+
+    fn on_error(&self, client_id: &str, error: &QuoteError) {
+        LogObserver.on_error(client_id, error);
+        let kind = match error {
+            QuoteError::Config(_) => "CONFIG",
+            QuoteError::Protocol(e) => e.code().name(),
+            QuoteError::Transport(_) => "TRANSPORT",
+            QuoteError::Generator(_) => "GENERATOR",
+        };
+        self.count("quote.errors", vec![("kind", kind.to_string())], 1);
+    }
+}
+
+impl Shared {
+    fn export(&self) {
+        let spans = std::mem::take(&mut *self.spans.lock().unwrap());
+        let dropped = self.dropped_spans.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(dropped, "OTLP span buffer overflowed");
+        }
+        let resource = json!({
+            "attributes": [attribute("service.name", &self.config.service_name)]
+        });
+        let scope = json!({ "name": "quote_common", "version": env!("CARGO_PKG_VERSION") });
+
+        if !spans.is_empty() {
+            let count = spans.len();
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": resource,
+                    "scopeSpans": [{ "scope": scope, "spans": spans }]
+                }]
+            });
+            match self.target.post("/v1/traces", &body.to_string()) {
+                Ok(()) => debug!(spans = count, "Exported OTLP traces"),
+                Err(e) => warn!(error = %e, "OTLP trace export failed"),
+            }
+        }
+
+        let metrics = self
+            .metrics
+            .lock()
+            .unwrap()
+            .to_json(self.started_nanos, unix_nanos(SystemTime::now()));
+        if metrics.is_empty() {
+            return;
+        }
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": resource,
+                "scopeMetrics": [{ "scope": scope, "metrics": metrics }]
+            }]
+        });
+        if let Err(e) = self.target.post("/v1/metrics", &body.to_string()) {
+            warn!(error = %e, "OTLP metrics export failed");
+        }
+    }
+}
+
+type Attributes = Vec<(&'static str, String)>;
+
+// Накопительные (cumulative) счетчики и гистограммы с момента запуска
+#[derive(Default)]
+struct Metrics {
+    counters: BTreeMap<(&'static str, Attributes), u64>,
+    histograms: BTreeMap<(&'static str, Attributes), Histogram>,
+}
+
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    fn add(&mut self, name: &'static str, attributes: Attributes, value: u64) {
+        *self.counters.entry((name, attributes)).or_insert(0) += value;
+    }
+
+    fn record(&mut self, name: &'static str, attributes: Attributes, elapsed: Duration) {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        let histogram = self
+            .histograms
+            .entry((name, attributes))
+            .or_insert_with(|| Histogram {
+                buckets: vec![0; DURATION_BOUNDS_MS.len() + 1],
+                count: 0,
+                sum: 0.0,
+            });
+        let bucket = DURATION_BOUNDS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(DURATION_BOUNDS_MS.len());
+        histogram.buckets[bucket] += 1;
+        histogram.count += 1;
+        histogram.sum += ms;
+    }
+
+    fn to_json(&self, start: u64, now: u64) -> Vec<Value> {
+        let mut sums: BTreeMap<&'static str, Vec<Value>> = BTreeMap::new();
+        for ((name, attributes), value) in &self.counters {
+            sums.entry(name).or_default().push(json!({
+                "attributes": attributes_json(attributes),
+                "startTimeUnixNano": start.to_string(),
+                "timeUnixNano": now.to_string(),
+                "asInt": value.to_string(),
+            }));
+        }
+        let mut histograms: BTreeMap<&'static str, Vec<Value>> = BTreeMap::new();
+        for ((name, attributes), histogram) in &self.histograms {
+            histograms.entry(name).or_default().push(json!({
+                "attributes": attributes_json(attributes),
+                "startTimeUnixNano": start.to_string(),
+                "timeUnixNano": now.to_string(),
+                "count": histogram.count.to_string(),
+                "sum": histogram.sum,
+                "bucketCounts": histogram.buckets.iter().map(u64::to_string).collect::<Vec<_>>(),
+                "explicitBounds": DURATION_BOUNDS_MS,
+            }));
+        }
+
+        // aggregationTemporality 2 - CUMULATIVE
+        let mut metrics: Vec<Value> = sums
+            .into_iter()
+            .map(|(name, points)| {
+                let unit = if name.starts_with("quote.bytes") {
+                    "By"
+                } else {
+                    "1"
+                };
+                json!({
+                    "name": name,
+                    "unit": unit,
+                    "sum": {
+                        "aggregationTemporality": 2,
+                        "isMonotonic": true,
+                        "dataPoints": points,
+                    }
+                })
+            })
+            .collect();
+        metrics.extend(histograms.into_iter().map(|(name, points)| {
+            json!({
+                "name": name,
+                "unit": "ms",
+                "histogram": { "aggregationTemporality": 2, "dataPoints": points }
+            })
+        }));
+        metrics
+    }
+}
+
+// Завершившийся только что спан длительностью elapsed в собственной трассе
+fn span_json(
+    name: &str,
+    kind: u8,
+    elapsed: Duration,
+    attributes: &[(&'static str, String)],
+    error: Option<String>,
+) -> Value {
+    let end = unix_nanos(SystemTime::now());
+    let start = end.saturating_sub(elapsed.as_nanos() as u64);
+    // status.code: 1 - OK, 2 - ERROR
+    let status = match error {
+        Some(message) => json!({ "code": 2, "message": message }),
+        None => json!({ "code": 1 }),
+    };
+    json!({
+        "traceId": format!("{:032x}", rand::random::<u128>()),
+        "spanId": format!("{:016x}", rand::random::<u64>()),
+        "name": name,
+        "kind": kind,
+        "startTimeUnixNano": start.to_string(),
+        "endTimeUnixNano": end.to_string(),
+        "attributes": attributes_json(attributes),
+        "status": status,
+    })
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn attributes_json(attributes: &[(&'static str, String)]) -> Vec<Value> {
+    attributes
+        .iter()
+        .map(|(key, value)| attribute(key, value))
+        .collect()
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64)
+}
+
+// Адрес коллектора: только http://, как обычно у коллектора в той же сети
+struct HttpTarget {
+    host: String,
+    addr: String,
+    base_path: String,
+}
+
+impl HttpTarget {
+    fn parse(endpoint: &str) -> Result<Self, String> {
+        let rest = endpoint.strip_prefix("http://").ok_or_else(|| {
+            format!(
+                "OTLP endpoint must start with http:// (got {}), HTTPS is not supported",
+                endpoint
+            )
+        })?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], rest[slash..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(format!("OTLP endpoint {} has no host", endpoint));
+        }
+        let addr = if authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()))
+        {
+            authority.to_string()
+        } else {
+            format!("{}:4318", authority)
+        };
+        Ok(HttpTarget {
+            host: authority.to_string(),
+            addr,
+            base_path: path.to_string(),
+        })
+    }
+
+    fn post(&self, path: &str, body: &str) -> Result<(), String> {
+        let addr = self
+            .addr
+            .to_socket_addrs()
+            .map_err(|e| format!("cannot resolve {}: {}", self.addr, e))?
+            .next()
+            .ok_or_else(|| format!("{} resolved to nothing", self.addr))?;
+        let mut stream =
+            TcpStream::connect_timeout(&addr, HTTP_TIMEOUT).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(HTTP_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let request = format!(
+            "POST {}{} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.base_path,
+            path,
+            self.host,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| e.to_string())?;
+
+        // Ответ дочитывается до закрытия, чтобы коллектор не получил сброс соединения
+        let mut response = Vec::new();
+        stream
+            .take(64 * 1024)
+            .read_to_end(&mut response)
+            .map_err(|e| e.to_string())?;
+        let status_line = String::from_utf8_lossy(&response);
+        let status = status_line.split_whitespace().nth(1).unwrap_or("");
+        if status.starts_with('2') {
+            Ok(())
+        } else {
+            Err(format!(
+                "collector answered {}",
+                status_line.lines().next().unwrap_or("nothing")
+            ))
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use crate::clock;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+const CONNECTION_WINDOW_MS: u64 = 60_000;
+const COMMAND_WINDOW_MS: u64 = 1_000;
+// Окно, в котором считаются отклоненные команды
+const VIOLATION_WINDOW_MS: u64 = 60_000;
+// Столько отклоненных команд за минуту - и соединение разрывается
+pub const DEFAULT_MAX_VIOLATIONS: u32 = 20;
+
+// Ограничения на один IP адрес; 0 - без ограничения
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimits {
+    pub connections_per_minute: u32,
+    pub commands_per_second: u32,
+    pub max_violations: u32,
+}
+
+impl RateLimits {
+    pub fn unlimited() -> Self {
+        RateLimits {
+            connections_per_minute: 0,
+            commands_per_second: 0,
+            max_violations: DEFAULT_MAX_VIOLATIONS,
+        }
+    }
+}
+
+// Решение по очередной команде клиента
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandDecision {
+    Allowed,
+    // Команда отклонена, клиент получает ошибку
+    Throttled,
+    // Лимит превышается постоянно: соединение нужно закрыть
+    Drop,
+}
+
+#[derive(Debug, Default)]
+struct IpState {
+    connections: VecDeque<u64>,
+    commands: VecDeque<u64>,
+    violations: VecDeque<u64>,
+}
+
+impl IpState {
+    fn prune(&mut self, now: u64) {
+        prune(&mut self.connections, now, CONNECTION_WINDOW_MS);
+        prune(&mut self.commands, now, COMMAND_WINDOW_MS);
+        prune(&mut self.violations, now, VIOLATION_WINDOW_MS);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.connections.is_empty() && self.commands.is_empty() && self.violations.is_empty()
+    }
+}
+
+// Скользящие окна подключений и команд по IP адресам клиентов
+#[derive(Debug)]
+pub struct RateLimiter {
+    // Меняются при перезагрузке конфигурации; накопленные окна сохраняются
+    limits: Mutex<RateLimits>,
+    state: Mutex<HashMap<IpAddr, IpState>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            limits: Mutex::new(limits),
+            state: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn limits(&self) -> RateLimits {
+        *self.limits.lock().unwrap()
+    }
+
+    pub fn set_limits(&self, limits: RateLimits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    // Учет нового подключения. false - лимит подключений с этого IP исчерпан.
+    pub fn allow_connection(&self, ip: IpAddr) -> bool {
+        let limit = self.limits().connections_per_minute;
+        let now = clock::now_millis();
+        let mut state = self.state.lock().unwrap();
+
+        // Заодно забываем адреса без активности в окнах
+        state.retain(|_, ip_state| {
+            ip_state.prune(now);
+            !ip_state.is_empty()
+        });
+
+        let ip_state = state.entry(ip).or_default();
+        if limit > 0 && ip_state.connections.len() >= limit as usize {
+            return false;
+        }
+        ip_state.connections.push_back(now);
+        true
+    }
+
+    pub fn check_command(&self, ip: IpAddr) -> CommandDecision {
+        let limits = self.limits();
+        let limit = limits.commands_per_second;
+        if limit == 0 {
+            return CommandDecision::Allowed;
+        }
+
+        let now = clock::now_millis();
+        let mut state = self.state.lock().unwrap();
+        let ip_state = state.entry(ip).or_default();
+        ip_state.prune(now);
+
+        if ip_state.commands.len() < limit as usize {
+            ip_state.commands.push_back(now);
+            return CommandDecision::Allowed;
+        }
+
+        ip_state.violations.push_back(now);
+        if limits.max_violations > 0 && ip_state.violations.len() >= limits.max_violations as usize
+        {
+            CommandDecision::Drop
+        } else {
+            CommandDecision::Throttled
+        }
+    }
+}
+
+fn prune(events: &mut VecDeque<u64>, now: u64, window_ms: u64) {
+    while let Some(&first) = events.front() {
+        if now.saturating_sub(first) < window_ms {
+            break;
+        }
+        events.pop_front();
+    }
+}
@@ -0,0 +1,113 @@
+use crate::clock;
+use crate::models::QuoteEvent;
+use crate::subscription::FeedReceiver;
+use crossbeam_channel::{Receiver, unbounded};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, error, info};
+
+// Расширение файлов записи; replay распознает формат по нему
+pub const RECORD_EXTENSION: &str = "jsonl";
+
+// Запись сгенерированных сделок на диск в JSONL файлы с ротацией по времени.
+// Каждая строка - котировка в том же JSON формате, что уходит клиентам.
+pub struct Recorder {
+    dir: PathBuf,
+    rotate_every: Duration,
+}
+
+impl Recorder {
+    pub fn new(dir: &str, rotate_every: Duration) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Recorder {
+            dir: PathBuf::from(dir),
+            rotate_every,
+        })
+    }
+
+    // Запуск записи: по потоку на ресивер и общий поток записи в файл
+    pub fn start(self, receivers: Vec<FeedReceiver>) {
+        info!(
+            "Recording {} tickers to {} (rotation every {}s)",
+            receivers.len(),
+            self.dir.display(),
+            self.rotate_every.as_secs()
+        );
+
+        let (tx, rx) = unbounded();
+        for receiver in receivers {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                // Пишем уже сериализованный генератором JSON
+                for message in receiver.iter() {
+                    if let QuoteEvent::Trade(_) = message.event
+                        && tx.send(message.payload).is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+
+        thread::spawn(move || {
+            if let Err(e) = self.write_loop(rx) {
+                error!("Recorder stopped: {}", e);
+            }
+        });
+    }
+
+    fn write_loop(&self, rx: Receiver<Arc<[u8]>>) -> io::Result<()> {
+        let mut writer = self.open_file()?;
+        let rotate_ms = self.rotate_every.as_millis() as u64;
+        let mut opened_at = clock::now_millis();
+        let mut recorded: u64 = 0;
+
+        for payload in rx.iter() {
+            if clock::now_millis().saturating_sub(opened_at) >= rotate_ms {
+                writer.flush()?;
+                writer = self.open_file()?;
+                opened_at = clock::now_millis();
+            }
+
+            writer.write_all(&payload)?;
+            writer.write_all(b"\n")?;
+            recorded += 1;
+
+            // Сбрасываем буфер, когда очередь опустела, чтобы файл не отставал от потока
+            if rx.is_empty() {
+                writer.flush()?;
+            }
+            if recorded.is_multiple_of(1000) {
+                debug!("Recorded {} quotes", recorded);
+            }
+        }
+
+        writer.flush()
+    }
+
+    // Имя файла содержит время открытия, поэтому файлы сортируются хронологически
+    fn open_file(&self) -> io::Result<BufWriter<File>> {
+        let name = format!(
+            "quotes-{}.{}",
+            clock::now_local().format("%Y%m%d-%H%M%S%.3f"),
+            RECORD_EXTENSION
+        );
+        let path = self.dir.join(name);
+        info!("Recording quotes to {}", path.display());
+        Ok(BufWriter::new(File::create(path)?))
+    }
+}
+
+// Файлы записи в каталоге в хронологическом порядке
+pub fn recorded_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == RECORD_EXTENSION))
+        .collect();
+    files.sort();
+    Ok(files)
+}
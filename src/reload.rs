@@ -0,0 +1,172 @@
+use crate::clock;
+use crate::server_config::{ServerConfig, TickerSource};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+// Итог перезагрузки конфигурации (SIGHUP): какие настройки применены на лету,
+// а какие изменены в файле, но вступят в силу только после перезапуска.
+// Имена - ключи server.toml (они же флаги командной строки).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReloadReport {
+    pub at_ms: u64,
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+    // Конфигурация не прочитана или файл тикеров не перечитан
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ReloadReport {
+    // Изменения от работающей конфигурации active к новой
+    pub fn diff(active: &ServerConfig, new: &ServerConfig) -> Self {
+        let mut report = ReloadReport {
+            at_ms: clock::now_millis(),
+            ..Default::default()
+        };
+        let mut applied = |name: &str, changed: bool| {
+            if changed {
+                report.applied.push(name.to_string());
+            }
+        };
+        let (old_gen, new_gen) = (&active.generation, &new.generation);
+        applied("volatility", old_gen.volatility != new_gen.volatility);
+        applied("interval_ms", old_gen.interval_ms != new_gen.interval_ms);
+        applied(
+            "idle_slowdown",
+            old_gen.idle_slowdown != new_gen.idle_slowdown,
+        );
+        applied(
+            "ping_timeout",
+            active.ping_timeout_secs != new.ping_timeout_secs,
+        );
+        let (old_slow, new_slow) = (
+            &active.transport.slow_consumer,
+            &new.transport.slow_consumer,
+        );
+        applied(
+            "slow_consumer_queue",
+            old_slow.max_queue != new_slow.max_queue,
+        );
+        applied(
+            "slow_consumer_lag_ms",
+            old_slow.max_lag_ms != new_slow.max_lag_ms,
+        );
+        applied(
+            "slow_consumer_secs",
+            old_slow.sustain_secs != new_slow.sustain_secs,
+        );
+        let (old_limits, new_limits) = (&active.rate_limits, &new.rate_limits);
+        applied(
+            "max_connections_per_minute",
+            old_limits.connections_per_minute != new_limits.connections_per_minute,
+        );
+        applied(
+            "max_commands_per_second",
+            old_limits.commands_per_second != new_limits.commands_per_second,
+        );
+
+        let mut restart = |name: &str, changed: bool| {
+            if changed {
+                report.restart_required.push(name.to_string());
+            }
+        };
+        restart("bind_addr", active.bind_addr != new.bind_addr);
+        restart("port", active.port != new.port);
+        restart("ping_port", active.ping_port != new.ping_port);
+        restart("metrics_port", active.metrics_port != new.metrics_port);
+        restart(
+            "dashboard_port",
+            active.dashboard_port != new.dashboard_port,
+        );
+        if active.tickers != new.tickers {
+            restart(ticker_source_key(&active.tickers, &new.tickers), true);
+        }
+        restart("seed", old_gen.seed != new_gen.seed);
+        restart("arrival", old_gen.arrival != new_gen.arrival);
+        restart(
+            "channel_capacity",
+            old_gen.channel_capacity != new_gen.channel_capacity,
+        );
+        restart("overflow", old_gen.overflow != new_gen.overflow);
+        restart("depth_levels", old_gen.depth_levels != new_gen.depth_levels);
+        restart("market_open", old_gen.session != new_gen.session);
+        restart("generator_workers", old_gen.workers != new_gen.workers);
+        restart("history_size", old_gen.history_size != new_gen.history_size);
+        restart(
+            "subscription_gc_secs",
+            old_gen.subscription_gc_secs != new_gen.subscription_gc_secs,
+        );
+        let (old_transport, new_transport) = (&active.transport, &new.transport);
+        restart(
+            "send_batch",
+            old_transport.send_batch != new_transport.send_batch,
+        );
+        restart(
+            "net_loss",
+            old_transport.impairment != new_transport.impairment,
+        );
+        restart(
+            "connection_workers",
+            old_transport.connection_workers != new_transport.connection_workers,
+        );
+        restart(
+            "idle_timeout_secs",
+            old_transport.idle_timeout_secs != new_transport.idle_timeout_secs,
+        );
+        restart("liveness", old_transport.liveness != new_transport.liveness);
+        restart("allow_cidr", active.access_list != new.access_list);
+        restart("admin_token", active.admin_token != new.admin_token);
+        restart("api_keys", active.api_keys != new.api_keys);
+        restart("require_auth", active.require_auth != new.require_auth);
+        restart(
+            "persist_clients",
+            active.persist_clients != new.persist_clients,
+        );
+        restart("record", active.record != new.record);
+        report
+    }
+
+    // Новая конфигурация не прочитана; работающая остается без изменений
+    pub fn failed(error: impl Into<String>) -> Self {
+        ReloadReport {
+            at_ms: clock::now_millis(),
+            error: Some(error.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn log(&self) {
+        if let Some(e) = &self.error {
+            error!(error = %e, "Configuration reload failed");
+        }
+        if !self.applied.is_empty() {
+            info!(settings = %self.applied.join(","), "Configuration reloaded, applied");
+        } else if self.error.is_none() {
+            info!("Configuration reloaded, no runtime settings changed");
+        }
+        if !self.restart_required.is_empty() {
+            warn!(
+                settings = %self.restart_required.join(","),
+                "Changed settings take effect only after a restart"
+            );
+        }
+    }
+}
+
+// Ключ, под которым меняется источник тикеров
+fn ticker_source_key(active: &TickerSource, new: &TickerSource) -> &'static str {
+    match (active, new) {
+        (TickerSource::File { path, .. }, TickerSource::File { path: new_path, .. })
+            if path == new_path =>
+        {
+            "watch_tickers"
+        }
+        (TickerSource::File { .. }, TickerSource::File { .. }) => "ticker_file",
+        (TickerSource::Replay { path, .. }, TickerSource::Replay { path: new_path, .. })
+            if path == new_path =>
+        {
+            "speed"
+        }
+        _ => "replay",
+    }
+}
@@ -0,0 +1,168 @@
+use crate::models::{AssetClass, StockQuote};
+use crate::recorder::{RECORD_EXTENSION, recorded_files};
+use std::io;
+use std::path::Path;
+use tracing::{info, warn};
+
+// Исторические котировки для воспроизведения вместо случайной генерации.
+// Источник - CSV файл, JSONL файл или каталог записи --record.
+#[derive(Debug, Clone)]
+pub struct ReplaySource {
+    pub quotes: Vec<StockQuote>,
+    // Множитель скорости: 1.0 - реальное время, 2.0 - в два раза быстрее
+    pub speed: f64,
+}
+
+impl ReplaySource {
+    pub fn from_file(filename: &str, speed: f64) -> io::Result<Self> {
+        if speed <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Replay speed must be positive, got {}", speed),
+            ));
+        }
+
+        let path = Path::new(filename);
+        let mut quotes = if path.is_dir() {
+            // Каталог, записанный с --record
+            let mut quotes = Vec::new();
+            for file in recorded_files(path)? {
+                quotes.extend(parse_jsonl(&std::fs::read_to_string(file)?)?);
+            }
+            quotes
+        } else if path.extension().is_some_and(|ext| ext == RECORD_EXTENSION) {
+            parse_jsonl(&std::fs::read_to_string(path)?)?
+        } else {
+            parse_csv(&std::fs::read_to_string(path)?)?
+        };
+        // Стабильная сортировка сохраняет порядок котировок с одинаковым временем
+        quotes.sort_by_key(|quote| quote.timestamp);
+
+        info!(
+            "Loaded {} historical quotes from {} (speed x{})",
+            quotes.len(),
+            filename,
+            speed
+        );
+        Ok(ReplaySource { quotes, speed })
+    }
+
+    // Тикеры в порядке первого появления
+    pub fn tickers(&self) -> Vec<String> {
+        let mut tickers: Vec<String> = Vec::new();
+        for quote in &self.quotes {
+            if !tickers.contains(&quote.ticker) {
+                tickers.push(quote.ticker.clone());
+            }
+        }
+        tickers
+    }
+
+    pub fn first_price(&self, ticker: &str) -> Option<f64> {
+        self.quotes
+            .iter()
+            .find(|quote| quote.ticker == ticker)
+            .map(|quote| quote.price)
+    }
+}
+
+// CSV с колонками timestamp,ticker,price,volume (timestamp в миллисекундах).
+// Заголовок необязателен; при его наличии порядок колонок может быть любым.
+pub fn parse_csv(content: &str) -> io::Result<Vec<StockQuote>> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let mut columns = [0usize, 1, 2, 3];
+    let mut quotes = Vec::new();
+
+    if let Some((line_no, first)) = lines.next() {
+        let fields = split_csv_line(first);
+        if fields.iter().any(|f| f.eq_ignore_ascii_case("ticker")) {
+            for (i, name) in ["timestamp", "ticker", "price", "volume"]
+                .iter()
+                .enumerate()
+            {
+                columns[i] = fields
+                    .iter()
+                    .position(|f| f.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| invalid_data(line_no, format!("missing column '{}'", name)))?;
+            }
+        } else {
+            quotes.push(parse_record(&fields, &columns, line_no)?);
+        }
+    }
+
+    for (line_no, line) in lines {
+        let fields = split_csv_line(line);
+        match parse_record(&fields, &columns, line_no) {
+            Ok(quote) => quotes.push(quote),
+            Err(e) => warn!("Skipping invalid replay record: {}", e),
+        }
+    }
+
+    Ok(quotes)
+}
+
+// JSONL в формате записи --record: одна котировка в строке. Записи клиента
+// (--record-dir) содержат и другие события с полем "type" - они пропускаются.
+pub fn parse_jsonl(content: &str) -> io::Result<Vec<StockQuote>> {
+    let mut quotes = Vec::new();
+    for (line_no, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.contains("\"type\":") {
+            continue;
+        }
+        match StockQuote::from_json(line) {
+            Some(quote) => quotes.push(quote),
+            None => warn!(
+                "Skipping invalid replay record: {}",
+                invalid_data(line_no, "invalid JSON quote".to_string())
+            ),
+        }
+    }
+    Ok(quotes)
+}
+
+fn split_csv_line(line: &str) -> Vec<String> {
+    line.split(',')
+        .map(|field| field.trim().trim_matches('"').to_string())
+        .collect()
+}
+
+fn parse_record(fields: &[String], columns: &[usize; 4], line_no: usize) -> io::Result<StockQuote> {
+    let field = |i: usize| {
+        fields
+            .get(columns[i])
+            .ok_or_else(|| invalid_data(line_no, "not enough columns".to_string()))
+    };
+
+    let timestamp = field(0)?.parse().map_err(|_| {
+        invalid_data(
+            line_no,
+            format!("invalid timestamp '{}'", fields[columns[0]]),
+        )
+    })?;
+    let ticker = field(1)?.to_uppercase();
+    let price = field(2)?
+        .parse()
+        .map_err(|_| invalid_data(line_no, format!("invalid price '{}'", fields[columns[2]])))?;
+    let volume = field(3)?
+        .parse()
+        .map_err(|_| invalid_data(line_no, format!("invalid volume '{}'", fields[columns[3]])))?;
+
+    Ok(StockQuote {
+        ticker,
+        price,
+        volume,
+        timestamp,
+        asset_class: AssetClass::Equity,
+    })
+}
+
+fn invalid_data(line_no: usize, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line_no + 1, message),
+    )
+}
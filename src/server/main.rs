@@ -1,18 +1,118 @@
-use clap::Parser;
-use log::{error, info};
-use quote_common::{QuoteGenerator, TcpServer};
+use clap::{CommandFactory, Parser, Subcommand};
+use quote_common::arg_config::{ArgSources, merge_args};
+use quote_common::client_manager::{DEFAULT_SLOW_CONSUMER_SECS, Liveness, SlowConsumerLimits};
+use quote_common::daemon::{self, Daemon, PidFile};
+use quote_common::generator::{
+    DEFAULT_CHANNEL_CAPACITY, DEFAULT_HISTORY_SIZE, DEFAULT_IDLE_SLOWDOWN,
+    DEFAULT_SUBSCRIPTION_GC_SECS, OverflowPolicy,
+};
+use quote_common::rate_limiter::DEFAULT_MAX_VIOLATIONS;
+use quote_common::server_config::{
+    DEFAULT_GENERATION_INTERVAL_MS, DEFAULT_PING_PORT, DEFAULT_PING_TIMEOUT_SECS, DEFAULT_PORT,
+    DEFAULT_TICKER_FILE, DEFAULT_VOLATILITY,
+};
+use quote_common::session::parse_time;
+use quote_common::tcp_server::{DEFAULT_CONNECTION_WORKERS, DEFAULT_IDLE_TIMEOUT_SECS};
+use quote_common::udp_batch::DEFAULT_SEND_BATCH;
+use quote_common::{
+    AccessList, ArrivalProcess, GenerationConfig, KeyStore, NetworkImpairment, RateLimits,
+    RecordConfig, ReloadReport, ScaledClock, ServerConfig, SessionSchedule, TcpServer,
+    TickerSource, TransportConfig, clock,
+};
+use quote_common::{LogFilter, LogFormat, LogSubscriber, logging};
+#[cfg(feature = "otel")]
+use quote_common::{OtelConfig, OtelObserver, QuoteError};
+use std::ffi::OsString;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tracing::{error, info};
 
 // Константы для конфигурации
-const DEFAULT_PORT: u16 = 8080;
-const DEFAULT_PING_PORT: u16 = 34254;
-const DEFAULT_PING_TIMEOUT: u64 = 5;
-const DEFAULT_GENERATION_INTERVAL: u64 = 500;
-const DEFAULT_VOLATILITY: f64 = 0.01;
+const DEFAULT_OPENING_GAP: f64 = 0.02;
+const DEFAULT_RECORD_ROTATE_SECS: u64 = 3600;
+const DEFAULT_CONNECTIONS_PER_MINUTE: u32 = 60;
+const DEFAULT_COMMANDS_PER_SECOND: u32 = 20;
+// Загружается без --config, если лежит в текущем каталоге
+const DEFAULT_CONFIG: &str = "server.toml";
+// Переменные окружения QUOTE_SERVER_PORT, QUOTE_SERVER_PING_PORT и т.д.
+const ENV_PREFIX: &str = "QUOTE_SERVER_";
+const ARG_SOURCES: ArgSources = ArgSources {
+    default_config: DEFAULT_CONFIG,
+    env_prefix: Some(ENV_PREFIX),
+};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<ServerCommand>,
+
+    // Флаги без подкоманды - то же, что run
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum ServerCommand {
+    /// Run the quote server (the default when no subcommand is given)
+    Run(Args),
+    /// Check the configuration file, flags, ticker file and API keys without starting the server
+    ValidateConfig(Args),
+    /// Print the tickers of the configured source with their initial prices and model parameters
+    ListTickers(Args),
+    /// Print a server.toml with every option, its description and default value
+    GenerateSampleConfig {
+        /// Write the sample to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Overwrite the output file if it exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Serve recorded quotes (CSV file, JSONL file or --record directory) instead of generating them
+    Replay {
+        /// Recorded quotes to replay
+        file: String,
+
+        #[command(flatten)]
+        args: Args,
+    },
+}
+
+impl Cli {
+    fn into_command(self) -> ServerCommand {
+        self.command.unwrap_or(ServerCommand::Run(self.args))
+    }
+}
+
+impl ServerCommand {
+    // Флаги запуска сервера; None для подкоманд, которые его не запускают
+    fn server_args(self) -> Option<Args> {
+        match self {
+            ServerCommand::Run(args) => Some(args),
+            ServerCommand::Replay { file, mut args } => {
+                args.replay = Some(file);
+                Some(args)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
 struct Args {
+    /// Server configuration file, re-read on SIGHUP; QUOTE_SERVER_* environment variables and command-line flags override its values [default: server.toml, if present]
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Address to listen on for TCP and UDP ping, IPv4 or IPv6 (default: all interfaces, dual-stack when IPv6 is available)
+    #[arg(long)]
+    bind_addr: Option<IpAddr>,
+
     /// TCP server port
     #[arg(short, long, default_value_t = DEFAULT_PORT)]
     port: u16,
@@ -21,119 +121,730 @@ struct Args {
     #[arg(long, default_value_t = DEFAULT_PING_PORT)]
     ping_port: u16,
 
+    /// HTTP port for Prometheus metrics at /metrics (disabled if not set)
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// HTTP port for the web admin dashboard (disabled if not set); admin rights as for ADMIN/AUTH
+    #[arg(long)]
+    dashboard_port: Option<u16>,
+
     /// Volatility for price generation (0.0 to 1.0)
     #[arg(short = 'v', long, default_value_t = DEFAULT_VOLATILITY)]
     volatility: f64,
 
     /// Generation interval in milliseconds
-    #[arg(short = 'i', long, default_value_t = DEFAULT_GENERATION_INTERVAL)]
+    #[arg(short = 'i', long, default_value_t = DEFAULT_GENERATION_INTERVAL_MS)]
     interval_ms: u64,
 
+    /// Quote arrival process: fixed (constant interval) or poisson (exponential gaps with the interval as mean)
+    #[arg(long, default_value = "fixed")]
+    arrival: String,
+
+    /// Generate quotes for tickers without subscribers N times less often (0 = not at all)
+    #[arg(long, default_value_t = DEFAULT_IDLE_SLOWDOWN)]
+    idle_slowdown: u32,
+
+    /// Maximum events queued per subscriber and ticker
+    #[arg(long, default_value_t = DEFAULT_CHANNEL_CAPACITY)]
+    channel_capacity: usize,
+
+    /// What to do when a subscriber queue is full: block, drop-oldest, drop-newest or disconnect (clients can override it with OVERFLOW)
+    #[arg(long, default_value = "drop-oldest")]
+    overflow: String,
+
     /// Ping timeout in seconds
-    #[arg(short = 't', long, default_value_t = DEFAULT_PING_TIMEOUT)]
+    #[arg(short = 't', long, default_value_t = DEFAULT_PING_TIMEOUT_SECS)]
     ping_timeout: u64,
 
-    /// Ticker file path
-    #[arg(short = 'f', long, default_value = "tickers.txt")]
+    /// How client liveness is tracked: udp (pings to --ping-port) or tcp (open control connection / PING over TCP)
+    #[arg(long, default_value = "udp")]
+    liveness: String,
+
+    /// Disconnect a client whose queues hold more than N events for --slow-consumer-secs (0 = no limit)
+    #[arg(long, default_value_t = 0)]
+    slow_consumer_queue: u64,
+
+    /// Disconnect a client whose oldest queued event is more than N ms old for --slow-consumer-secs (0 = no limit)
+    #[arg(long, default_value_t = 0)]
+    slow_consumer_lag_ms: u64,
+
+    /// How many seconds a client may stay over the slow consumer limits before it is disconnected
+    #[arg(long, default_value_t = DEFAULT_SLOW_CONSUMER_SECS)]
+    slow_consumer_secs: u64,
+
+    /// Ticker file path (plain list or .toml with per-ticker parameters)
+    #[arg(short = 'f', long, default_value = DEFAULT_TICKER_FILE)]
     ticker_file: String,
 
-    /// Log level (error, warn, info, debug, trace)
+    /// Watch the ticker file and add/remove tickers at runtime when it changes
+    #[arg(long)]
+    watch_tickers: bool,
+
+    /// Market open time, HH:MM local time (quotes are generated around the clock if not set)
+    #[arg(long, requires = "market_close")]
+    market_open: Option<String>,
+
+    /// Market close time, HH:MM local time
+    #[arg(long, requires = "market_open")]
+    market_close: Option<String>,
+
+    /// Pre-market start time, HH:MM local time (slow quotes until market open)
+    #[arg(long, requires = "market_open")]
+    pre_market_open: Option<String>,
+
+    /// Maximum relative price gap applied at market open
+    #[arg(long, default_value_t = DEFAULT_OPENING_GAP)]
+    opening_gap: f64,
+
+    /// Order book depth levels per side (0 disables Level-2 simulation)
+    #[arg(long, default_value_t = 0)]
+    depth_levels: usize,
+
+    /// Generation worker threads; tickers are split between them (for thousands of tickers)
+    #[arg(long, default_value_t = 1)]
+    generator_workers: usize,
+
+    /// Trades kept in memory per ticker for history queries (0 disables)
+    #[arg(long, default_value_t = DEFAULT_HISTORY_SIZE)]
+    history_size: usize,
+
+    /// Sweep subscriber channels for closed clients every N seconds (0 disables)
+    #[arg(long, default_value_t = DEFAULT_SUBSCRIPTION_GC_SECS)]
+    subscription_gc_secs: u64,
+
+    /// Record every generated quote to rolling JSONL files in this directory
+    #[arg(long)]
+    record: Option<String>,
+
+    /// Start a new record file every N seconds
+    #[arg(long, default_value_t = DEFAULT_RECORD_ROTATE_SECS)]
+    record_rotate_secs: u64,
+
+    /// Replay historical quotes (CSV file, JSONL file or --record directory) instead of generating random data
+    #[arg(long)]
+    replay: Option<String>,
+
+    /// Replay speed multiplier (1.0 = real time)
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// Run the server clock N times faster than real time (timestamps, intervals, sessions, ping timeouts)
+    #[arg(long, default_value_t = 1.0)]
+    time_scale: f64,
+
+    /// Seed for deterministic price generation (random if not set)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Token required for admin commands (ADMIN <token>); without it admin commands are allowed from localhost only
+    #[arg(long)]
+    admin_token: Option<String>,
+
+    /// API keys file (TOML, [[keys]] tables with key, name, tickers, max_tickers, admin) for AUTH <key>
+    #[arg(long)]
+    api_keys: Option<String>,
+
+    /// Allow only HELP, LIST and HELLO before AUTH <key>; requires --api-keys
+    #[arg(long)]
+    require_auth: bool,
+
+    /// Maximum new connections per minute from one IP (0 = unlimited)
+    #[arg(long, default_value_t = DEFAULT_CONNECTIONS_PER_MINUTE)]
+    max_connections_per_minute: u32,
+
+    /// Maximum commands per second from one IP; persistent excess disconnects the client (0 = unlimited)
+    #[arg(long, default_value_t = DEFAULT_COMMANDS_PER_SECOND)]
+    max_commands_per_second: u32,
+
+    /// Simulate network loss: drop this percentage of outgoing UDP datagrams
+    #[arg(long, default_value_t = 0.0)]
+    net_loss: f64,
+
+    /// Simulate latency jitter: delay each datagram by a random 0..N milliseconds
+    #[arg(long, default_value_t = 0)]
+    net_jitter_ms: u64,
+
+    /// Simulate duplication: send this percentage of datagrams twice
+    #[arg(long, default_value_t = 0.0)]
+    net_duplicate: f64,
+
+    /// Simulate reordering: swap this percentage of datagrams with the next one
+    #[arg(long, default_value_t = 0.0)]
+    net_reorder: f64,
+
+    /// Max UDP datagrams per send syscall (sendmmsg on Linux); 1 disables batching
+    #[arg(long, default_value_t = DEFAULT_SEND_BATCH)]
+    send_batch: usize,
+
+    /// TCP control connections handled at once; beyond them clients get ERR 429 right away
+    #[arg(long, default_value_t = DEFAULT_CONNECTION_WORKERS)]
+    connection_workers: usize,
+
+    /// Close connections that neither authenticated nor started a stream after this many seconds without a command (0 = never)
+    #[arg(long, default_value_t = DEFAULT_IDLE_TIMEOUT_SECS)]
+    idle_timeout_secs: u64,
+
+    /// Accept clients only from this subnet (e.g. 10.0.0.0/8); may be repeated
+    #[arg(long = "allow-cidr", value_name = "CIDR")]
+    allow_cidr: Vec<String>,
+
+    /// Reject clients from this subnet, even if allowed by --allow-cidr; may be repeated
+    #[arg(long = "deny-cidr", value_name = "CIDR")]
+    deny_cidr: Vec<String>,
+
+    /// Save client subscriptions to this JSON file and restore their streams on restart
+    #[arg(long)]
+    persist_clients: Option<String>,
+
+    /// Export traces and metrics to this OpenTelemetry collector (OTLP/HTTP, e.g. http://localhost:4318)
+    #[cfg(feature = "otel")]
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// Service name reported to the OpenTelemetry collector
+    #[cfg(feature = "otel")]
+    #[arg(long, default_value = "quote-server")]
+    otlp_service_name: String,
+
+    /// Seconds between OTLP exports
+    #[cfg(feature = "otel")]
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    otlp_interval_secs: u64,
+
+    /// Log level (error, warn, info, debug, trace); RUST_LOG overrides it with a full filter
     #[arg(long, default_value = "info")]
     log_level: String,
 
+    /// Log output format (text, json)
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
+
     /// Enable colored output
     #[arg(long, default_value_t = true)]
     color: bool,
-}
-
-fn setup_logging(level: &str, color: bool) {
-    use env_logger::Env;
 
-    // Создаем специальное окружение с нужным уровнем логирования
-    let env = Env::default().filter_or("RUST_LOG", format!("quote_system={}", level));
+    /// Write the log to this file (appended) instead of stderr
+    #[arg(long)]
+    log_file: Option<String>,
 
-    let mut builder = env_logger::Builder::from_env(env);
+    /// Detach from the terminal and run in the background (Linux); stdout and stderr go to --log-file or /dev/null
+    #[arg(long)]
+    daemon: bool,
 
-    // Настраиваем формат
-    builder
-        .format_timestamp(Some(env_logger::TimestampPrecision::Millis))
-        .format_level(true)
-        .format_target(false)
-        .format_module_path(false);
+    /// Write the server PID to this file and remove it on exit
+    #[arg(long)]
+    pid_file: Option<String>,
+}
 
-    if color {
-        builder.format_indent(Some(4));
+// --log-level относится к библиотеке и самой программе; RUST_LOG заменяет
+// фильтр целиком: RUST_LOG=warn,quote_common::tcp_server=debug
+fn log_filter(level: &str) -> LogFilter {
+    let default = format!("quote_common={level},quote_server={level}");
+    match LogFilter::from_env_or(&default) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Invalid log filter: {}", e);
+            LogFilter::parse("quote_common=info,quote_server=info").expect("valid default filter")
+        }
     }
+}
 
-    // Инициализируем логгер
-    if let Err(e) = builder.try_init() {
+fn setup_logging(level: &str, format: LogFormat, color: bool, log_file: Option<&str>) {
+    let filter = log_filter(level);
+    let subscriber = match log_file.map(|path| daemon::open_log(Path::new(path))) {
+        Some(Ok(file)) => LogSubscriber::new(filter, format).with_file(file),
+        Some(Err(e)) => {
+            eprintln!("{}, logging to stderr", e);
+            LogSubscriber::new(filter, format).with_color(color)
+        }
+        None => LogSubscriber::new(filter, format).with_color(color),
+    };
+    if let Err(e) = logging::install(subscriber) {
         eprintln!("Failed to initialize logger: {}", e);
-        // Если не удалось инициализировать логгер, выводим хотя бы это сообщение
         eprintln!("Logging disabled. Using fallback to stdout.");
     }
 }
 
+// Уведомление systemd (Type=notify); без NOTIFY_SOCKET ничего не делает
+fn notify_systemd(state: &str) {
+    if let Err(e) = daemon::notify(state) {
+        error!(state, error = %e, "Failed to notify systemd");
+    }
+}
+
+// Параметры из server.toml и переменных окружения идут перед флагами
+// командной строки, после имени подкоманды. Подкоманды без --config
+// (generate-sample-config, help) файл не читают.
+fn merged_argv(argv: &[OsString]) -> Result<Vec<OsString>, String> {
+    let mut cli = Cli::command();
+    cli.build();
+    let subcommand = argv
+        .get(1)
+        .and_then(|arg| arg.to_str())
+        .and_then(|name| cli.find_subcommand(name));
+    let Some(subcommand) = subcommand else {
+        return merge_args(&cli, argv.to_vec(), &ARG_SOURCES);
+    };
+    if !subcommand
+        .get_arguments()
+        .any(|arg| arg.get_id() == "config")
+    {
+        return Ok(argv.to_vec());
+    }
+    let mut rest = vec![argv[0].clone()];
+    rest.extend_from_slice(&argv[2..]);
+    let merged = merge_args(subcommand, rest, &ARG_SOURCES)?;
+    Ok(argv[..2].iter().chain(&merged[1..]).cloned().collect())
+}
+
+// Флаги и файл конфигурации заново, как при запуске
+fn load_config(argv: &[OsString]) -> Result<(Args, ServerConfig), Box<dyn std::error::Error>> {
+    let cli = Cli::try_parse_from(merged_argv(argv)?)?;
+    let args = cli
+        .into_command()
+        .server_args()
+        .ok_or("the server was not started with run or replay")?;
+    let config = server_config(&args)?;
+    Ok((args, config))
+}
+
+// SIGHUP: новая конфигурация применяется к работающему серверу, уровень
+// журнала меняется сразу. active - флаги, с которыми работает программа.
+fn reload_config(server: &TcpServer, argv: &[OsString], active: &Mutex<Args>) {
+    info!("Reloading configuration...");
+    notify_systemd("RELOADING=1");
+    let report = match load_config(argv) {
+        Ok((args, config)) => {
+            let mut active = active.lock().unwrap();
+            let mut report = server.reload(&config);
+            if args.log_level != active.log_level
+                && logging::set_filter(log_filter(&args.log_level))
+            {
+                report.applied.push("log_level".to_string());
+                active.log_level = args.log_level.clone();
+            }
+            report
+                .restart_required
+                .extend(restart_only_changes(&active, &args));
+            report
+        }
+        Err(e) => ReloadReport::failed(e.to_string()),
+    };
+    server.publish_reload(report);
+    notify_systemd("READY=1");
+}
+
+// Флаги самой программы вне ServerConfig, которые не меняются на лету
+fn restart_only_changes(active: &Args, new: &Args) -> Vec<String> {
+    let mut changed = Vec::new();
+    let mut check = |name: &str, differs: bool| {
+        if differs {
+            changed.push(name.to_string());
+        }
+    };
+    check("log_format", active.log_format != new.log_format);
+    check("color", active.color != new.color);
+    check("log_file", active.log_file != new.log_file);
+    check("daemon", active.daemon != new.daemon);
+    check("pid_file", active.pid_file != new.pid_file);
+    check("time_scale", active.time_scale != new.time_scale);
+    #[cfg(feature = "otel")]
+    check("otlp_endpoint", active.otlp_endpoint != new.otlp_endpoint);
+    changed
+}
+
+// Наблюдатель, выгружающий трассы и метрики, если задан --otlp-endpoint
+#[cfg(feature = "otel")]
+fn with_otel(
+    server: TcpServer,
+    args: &Args,
+) -> Result<(TcpServer, Option<Arc<OtelObserver>>), QuoteError> {
+    let Some(endpoint) = &args.otlp_endpoint else {
+        return Ok((server, None));
+    };
+    let mut config = OtelConfig::new(endpoint.clone());
+    config.service_name = args.otlp_service_name.clone();
+    config.export_interval = Duration::from_secs(args.otlp_interval_secs);
+    let observer = OtelObserver::start(config)?;
+    Ok((server.with_observer(observer.clone()), Some(observer)))
+}
+
+// Расписание сессии из аргументов командной строки
+fn session_schedule(args: &Args) -> Result<Option<SessionSchedule>, String> {
+    let (Some(open), Some(close)) = (&args.market_open, &args.market_close) else {
+        return Ok(None);
+    };
+
+    let mut schedule = SessionSchedule::new(parse_time(open)?, parse_time(close)?)
+        .with_opening_gap(args.opening_gap);
+    if let Some(pre_market) = &args.pre_market_open {
+        schedule = schedule.with_pre_market(parse_time(pre_market)?);
+    }
+    Ok(Some(schedule))
+}
+
+// Конфигурация сервера из аргументов командной строки
+fn server_config(args: &Args) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+    let tickers = match &args.replay {
+        Some(path) => TickerSource::Replay {
+            path: path.clone(),
+            speed: args.speed,
+        },
+        None => TickerSource::File {
+            path: args.ticker_file.clone(),
+            watch: args.watch_tickers,
+        },
+    };
+    let arrival = ArrivalProcess::parse(&args.arrival).ok_or_else(|| {
+        format!(
+            "Invalid arrival process '{}', expected fixed or poisson",
+            args.arrival
+        )
+    })?;
+    let overflow = OverflowPolicy::parse(&args.overflow).ok_or_else(|| {
+        format!(
+            "Invalid overflow policy '{}', expected block, drop-oldest, drop-newest or disconnect",
+            args.overflow
+        )
+    })?;
+    let liveness = Liveness::parse(&args.liveness)
+        .ok_or_else(|| format!("Invalid liveness '{}', expected udp or tcp", args.liveness))?;
+
+    let config = ServerConfig {
+        bind_addr: args.bind_addr,
+        port: args.port,
+        ping_port: args.ping_port,
+        metrics_port: args.metrics_port,
+        dashboard_port: args.dashboard_port,
+        ping_timeout_secs: args.ping_timeout,
+        tickers,
+        generation: GenerationConfig {
+            interval_ms: args.interval_ms,
+            volatility: args.volatility,
+            seed: args.seed,
+            arrival,
+            idle_slowdown: args.idle_slowdown,
+            channel_capacity: args.channel_capacity,
+            overflow,
+            depth_levels: args.depth_levels,
+            workers: args.generator_workers,
+            subscription_gc_secs: args.subscription_gc_secs,
+            history_size: args.history_size,
+            session: session_schedule(args)?,
+        },
+        transport: TransportConfig {
+            send_batch: args.send_batch,
+            impairment: NetworkImpairment {
+                loss_pct: args.net_loss,
+                duplicate_pct: args.net_duplicate,
+                reorder_pct: args.net_reorder,
+                jitter_ms: args.net_jitter_ms,
+            },
+            liveness,
+            connection_workers: args.connection_workers,
+            idle_timeout_secs: args.idle_timeout_secs,
+            slow_consumer: SlowConsumerLimits {
+                max_queue: args.slow_consumer_queue,
+                max_lag_ms: args.slow_consumer_lag_ms,
+                sustain_secs: args.slow_consumer_secs,
+            },
+        },
+        rate_limits: RateLimits {
+            connections_per_minute: args.max_connections_per_minute,
+            commands_per_second: args.max_commands_per_second,
+            max_violations: DEFAULT_MAX_VIOLATIONS,
+        },
+        access_list: AccessList::parse(&args.allow_cidr, &args.deny_cidr)?,
+        admin_token: args.admin_token.clone(),
+        api_keys: args.api_keys.as_deref().map(KeyStore::load).transpose()?,
+        require_auth: args.require_auth,
+        persist_clients: args.persist_clients.clone(),
+        record: args.record.as_ref().map(|dir| RecordConfig {
+            dir: dir.clone(),
+            rotate_every: Duration::from_secs(args.record_rotate_secs),
+        }),
+    };
+    config.validate()?;
+    Ok(config)
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let raw_argv: Vec<OsString> = std::env::args_os().collect();
+    let argv = match merged_argv(&raw_argv) {
+        Ok(argv) => argv,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let result = match Cli::parse_from(argv).into_command() {
+        ServerCommand::ValidateConfig(args) => validate_config(&args),
+        ServerCommand::ListTickers(args) => list_tickers(&args),
+        ServerCommand::GenerateSampleConfig { output, force } => {
+            generate_sample_config(output.as_deref(), force)
+        }
+        command => {
+            let args = command.server_args().expect("run or replay");
+            return run(args, raw_argv);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// Проверка всего, что читается при запуске: флагов, server.toml, файла
+// тикеров или истории, API ключей и расписания сессии
+fn validate_config(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    if args.time_scale != 1.0 {
+        ScaledClock::new(args.time_scale)?;
+    }
+    let config = server_config(args)?;
+    let generator = config.ticker_generator()?;
+    if let Some(path) = &args.config {
+        println!("Config: {}", path);
+    }
+    match &config.tickers {
+        TickerSource::File { path, .. } => {
+            println!("Tickers: {} from {}", generator.tickers().len(), path)
+        }
+        TickerSource::Replay { path, .. } => {
+            println!(
+                "Replay: {} tickers from {}",
+                generator.tickers().len(),
+                path
+            )
+        }
+        TickerSource::List(tickers) => println!("Tickers: {}", tickers.len()),
+    }
+    if let Some(keys) = &config.api_keys {
+        println!("API keys: {}", keys.len());
+    }
+    println!("Configuration is valid");
+    Ok(())
+}
+
+// Тикеры источника с начальными ценами и параметрами модели
+fn list_tickers(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let generator = server_config(args)?.ticker_generator()?;
+    let mut table = format!(
+        "{:<10} {:<6} {:>12} {:>10} {:>8} {:>10} {:>11} {:>11}\n",
+        "TICKER",
+        "CLASS",
+        "PRICE",
+        "VOLATILITY",
+        "DRIFT",
+        "SPREAD_BPS",
+        "BASE_VOLUME",
+        "INTERVAL_MS"
+    );
+    for ticker in generator.params_snapshot(None) {
+        let class = if ticker.asset_class.is_equity() {
+            "equity"
+        } else {
+            "fx"
+        };
+        table.push_str(&format!(
+            "{:<10} {:<6} {:>12.*} {:>10} {:>8} {:>10} {:>11} {:>11}\n",
+            ticker.ticker,
+            class,
+            ticker.asset_class.price_decimals(),
+            ticker.price,
+            ticker.volatility,
+            ticker.drift,
+            ticker.spread_bps,
+            ticker.base_volume,
+            ticker
+                .interval_ms
+                .map_or("default".to_string(), |ms| ms.to_string())
+        ));
+    }
+    // Вывод, обрезанный head или less, - не ошибка
+    match std::io::stdout().lock().write_all(table.as_bytes()) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// server.toml со всеми флагами run: описание, затем закомментированный ключ
+// со значением по умолчанию
+fn sample_config() -> String {
+    let mut cli = Cli::command();
+    cli.build();
+    let run = cli.find_subcommand("run").expect("run subcommand");
+    let mut sample = String::from(
+        "# quote-server configuration. Keys are long flag names; QUOTE_SERVER_* environment\n\
+         # variables and command-line flags override them. Uncomment to change a default.\n",
+    );
+    for arg in run.get_arguments() {
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        if matches!(long, "config" | "help" | "version") {
+            continue;
+        }
+        if let Some(help) = arg.get_help() {
+            sample.push_str(&format!("\n# {}\n", help));
+        }
+        let value = match arg.get_default_values().first().and_then(|v| v.to_str()) {
+            Some(value) if value.parse::<f64>().is_ok() || value == "true" || value == "false" => {
+                value.to_string()
+            }
+            Some(value) => format!("{:?}", value),
+            None if matches!(arg.get_action(), clap::ArgAction::Append) => "[]".to_string(),
+            None => format!(
+                "<{}>",
+                arg.get_value_names()
+                    .and_then(|names| names.first())
+                    .map_or(long.to_uppercase(), |name| name.to_string())
+            ),
+        };
+        sample.push_str(&format!("# {} = {}\n", long.replace('-', "_"), value));
+    }
+    sample
+}
+
+fn generate_sample_config(
+    output: Option<&str>,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sample = sample_config();
+    let Some(path) = output else {
+        print!("{}", sample);
+        return Ok(());
+    };
+    if !force && Path::new(path).exists() {
+        return Err(format!("{} already exists, use --force to overwrite it", path).into());
+    }
+    std::fs::write(path, sample).map_err(|e| format!("cannot write {}: {}", path, e))?;
+    println!("Wrote sample configuration to {}", path);
+    Ok(())
+}
+
+// Запуск сервера: до Ctrl+C, SIGTERM или ошибки привязки портов
+fn run(args: Args, raw_argv: Vec<OsString>) -> Result<(), Box<dyn std::error::Error>> {
+    // Уход в фон до запуска любых потоков: после fork остается только текущий
+    let mut background = if args.daemon {
+        Some(Daemon::detach(args.log_file.as_deref().map(Path::new))?)
+    } else {
+        None
+    };
 
     // Инициализация логирования
-    setup_logging(&args.log_level, args.color);
+    setup_logging(
+        &args.log_level,
+        args.log_format,
+        args.color,
+        args.log_file.as_deref(),
+    );
+    let pid_file = args.pid_file.as_deref().map(PidFile::create).transpose()?;
 
     println!("=== Quote Server Starting ===");
     println!("TCP Port: {}", args.port);
     println!("Ping Port: {}", args.ping_port);
     println!("Log Level: {}", args.log_level);
+    if let Some(config) = &args.config {
+        println!("Config: {}", config);
+    }
     println!("=============================");
 
     info!("Starting Quote Server...");
     info!("Configuration:");
+    if let Some(ip) = args.bind_addr {
+        info!("  Bind address: {}", ip);
+    }
     info!("  TCP Server port: {}", args.port);
     info!("  Ping handler port: {}", args.ping_port);
+    if let Some(port) = args.metrics_port {
+        info!("  Metrics port: {}", port);
+    }
+    if let Some(port) = args.dashboard_port {
+        info!("  Dashboard port: {}", port);
+    }
     info!("  Volatility: {}", args.volatility);
     info!("  Generation interval: {}ms", args.interval_ms);
+    info!("  Arrival process: {}", args.arrival);
     info!("  Ping timeout: {}s", args.ping_timeout);
     info!("  Ticker file: {}", args.ticker_file);
+    if let (Some(open), Some(close)) = (&args.market_open, &args.market_close) {
+        info!("  Market session: {} - {}", open, close);
+    }
+    if let Some(seed) = args.seed {
+        info!("  Seed: {}", seed);
+    }
     info!("  Log level: {}", args.log_level);
     info!("  Colored output: {}", args.color);
 
-    // Загрузка тикеров из файла
-    println!("Loading tickers from {}...", args.ticker_file);
-    info!("Loading tickers from {}...", args.ticker_file);
-    let generator = QuoteGenerator::from_file(&args.ticker_file, args.volatility)?;
-    println!("Loaded tickers successfully");
-    info!("Loaded tickers successfully");
+    // Ускоренное время включается до создания генератора, чтобы все метки шли по нему
+    if args.time_scale != 1.0 {
+        let scaled = ScaledClock::new(args.time_scale)?;
+        info!("  Time scale: x{}", scaled.scale());
+        clock::set_clock(Arc::new(scaled));
+    }
 
-    // Запуск генератора котировок
-    generator.clone().start(args.interval_ms);
-    info!(
-        "Quote generator started with interval {}ms",
-        args.interval_ms
-    );
+    let config = server_config(&args)?;
+    match &config.tickers {
+        TickerSource::Replay { path, .. } => println!("Loading replay data from {}...", path),
+        TickerSource::File { path, .. } => {
+            println!("Loading tickers from {}...", path);
+            info!("Loading tickers from {}...", path);
+        }
+        TickerSource::List(_) => {}
+    }
 
     // Создание TCP сервера
     info!("Initializing TCP server...");
-    let tcp_server = TcpServer::new(generator, args.ping_timeout, args.ping_port);
+    let tcp_server = TcpServer::from_config(config)?;
+    #[cfg(feature = "otel")]
+    let (tcp_server, otel) = with_otel(tcp_server, &args)?;
+    println!("Loaded tickers successfully");
+    info!("Loaded tickers successfully");
+
+    // Ctrl+C и SIGTERM останавливают сервер штатно: клиенты получают
+    // SERVER_SHUTTING_DOWN, UDP потоки и генератор завершаются
+    let shutdown_server = tcp_server.clone();
+    ctrlc::set_handler(move || {
+        println!("Shutting down...");
+        notify_systemd("STOPPING=1");
+        shutdown_server.shutdown();
+    })?;
 
-    // Запуск TCP сервера
+    // SIGHUP перечитывает файл конфигурации, переменные окружения и файл тикеров
+    let reload_server = tcp_server.clone();
+    let active_args = Mutex::new(args.clone());
+    daemon::on_hangup(move || reload_config(&reload_server, &raw_argv, &active_args))?;
+
+    // Запуск TCP сервера; возвращает управление после остановки
     println!("Starting TCP server on port {}...", args.port);
     info!("Starting TCP server on port {}...", args.port);
-    match tcp_server.run(args.port) {
+    println!("Press Ctrl+C to stop the server");
+    let result = tcp_server.spawn().map(|handle| {
+        // Порты привязаны: запустивший демона процесс и systemd узнают о готовности
+        if let Some(background) = &mut background {
+            background.ready();
+        }
+        notify_systemd("READY=1");
+        handle.join();
+    });
+    #[cfg(feature = "otel")]
+    if let Some(otel) = &otel {
+        otel.shutdown();
+    }
+    match result {
         Ok(_) => {
-            println!("Server is running and ready for connections");
-            println!("Press Ctrl+C to stop the server");
-            info!("Server is running and ready for connections");
-            info!("Press Ctrl+C to stop the server");
-
-            // Бесконечный цикл для главного потока
-            loop {
-                std::thread::sleep(Duration::from_secs(1));
-            }
+            println!("Server stopped");
+            info!("Server stopped");
+            Ok(())
         }
         Err(e) => {
             eprintln!("Failed to start TCP server: {}", e);
             eprintln!("Please check if port {} is available", args.port);
             error!("Failed to start TCP server: {}", e);
             error!("Please check if port {} is available", args.port);
+            drop(pid_file);
             std::process::exit(1);
         }
     }
@@ -0,0 +1,292 @@
+use crate::access_list::AccessList;
+use crate::auth::KeyStore;
+use crate::client_manager::{Liveness, SlowConsumerLimits};
+use crate::error::QuoteError;
+use crate::generator::{
+    ArrivalProcess, DEFAULT_CHANNEL_CAPACITY, DEFAULT_HISTORY_SIZE, DEFAULT_IDLE_SLOWDOWN,
+    DEFAULT_SUBSCRIPTION_GC_SECS, OverflowPolicy, QuoteGenerator,
+};
+use crate::impairment::NetworkImpairment;
+use crate::rate_limiter::RateLimits;
+use crate::recorder::Recorder;
+use crate::session::SessionSchedule;
+use crate::tcp_server::{DEFAULT_CONNECTION_WORKERS, DEFAULT_IDLE_TIMEOUT_SECS};
+use crate::ticker_config::{self, TickerSpec};
+use crate::udp_batch::{DEFAULT_SEND_BATCH, MAX_SEND_BATCH};
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::info;
+
+pub const DEFAULT_PORT: u16 = 8080;
+pub const DEFAULT_PING_PORT: u16 = 34254;
+pub const DEFAULT_PING_TIMEOUT_SECS: u64 = 5;
+pub const DEFAULT_GENERATION_INTERVAL_MS: u64 = 500;
+pub const DEFAULT_VOLATILITY: f64 = 0.01;
+pub const DEFAULT_TICKER_FILE: &str = "tickers.txt";
+// Как часто проверяется изменение файла тикеров при watch
+const TICKER_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Откуда генератор берет тикеры
+#[derive(Debug, Clone, PartialEq)]
+pub enum TickerSource {
+    // Простой список или .toml с параметрами; watch - подхватывать изменения файла
+    File { path: String, watch: bool },
+    // Тикеры с параметрами по умолчанию, без файла
+    List(Vec<String>),
+    // История (CSV, JSONL или каталог записи) воспроизводится вместо генерации
+    Replay { path: String, speed: f64 },
+}
+
+// Параметры генерации котировок; при воспроизведении истории используется
+// только интервал и очереди подписчиков
+#[derive(Debug, Clone)]
+pub struct GenerationConfig {
+    pub interval_ms: u64,
+    pub volatility: f64,
+    // Детерминированные ценовые ряды; None - случайные
+    pub seed: Option<u64>,
+    pub arrival: ArrivalProcess,
+    pub idle_slowdown: u32,
+    pub channel_capacity: usize,
+    pub overflow: OverflowPolicy,
+    // Уровней стакана на сторону; 0 - без Level 2
+    pub depth_levels: usize,
+    pub session: Option<SessionSchedule>,
+    // Потоков генерации, между которыми делятся тикеры
+    pub workers: usize,
+    // Период сборки закрытых подписок, с; 0 - без сборки
+    pub subscription_gc_secs: u64,
+    // Последних сделок каждого тикера в памяти; 0 - без истории
+    pub history_size: usize,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        GenerationConfig {
+            interval_ms: DEFAULT_GENERATION_INTERVAL_MS,
+            volatility: DEFAULT_VOLATILITY,
+            seed: None,
+            arrival: ArrivalProcess::default(),
+            idle_slowdown: DEFAULT_IDLE_SLOWDOWN,
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            overflow: OverflowPolicy::default(),
+            depth_levels: 0,
+            session: None,
+            workers: 1,
+            subscription_gc_secs: DEFAULT_SUBSCRIPTION_GC_SECS,
+            history_size: DEFAULT_HISTORY_SIZE,
+        }
+    }
+}
+
+// Доставка котировок клиентам и проверка того, что клиент жив
+#[derive(Debug, Clone)]
+pub struct TransportConfig {
+    // Датаграмм на один системный вызов (sendmmsg); 1 - без пачек
+    pub send_batch: usize,
+    pub impairment: NetworkImpairment,
+    pub liveness: Liveness,
+    // Потоков обработки TCP соединений: столько соединений обслуживается
+    // одновременно, следующие сразу получают отказ
+    pub connection_workers: usize,
+    // Секунд без команд до закрытия соединения без входа и без потока; 0 - без ограничения
+    pub idle_timeout_secs: u64,
+    // Когда отключать клиентов, которые не успевают забирать свои очереди
+    pub slow_consumer: SlowConsumerLimits,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        TransportConfig {
+            send_batch: DEFAULT_SEND_BATCH,
+            impairment: NetworkImpairment::default(),
+            liveness: Liveness::default(),
+            connection_workers: DEFAULT_CONNECTION_WORKERS,
+            idle_timeout_secs: DEFAULT_IDLE_TIMEOUT_SECS,
+            slow_consumer: SlowConsumerLimits::default(),
+        }
+    }
+}
+
+// Запись всех котировок на диск в JSONL файлы
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordConfig {
+    pub dir: String,
+    pub rotate_every: Duration,
+}
+
+// Все настройки сервера: по ним TcpServer::from_config собирает генератор и
+// сервер без разбора командной строки
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    // Адрес TCP и UDP ping сокетов; None - все интерфейсы, IPv6 и IPv4
+    // (dual-stack), а без IPv6 - только IPv4
+    pub bind_addr: Option<IpAddr>,
+    // TCP порт; 0 - любой свободный
+    pub port: u16,
+    pub ping_port: u16,
+    // HTTP порт с /metrics для Prometheus на том же адресе; None - выключено
+    pub metrics_port: Option<u16>,
+    // HTTP порт веб-дашборда администратора; None - выключен
+    pub dashboard_port: Option<u16>,
+    pub ping_timeout_secs: u64,
+    pub tickers: TickerSource,
+    pub generation: GenerationConfig,
+    pub transport: TransportConfig,
+    pub rate_limits: RateLimits,
+    pub access_list: AccessList,
+    // Токен для административных команд; без него они доступны только с localhost
+    pub admin_token: Option<String>,
+    // API ключи для команды AUTH и их права
+    pub api_keys: Option<KeyStore>,
+    // Без AUTH доступны только HELP, LIST и HELLO
+    pub require_auth: bool,
+    // Файл, в котором сохраняются и из которого восстанавливаются подписки
+    pub persist_clients: Option<String>,
+    pub record: Option<RecordConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_addr: None,
+            port: DEFAULT_PORT,
+            ping_port: DEFAULT_PING_PORT,
+            metrics_port: None,
+            dashboard_port: None,
+            ping_timeout_secs: DEFAULT_PING_TIMEOUT_SECS,
+            tickers: TickerSource::File {
+                path: DEFAULT_TICKER_FILE.to_string(),
+                watch: false,
+            },
+            generation: GenerationConfig::default(),
+            transport: TransportConfig::default(),
+            rate_limits: RateLimits::unlimited(),
+            access_list: AccessList::default(),
+            admin_token: None,
+            api_keys: None,
+            require_auth: false,
+            persist_clients: None,
+            record: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn validate(&self) -> Result<(), QuoteError> {
+        self.transport
+            .impairment
+            .validate()
+            .map_err(QuoteError::Config)?;
+        if self.transport.send_batch == 0 || self.transport.send_batch > MAX_SEND_BATCH {
+            return Err(QuoteError::Config(format!(
+                "Send batch must be between 1 and {}",
+                MAX_SEND_BATCH
+            )));
+        }
+        if self.transport.connection_workers == 0 {
+            return Err(QuoteError::Config(
+                "At least one connection worker is required".to_string(),
+            ));
+        }
+        if self.require_auth && self.api_keys.as_ref().is_none_or(KeyStore::is_empty) {
+            return Err(QuoteError::Config(
+                "require_auth needs at least one API key".to_string(),
+            ));
+        }
+        if self.generation.interval_ms == 0 {
+            return Err(QuoteError::Config(
+                "Generation interval must be positive".to_string(),
+            ));
+        }
+        if self.generation.workers == 0 {
+            return Err(QuoteError::Config(
+                "At least one generator worker is required".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Тикеры из файла или списка заново, для перезагрузки без перезапуска;
+    // None при воспроизведении истории
+    pub(crate) fn load_ticker_specs(
+        &self,
+    ) -> Result<Option<Vec<(String, TickerSpec)>>, QuoteError> {
+        match &self.tickers {
+            TickerSource::File { path, .. } => ticker_config::load_ticker_specs(path)
+                .map(Some)
+                .map_err(|e| {
+                    QuoteError::Config(format!("cannot reload tickers from {}: {}", path, e))
+                }),
+            TickerSource::List(tickers) => Ok(Some(
+                tickers
+                    .iter()
+                    .map(|ticker| (ticker.to_uppercase(), Default::default()))
+                    .collect(),
+            )),
+            TickerSource::Replay { .. } => Ok(None),
+        }
+    }
+
+    // Генератор только с тикерами источника и их параметрами: без стакана,
+    // сессии, записи и наблюдения за файлом. Для проверки конфигурации и
+    // списка тикеров без запуска сервера.
+    pub fn ticker_generator(&self) -> Result<QuoteGenerator, QuoteError> {
+        let generation = &self.generation;
+        let generator = match &self.tickers {
+            TickerSource::Replay { path, speed } => {
+                info!("Loading replay data from {} (speed x{})...", path, speed);
+                QuoteGenerator::from_replay(path, *speed)?
+            }
+            TickerSource::File { path, .. } => {
+                QuoteGenerator::from_file_with_seed(path, generation.volatility, generation.seed)?
+            }
+            TickerSource::List(tickers) => {
+                let specs = tickers
+                    .iter()
+                    .map(|ticker| (ticker.to_uppercase(), Default::default()))
+                    .collect();
+                QuoteGenerator::with_specs(specs, generation.volatility, generation.seed)
+            }
+        };
+        Ok(generator)
+    }
+
+    // Генератор по источнику тикеров и параметрам генерации. Запись на диск
+    // подписывается сразу, до запуска генератора, чтобы не пропустить котировки.
+    pub(crate) fn build_generator(&self) -> Result<QuoteGenerator, QuoteError> {
+        let generation = &self.generation;
+        let generator = self.ticker_generator()?;
+        let generator = match &self.tickers {
+            TickerSource::Replay { .. } => generator,
+            _ => {
+                let mut generator = generator
+                    .with_depth(generation.depth_levels)
+                    .with_arrival(generation.arrival)
+                    .with_idle_slowdown(generation.idle_slowdown)
+                    .with_workers(generation.workers);
+                if let Some(schedule) = &generation.session {
+                    generator = generator.with_session(schedule.clone());
+                }
+                generator
+            }
+        };
+        let generator = generator
+            .with_channel_capacity(generation.channel_capacity, generation.overflow)
+            .with_subscription_gc(Duration::from_secs(generation.subscription_gc_secs))
+            .with_history(generation.history_size);
+
+        if let TickerSource::File { path, watch: true } = &self.tickers {
+            generator.watch_ticker_file(path, TICKER_FILE_POLL_INTERVAL);
+        }
+        if let Some(record) = &self.record {
+            info!("Recording quotes to {}", record.dir);
+            let recorder = Recorder::new(&record.dir, record.rotate_every).map_err(|e| {
+                QuoteError::Config(format!("cannot record to {}: {}", record.dir, e))
+            })?;
+            let (_, receivers) = generator.subscribe_to_tickers(generator.tickers());
+            recorder.start(receivers);
+        }
+        Ok(generator)
+    }
+}
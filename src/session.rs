@@ -0,0 +1,101 @@
+use crate::clock;
+use crate::models::MarketStatus;
+use chrono::NaiveTime;
+
+// Расписание торговой сессии: премаркет (необязательный), открытие и закрытие.
+// Время задается в локальном часовом поясе сервера.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSchedule {
+    pub pre_market: Option<NaiveTime>,
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+    // Максимальный относительный гэп цены при открытии сессии
+    pub opening_gap: f64,
+}
+
+impl SessionSchedule {
+    pub fn new(open: NaiveTime, close: NaiveTime) -> Self {
+        SessionSchedule {
+            pre_market: None,
+            open,
+            close,
+            opening_gap: 0.02,
+        }
+    }
+
+    pub fn with_pre_market(mut self, pre_market: NaiveTime) -> Self {
+        self.pre_market = Some(pre_market);
+        self
+    }
+
+    pub fn with_opening_gap(mut self, opening_gap: f64) -> Self {
+        self.opening_gap = opening_gap;
+        self
+    }
+
+    // Фаза сессии в заданный момент времени. Поддерживаются сессии через полночь.
+    pub fn status_at(&self, time: NaiveTime) -> MarketStatus {
+        if in_range(time, self.open, self.close) {
+            MarketStatus::Open
+        } else if let Some(pre_market) = self.pre_market
+            && in_range(time, pre_market, self.open)
+        {
+            MarketStatus::PreMarket
+        } else {
+            MarketStatus::Closed
+        }
+    }
+
+    pub fn current_status(&self) -> MarketStatus {
+        self.status_at(clock::now_local().time())
+    }
+
+    // Обычные часы торгов 09:30-16:00, используются для профиля объема без расписания
+    pub fn regular_hours() -> Self {
+        SessionSchedule::new(
+            NaiveTime::from_hms_opt(9, 30, 0).unwrap(),
+            NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+        )
+    }
+
+    // Доля прошедшей основной сессии (0.0..1.0), None вне сессии
+    pub fn day_fraction_at(&self, time: NaiveTime) -> Option<f64> {
+        if !in_range(time, self.open, self.close) {
+            return None;
+        }
+        let length = seconds_between(self.open, self.close);
+        if length <= 0.0 {
+            return None;
+        }
+        Some(seconds_between(self.open, time) / length)
+    }
+
+    pub fn current_day_fraction(&self) -> Option<f64> {
+        self.day_fraction_at(clock::now_local().time())
+    }
+}
+
+// Секунды от start до end с переходом через полночь
+fn seconds_between(start: NaiveTime, end: NaiveTime) -> f64 {
+    let seconds = (end - start).num_milliseconds() as f64 / 1000.0;
+    if seconds < 0.0 {
+        seconds + 24.0 * 3600.0
+    } else {
+        seconds
+    }
+}
+
+fn in_range(time: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        time >= start || time < end
+    }
+}
+
+// Разбор времени в формате HH:MM или HH:MM:SS
+pub fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M:%S"))
+        .map_err(|_| format!("Invalid time '{}', expected HH:MM or HH:MM:SS", value))
+}
@@ -0,0 +1,264 @@
+use crate::generator::{OverflowStats, QuoteGenerator};
+use crate::models::{FeedMessage, QuoteEvent, StockQuote};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Select};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+#[cfg(feature = "stream")]
+use {
+    crossbeam_channel::{Sender, TryRecvError, unbounded},
+    std::pin::Pin,
+    std::task::{Context, Poll, Waker},
+    std::thread,
+};
+
+static NEXT_SUBSCRIPTION_ID: AtomicU64 = AtomicU64::new(1);
+
+// Идентификатор подписки генератора: по нему QuoteGenerator::unsubscribe
+// снимает ровно те каналы, что были созданы этой подпиской
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+impl SubscriptionId {
+    pub(crate) fn next() -> Self {
+        SubscriptionId(NEXT_SUBSCRIPTION_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for SubscriptionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sub-{}", self.0)
+    }
+}
+
+// Ресивер тикера из QuoteGenerator::subscribe_to_tickers: обычный Receiver
+// (через Deref) вместе с долей владения подписки. Когда удалены все ресиверы
+// подписки, сборщик подписок генератора снимает ее и без unsubscribe.
+#[derive(Clone)]
+pub struct FeedReceiver {
+    receiver: Receiver<FeedMessage>,
+    _owner: Arc<OverflowStats>,
+}
+
+impl FeedReceiver {
+    pub(crate) fn new(receiver: Receiver<FeedMessage>, owner: Arc<OverflowStats>) -> Self {
+        FeedReceiver {
+            receiver,
+            _owner: owner,
+        }
+    }
+}
+
+impl Deref for FeedReceiver {
+    type Target = Receiver<FeedMessage>;
+
+    fn deref(&self) -> &Receiver<FeedMessage> {
+        &self.receiver
+    }
+}
+
+// Очередь событий клиента из QuoteGenerator::subscribe_client: общая для всех
+// тикеров подписки, ее разбирает UDP задача клиента. Закрывается, когда
+// подписка снята.
+pub enum ClientFeed {
+    Queue(mpsc::Receiver<FeedMessage>),
+    // Кольцо drop-oldest: вытесненные события генератор уже учел в потерях
+    Ring(broadcast::Receiver<FeedMessage>),
+}
+
+impl ClientFeed {
+    // Следующее событие; None - подписка снята
+    pub async fn recv(&mut self) -> Option<FeedMessage> {
+        match self {
+            ClientFeed::Queue(rx) => rx.recv().await,
+            ClientFeed::Ring(rx) => loop {
+                match rx.recv().await {
+                    Ok(message) => return Some(message),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            },
+        }
+    }
+
+    // Событие, если оно уже в очереди
+    pub fn try_recv(&mut self) -> Option<FeedMessage> {
+        match self {
+            ClientFeed::Queue(rx) => rx.try_recv().ok(),
+            ClientFeed::Ring(rx) => loop {
+                match rx.try_recv() {
+                    Ok(message) => return Some(message),
+                    Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                    Err(_) => return None,
+                }
+            },
+        }
+    }
+
+    // Событий в очереди
+    pub fn len(&self) -> usize {
+        match self {
+            ClientFeed::Queue(rx) => rx.len(),
+            ClientFeed::Ring(rx) => rx.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Подписка на сделки тикеров без TCP/UDP: итератор блокируется до очередной
+// сделки и заканчивается, когда все тикеры подписки удалены из генератора.
+// Котировки bid/ask, стакан и статусы пропускаются.
+// С feature stream подписка - еще и асинхронный Stream.
+pub struct Subscription {
+    generator: QuoteGenerator,
+    id: SubscriptionId,
+    receivers: Vec<FeedReceiver>,
+    // Поток, будящий задачу, когда в очередях появились события
+    #[cfg(feature = "stream")]
+    waiter: Option<Sender<(Vec<FeedReceiver>, Waker)>>,
+}
+
+impl Subscription {
+    pub(crate) fn new(generator: &QuoteGenerator, tickers: Vec<String>) -> Self {
+        let (id, receivers) = generator.subscribe_to_tickers(tickers);
+        Subscription {
+            generator: generator.clone(),
+            id,
+            receivers,
+            #[cfg(feature = "stream")]
+            waiter: None,
+        }
+    }
+
+    // Очередная сделка без блокировки потока; None, когда подписка закончилась
+    #[cfg(feature = "stream")]
+    pub async fn next_quote(&mut self) -> Option<StockQuote> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    // Ждать очередь будет поток: очереди генератора не умеют будить задачи.
+    // Он получает текущие очереди вместе с Waker и будит задачу, как только
+    // одна из них готова; завершается вместе с подпиской.
+    #[cfg(feature = "stream")]
+    fn register(&mut self, waker: &Waker) {
+        let waiter = self.waiter.get_or_insert_with(|| {
+            let (sender, requests) = unbounded::<(Vec<FeedReceiver>, Waker)>();
+            thread::spawn(move || {
+                for (receivers, waker) in requests {
+                    let mut select = Select::new();
+                    for receiver in &receivers {
+                        select.recv(receiver);
+                    }
+                    select.ready();
+                    waker.wake();
+                }
+            });
+            sender
+        });
+        let _ = waiter.send((self.receivers.clone(), waker.clone()));
+    }
+
+    // Очередная сделка не дольше чем за timeout; None по таймауту или
+    // когда подписка закончилась
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<StockQuote> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            match self.recv_event(Some(remaining)) {
+                Ok(QuoteEvent::Trade(quote)) => return Some(quote),
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn recv_event(&mut self, timeout: Option<Duration>) -> Result<QuoteEvent, RecvTimeoutError> {
+        loop {
+            if self.receivers.is_empty() {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let mut select = Select::new();
+            for receiver in &self.receivers {
+                select.recv(receiver);
+            }
+            let index = match timeout {
+                Some(timeout) => select
+                    .ready_timeout(timeout)
+                    .map_err(|_| RecvTimeoutError::Timeout)?,
+                None => select.ready(),
+            };
+            match self.receivers[index].try_recv() {
+                Ok(message) => return Ok(message.event),
+                // Тикер удален из генератора: его канал закрыт
+                Err(e) if e.is_disconnected() => {
+                    self.receivers.swap_remove(index);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = StockQuote;
+
+    fn next(&mut self) -> Option<StockQuote> {
+        loop {
+            match self.recv_event(None) {
+                Ok(QuoteEvent::Trade(quote)) => return Some(quote),
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+// Тот же интерфейс, что у futures_core::Stream; без внешних зависимостей
+#[cfg(feature = "stream")]
+pub trait Stream {
+    type Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+}
+
+#[cfg(feature = "stream")]
+impl Stream for Subscription {
+    type Item = StockQuote;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<StockQuote>> {
+        let this = self.get_mut();
+        let mut index = 0;
+        while index < this.receivers.len() {
+            match this.receivers[index].try_recv() {
+                Ok(message) => {
+                    if let QuoteEvent::Trade(quote) = message.event {
+                        // Следующий опрос начнется с других тикеров
+                        this.receivers.rotate_left(index + 1);
+                        return Poll::Ready(Some(quote));
+                    }
+                }
+                Err(TryRecvError::Disconnected) => {
+                    this.receivers.swap_remove(index);
+                }
+                Err(TryRecvError::Empty) => index += 1,
+            }
+        }
+        if this.receivers.is_empty() {
+            return Poll::Ready(None);
+        }
+        this.register(cx.waker());
+        Poll::Pending
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.generator.unsubscribe(self.id);
+    }
+}
@@ -1,66 +1,837 @@
-use crate::client_manager::ClientManager;
-use crate::generator::QuoteGenerator;
-use crate::models::{ClientConfig, Command, CommandError};
+use crate::access_list::AccessList;
+use crate::auth::{ApiKey, KeyStore};
+use crate::client_manager::{ClientManager, Liveness};
+use crate::control::{
+    ControlEncoding, ControlMode, FRAMED, JSON_CONTROL, Reply, encode_error, parse_command,
+};
+use crate::error::QuoteError;
+use crate::generator::{GenerationDefaults, QuoteGenerator};
+use crate::http::{self, Request, Response};
+use crate::impairment::NetworkImpairment;
+use crate::line_reader::{Line, LineReader, MAX_FRAME_LEN, MAX_LINE_LEN};
+use crate::metrics::{Gauges, ServerMetrics};
+use crate::models::{
+    Capabilities, ClientConfig, Command, CommandError, HelloResponse, PROTOCOL_VERSION,
+};
+use crate::observer::{LogObserver, ServerObserver};
+use crate::rate_limiter::{CommandDecision, RateLimiter};
+use crate::reload::ReloadReport;
+use crate::server_config::ServerConfig;
+use crate::ticker_config::TickerSpec;
 use crate::udp_sender::UdpSender;
-use log::{debug, error, info, trace, warn};
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::Arc;
-use std::thread;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Semaphore, oneshot, watch};
+use tokio::task;
+use tracing::{Instrument, Span, debug, error, error_span, info, trace, warn};
+
+pub const DEFAULT_CONNECTION_WORKERS: usize = 256;
+// Через сколько секунд без команд закрывается соединение без входа и без потока
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 60;
 
 pub struct TcpServer {
     generator: Arc<QuoteGenerator>,
     client_manager: Arc<ClientManager>,
+    bind_addr: Option<IpAddr>,
+    port: u16,
     ping_handler_port: u16,
+    metrics_port: Option<u16>,
+    dashboard_port: Option<u16>,
+    generation_interval_ms: u64,
+    // Токен для административных команд; без него они доступны только с localhost
+    admin_token: Option<String>,
+    // API ключи для AUTH; require_auth - без AUTH доступны только HELP, LIST и HELLO
+    api_keys: Option<Arc<KeyStore>>,
+    require_auth: bool,
+    // Ограничение подключений и команд с одного IP
+    rate_limiter: Arc<RateLimiter>,
+    // Разрешенные и запрещенные подсети для TCP подключений и UDP ping
+    access_list: Arc<AccessList>,
+    liveness: Liveness,
+    // Имитация плохой сети для UDP потоков
+    impairment: NetworkImpairment,
+    send_batch: usize,
+    // Сколько TCP соединений обслуживается одновременно; следующие сразу
+    // получают отказ
+    connection_workers: usize,
+    // Сколько ждать команды от соединения без входа и без потока; None - без ограничения
+    idle_timeout: Option<Duration>,
+    // Сигнал завершения: цикл приема соединений выходит и закрывает клиентов
+    shutdown: Arc<watch::Sender<bool>>,
+    // Открытые TCP соединения по номеру, чтобы предупредить их о завершении
+    connections: Arc<Mutex<HashMap<u64, Connection>>>,
+    next_connection_id: Arc<AtomicU64>,
+    // Задачи UdpSender, завершения которых ждет остановка сервера
+    sender_tasks: Arc<Mutex<Vec<task::JoinHandle<()>>>>,
+    // Идентификатор запуска из приветствия: по его смене клиент понимает,
+    // что сервер перезапущен
+    instance_id: String,
+    // События жизненного цикла для встраивающего кода; по умолчанию журнал
+    observer: Arc<dyn ServerObserver>,
+    // Счетчики генератора, менеджера клиентов и отправителей для /metrics
+    metrics: Arc<ServerMetrics>,
+    // Работающая конфигурация: с ней сравнивается новая при перезагрузке
+    config: Arc<Mutex<ServerConfig>>,
+    // Итог последней перезагрузки для RELOAD_STATUS
+    last_reload: Arc<Mutex<Option<ReloadReport>>>,
+}
+
+// Запущенный сервер: адреса, на которых он слушает, и ожидание остановки
+pub struct ServerHandle {
+    server: TcpServer,
+    local_addr: SocketAddr,
+    ping_addr: SocketAddr,
+    metrics_addr: Option<SocketAddr>,
+    dashboard_addr: Option<SocketAddr>,
+    thread: JoinHandle<()>,
 }
 
+impl ServerHandle {
+    // TCP адрес; при порте 0 здесь выбранный системой порт
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn ping_addr(&self) -> SocketAddr {
+        self.ping_addr
+    }
+
+    // HTTP адрес /metrics; None, если metrics_port не задан
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    // HTTP адрес веб-дашборда; None, если dashboard_port не задан
+    pub fn dashboard_addr(&self) -> Option<SocketAddr> {
+        self.dashboard_addr
+    }
+
+    pub fn shutdown(&self) {
+        self.server.shutdown();
+    }
+
+    // Ожидание остановки: после возврата клиенты предупреждены, UDP задачи,
+    // обработчик ping и генератор завершены
+    pub fn join(self) {
+        let _ = self.thread.join();
+    }
+}
+
+// Открытое управляющее соединение: канал, которым сервер закрывает его со
+// своим сообщением, режим протокола и сессия, которую соединение сейчас ведет
+struct Connection {
+    closer: Option<oneshot::Sender<Vec<u8>>>,
+    mode: ControlMode,
+    session: Option<String>,
+}
+
+impl Connection {
+    // Сообщение от сервера в режиме соединения, после которого оно закрывается.
+    // Пишет его задача соединения, поэтому вызов не ждет сеть.
+    fn close_with(&mut self, text: &str, json: &str) {
+        let message = match self.mode.encoding {
+            ControlEncoding::Text => text,
+            ControlEncoding::Json => json,
+        };
+        if let Some(closer) = self.closer.take() {
+            let _ = closer.send(self.mode.wrap(message));
+        }
+    }
+}
+
+// Пауза после ошибки приема (например, кончились дескрипторы)
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_millis(100);
+// Сколько ждать отправки отказа соединению сверх лимитов
+const REJECT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// Сообщение клиентам при остановке сервера
+const SHUTDOWN_MESSAGE: &str = "SERVER_SHUTTING_DOWN\n";
+const SHUTDOWN_MESSAGE_JSON: &str = "{\"event\":\"SERVER_SHUTTING_DOWN\"}\n";
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+// Страница дашборда; данные она берет из /api/command и /metrics
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+// Сессия, от имени которой выполняются команды дашборда
+const DASHBOARD_SESSION: &str = "dashboard";
+
+// В режиме TCP liveness открытое соединение продлевает сессию с таким периодом
+const TCP_LIVENESS_INTERVAL: Duration = Duration::from_secs(1);
+
 impl TcpServer {
-    pub fn new(generator: QuoteGenerator, ping_timeout_secs: u64, ping_handler_port: u16) -> Self {
+    // Сервер по конфигурации: генератор строится из источника тикеров,
+    // запускается в spawn() и останавливается вместе с сервером
+    pub fn from_config(config: ServerConfig) -> Result<Self, QuoteError> {
+        config.validate()?;
+        let generator = config.build_generator()?;
+        Ok(Self::new(generator, &config))
+    }
+
+    // Сервер с готовым генератором; источник тикеров и запись из config не используются
+    pub fn new(generator: QuoteGenerator, config: &ServerConfig) -> Self {
         info!(
             "Initializing TCP server with ping timeout: {}s, ping port: {}",
-            ping_timeout_secs, ping_handler_port
+            config.ping_timeout_secs, config.ping_port
         );
 
-        let client_manager = Arc::new(ClientManager::new(ping_timeout_secs));
+        let metrics = Arc::new(ServerMetrics::new());
+        let generator = Arc::new(generator.with_metrics(metrics.clone()));
+        let mut manager =
+            ClientManager::new(config.ping_timeout_secs).with_metrics(metrics.clone());
+        // Подписки сохраняются в файл и восстанавливаются при следующем запуске
+        if let Some(path) = &config.persist_clients {
+            manager = manager.with_persistence(path);
+        }
+        manager.set_slow_consumer_limits(config.transport.slow_consumer);
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let client_manager = Arc::new(Self::client_manager(
+            generator.clone(),
+            connections.clone(),
+            manager,
+        ));
+
+        let transport = &config.transport;
+        info!("Client liveness via {:?}", transport.liveness);
+        let impairment = transport.impairment;
+        if impairment.is_active() {
+            warn!(
+                "Simulating network impairment: loss {}%, duplicate {}%, reorder {}%, jitter {}ms",
+                impairment.loss_pct,
+                impairment.duplicate_pct,
+                impairment.reorder_pct,
+                impairment.jitter_ms
+            );
+        }
+        let limits = config.rate_limits;
+        info!(
+            "Rate limits per IP: {} connections/min, {} commands/s (0 = unlimited)",
+            limits.connections_per_minute, limits.commands_per_second
+        );
+        let access_list = &config.access_list;
+        if access_list.is_restricted() {
+            let join = |cidrs: &[crate::access_list::Cidr]| {
+                cidrs
+                    .iter()
+                    .map(|cidr| cidr.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            info!(
+                "Access list: allow [{}], deny [{}]",
+                join(access_list.allow()),
+                join(access_list.deny())
+            );
+        }
 
         TcpServer {
-            generator: Arc::new(generator),
+            generator,
             client_manager,
-            ping_handler_port,
+            bind_addr: config.bind_addr,
+            port: config.port,
+            ping_handler_port: config.ping_port,
+            metrics_port: config.metrics_port,
+            dashboard_port: config.dashboard_port,
+            generation_interval_ms: config.generation.interval_ms,
+            admin_token: config.admin_token.clone(),
+            api_keys: config.api_keys.clone().map(Arc::new),
+            require_auth: config.require_auth,
+            rate_limiter: Arc::new(RateLimiter::new(limits)),
+            access_list: Arc::new(access_list.clone()),
+            liveness: transport.liveness,
+            impairment,
+            send_batch: transport.send_batch.max(1),
+            connection_workers: transport.connection_workers.max(1),
+            idle_timeout: (transport.idle_timeout_secs > 0)
+                .then(|| Duration::from_secs(transport.idle_timeout_secs)),
+            shutdown: Arc::new(watch::Sender::new(false)),
+            connections,
+            next_connection_id: Arc::new(AtomicU64::new(0)),
+            sender_tasks: Arc::new(Mutex::new(Vec::new())),
+            instance_id: format!("{:016x}", rand::random::<u64>()),
+            observer: Arc::new(LogObserver),
+            metrics,
+            config: Arc::new(Mutex::new(config.clone())),
+            last_reload: Arc::new(Mutex::new(None)),
         }
     }
 
-    pub fn run(&self, port: u16) -> std::io::Result<()> {
-        // Запускаем обработчик ping сообщений
+    // Наблюдатель получает события всех соединений, UDP потоков и таймаутов ping;
+    // задается до spawn()
+    pub fn with_observer(mut self, observer: Arc<dyn ServerObserver>) -> Self {
+        self.client_manager.set_observer(observer.clone());
+        self.observer = observer;
+        self
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    pub fn metrics(&self) -> &Arc<ServerMetrics> {
+        &self.metrics
+    }
+
+    // Новая конфигурация без перезапуска (SIGHUP): волатильность, интервалы,
+    // idle_slowdown, таймаут ping, пороги медленных потребителей и лимиты
+    // применяются сразу, файл тикеров
+    // перечитывается (параметры, заданные через SET, заменяются файлом).
+    // Остальные изменения перечислены в restart_required и не применяются.
+    pub fn reload(&self, config: &ServerConfig) -> ReloadReport {
+        let mut active = self.config.lock().unwrap();
+        let mut report = ReloadReport::diff(&active, config);
+
+        let generation = &config.generation;
+        self.generator.set_defaults(GenerationDefaults {
+            volatility: generation.volatility,
+            interval_ms: generation.interval_ms,
+            idle_slowdown: generation.idle_slowdown,
+        });
         self.client_manager
-            .start_ping_handler(self.ping_handler_port);
+            .set_ping_timeout_secs(config.ping_timeout_secs);
+        self.client_manager
+            .set_slow_consumer_limits(config.transport.slow_consumer);
+        self.rate_limiter.set_limits(config.rate_limits);
+        active.generation.volatility = generation.volatility;
+        active.generation.interval_ms = generation.interval_ms;
+        active.generation.idle_slowdown = generation.idle_slowdown;
+        active.ping_timeout_secs = config.ping_timeout_secs;
+        active.transport.slow_consumer = config.transport.slow_consumer;
+        active.rate_limits = config.rate_limits;
+
+        // Тикеры берутся из работающего источника: смена файла требует перезапуска
+        match active.load_ticker_specs() {
+            Ok(Some(specs)) => self.generator.request_reload(specs),
+            Ok(None) => {}
+            Err(e) => report.error = Some(e.to_string()),
+        }
+        report
+    }
+
+    // Итог перезагрузки в журнал и в ответ RELOAD_STATUS
+    pub fn publish_reload(&self, report: ReloadReport) {
+        report.log();
+        *self.last_reload.lock().unwrap() = Some(report);
+    }
+
+    pub fn last_reload(&self) -> Option<ReloadReport> {
+        self.last_reload.lock().unwrap().clone()
+    }
 
-        let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
-        info!("TCP server listening on port {}", port);
+    // Счетчики и текущее состояние сервера в текстовом формате Prometheus
+    pub fn render_metrics(&self) -> String {
+        let clients = self.client_manager.list_clients();
+        let gauges = Gauges {
+            tcp_connections: self.connections.lock().unwrap().len(),
+            streaming_clients: clients.len(),
+            tickers: self.generator.subscription_stats(),
+            client_queues: clients
+                .into_iter()
+                .map(|(client_id, config)| (client_id, config.sender.stats().queue_len))
+                .collect(),
+        };
+        self.metrics.render(&gauges)
+    }
+
+    fn handle_http(&self, request: &Request) -> Response {
+        if request.path != "/metrics" {
+            return Response::not_found();
+        }
+        if request.method != "GET" {
+            return Response::text(405, "only GET is supported\n");
+        }
+        Response::new(200, PROMETHEUS_CONTENT_TYPE, self.render_metrics())
+    }
+
+    // Дашборд: страница, команды администратора и те же /metrics
+    fn handle_dashboard(&self, request: &Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => Response::new(200, "text/html; charset=utf-8", DASHBOARD_HTML),
+            ("POST", "/api/command") => self.handle_http_command(request),
+            (_, "/" | "/api/command") => Response::text(405, "method not allowed\n"),
+            _ => self.handle_http(request),
+        }
+    }
+
+    // Административная команда или SNAPSHOT JSON протокола из тела запроса.
+    // Права как у TCP соединения: X-Admin-Token вместо ADMIN, X-Api-Key вместо
+    // AUTH, без токена администратора - только с localhost
+    fn handle_http_command(&self, request: &Request) -> Response {
+        let peer_ip = request.peer.ip().to_canonical();
+        let error = |e: CommandError| {
+            let response =
+                Response::json(e.code().status(), encode_error(&e, ControlEncoding::Json));
+            self.observer.on_error(DASHBOARD_SESSION, &e.into());
+            response
+        };
+        if self.rate_limiter.check_command(peer_ip) != CommandDecision::Allowed {
+            return error(CommandError::RateLimited("slow down".to_string()));
+        }
+        if let Err(e) = self.check_http_origin(request) {
+            return error(e);
+        }
+
+        let input = String::from_utf8_lossy(&request.body);
+        let command = match parse_command(input.trim(), ControlEncoding::Json) {
+            Ok(command) => command,
+            Err(e) => return error(e),
+        };
+        if !command.is_http() {
+            return error(CommandError::NotSupported(format!(
+                "{} is not available over HTTP",
+                command.name()
+            )));
+        }
 
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let server = self.clone();
-                    thread::spawn(move || {
-                        if let Err(e) = server.handle_client(stream) {
-                            warn!("Client handler error: {}", e);
-                        }
-                    });
+        let mut admin = match &self.admin_token {
+            Some(token) => request.header("x-admin-token") == Some(token.as_str()),
+            None => peer_ip.is_loopback(),
+        };
+        let mut api_key = None;
+        if let Some(key) = request.header("x-api-key") {
+            let Some(found) = self.api_keys.as_ref().and_then(|keys| keys.get(key)) else {
+                return error(CommandError::Unauthorized("invalid API key".to_string()));
+            };
+            admin |= found.admin;
+            api_key = Some(found.clone());
+        }
+
+        let span = error_span!("dashboard", peer = %request.peer);
+        let _entered = span.enter();
+        let mut client_id = DASHBOARD_SESSION.to_string();
+        let name = command.name();
+        let started = Instant::now();
+        let result = self.handle_command(command, &mut client_id, &mut admin, &mut api_key);
+        self.observer
+            .on_command(&client_id, name, started.elapsed(), result.as_ref().err());
+        match result {
+            Ok(reply) => Response::json(200, reply.to_json()),
+            Err(e) => error(e),
+        }
+    }
+
+    // Защита от подделки запросов со сторонних страниц: только
+    // application/json (браузер сначала шлет CORS preflight, на который сервер
+    // не отвечает разрешением), Origin только свой, а без токена
+    // администратора Host локального запроса должен быть локальным - иначе
+    // страница через DNS rebinding выдала бы себя за localhost
+    fn check_http_origin(&self, request: &Request) -> Result<(), CommandError> {
+        let content_type = request.header("content-type").unwrap_or("");
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        if !media_type.eq_ignore_ascii_case("application/json") {
+            return Err(CommandError::InvalidFormat(
+                "Content-Type must be application/json".to_string(),
+            ));
+        }
+        let host = request.header("host").unwrap_or("");
+        if let Some(origin) = request.header("origin") {
+            let own = origin
+                .strip_prefix("http://")
+                .is_some_and(|origin| origin.eq_ignore_ascii_case(host));
+            if !own {
+                return Err(CommandError::Forbidden(format!(
+                    "cross-origin request from {}",
+                    origin
+                )));
+            }
+        }
+        let loopback_admin =
+            self.admin_token.is_none() && request.peer.ip().to_canonical().is_loopback();
+        if loopback_admin && !is_loopback_host(host) {
+            return Err(CommandError::Forbidden(format!(
+                "Host {} is not local; start the server with --admin-token",
+                host
+            )));
+        }
+        Ok(())
+    }
+
+    // HTTP сервер на адресе TCP сервера и под тем же списком доступа;
+    // вызывается внутри среды выполнения сервера
+    fn start_http(
+        &self,
+        ip: IpAddr,
+        port: u16,
+        handler: fn(&TcpServer, &Request) -> Response,
+    ) -> std::io::Result<(SocketAddr, task::JoinHandle<()>)> {
+        let listener = std::net::TcpListener::bind(SocketAddr::new(ip, port))?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let addr = listener.local_addr()?;
+        let server = self.clone();
+        let serving = tokio::spawn(http::serve(
+            listener,
+            self.access_list.clone(),
+            self.shutdown.subscribe(),
+            move |request| handler(&server, request),
+        ));
+        Ok((addr, serving))
+    }
+
+    // Что этот сервер умеет; depth - только с включенным стаканом
+    fn capabilities(&self) -> Capabilities {
+        let mut features = vec![
+            "quotes",
+            "conflate",
+            "rate",
+            "every",
+            "overflow",
+            "resume",
+            "bars",
+            "bar-stream",
+            "snapshot",
+            JSON_CONTROL,
+            FRAMED,
+        ];
+        if self.generator.depth_enabled() {
+            features.push("depth");
+        }
+        if self.api_keys.is_some() {
+            features.push("auth");
+        }
+        Capabilities {
+            formats: vec!["json".to_string()],
+            compression: Vec::new(),
+            transports: vec!["udp".to_string()],
+            features: features.into_iter().map(str::to_string).collect(),
+        }
+    }
+
+    // Подписка удаленного клиента сразу снимается в генераторе, а медленный
+    // потребитель узнает причину отключения по своему TCP соединению
+    fn client_manager(
+        generator: Arc<QuoteGenerator>,
+        connections: Arc<Mutex<HashMap<u64, Connection>>>,
+        manager: ClientManager,
+    ) -> ClientManager {
+        manager
+            .with_on_remove(move |_, config| {
+                if let Some(id) = config.subscription {
+                    generator.unsubscribe(id);
+                }
+            })
+            .with_on_slow_consumer(move |client_id, reason| {
+                let text = format!("SLOW_CONSUMER {}\n", reason);
+                let json = format!(
+                    "{}\n",
+                    serde_json::json!({"event": "SLOW_CONSUMER", "detail": reason})
+                );
+                close_session(&connections, client_id, &text, &json);
+            })
+    }
+
+    // Запуск в фоновом потоке со своей средой выполнения tokio: соединения,
+    // UDP отправители, обработчик ping и HTTP работают в ней задачами.
+    // Порты привязываются до возврата, поэтому ошибки привязки возвращаются
+    // отсюда, а local_addr() сразу известен (в том числе для порта 0).
+    pub fn spawn(&self) -> Result<ServerHandle, QuoteError> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("quote-server")
+            .build()?;
+        // Сокеты регистрируются и задачи запускаются в этой среде
+        let context = runtime.enter();
+        let listener = self.bind_listener()?;
+        let local_addr = listener.local_addr()?;
+        // Ping слушается на том же адресе, что и TCP
+        let ping_handler = self.client_manager.start_ping_handler(
+            SocketAddr::new(local_addr.ip(), self.ping_handler_port),
+            self.access_list.clone(),
+        )?;
+        let ping_addr = ping_handler.local_addr();
+        let mut http_tasks = Vec::new();
+        let metrics_addr = match self.metrics_port {
+            Some(port) => {
+                let (addr, serving) = self.start_http(local_addr.ip(), port, Self::handle_http)?;
+                info!(port = addr.port(), "Metrics endpoint listening at /metrics");
+                http_tasks.push(serving);
+                Some(addr)
+            }
+            None => None,
+        };
+        let dashboard_addr = match self.dashboard_port {
+            Some(port) => {
+                let (addr, serving) =
+                    self.start_http(local_addr.ip(), port, Self::handle_dashboard)?;
+                info!(port = addr.port(), "Admin dashboard listening");
+                http_tasks.push(serving);
+                Some(addr)
+            }
+            None => None,
+        };
+
+        let generator_handle = (*self.generator).clone().start(self.generation_interval_ms);
+        info!(
+            "Quote generator started with interval {}ms",
+            self.generation_interval_ms
+        );
+        // Генератор уже запущен: возобновляем потоки клиентов прошлого запуска
+        self.restore_clients();
+        drop(context);
+
+        info!(
+            port = local_addr.port(),
+            instance = %self.instance_id,
+            "TCP server listening"
+        );
+        let server = self.clone();
+        let thread = thread::spawn(move || {
+            runtime.block_on(async {
+                server.accept_loop(listener).await;
+                ping_handler.shutdown().await;
+                for serving in http_tasks {
+                    let _ = serving.await;
                 }
+            });
+            generator_handle.shutdown();
+        });
+        Ok(ServerHandle {
+            server: self.clone(),
+            local_addr,
+            ping_addr,
+            metrics_addr,
+            dashboard_addr,
+            thread,
+        })
+    }
+
+    // Без явного адреса - dual-stack [::], принимающий и IPv4 клиентов;
+    // если IPv6 в системе нет, то 0.0.0.0
+    fn bind_listener(&self) -> std::io::Result<TcpListener> {
+        let bind = |ip: IpAddr| std::net::TcpListener::bind(SocketAddr::new(ip, self.port));
+        let listener = match self.bind_addr {
+            Some(ip) => bind(ip)?,
+            None => match bind(Ipv6Addr::UNSPECIFIED.into()) {
+                Ok(listener) => listener,
+                Err(e) if e.kind() != std::io::ErrorKind::AddrInUse => {
+                    debug!("IPv6 is unavailable ({}), listening on IPv4 only", e);
+                    bind(Ipv4Addr::UNSPECIFIED.into())?
+                }
+                Err(e) => return Err(e),
+            },
+        };
+        listener.set_nonblocking(true)?;
+        TcpListener::from_std(listener)
+    }
+
+    // Запуск и прием соединений до shutdown() в текущем потоке
+    pub fn run_until_shutdown(&self) -> Result<(), QuoteError> {
+        self.spawn()?.join();
+        Ok(())
+    }
+
+    async fn accept_loop(&self, listener: TcpListener) {
+        // Соединение держит свое разрешение до закрытия; когда разрешений
+        // нет, следующее соединение сразу получает отказ
+        let limit = self
+            .connection_workers
+            .min(Semaphore::MAX_PERMITS)
+            .min(u32::MAX as usize);
+        let slots = Arc::new(Semaphore::new(limit));
+        let mut stop = self.shutdown.subscribe();
+        loop {
+            let accepted = tokio::select! {
+                _ = stop.wait_for(|stop| *stop) => break,
+                accepted = listener.accept() => accepted,
+            };
+            let stream = match accepted {
+                Ok((stream, _)) => stream,
                 Err(e) => {
                     error!("Failed to accept connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_PAUSE).await;
+                    continue;
                 }
+            };
+            if let Some(ip) = peer_ip(&stream)
+                && !self.access_list.is_allowed(ip)
+            {
+                warn!(%ip, "Connection denied by access list");
+                reject(stream, CommandError::Forbidden("access denied".to_string()));
+                continue;
             }
+            if let Some(ip) = peer_ip(&stream)
+                && !self.rate_limiter.allow_connection(ip)
+            {
+                warn!(%ip, "Too many connections, rejecting");
+                let error =
+                    CommandError::RateLimited("too many connections, try again later".to_string());
+                reject(stream, error);
+                continue;
+            }
+            let Ok(slot) = slots.clone().try_acquire_owned() else {
+                warn!(
+                    limit = self.connection_workers,
+                    "Connection limit reached, rejecting"
+                );
+                self.metrics.record_busy_rejection();
+                let error = CommandError::RateLimited("server busy, try again later".to_string());
+                reject(stream, error);
+                continue;
+            };
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle_connection(stream).await {
+                    warn!("Client handler error: {}", e);
+                }
+                drop(slot);
+            });
         }
 
-        Ok(())
+        self.close_all().await;
+        // Соединения, принятые до сигнала, видят его и сразу закрываются:
+        // все разрешения возвращаются, когда закончены все задачи соединений
+        let _ = slots.acquire_many(limit as u32).await;
+    }
+
+    // Запрос остановки: сервер перестает принимать соединения, предупреждает
+    // клиентов, останавливает UDP задачи и возвращает управление.
+    // Можно вызывать из обработчика сигнала.
+    pub fn shutdown(&self) {
+        info!("Shutdown requested");
+        self.shutdown.send_replace(true);
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    async fn close_all(&self) {
+        let connections: Vec<Connection> = self
+            .connections
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, connection)| connection)
+            .collect();
+        info!("Closing {} TCP connections", connections.len());
+        for mut connection in connections {
+            connection.close_with(SHUTDOWN_MESSAGE, SHUTDOWN_MESSAGE_JSON);
+        }
+
+        self.client_manager.stop_all_senders();
+        let senders: Vec<task::JoinHandle<()>> =
+            self.sender_tasks.lock().unwrap().drain(..).collect();
+        for sender in senders {
+            let _ = sender.await;
+        }
+        info!("All UDP senders stopped");
+    }
+
+    // Регистрация клиента, подписка на тикеры и запуск UdpSender
+    fn start_stream(&self, client_id: &str, mut config: ClientConfig) {
+        // Подписываем клиента на тикеры и получаем его очередь. Id подписки
+        // хранится в конфиге, чтобы удаление клиента сняло именно ее.
+        let (subscription, feed) = self.generator.subscribe_client(
+            client_id,
+            config.tickers.clone(),
+            config.sender.overflow_stats(),
+            &config.options,
+        );
+        config.subscription = Some(subscription);
+
+        // Добавляем клиента в менеджер; прежняя задача отправки той же сессии
+        // останавливается вместе со своей подпиской
+        self.client_manager
+            .add_client(client_id.to_string(), config.clone());
+
+        // Создаем и запускаем UDP отправитель для этого клиента
+        let udp_sender = UdpSender::new(client_id.to_string(), config.clone(), feed)
+            .with_impairment(self.impairment)
+            .with_send_batch(self.send_batch)
+            .with_observer(self.observer.clone())
+            .with_metrics(self.metrics.clone());
+        match udp_sender.start() {
+            Ok(handle) => {
+                let mut senders = self.sender_tasks.lock().unwrap();
+                senders.retain(|sender| !sender.is_finished());
+                senders.push(handle);
+            }
+            Err(e) => {
+                self.observer.on_error(client_id, &e);
+                return;
+            }
+        }
+
+        self.observer.on_stream_started(client_id, &config);
     }
 
-    fn handle_client(&self, mut stream: TcpStream) -> std::io::Result<()> {
+    fn restore_clients(&self) {
+        let persisted = self.client_manager.load_persisted();
+        if persisted.is_empty() {
+            return;
+        }
+        info!("Restoring {} persisted clients", persisted.len());
+
+        for (client_id, mut config) in persisted {
+            // Тикеры могли пропасть из файла тикеров между запусками
+            let (known, unknown): (Vec<String>, Vec<String>) = config
+                .tickers
+                .drain(..)
+                .partition(|ticker| self.generator.has_ticker(ticker));
+            if !unknown.is_empty() {
+                warn!(
+                    %client_id,
+                    tickers = %unknown.join(","),
+                    "Skipping unknown tickers of persisted client"
+                );
+            }
+            if known.is_empty() {
+                warn!(%client_id, "Persisted client has no known tickers, not restored");
+                continue;
+            }
+            config.tickers = known;
+            if config.options.depth && !self.generator.depth_enabled() {
+                config.options.depth = false;
+            }
+            self.start_stream(&client_id, config);
+        }
+    }
+
+    // Учет открытого соединения на время его обработки
+    async fn handle_connection(&self, stream: TcpStream) -> std::io::Result<()> {
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        self.metrics.record_connection();
+        let (closer, closed) = oneshot::channel();
+        let connection = Connection {
+            closer: Some(closer),
+            mode: ControlMode::default(),
+            session: None,
+        };
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(connection_id, connection);
+        // Соединение начало обработку уже после close_all
+        if self.is_shutting_down() {
+            self.connections.lock().unwrap().remove(&connection_id);
+            let mut stream = stream;
+            let _ = stream.write_all(SHUTDOWN_MESSAGE.as_bytes()).await;
+            return Ok(());
+        }
+        let result = self.handle_client(stream, connection_id, closed).await;
+        self.connections.lock().unwrap().remove(&connection_id);
+        result
+    }
+
+    // Режим и сессия соединения, чтобы сообщения сервера (остановка,
+    // отключение медленного потребителя) дошли до него в том же режиме
+    fn update_connection(&self, connection_id: u64, update: impl FnOnce(&mut Connection)) {
+        if let Some(connection) = self.connections.lock().unwrap().get_mut(&connection_id) {
+            update(connection);
+        }
+    }
+
+    async fn handle_client(
+        &self,
+        stream: TcpStream,
+        connection_id: u64,
+        closed: oneshot::Receiver<Vec<u8>>,
+    ) -> std::io::Result<()> {
         let peer_addr = match stream.peer_addr() {
             Ok(addr) => {
-                debug!("New connection from {}", addr);
+                debug!(peer = %addr, "New connection");
                 addr
             }
             Err(e) => {
@@ -69,182 +840,695 @@ impl TcpServer {
             }
         };
 
-        let client_id = format!("{}", peer_addr);
-        info!("Handling client: {}", client_id);
+        // Клиент идентифицируется токеном сессии, а не адресом TCP соединения:
+        // после переподключения он продолжает подписку командой RESUME <token>
+        let client_id = ClientManager::new_session_token();
+        // Уровень ERROR: контекст соединения нужен и предупреждениям при RUST_LOG=warn
+        let span = error_span!(
+            "connection",
+            id = connection_id,
+            peer = %peer_addr,
+            client_id = %client_id
+        );
+        self.serve_client(stream, peer_addr, connection_id, client_id, closed)
+            .instrument(span)
+            .await
+    }
+
+    // Команды соединения до его закрытия клиентом или сервером
+    async fn serve_client(
+        &self,
+        stream: TcpStream,
+        peer_addr: SocketAddr,
+        connection_id: u64,
+        mut client_id: String,
+        mut closed: oneshot::Receiver<Vec<u8>>,
+    ) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        self.update_connection(connection_id, |connection| {
+            connection.session = Some(client_id.clone())
+        });
+        self.observer.on_client_connected(&client_id, peer_addr);
+
+        // С токеном администратором становится соединение, выполнившее ADMIN <token>,
+        // без токена - любое локальное соединение
+        let peer_ip = peer_addr.ip().to_canonical();
+        let mut admin = self.admin_token.is_none() && peer_ip.is_loopback();
+        // Ключ, с которым соединение выполнило AUTH, и его права
+        let mut api_key: Option<ApiKey> = None;
 
         // Приветственное сообщение
-        let welcome_msg = "Welcome to Quote Server!\n\
+        let welcome_msg = format!(
+            "SESSION {}\nINSTANCE {}\n{}",
+            client_id,
+            self.instance_id,
+            "Welcome to Quote Server!\n\
                           Available commands:\n\
-                          STREAM udp://<host>:<port> <ticker1>,<ticker2>,... - Start streaming quotes\n\
+                          STREAM udp://<host>:<port> <ticker1>,<ticker2>,... [TRADES|QUOTES|BOTH] [DEPTH] [CONFLATE] [RATE N/s] [EVERY N] [OVERFLOW <policy>] [BARS <1s|1m|5m>] - Start streaming quotes\n\
+                          BARS <ticker> <1s|1m|5m> [count] - Show recent OHLCV bars\n\
+                          SNAPSHOT [ticker1,ticker2,...] - Show the last trade per ticker\n\
+                          SHOCK <ticker> <+/-N%> [seconds] - Inject a market shock (admin)\n\
+                          ADD_TICKER <ticker> [price] [volume] - Add a ticker (admin)\n\
+                          REMOVE_TICKER <ticker> - Remove a ticker (admin)\n\
+                          SET <ticker|*> key=value ... - Tune model parameters (admin)\n\
+                          CLIENTS - List connected clients (admin)\n\
+                          KICK <client_id> - Disconnect a client and stop its stream (admin)\n\
+                          PARAMS [ticker] - Show prices and model parameters (admin)\n\
+                          RELOAD_STATUS - Show the result of the last configuration reload (admin)\n\
+                          ADMIN <token> - Authenticate for admin commands\n\
+                          AUTH <key> - Authenticate with an API key\n\
+                          LIST - List available tickers\n\
+                          HELLO <version> [capability,...] - Negotiate protocol version and capabilities\n\
+                          RESUME <token> - Resume the streaming session of an earlier connection\n\
+                          STATS - Show delivery statistics of this session\n\
+                          ID - Show the session token to put into UDP pings\n\
                           PING - Send ping to server\n\
                           STOP - Stop current streaming\n\
-                          HELP - Show this help\n";
+                          HELP - Show this help\n"
+        );
 
-        if let Err(e) = stream.write_all(welcome_msg.as_bytes()) {
-            error!("Failed to send welcome message to {}: {}", client_id, e);
+        if let Err(e) = writer.write_all(welcome_msg.as_bytes()).await {
+            error!(error = %e, "Failed to send welcome message");
             return Err(e);
         }
 
-        debug!("Sent welcome message to {}", client_id);
+        debug!("Sent welcome message");
 
+        // Клиент жив, пока открыто соединение: чтение периодически прерывается,
+        // чтобы обновить время последнего ping. После закрытия соединения сессия
+        // живет обычный таймаут ping и может быть продолжена через RESUME.
+        // Те же прерывания закрывают молчащие соединения без входа и без
+        // потока: иначе они навсегда заняли бы места в лимите соединений.
+        let read_timeout = match (self.liveness, self.idle_timeout) {
+            (Liveness::Tcp, Some(idle)) => Some(idle.min(TCP_LIVENESS_INTERVAL)),
+            (Liveness::Tcp, None) => Some(TCP_LIVENESS_INTERVAL),
+            (_, idle) => idle.map(|idle| idle.min(TCP_LIVENESS_INTERVAL)),
+        };
+        let mut last_command = Instant::now();
+
+        // Текстовые команды строками до HELLO с json-control или framed
+        let mut mode = ControlMode::default();
+        let mut lines = LineReader::new(reader);
         loop {
-            let mut buf = [0; 1024];
-            let n = match stream.read(&mut buf) {
-                Ok(0) => {
-                    info!("Client {} disconnected", client_id);
-                    self.client_manager.remove_client(&client_id);
+            let next = lines.next_command_async(mode.framed);
+            let read = async {
+                match read_timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, next).await.ok(),
+                    None => Some(next.await),
+                }
+            };
+            let read = tokio::select! {
+                biased;
+                // Сервер закрывает соединение: остановка, KICK, медленный потребитель
+                message = &mut closed => {
+                    if let Ok(message) = message {
+                        let _ = writer.write_all(&message).await;
+                    }
+                    let _ = writer.shutdown().await;
+                    info!("Connection closed by server");
                     return Ok(());
                 }
-                Ok(n) => {
-                    trace!("Received {} bytes from {}", n, client_id);
-                    n
+                read = read => read,
+            };
+            let input = match read {
+                Some(Ok(None)) => {
+                    // Сессия остается до таймаута ping: клиент может переподключиться
+                    info!("Client disconnected, session kept for resume");
+                    return Ok(());
                 }
-                Err(e) => {
-                    error!("Read error from {}: {}", client_id, e);
-                    self.client_manager.remove_client(&client_id);
+                Some(Ok(Some(Line::Command(input)))) => input,
+                Some(Ok(Some(Line::TooLong))) => {
+                    warn!("Command line is too long, ignoring it");
+                    let limit = if mode.framed {
+                        MAX_FRAME_LEN
+                    } else {
+                        MAX_LINE_LEN
+                    };
+                    let error = CommandError::LineTooLong(limit);
+                    if writer.write_all(&mode.error(&error)).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                // Истек таймаут чтения; недочитанная команда осталась в буфере
+                None => {
+                    if self.liveness == Liveness::Tcp {
+                        self.client_manager.touch(&client_id);
+                    }
+                    if let Some(idle) = self.idle_timeout
+                        && last_command.elapsed() >= idle
+                        && !self.is_engaged(&client_id, admin, api_key.as_ref())
+                    {
+                        warn!(idle_secs = idle.as_secs(), "Closing idle connection");
+                        let error = CommandError::RateLimited(format!(
+                            "idle for {}s, disconnecting",
+                            idle.as_secs()
+                        ));
+                        let _ = writer.write_all(&mode.error(&error)).await;
+                        break;
+                    }
+                    continue;
+                }
+                Some(Err(e)) => {
+                    error!(error = %e, "Read error");
                     return Err(e);
                 }
             };
 
-            let input = String::from_utf8_lossy(&buf[..n]).trim().to_string();
-            debug!("Command from {}: {}", client_id, input);
+            debug!(command = %input, "Command received");
+            last_command = Instant::now();
 
-            match Command::parse(&input) {
-                Ok(command) => match self.handle_command(command, &client_id, &mut stream) {
-                    Ok(should_continue) => {
-                        if !should_continue {
-                            info!("Client {} requested stop", client_id);
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Command error for {}: {}", client_id, e);
-                        let error_msg = format!("{}\n", e);
-                        if let Err(e) = stream.write_all(error_msg.as_bytes()) {
-                            error!("Failed to write error to client {}: {}", client_id, e);
-                            break;
-                        }
+            // Шторм команд: сначала отклоняем лишние, при продолжении - отключаем
+            match self.rate_limiter.check_command(peer_ip) {
+                CommandDecision::Allowed => {}
+                CommandDecision::Throttled => {
+                    trace!("Throttled command");
+                    let error = CommandError::RateLimited("slow down".to_string());
+                    if writer.write_all(&mode.error(&error)).await.is_err() {
+                        break;
                     }
-                },
+                    continue;
+                }
+                CommandDecision::Drop => {
+                    warn!("Client keeps exceeding rate limits, disconnecting");
+                    let error = CommandError::RateLimited("disconnecting".to_string());
+                    let _ = writer.write_all(&mode.error(&error)).await;
+                    break;
+                }
+            }
+
+            let command = match parse_command(&input, mode.encoding) {
+                Ok(command) => command,
                 Err(e) => {
-                    warn!(
-                        "Parse error for command '{}' from {}: {}",
-                        input, client_id, e
-                    );
-                    let error_msg = format!("{}\n", e);
-                    if let Err(e) = stream.write_all(error_msg.as_bytes()) {
-                        error!("Failed to write error to client {}: {}", client_id, e);
+                    debug!(command = %input, "Unparsed command");
+                    let mut response = mode.error(&e);
+                    self.observer.on_error(&client_id, &e.into());
+                    if mode == ControlMode::default() {
+                        response.extend_from_slice(b"Type HELP for available commands\n");
+                    }
+                    if let Err(e) = writer.write_all(&response).await {
+                        error!(error = %e, "Failed to write error");
                         break;
                     }
+                    continue;
+                }
+            };
 
-                    let help_msg = "Type HELP for available commands\n";
-                    if let Err(e) = stream.write_all(help_msg.as_bytes()) {
-                        error!("Failed to send help to client {}: {}", client_id, e);
+            let session = client_id.clone();
+            let name = command.name();
+            let started = Instant::now();
+            // Команды берут блокировки и пишут файл подписок: поток среды
+            // выполнения на это время уступает свои задачи другим потокам
+            let result = task::block_in_place(|| {
+                self.handle_command(command, &mut client_id, &mut admin, &mut api_key)
+            });
+            self.observer
+                .on_command(&client_id, name, started.elapsed(), result.as_ref().err());
+            let response = match result {
+                Ok(reply) => reply,
+                Err(e) => {
+                    let response = mode.error(&e);
+                    self.observer.on_error(&client_id, &e.into());
+                    if let Err(e) = writer.write_all(&response).await {
+                        error!(error = %e, "Failed to write error");
                         break;
                     }
+                    continue;
                 }
+            };
+            // После RESUME соединение продолжает другую сессию
+            if client_id != session {
+                Span::current().record("client_id", client_id.as_str());
+                self.update_connection(connection_id, |connection| {
+                    connection.session = Some(client_id.clone())
+                });
+            }
+            if let Err(e) = writer.write_all(&mode.reply(&response)).await {
+                error!(error = %e, "Failed to write response");
+                break;
+            }
+            match &response {
+                Reply::StreamingStopped => {
+                    info!("Client requested stop");
+                    break;
+                }
+                // Ответ на HELLO еще в прежнем режиме, следующие - в новом
+                Reply::Hello(hello) => {
+                    mode = ControlMode::negotiated(&hello.accepted);
+                    self.update_connection(connection_id, |connection| connection.mode = mode);
+                }
+                _ => {}
             }
         }
 
-        self.client_manager.remove_client(&client_id);
-        info!("Client {} handler finished", client_id);
+        info!("Client handler finished");
         Ok(())
     }
 
+    // Соединение вошло по ключу или токену либо ведет поток: на него
+    // ограничение простоя не действует
+    fn is_engaged(&self, client_id: &str, admin: bool, api_key: Option<&ApiKey>) -> bool {
+        api_key.is_some()
+            || (admin && self.admin_token.is_some())
+            || self.client_manager.get_client(client_id).is_some()
+    }
+
     fn handle_command(
         &self,
         command: Command,
-        client_id: &str,
-        stream: &mut TcpStream,
-    ) -> Result<bool, CommandError> {
+        client_id: &mut String,
+        admin: &mut bool,
+        api_key: &mut Option<ApiKey>,
+    ) -> Result<Reply, CommandError> {
+        if self.require_auth && api_key.is_none() && !command.is_public() {
+            warn!("Client sent a command before AUTH");
+            return Err(CommandError::Unauthorized(
+                "AUTH <key> required".to_string(),
+            ));
+        }
+
+        if command.is_admin() && !*admin {
+            warn!("Client tried admin command without authorization");
+            return Err(CommandError::Forbidden(
+                "admin command requires ADMIN <token>".to_string(),
+            ));
+        }
+
         match command {
-            Command::Stream { udp_addr, tickers } => {
+            Command::Stream {
+                udp_addr,
+                tickers,
+                options,
+            } => {
                 info!(
-                    "Client {} requested stream to {} for tickers: {}",
-                    client_id,
-                    udp_addr,
-                    tickers.join(", ")
+                    %udp_addr,
+                    tickers = %tickers.join(","),
+                    feed = %options.feed,
+                    "Client requested stream"
                 );
 
                 // Проверяем, что все тикеры существуют
                 for ticker in &tickers {
                     if !self.generator.has_ticker(ticker) {
-                        warn!("Client {} requested invalid ticker: {}", client_id, ticker);
+                        warn!(%ticker, "Client requested invalid ticker");
                         return Err(CommandError::InvalidTicker(ticker.clone()));
                     }
                 }
 
-                info!("All tickers validated for client {}", client_id);
-
-                // Создаем конфигурацию клиента
-                let config = ClientConfig::new(udp_addr.clone(), tickers.clone());
+                if let Some(key) = api_key.as_ref()
+                    && let Err(e) = key.check_stream(&tickers)
+                {
+                    warn!(key = %key.name, "Client stream rejected by key");
+                    return Err(e);
+                }
 
-                // Добавляем клиента в менеджер
-                self.client_manager
-                    .add_client(client_id.to_string(), config.clone());
+                if options.depth && !self.generator.depth_enabled() {
+                    warn!("Client requested DEPTH but order book simulation is disabled");
+                    return Err(CommandError::NotSupported(
+                        "DEPTH is not enabled on this server".to_string(),
+                    ));
+                }
 
-                // Подписываем клиента на тикеры и получаем ресиверы
-                let receivers = self.generator.subscribe_to_tickers(tickers.clone());
+                info!("All tickers validated");
 
-                // Создаем UDP отправитель для этого клиента
-                let udp_sender = UdpSender::new(client_id.to_string(), config, receivers);
+                // Создаем конфигурацию клиента
+                let config = ClientConfig::new(udp_addr, tickers).with_options(options);
+                self.start_stream(client_id, config);
 
-                // Запускаем UDP отправитель
-                udp_sender.start();
+                Ok(Reply::StreamingStarted)
+            }
+            Command::Bars {
+                ticker,
+                interval,
+                count,
+            } => {
+                debug!(%ticker, %interval, count, "Client requested bars");
+                if !self.generator.has_ticker(&ticker) {
+                    return Err(CommandError::InvalidTicker(ticker));
+                }
+                if let Some(key) = api_key.as_ref()
+                    && !key.allows_ticker(&ticker)
+                {
+                    return Err(CommandError::Forbidden(format!(
+                        "key {} is not allowed to access {}",
+                        key.name, ticker
+                    )));
+                }
 
+                Ok(Reply::Bars(
+                    self.generator.recent_bars(&ticker, interval, count),
+                ))
+            }
+            Command::Snapshot { tickers } => {
+                debug!(tickers = %tickers.join(","), "Client requested snapshot");
+                for ticker in &tickers {
+                    if !self.generator.has_ticker(ticker) {
+                        return Err(CommandError::InvalidTicker(ticker.clone()));
+                    }
+                    if let Some(key) = api_key.as_ref()
+                        && !key.allows_ticker(ticker)
+                    {
+                        return Err(CommandError::Forbidden(format!(
+                            "key {} is not allowed to access {}",
+                            key.name, ticker
+                        )));
+                    }
+                }
+                let quotes = if tickers.is_empty() {
+                    self.generator.last_quotes()
+                } else {
+                    tickers
+                        .iter()
+                        .filter_map(|ticker| self.generator.last_quote(ticker))
+                        .collect()
+                };
+                // Без списка ключ ограничивает снимок своими тикерами, как LIST
+                let quotes = quotes
+                    .into_iter()
+                    .filter(|quote| {
+                        api_key
+                            .as_ref()
+                            .is_none_or(|key| key.allows_ticker(&quote.ticker))
+                    })
+                    .collect();
+                Ok(Reply::Snapshot(quotes))
+            }
+            Command::Shock {
+                ticker,
+                percent,
+                duration_secs,
+            } => {
+                warn!(%ticker, percent, duration_secs, "Client injected market shock");
+                let duration = Duration::from_secs(duration_secs);
+                match self.generator.apply_shock(&ticker, percent, duration) {
+                    Some(price) => Ok(Reply::ShockApplied { ticker, price }),
+                    None => Err(CommandError::InvalidTicker(ticker)),
+                }
+            }
+            Command::Admin { token } => {
+                if self.admin_token.as_deref() == Some(token.as_str()) {
+                    info!("Client authenticated as admin");
+                    *admin = true;
+                    Ok(Reply::AdminOk)
+                } else {
+                    warn!("Client sent invalid admin token");
+                    Err(CommandError::Unauthorized(
+                        "invalid admin token".to_string(),
+                    ))
+                }
+            }
+            Command::Auth { key } => {
+                let Some(keys) = &self.api_keys else {
+                    return Err(CommandError::NotSupported(
+                        "AUTH is not enabled on this server".to_string(),
+                    ));
+                };
+                let Some(found) = keys.get(&key) else {
+                    warn!("Client sent unknown API key");
+                    return Err(CommandError::Unauthorized("invalid API key".to_string()));
+                };
+                info!(key = %found.name, "Client authenticated with key");
+                if found.admin {
+                    *admin = true;
+                }
+                *api_key = Some(found.clone());
+                Ok(Reply::AuthOk {
+                    name: found.name.clone(),
+                })
+            }
+            Command::AddTicker {
+                ticker,
+                price,
+                volume,
+            } => {
+                let spec = TickerSpec {
+                    initial_price: price,
+                    base_volume: volume,
+                    ..TickerSpec::default()
+                };
+                if !self.generator.add_ticker(&ticker, spec) {
+                    return Err(CommandError::TickerExists(ticker));
+                }
+                info!(%ticker, "Client added ticker");
+                Ok(Reply::TickerAdded(ticker))
+            }
+            Command::RemoveTicker { ticker } => {
+                if !self.generator.remove_ticker(&ticker) {
+                    return Err(CommandError::InvalidTicker(ticker));
+                }
+                info!(%ticker, "Client removed ticker");
+                Ok(Reply::TickerRemoved(ticker))
+            }
+            Command::Set { ticker, updates } => {
+                let tickers = if ticker == "*" {
+                    self.generator.tickers()
+                } else {
+                    vec![ticker.clone()]
+                };
+                for ticker in &tickers {
+                    if !self.generator.update_params(ticker, &updates) {
+                        return Err(CommandError::InvalidTicker(ticker.clone()));
+                    }
+                }
+                info!(%ticker, ?updates, "Client updated parameters");
+                Ok(Reply::ParamsUpdated(ticker))
+            }
+            Command::Resume { token } => {
+                let Some(config) = self.client_manager.resume(&token) else {
+                    warn!("Client tried to resume unknown session");
+                    return Err(CommandError::UnknownSession(token));
+                };
                 info!(
-                    "Started UDP streaming for client {} to {}",
-                    client_id, udp_addr
+                    session = %token,
+                    tickers = %config.tickers.join(","),
+                    udp_addr = %config.udp_addr,
+                    "Connection resumed session"
                 );
-
-                stream.write_all(b"STREAMING_STARTED\n")?;
-
-                Ok(true)
+                *client_id = token;
+                Ok(Reply::Resumed {
+                    udp_addr: config.udp_addr.clone(),
+                    tickers: config.tickers.clone(),
+                })
+            }
+            Command::Clients => {
+                debug!("Client requested client list");
+                let mut clients = Vec::new();
+                for (id, config) in self.client_manager.list_clients() {
+                    let stats = config.sender.stats();
+                    clients.push(serde_json::json!({
+                        "client_id": id,
+                        "udp_addr": config.udp_addr,
+                        "tickers": config.tickers,
+                        "last_ping_secs_ago": config.ping_age_secs(),
+                        "quotes_sent": stats.quotes_sent,
+                        "bytes_sent": stats.bytes_sent,
+                        "send_errors": stats.send_errors,
+                        "last_send_ms": stats.last_send_ms,
+                        "degraded": stats.degraded,
+                        "queue_len": stats.queue_len,
+                        "queue_lag_ms": stats.queue_lag_ms,
+                        "queue_dropped": stats.queue_dropped,
+                    }));
+                }
+                Ok(Reply::Clients(clients))
+            }
+            Command::Kick { client_id: target } => {
+                if !self.client_manager.kick(&target) {
+                    return Err(CommandError::UnknownSession(target));
+                }
+                // Соединение выгнанной сессии закрывается, иначе она сразу
+                // начала бы новый STREAM
+                let text = format!("KICKED {}\n", target);
+                let json = format!(
+                    "{}\n",
+                    serde_json::json!({"event": "KICKED", "client_id": target})
+                );
+                close_session(&self.connections, &target, &text, &json);
+                info!(%target, "Client kicked client");
+                Ok(Reply::Kicked(target))
+            }
+            Command::Params { ticker } => {
+                if let Some(ticker) = &ticker
+                    && !self.generator.has_ticker(ticker)
+                {
+                    return Err(CommandError::InvalidTicker(ticker.clone()));
+                }
+                Ok(Reply::Params(
+                    self.generator.params_snapshot(ticker.as_deref()),
+                ))
+            }
+            Command::ReloadStatus => Ok(Reply::ReloadStatus(self.last_reload())),
+            Command::Stats => {
+                let Some(config) = self.client_manager.get_client(client_id) else {
+                    return Err(CommandError::NotStreaming);
+                };
+                Ok(Reply::Stats(config.sender.stats()))
+            }
+            Command::Hello {
+                version,
+                capabilities,
+            } => {
+                let offered = self.capabilities();
+                let response = HelloResponse {
+                    version: version.min(PROTOCOL_VERSION),
+                    accepted: capabilities
+                        .into_iter()
+                        .filter(|name| offered.contains(name))
+                        .collect(),
+                    capabilities: offered,
+                };
+                debug!(
+                    version = response.version,
+                    accepted = ?response.accepted,
+                    "Client negotiated protocol"
+                );
+                Ok(Reply::Hello(response))
+            }
+            Command::List => {
+                let tickers: Vec<String> = self
+                    .generator
+                    .tickers()
+                    .into_iter()
+                    .filter(|ticker| api_key.as_ref().is_none_or(|key| key.allows_ticker(ticker)))
+                    .collect();
+                Ok(Reply::Tickers(tickers))
+            }
+            Command::Id => {
+                debug!("Client requested its session id");
+                Ok(Reply::Id(client_id.clone()))
             }
             Command::Ping => {
-                debug!("Client {} sent PING", client_id);
-                if self.client_manager.update_ping(client_id) {
-                    stream.write_all(b"PONG\n")?;
-                    trace!("Sent PONG to {}", client_id);
-                } else {
-                    warn!("Client {} sent PING but is not streaming", client_id);
-                    stream.write_all(b"ERROR: Not streaming\n")?;
+                debug!("Client sent PING");
+                if !self.client_manager.update_ping(client_id) {
+                    warn!("Client sent PING but is not streaming");
+                    return Err(CommandError::NotStreaming);
                 }
-                Ok(true)
+                trace!("Sent PONG");
+                Ok(Reply::Pong)
             }
             Command::Stop => {
-                info!("Client {} requested STOP", client_id);
-                // Отписываем клиента от тикеров
-                let config = self.client_manager.remove_client(client_id);
-                if let Some(config) = config {
-                    self.generator.unsubscribe_from_tickers(config.tickers);
-                }
-                stream.write_all(b"STREAMING_STOPPED\n")?;
-                Ok(false)
+                info!("Client requested STOP");
+                // Удаление останавливает UDP поток и отписывает клиента от тикеров
+                self.client_manager.remove_client(client_id);
+                Ok(Reply::StreamingStopped)
             }
             Command::Help => {
-                debug!("Client {} requested HELP", client_id);
+                debug!("Client requested HELP");
                 let help_msg = "Available commands:\n\
-                              STREAM udp://<host>:<port> <ticker1>,<ticker2>,... - Start streaming quotes to UDP address\n\
+                              STREAM udp://<host>:<port> <ticker1>,<ticker2>,... [TRADES|QUOTES|BOTH] [DEPTH] [CONFLATE] [RATE N/s] [EVERY N] [OVERFLOW <policy>] [BARS <1s|1m|5m>] - Start streaming quotes to UDP address\n\
+                              Feeds: TRADES - trades only (default), QUOTES - bid/ask only, BOTH - trades and bid/ask\n\
+                              DEPTH - also stream order book snapshot and incremental depth updates\n\
+                              CONFLATE - when delivery falls behind, send only the latest trade and bid/ask per ticker\n\
+                              RATE N/s - at most N trades (and N bid/ask quotes) per ticker per second; EVERY N - only every N-th one\n\
+                              OVERFLOW block|drop-oldest|drop-newest|disconnect - what to do when this client's queue is full (default: server policy)\n\
+                              BARS 1s|1m|5m - instead of trades, quotes and depth receive one closed OHLCV bar per ticker per interval\n\
+                              BARS <ticker> <1s|1m|5m> [count] - Show recent OHLCV bars (one JSON per line, then BARS_END)\n\
+                              SNAPSHOT [ticker1,ticker2,...] - Show the last trade of these tickers or of all tickers (one JSON per line, then SNAPSHOT_END)\n\
+                              SHOCK <ticker> <+/-N%> [seconds] - Move the price by N% and raise volatility for a while (default 30s)\n\
+                              ADD_TICKER <ticker> [price] [volume] - Add a ticker while the server is running\n\
+                              REMOVE_TICKER <ticker> - Remove a ticker; subscribers receive a delisted status\n\
+                              SET <ticker|*> key=value ... - Set volatility, drift, spread_bps, base_volume or interval_ms\n\
+                              SET_VOLATILITY <ticker|*> <value> - Shorthand for SET <ticker> volatility=<value>\n\
+                              CLIENTS - List clients: id, UDP target, tickers, seconds since last ping, quotes sent (one JSON per line, then CLIENTS_END)\n\
+                              KICK <client_id> - Remove a client and stop its UDP stream\n\
+                              PARAMS [ticker] - Show price, volatility, drift, spread_bps, base_volume, interval_ms, subscribers and halt state per ticker (one JSON per line, then PARAMS_END)\n\
+                              RELOAD_STATUS - Settings applied by the last configuration reload (SIGHUP) and those that need a restart (JSON, null before the first reload)\n\
+                              ADMIN <token> - Authenticate this connection for admin commands (SHOCK, ADD_TICKER, REMOVE_TICKER, SET, CLIENTS, KICK, PARAMS, RELOAD_STATUS)\n\
+                              AUTH <key> - Authenticate with an API key; the key limits which and how many tickers can be streamed and may grant admin rights\n\
+                              LIST - Reply TICKERS <ticker1>,<ticker2>,... with the tickers this connection may stream\n\
+                              RESUME <token> - Continue the session with this token (sent as SESSION <token> on connect); the UDP stream is not interrupted\n\
+                              HELLO <version> [capability,...] - Reply HELLO <json> with the negotiated version, server capabilities and the accepted requested ones\n\
+                              STATS - Quotes and bytes sent to this session, send errors and last send time (JSON)\n\
+                              ID - Reply ID <token> with the current session token; UDP pings must be sent as PING <token>\n\
                               PING - Send ping to keep connection alive\n\
                               STOP - Stop current streaming\n\
                               HELP - Show this help\n\n\
                               Example:\n\
-                              STREAM udp://127.0.0.1:34254 AAPL,TSLA,GOOGL\n";
-                stream.write_all(help_msg.as_bytes())?;
-                Ok(true)
+                              STREAM udp://127.0.0.1:34254 AAPL,TSLA,GOOGL\n\
+                              STREAM udp://127.0.0.1:34254 AAPL BOTH\n\
+                              BARS AAPL 1m 5\n\
+                              SNAPSHOT AAPL,TSLA\n\
+                              SHOCK AAPL -5%\n";
+                Ok(Reply::Help(help_msg))
             }
         }
     }
 }
 
+// Сообщение соединениям сессии и их закрытие: задача соединения отправляет
+// сообщение, закрывает сокет и завершается
+fn close_session(
+    connections: &Mutex<HashMap<u64, Connection>>,
+    session: &str,
+    text: &str,
+    json: &str,
+) {
+    let mut connections = connections.lock().unwrap();
+    for connection in connections.values_mut() {
+        if connection.session.as_deref() == Some(session) {
+            connection.close_with(text, json);
+        }
+    }
+}
+
+// IP клиента; на dual-stack сокете IPv4 клиенты приходят как ::ffff:a.b.c.d
+fn peer_ip(stream: &TcpStream) -> Option<IpAddr> {
+    stream.peer_addr().ok().map(|addr| addr.ip().to_canonical())
+}
+
+// Отказ только что принятому соединению: ответ пишет своя короткая задача,
+// чтобы цикл приема не ждал клиента
+fn reject(mut stream: TcpStream, error: CommandError) {
+    tokio::spawn(async move {
+        let message = format!("{}\n", error);
+        let write = stream.write_all(message.as_bytes());
+        let _ = tokio::time::timeout(REJECT_WRITE_TIMEOUT, write).await;
+    });
+}
+
+// Заголовок Host вида localhost[:port], 127.0.0.1[:port] или [::1][:port]
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(""),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.to_canonical().is_loopback())
+}
+
 impl Clone for TcpServer {
     fn clone(&self) -> Self {
         debug!("Cloning TCP server instance");
         TcpServer {
             generator: self.generator.clone(),
             client_manager: self.client_manager.clone(),
+            bind_addr: self.bind_addr,
+            port: self.port,
             ping_handler_port: self.ping_handler_port,
+            metrics_port: self.metrics_port,
+            dashboard_port: self.dashboard_port,
+            generation_interval_ms: self.generation_interval_ms,
+            admin_token: self.admin_token.clone(),
+            api_keys: self.api_keys.clone(),
+            require_auth: self.require_auth,
+            rate_limiter: self.rate_limiter.clone(),
+            access_list: self.access_list.clone(),
+            liveness: self.liveness,
+            impairment: self.impairment,
+            send_batch: self.send_batch,
+            connection_workers: self.connection_workers,
+            idle_timeout: self.idle_timeout,
+            shutdown: self.shutdown.clone(),
+            connections: self.connections.clone(),
+            next_connection_id: self.next_connection_id.clone(),
+            sender_tasks: self.sender_tasks.clone(),
+            instance_id: self.instance_id.clone(),
+            observer: self.observer.clone(),
+            metrics: self.metrics.clone(),
+            config: self.config.clone(),
+            last_reload: self.last_reload.clone(),
         }
     }
 }
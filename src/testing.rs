@@ -0,0 +1,438 @@
+use crate::clock::{self, MockClock};
+use crate::error::QuoteError;
+use crate::models::{QuoteEvent, StockQuote};
+use crate::server_config::{ServerConfig, TickerSource};
+use crate::tcp_server::{ServerHandle, TcpServer};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Сервер и клиенты внутри процесса для сквозных тестов: STREAM, PING, STOP
+// и доставка по UDP без запуска бинарников и без фиксированных портов
+
+// Тикеры тестового сервера по умолчанию
+pub const DEFAULT_TEST_TICKERS: &[&str] = &["AAPL", "TSLA", "GOOGL"];
+// Зерно генератора по умолчанию: ценовые ряды повторяются от запуска к запуску
+pub const DEFAULT_TEST_SEED: u64 = 42;
+// Интервал котировок по умолчанию: тесты получают данные без долгого ожидания
+pub const DEFAULT_TEST_INTERVAL_MS: u64 = 10;
+// Сколько клиент ждет ответа на команду или котировку по умолчанию
+pub const DEFAULT_TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Донастройка ServerConfig тестом (with_config)
+type Configure = Box<dyn FnOnce(&mut ServerConfig)>;
+
+// Номер временного каталога в пределах процесса
+static NEXT_DIR: AtomicU64 = AtomicU64::new(0);
+
+// Настройки тестового сервера до запуска
+pub struct TestServerBuilder {
+    tickers: Vec<String>,
+    ticker_config: Option<String>,
+    seed: u64,
+    interval_ms: u64,
+    mock_clock: Option<u64>,
+    configure: Option<Configure>,
+}
+
+impl Default for TestServerBuilder {
+    fn default() -> Self {
+        TestServerBuilder {
+            tickers: DEFAULT_TEST_TICKERS.iter().map(|t| t.to_string()).collect(),
+            ticker_config: None,
+            seed: DEFAULT_TEST_SEED,
+            interval_ms: DEFAULT_TEST_INTERVAL_MS,
+            mock_clock: None,
+            configure: None,
+        }
+    }
+}
+
+impl TestServerBuilder {
+    // Список тикеров с параметрами по умолчанию (tickers.txt)
+    pub fn with_tickers(mut self, tickers: &[&str]) -> Self {
+        self.tickers = tickers.iter().map(|t| t.to_string()).collect();
+        self.ticker_config = None;
+        self
+    }
+
+    // Содержимое tickers.toml с индивидуальными параметрами тикеров
+    pub fn with_ticker_config(mut self, toml: impl Into<String>) -> Self {
+        self.ticker_config = Some(toml.into());
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_interval_ms(mut self, interval_ms: u64) -> Self {
+        self.interval_ms = interval_ms;
+        self
+    }
+
+    // Ручные часы процесса с началом в start_millis: котировки и таймауты
+    // сессий идут только через TestServer::advance. Часы общие для всего
+    // процесса, поэтому такие тесты не запускаются параллельно с другими.
+    pub fn with_mock_clock(mut self, start_millis: u64) -> Self {
+        self.mock_clock = Some(start_millis);
+        self
+    }
+
+    // Остальные параметры сервера; адреса, порты и источник тикеров
+    // выставляются тестовым сервером после этого вызова
+    pub fn with_config(mut self, configure: impl FnOnce(&mut ServerConfig) + 'static) -> Self {
+        self.configure = Some(Box::new(configure));
+        self
+    }
+
+    pub fn start(self) -> Result<TestServer, QuoteError> {
+        let dir = temp_dir()?;
+        let ticker_file = match &self.ticker_config {
+            Some(toml) => write_file(&dir, "tickers.toml", toml)?,
+            None => write_file(&dir, "tickers.txt", &(self.tickers.join("\n") + "\n"))?,
+        };
+
+        let mut config = ServerConfig::default();
+        config.generation.seed = Some(self.seed);
+        config.generation.interval_ms = self.interval_ms;
+        if let Some(configure) = self.configure {
+            configure(&mut config);
+        }
+        config.bind_addr = Some(Ipv4Addr::LOCALHOST.into());
+        config.port = 0;
+        config.ping_port = 0;
+        config.tickers = TickerSource::File {
+            path: ticker_file.to_string_lossy().into_owned(),
+            watch: false,
+        };
+
+        let clock = self.mock_clock.map(|start| {
+            let mock = Arc::new(MockClock::new(start));
+            clock::set_clock(mock.clone());
+            mock
+        });
+        let handle = TcpServer::from_config(config).and_then(|server| server.spawn());
+        let handle = match handle {
+            Ok(handle) => handle,
+            Err(e) => {
+                if clock.is_some() {
+                    clock::reset_clock();
+                }
+                let _ = fs::remove_dir_all(&dir);
+                return Err(e);
+            }
+        };
+        Ok(TestServer {
+            handle: Some(handle),
+            clock,
+            dir,
+        })
+    }
+}
+
+// Запущенный тестовый сервер на свободных портах localhost. При удалении
+// останавливается, ждет завершения своих потоков и удаляет временные файлы.
+pub struct TestServer {
+    handle: Option<ServerHandle>,
+    clock: Option<Arc<MockClock>>,
+    dir: PathBuf,
+}
+
+impl TestServer {
+    // Сервер с тикерами DEFAULT_TEST_TICKERS, зерном DEFAULT_TEST_SEED и системными часами
+    pub fn start() -> Result<Self, QuoteError> {
+        Self::builder().start()
+    }
+
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder::default()
+    }
+
+    pub fn handle(&self) -> &ServerHandle {
+        self.handle.as_ref().expect("test server is running")
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.handle().local_addr()
+    }
+
+    pub fn ping_addr(&self) -> SocketAddr {
+        self.handle().ping_addr()
+    }
+
+    // Временный каталог сервера с файлом тикеров
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Ручные часы; None - сервер работает по системным
+    pub fn clock(&self) -> Option<&Arc<MockClock>> {
+        self.clock.as_ref()
+    }
+
+    // Сдвиг ручных часов
+    pub fn advance(&self, duration: Duration) {
+        self.clock
+            .as_ref()
+            .expect("test server started without with_mock_clock")
+            .advance(duration);
+    }
+
+    pub fn client(&self) -> io::Result<TestClient> {
+        TestClient::connect(self)
+    }
+
+    // Остановка с ожиданием: после возврата клиенты получили SERVER_SHUTTING_DOWN
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        handle.shutdown();
+        match &self.clock {
+            // На ручных часах потоки, ждущие по часам процесса, завершаются,
+            // только пока время идет
+            Some(mock) => {
+                let joiner = thread::spawn(move || handle.join());
+                while !joiner.is_finished() {
+                    mock.advance(Duration::from_millis(100));
+                    thread::sleep(Duration::from_millis(1));
+                }
+                let _ = joiner.join();
+                clock::reset_clock();
+            }
+            None => handle.join(),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Клиент тестового сервера: управляющее TCP соединение, UDP сокет для
+// котировок и отдельный сокет для PING
+pub struct TestClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    feed: UdpSocket,
+    ping_socket: UdpSocket,
+    ping_addr: SocketAddr,
+    session: String,
+    instance: String,
+}
+
+impl TestClient {
+    // Подключение с чтением приветствия
+    pub fn connect(server: &TestServer) -> io::Result<Self> {
+        Self::connect_to(server.addr(), server.ping_addr())
+    }
+
+    pub fn connect_to(addr: SocketAddr, ping_addr: SocketAddr) -> io::Result<Self> {
+        let writer = TcpStream::connect(addr)?;
+        writer.set_read_timeout(Some(DEFAULT_TEST_TIMEOUT))?;
+        let reader = BufReader::new(writer.try_clone()?);
+        let feed = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        feed.set_read_timeout(Some(DEFAULT_TEST_TIMEOUT))?;
+        let ping_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        ping_socket.set_read_timeout(Some(DEFAULT_TEST_TIMEOUT))?;
+
+        let mut client = TestClient {
+            reader,
+            writer,
+            feed,
+            ping_socket,
+            ping_addr,
+            session: String::new(),
+            instance: String::new(),
+        };
+        // Приветствие заканчивается справкой, последняя строка - HELP
+        loop {
+            let line = client.read_line()?;
+            if let Some(session) = line.strip_prefix("SESSION ") {
+                client.session = session.to_string();
+            } else if let Some(instance) = line.strip_prefix("INSTANCE ") {
+                client.instance = instance.to_string();
+            } else if line.starts_with("HELP ") {
+                break;
+            }
+        }
+        Ok(client)
+    }
+
+    // Токен сессии из приветствия
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    // Идентификатор запуска сервера из приветствия
+    pub fn instance(&self) -> &str {
+        &self.instance
+    }
+
+    // Адрес, на который сервер шлет котировки этого клиента
+    pub fn udp_addr(&self) -> SocketAddr {
+        self.feed
+            .local_addr()
+            .expect("bound UDP socket has an address")
+    }
+
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        self.writer.write_all(format!("{}\n", line).as_bytes())
+    }
+
+    // Строка ответа без перевода строки; закрытое соединение - UnexpectedEof
+    pub fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    // Команда с однострочным ответом
+    pub fn command(&mut self, line: &str) -> io::Result<String> {
+        self.send(line)?;
+        self.read_line()
+    }
+
+    // Команда с ответом из нескольких строк до end включительно
+    // (BARS_END, CLIENTS_END, PARAMS_END)
+    pub fn command_until(&mut self, line: &str, end: &str) -> io::Result<Vec<String>> {
+        self.send(line)?;
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            let done = line == end;
+            lines.push(line);
+            if done {
+                return Ok(lines);
+            }
+        }
+    }
+
+    // STREAM на UDP сокет клиента; options - необязательные слова команды
+    // (BOTH, DEPTH, RATE 5/s, ...). Ответ - STREAMING_STARTED или ошибка.
+    pub fn stream(&mut self, tickers: &[&str], options: &str) -> io::Result<String> {
+        let command = format!(
+            "STREAM udp://{} {} {}",
+            self.udp_addr(),
+            tickers.join(","),
+            options
+        );
+        self.command(command.trim_end())
+    }
+
+    pub fn stop(&mut self) -> io::Result<String> {
+        self.command("STOP")
+    }
+
+    // PING по UDP на порт обработчика ping; true - пришел PONG
+    pub fn ping(&self) -> io::Result<bool> {
+        let message = format!("PING {}", self.session);
+        self.ping_socket
+            .send_to(message.as_bytes(), self.ping_addr)?;
+        let mut buf = [0; 64];
+        match self.ping_socket.recv_from(&mut buf) {
+            Ok((size, _)) => Ok(&buf[..size] == b"PONG"),
+            Err(e) if is_timeout(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Следующее событие потока; None - за timeout ничего не пришло
+    pub fn recv_event(&self, timeout: Duration) -> io::Result<Option<QuoteEvent>> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0; 4096];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            self.feed.set_read_timeout(Some(left))?;
+            match self.feed.recv_from(&mut buf) {
+                Ok((size, _)) => {
+                    let message = String::from_utf8_lossy(&buf[..size]);
+                    if let Some(event) = QuoteEvent::from_json(&message) {
+                        return Ok(Some(event));
+                    }
+                }
+                Err(e) if is_timeout(&e) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Следующая сделка; котировки bid/ask, стакан и статусы пропускаются
+    pub fn recv_quote(&self, timeout: Duration) -> io::Result<Option<StockQuote>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.recv_event(left)? {
+                Some(QuoteEvent::Trade(quote)) => return Ok(Some(quote)),
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    // count сделок за timeout; меньше, если столько не пришло
+    pub fn recv_quotes(&self, count: usize, timeout: Duration) -> io::Result<Vec<StockQuote>> {
+        let deadline = Instant::now() + timeout;
+        let mut quotes = Vec::with_capacity(count);
+        while quotes.len() < count {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.recv_quote(left)? {
+                Some(quote) => quotes.push(quote),
+                None => break,
+            }
+        }
+        Ok(quotes)
+    }
+
+    // Отбрасывание уже пришедших датаграмм, например после STOP
+    pub fn drain(&self) -> io::Result<usize> {
+        let mut dropped = 0;
+        while self.recv_event(Duration::from_millis(50))?.is_some() {
+            dropped += 1;
+        }
+        Ok(dropped)
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+// Отдельный каталог на каждый сервер: тесты в одном процессе не делят файлы
+fn temp_dir() -> Result<PathBuf, QuoteError> {
+    let dir = std::env::temp_dir().join(format!(
+        "quote-test-{}-{}",
+        std::process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn write_file(dir: &Path, name: &str, contents: &str) -> Result<PathBuf, QuoteError> {
+    let path = dir.join(name);
+    fs::write(&path, contents)?;
+    Ok(path)
+}
@@ -0,0 +1,297 @@
+use crate::models::AssetClass;
+use crate::toml_lite;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use tracing::{debug, info};
+
+// Параметры одного тикера из tickers.toml. Все поля необязательные:
+// незаданные берутся из секции [defaults] или из значений генератора.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TickerSpec {
+    // Класс инструмента (equity по умолчанию или fx)
+    pub asset_class: Option<AssetClass>,
+    // Размер пипса валютной пары; volatility для fx задается в пипсах
+    pub pip_size: Option<f64>,
+    pub initial_price: Option<f64>,
+    pub base_volume: Option<u32>,
+    pub volatility: Option<f64>,
+    pub tick_size: Option<f64>,
+    // Параметры модели: снос цены за один шаг генерации
+    pub drift: Option<f64>,
+    pub volume_profile: Option<VolumeProfile>,
+    // Спред bid/ask в базисных пунктах от средней цены
+    pub spread_bps: Option<f64>,
+    // Ценовой коридор (limit-up/limit-down) с остановкой торгов при выходе из него
+    pub price_band: Option<PriceBand>,
+    // Собственный интервал генерации котировок тикера, мс
+    pub interval_ms: Option<u64>,
+    // Состав синтетического индекса; задается в секции [indices], не в [tickers]
+    #[serde(skip)]
+    pub index: Option<IndexSpec>,
+}
+
+// Внутридневной профиль объема: множители объема на открытии, в середине дня
+// и на закрытии, между ними - квадратичная интерполяция (U-образная кривая)
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VolumeProfile {
+    #[serde(default = "VolumeProfile::default_open")]
+    pub open: f64,
+    #[serde(default = "VolumeProfile::default_midday")]
+    pub midday: f64,
+    #[serde(default = "VolumeProfile::default_close")]
+    pub close: f64,
+}
+
+impl VolumeProfile {
+    fn default_open() -> f64 {
+        2.5
+    }
+
+    fn default_midday() -> f64 {
+        0.5
+    }
+
+    fn default_close() -> f64 {
+        3.0
+    }
+
+    // Множитель объема для доли прошедшей сессии (0.0 - открытие, 1.0 - закрытие)
+    pub fn factor(&self, day_fraction: f64) -> f64 {
+        let x = day_fraction.clamp(0.0, 1.0);
+        if x < 0.5 {
+            self.midday + (self.open - self.midday) * (1.0 - 2.0 * x).powi(2)
+        } else {
+            self.midday + (self.close - self.midday) * (2.0 * x - 1.0).powi(2)
+        }
+    }
+}
+
+impl Default for VolumeProfile {
+    fn default() -> Self {
+        VolumeProfile {
+            open: Self::default_open(),
+            midday: Self::default_midday(),
+            close: Self::default_close(),
+        }
+    }
+}
+
+// Коридор цены: если цена за окно window_secs отклоняется от средней больше
+// чем на percent процентов, торги по тикеру останавливаются на halt_secs секунд
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PriceBand {
+    pub percent: f64,
+    #[serde(default = "PriceBand::default_window_secs")]
+    pub window_secs: u64,
+    #[serde(default = "PriceBand::default_halt_secs")]
+    pub halt_secs: u64,
+}
+
+impl PriceBand {
+    fn default_window_secs() -> u64 {
+        300
+    }
+
+    fn default_halt_secs() -> u64 {
+        60
+    }
+}
+
+// Синтетический индекс: сумма цен составляющих с весами, деленная на divisor.
+// Пересчитывается при каждой котировке любой из составляющих.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndexSpec {
+    pub constituents: HashMap<String, f64>,
+    #[serde(default = "IndexSpec::default_divisor")]
+    pub divisor: f64,
+}
+
+impl IndexSpec {
+    fn default_divisor() -> f64 {
+        1.0
+    }
+
+    // Значение индекса по ценам составляющих; отсутствующие составляющие пропускаются
+    pub fn level(&self, prices: &HashMap<String, f64>) -> Option<f64> {
+        let mut sum = 0.0;
+        let mut found = false;
+        for (ticker, weight) in &self.constituents {
+            if let Some(price) = prices.get(ticker) {
+                sum += weight * price;
+                found = true;
+            }
+        }
+        found.then(|| sum / self.divisor)
+    }
+}
+
+impl TickerSpec {
+    // Значения из self имеют приоритет над defaults
+    pub fn merged_with(&self, defaults: &TickerSpec) -> TickerSpec {
+        TickerSpec {
+            asset_class: self.asset_class.or(defaults.asset_class),
+            pip_size: self.pip_size.or(defaults.pip_size),
+            initial_price: self.initial_price.or(defaults.initial_price),
+            base_volume: self.base_volume.or(defaults.base_volume),
+            volatility: self.volatility.or(defaults.volatility),
+            tick_size: self.tick_size.or(defaults.tick_size),
+            drift: self.drift.or(defaults.drift),
+            volume_profile: self.volume_profile.or(defaults.volume_profile),
+            spread_bps: self.spread_bps.or(defaults.spread_bps),
+            price_band: self.price_band.or(defaults.price_band),
+            interval_ms: self.interval_ms.or(defaults.interval_ms),
+            index: self.index.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TickerFile {
+    #[serde(default)]
+    defaults: TickerSpec,
+    #[serde(default)]
+    tickers: HashMap<String, TickerSpec>,
+    #[serde(default)]
+    indices: HashMap<String, IndexSpec>,
+}
+
+// Загрузка тикеров из tickers.toml или из простого списка (по тикеру на строку).
+// Формат определяется по расширению файла.
+pub fn load_ticker_specs(filename: &str) -> io::Result<Vec<(String, TickerSpec)>> {
+    let content = std::fs::read_to_string(filename)?;
+
+    let is_toml = Path::new(filename)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+
+    if is_toml {
+        info!("Parsing ticker configuration as TOML: {}", filename);
+        parse_toml_specs(&content)
+    } else {
+        Ok(parse_plain_specs(&content))
+    }
+}
+
+pub fn parse_plain_specs(content: &str) -> Vec<(String, TickerSpec)> {
+    content
+        .lines()
+        .map(|line| line.trim().to_uppercase())
+        .filter(|line| !line.is_empty())
+        .map(|ticker| (ticker, TickerSpec::default()))
+        .collect()
+}
+
+pub fn parse_toml_specs(content: &str) -> io::Result<Vec<(String, TickerSpec)>> {
+    let file: TickerFile =
+        toml_lite::from_str(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut specs: Vec<(String, TickerSpec)> = file
+        .tickers
+        .iter()
+        .map(|(ticker, spec)| {
+            (
+                ticker.trim().to_uppercase(),
+                spec.merged_with(&file.defaults),
+            )
+        })
+        .collect();
+    specs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (ticker, spec) in &specs {
+        if let Some(tick_size) = spec.tick_size
+            && tick_size <= 0.0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("tick_size for {} must be positive", ticker),
+            ));
+        }
+        if let Some(pip_size) = spec.pip_size
+            && pip_size <= 0.0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("pip_size for {} must be positive", ticker),
+            ));
+        }
+        if spec.interval_ms == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("interval_ms for {} must be positive", ticker),
+            ));
+        }
+        if let Some(band) = spec.price_band
+            && band.percent <= 0.0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("price_band.percent for {} must be positive", ticker),
+            ));
+        }
+        debug!("Ticker spec {}: {:?}", ticker, spec);
+    }
+
+    // Индексы идут после обычных тикеров: их начальное значение считается по составляющим
+    let mut indices: Vec<(String, TickerSpec)> = Vec::new();
+    for (name, index) in file.indices {
+        let name = name.trim().to_uppercase();
+        let index = IndexSpec {
+            constituents: index
+                .constituents
+                .into_iter()
+                .map(|(ticker, weight)| (ticker.trim().to_uppercase(), weight))
+                .collect(),
+            ..index
+        };
+        validate_index(&name, &index, &specs)?;
+        debug!("Index {}: {:?}", name, index);
+        let spec = TickerSpec {
+            index: Some(index),
+            ..TickerSpec::default()
+        };
+        indices.push((name, spec));
+    }
+    indices.sort_by(|a, b| a.0.cmp(&b.0));
+    specs.extend(indices);
+
+    Ok(specs)
+}
+
+fn validate_index(name: &str, index: &IndexSpec, specs: &[(String, TickerSpec)]) -> io::Result<()> {
+    let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+
+    if specs.iter().any(|(ticker, _)| ticker == name) {
+        return invalid(format!(
+            "index {} clashes with a ticker of the same name",
+            name
+        ));
+    }
+    if index.constituents.is_empty() {
+        return invalid(format!("index {} has no constituents", name));
+    }
+    if !(index.divisor > 0.0 && index.divisor.is_finite()) {
+        return invalid(format!("divisor for index {} must be positive", name));
+    }
+    for (ticker, weight) in &index.constituents {
+        if !specs.iter().any(|(t, _)| t == ticker) {
+            return invalid(format!(
+                "index {} refers to unknown ticker {}",
+                name, ticker
+            ));
+        }
+        if !weight.is_finite() {
+            return invalid(format!(
+                "weight of {} in index {} must be a number",
+                ticker, name
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,396 @@
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+// Минимальный разбор TOML: таблицы, массивы таблиц, dotted-ключи, строки,
+// числа, bool, массивы и inline-таблицы. Результат - serde_json::Value,
+// который затем десериализуется в нужную структуру через serde.
+
+#[derive(Error, Debug)]
+#[error("TOML parse error at line {line}: {message}")]
+pub struct TomlError {
+    pub line: usize,
+    pub message: String,
+}
+
+pub fn parse(input: &str) -> Result<Value, TomlError> {
+    Parser::new(input).parse_document().map(Value::Object)
+}
+
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, TomlError> {
+    let value = parse(input)?;
+    serde_json::from_value(value).map_err(|e| TomlError {
+        line: 0,
+        message: e.to_string(),
+    })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Self {
+        Parser {
+            chars: input.chars().collect(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, TomlError> {
+        Err(TomlError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), TomlError> {
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => self.error(format!("expected '{}', found '{}'", expected, c)),
+            None => self.error(format!("expected '{}', found end of input", expected)),
+        }
+    }
+
+    // Пробелы и табы в пределах строки
+    fn skip_inline_ws(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while let Some(c) = self.peek() {
+                if c == '\n' {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+    }
+
+    // Пробелы, переводы строк и комментарии
+    fn skip_ws_and_comments(&mut self) {
+        loop {
+            self.skip_inline_ws();
+            match self.peek() {
+                Some('#') => self.skip_comment(),
+                Some('\n') | Some('\r') => {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn expect_line_end(&mut self) -> Result<(), TomlError> {
+        self.skip_inline_ws();
+        self.skip_comment();
+        match self.peek() {
+            None => Ok(()),
+            Some('\r') | Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("unexpected '{}' after value", c)),
+        }
+    }
+
+    fn parse_document(&mut self) -> Result<Map<String, Value>, TomlError> {
+        let mut root = Map::new();
+        let mut current: Vec<String> = Vec::new();
+
+        loop {
+            self.skip_ws_and_comments();
+            match self.peek() {
+                None => break,
+                Some('[') => {
+                    self.bump();
+                    let is_array = self.peek() == Some('[');
+                    if is_array {
+                        self.bump();
+                    }
+                    self.skip_inline_ws();
+                    let path = self.parse_key_path()?;
+                    self.skip_inline_ws();
+                    self.expect(']')?;
+                    if is_array {
+                        self.expect(']')?;
+                    }
+                    self.expect_line_end()?;
+
+                    if is_array {
+                        let (last, parents) = path.split_last().unwrap();
+                        let parent = self.table_at(&mut root, parents)?;
+                        let entry = parent
+                            .entry(last.clone())
+                            .or_insert_with(|| Value::Array(Vec::new()));
+                        match entry {
+                            Value::Array(items) => items.push(Value::Object(Map::new())),
+                            _ => return self.error(format!("key '{}' is not an array", last)),
+                        }
+                    } else {
+                        self.table_at(&mut root, &path)?;
+                    }
+                    current = path;
+                }
+                Some(_) => {
+                    let path = self.parse_key_path()?;
+                    // Ключ проверяется до значения, чтобы ошибка пришлась на его строку
+                    let (last, parents) = path.split_last().unwrap();
+                    let mut full = current.clone();
+                    full.extend_from_slice(parents);
+                    let table = self.table_at(&mut root, &full)?;
+                    if table.contains_key(last) {
+                        return self.error(format!("duplicate key '{}'", last));
+                    }
+                    self.skip_inline_ws();
+                    self.expect('=')?;
+                    self.skip_inline_ws();
+                    let value = self.parse_value()?;
+                    self.expect_line_end()?;
+                    table.insert(last.clone(), value);
+                }
+            }
+        }
+
+        Ok(root)
+    }
+
+    // Находит (или создает) таблицу по пути; для массивов таблиц берется последний элемент
+    fn table_at<'m>(
+        &self,
+        root: &'m mut Map<String, Value>,
+        path: &[String],
+    ) -> Result<&'m mut Map<String, Value>, TomlError> {
+        let mut table = root;
+        for key in path {
+            let entry = table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            table = match entry {
+                Value::Object(map) => map,
+                Value::Array(items) => match items.last_mut() {
+                    Some(Value::Object(map)) => map,
+                    _ => return self.error(format!("key '{}' is not a table", key)),
+                },
+                _ => return self.error(format!("key '{}' is not a table", key)),
+            };
+        }
+        Ok(table)
+    }
+
+    fn parse_key_path(&mut self) -> Result<Vec<String>, TomlError> {
+        let mut path = vec![self.parse_key()?];
+        loop {
+            self.skip_inline_ws();
+            if self.peek() != Some('.') {
+                break;
+            }
+            self.bump();
+            self.skip_inline_ws();
+            path.push(self.parse_key()?);
+        }
+        Ok(path)
+    }
+
+    fn parse_key(&mut self) -> Result<String, TomlError> {
+        match self.peek() {
+            Some('"') => self.parse_basic_string(),
+            Some('\'') => self.parse_literal_string(),
+            _ => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                if start == self.pos {
+                    return self.error("expected key");
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, TomlError> {
+        // Многострочные строки не поддерживаются: без этой проверки """ читается
+        // как пустая строка и дает невнятную ошибку про лишнюю кавычку
+        let rest = &self.chars[self.pos..];
+        if rest.starts_with(&['"'; 3]) || rest.starts_with(&['\''; 3]) {
+            return self.error("multi-line strings are not supported");
+        }
+        match self.peek() {
+            Some('"') => self.parse_basic_string().map(Value::String),
+            Some('\'') => self.parse_literal_string().map(Value::String),
+            Some('[') => self.parse_array(),
+            Some('{') => self.parse_inline_table(),
+            Some(_) => self.parse_scalar(),
+            None => self.error("expected value, found end of input"),
+        }
+    }
+
+    fn parse_basic_string(&mut self) -> Result<String, TomlError> {
+        self.expect('"')?;
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return self.error("unterminated string"),
+                Some('"') => return Ok(out),
+                Some('\\') => {
+                    let escaped = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => {
+                            let hex: String = (0..4).filter_map(|_| self.bump()).collect();
+                            match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                                Some(c) => c,
+                                None => return self.error(format!("invalid escape \\u{}", hex)),
+                            }
+                        }
+                        Some(c) => return self.error(format!("invalid escape \\{}", c)),
+                        None => return self.error("unterminated string"),
+                    };
+                    out.push(escaped);
+                }
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, TomlError> {
+        self.expect('\'')?;
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return self.error("unterminated string"),
+                Some('\'') => return Ok(out),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, TomlError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws_and_comments();
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(Value::Array(items));
+            }
+            items.push(self.parse_value()?);
+            self.skip_ws_and_comments();
+            match self.bump() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return self.error("expected ',' or ']' in array"),
+            }
+        }
+    }
+
+    fn parse_inline_table(&mut self) -> Result<Value, TomlError> {
+        self.expect('{')?;
+        let mut table = Map::new();
+        self.skip_inline_ws();
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(Value::Object(table));
+        }
+        loop {
+            self.skip_inline_ws();
+            let path = self.parse_key_path()?;
+            self.skip_inline_ws();
+            self.expect('=')?;
+            self.skip_inline_ws();
+            let value = self.parse_value()?;
+
+            let (last, parents) = path.split_last().unwrap();
+            let target = self.table_at(&mut table, parents)?;
+            if target.insert(last.clone(), value).is_some() {
+                return self.error(format!("duplicate key '{}'", last));
+            }
+
+            self.skip_inline_ws();
+            match self.bump() {
+                Some(',') => {}
+                Some('}') => return Ok(Value::Object(table)),
+                _ => return self.error("expected ',' or '}' in inline table"),
+            }
+        }
+    }
+
+    // Числа, bool и даты/время (последние сохраняются как строки)
+    fn parse_scalar(&mut self) -> Result<Value, TomlError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || matches!(c, ',' | ']' | '}' | '#') {
+                break;
+            }
+            self.pos += 1;
+        }
+        let token: String = self.chars[start..self.pos].iter().collect();
+
+        match token.as_str() {
+            "" => return self.error("expected value"),
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            _ => {}
+        }
+
+        // Локальное время/дата вида 09:30:00 или 2024-01-02
+        let bytes = token.as_bytes();
+        let is_date = bytes.len() >= 10 && bytes[4] == b'-' && bytes[7] == b'-';
+        if token.contains(':') || is_date {
+            return Ok(Value::String(token));
+        }
+
+        let cleaned = token.replace('_', "");
+        let is_float = !cleaned.starts_with("0x")
+            && (cleaned.contains('.') || cleaned.contains('e') || cleaned.contains('E'));
+
+        if is_float {
+            match cleaned.parse::<f64>() {
+                Ok(f) => serde_json::Number::from_f64(f)
+                    .map(Value::Number)
+                    .ok_or_else(|| TomlError {
+                        line: self.line,
+                        message: format!("unsupported float: {}", token),
+                    }),
+                Err(_) => self.error(format!("invalid value: {}", token)),
+            }
+        } else if let Some(hex) = cleaned.strip_prefix("0x") {
+            match i64::from_str_radix(hex, 16) {
+                Ok(i) => Ok(Value::from(i)),
+                Err(_) => self.error(format!("invalid value: {}", token)),
+            }
+        } else {
+            match cleaned.parse::<i64>() {
+                Ok(i) => Ok(Value::from(i)),
+                Err(_) => self.error(format!("invalid value: {}", token)),
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+// Сколько датаграмм отправляется одним системным вызовом по умолчанию
+pub const DEFAULT_SEND_BATCH: usize = 32;
+// Больше UIO_MAXIOV ядро за раз не принимает
+pub const MAX_SEND_BATCH: usize = 1024;
+
+// Отправка пачки датаграмм на один адрес. Ok - отправлены все; Err - сколько
+// успело уйти до ошибки и сама ошибка. Пока буфер сокета полон, задача ждет.
+#[cfg(target_os = "linux")]
+pub(crate) async fn send_batch(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    payloads: &[Arc<[u8]>],
+) -> Result<(), (usize, io::Error)> {
+    use tokio::io::Interest;
+
+    // Ядро может отправить только часть пачки: досылаем остаток
+    let mut sent = 0;
+    while sent < payloads.len() {
+        let rest = &payloads[sent..];
+        let result = socket
+            .async_io(Interest::WRITABLE, || sendmmsg(socket, target, rest))
+            .await;
+        match result {
+            Ok(count) => sent += count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err((sent, e)),
+        }
+    }
+    Ok(())
+}
+
+// Один вызов sendmmsg. Заголовки с указателями собираются на каждую попытку,
+// чтобы не держать их в задаче между ожиданиями.
+#[cfg(target_os = "linux")]
+fn sendmmsg(socket: &UdpSocket, target: SocketAddr, payloads: &[Arc<[u8]>]) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    let (addr, addr_len) = raw_addr(target);
+    let mut iovecs: Vec<libc::iovec> = payloads
+        .iter()
+        .map(|payload| libc::iovec {
+            iov_base: payload.as_ptr() as *mut libc::c_void,
+            iov_len: payload.len(),
+        })
+        .collect();
+    let mut headers: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .map(|iov| {
+            // SAFETY: mmsghdr - POD структура, нули в ней допустимы
+            let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            header.msg_hdr.msg_name = &addr as *const libc::sockaddr_storage as *mut libc::c_void;
+            header.msg_hdr.msg_namelen = addr_len;
+            header.msg_hdr.msg_iov = iov;
+            header.msg_hdr.msg_iovlen = 1;
+            header
+        })
+        .collect();
+
+    // SAFETY: заголовки ссылаются на iovecs, payloads и addr, живущие до конца функции
+    let result = unsafe {
+        libc::sendmmsg(
+            socket.as_raw_fd(),
+            headers.as_mut_ptr(),
+            headers.len() as libc::c_uint,
+            0,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(result as usize)
+}
+
+// Переносимый вариант: по системному вызову на датаграмму
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn send_batch(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    payloads: &[Arc<[u8]>],
+) -> Result<(), (usize, io::Error)> {
+    for (sent, payload) in payloads.iter().enumerate() {
+        socket
+            .send_to(payload, target)
+            .await
+            .map_err(|error| (sent, error))?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn raw_addr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // SAFETY: sockaddr_storage - POD структура, нули в ней допустимы
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            // SAFETY: sockaddr_storage достаточно велика и выровнена для sockaddr_in
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr = libc::in_addr {
+                s_addr: u32::from_ne_bytes(v4.ip().octets()),
+            };
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            // SAFETY: sockaddr_storage достаточно велика и выровнена для sockaddr_in6
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_flowinfo = v6.flowinfo();
+            sin6.sin6_addr = libc::in6_addr {
+                s6_addr: v6.ip().octets(),
+            };
+            sin6.sin6_scope_id = v6.scope_id();
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
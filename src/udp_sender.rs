@@ -1,142 +1,562 @@
-use crate::models::{ClientConfig, StockQuote};
-use crossbeam_channel::Receiver;
-use log::{debug, error, info, trace};
-use std::net::UdpSocket;
-use std::thread;
+use crate::error::QuoteError;
+use crate::generator::OverflowStats;
+use crate::impairment::{ImpairedLink, NetworkImpairment};
+use crate::metrics::ServerMetrics;
+use crate::models::{
+    ClientConfig, CommandError, FeedMessage, QuoteEvent, StreamOptions, current_millis,
+};
+use crate::observer::{LogObserver, ServerObserver};
+use crate::subscription::ClientFeed;
+use crate::udp_batch::{self, DEFAULT_SEND_BATCH};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{Instrument, debug, error_span, info, trace, warn};
+
+// Как часто задача отправки без новых котировок обновляет учет очереди
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+// Пауза после первой ошибки отправки; дальше удваивается до максимума
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+// Состояние отправителя, общее с ClientManager: сигнал остановки и счетчики доставки
+#[derive(Debug, Default)]
+pub struct SenderState {
+    stopped: AtomicBool,
+    // Будит задачу отправки, ждущую событий, при остановке
+    wake: Notify,
+    quotes_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    send_errors: AtomicU64,
+    // Время последней успешной отправки, мс; 0 - еще не было
+    last_send_ms: AtomicU64,
+    // Отправка не удается: задача на паузе и повторяет попытки
+    degraded: AtomicBool,
+    // Событий в очередях клиента и возраст самого старого из них, мс
+    queue_len: AtomicU64,
+    queue_lag_ms: AtomicU64,
+    // Потери в переполненных очередях генератора и отключение по переполнению
+    overflow: Arc<OverflowStats>,
+}
+
+// Снимок счетчиков доставки клиента
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DeliveryStats {
+    pub quotes_sent: u64,
+    pub bytes_sent: u64,
+    pub send_errors: u64,
+    pub last_send_ms: Option<u64>,
+    pub degraded: bool,
+    pub queue_len: u64,
+    pub queue_lag_ms: u64,
+    pub queue_dropped: u64,
+}
+
+impl SenderState {
+    // Остановка отправки: задачи завершаются, подписки освобождаются
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.wake.notify_one();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    // Ожидание stop(); сигнал, поданный до ожидания, не теряется
+    async fn stop_requested(&self) {
+        self.wake.notified().await;
+    }
+
+    pub fn stats(&self) -> DeliveryStats {
+        let last_send_ms = self.last_send_ms.load(Ordering::Relaxed);
+        DeliveryStats {
+            quotes_sent: self.quotes_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            send_errors: self.send_errors.load(Ordering::Relaxed),
+            last_send_ms: (last_send_ms > 0).then_some(last_send_ms),
+            degraded: self.is_degraded(),
+            queue_len: self.queue_len.load(Ordering::Relaxed),
+            queue_lag_ms: self.queue_lag_ms.load(Ordering::Relaxed),
+            queue_dropped: self.overflow.dropped(),
+        }
+    }
+
+    // Учет, который генератор ведет при переполнении очередей клиента
+    pub fn overflow_stats(&self) -> &Arc<OverflowStats> {
+        &self.overflow
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    fn record_send(&self, bytes: usize) {
+        self.quotes_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.last_send_ms.store(current_millis(), Ordering::Relaxed);
+    }
+
+    fn record_queue(&self, len: usize, lag: Duration) {
+        self.queue_len.store(len as u64, Ordering::Relaxed);
+        self.queue_lag_ms
+            .store(lag.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn record_error(&self) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
 pub struct UdpSender {
     client_id: String,
     config: ClientConfig,
-    quote_receivers: Vec<Receiver<StockQuote>>,
+    feed: ClientFeed,
+    impairment: NetworkImpairment,
+    send_batch: usize,
+    observer: Arc<dyn ServerObserver>,
+    metrics: Arc<ServerMetrics>,
 }
 
 impl UdpSender {
-    pub fn new(
-        client_id: String,
-        config: ClientConfig,
-        quote_receivers: Vec<Receiver<StockQuote>>,
-    ) -> Self {
-        debug!("Creating UDP sender for client: {}", client_id);
+    pub fn new(client_id: String, config: ClientConfig, feed: ClientFeed) -> Self {
+        debug!(%client_id, "Creating UDP sender");
         UdpSender {
             client_id,
             config,
-            quote_receivers,
+            feed,
+            impairment: NetworkImpairment::default(),
+            send_batch: DEFAULT_SEND_BATCH,
+            observer: Arc::new(LogObserver),
+            metrics: Arc::new(ServerMetrics::new()),
         }
     }
 
-    pub fn start(self) {
+    // Потери, задержки, дубли и перестановки датаграмм для проверки потребителя
+    pub fn with_impairment(mut self, impairment: NetworkImpairment) -> Self {
+        self.impairment = impairment;
+        self
+    }
+
+    // Сколько датаграмм накапливать для одного системного вызова; 1 - без пачек
+    pub fn with_send_batch(mut self, send_batch: usize) -> Self {
+        self.send_batch = send_batch.max(1);
+        self
+    }
+
+    // Кому сообщать об отправленных датаграммах
+    pub fn with_observer(mut self, observer: Arc<dyn ServerObserver>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    // Общий реестр счетчиков; датаграммы и ошибки всех клиентов суммируются в нем
+    pub fn with_metrics(mut self, metrics: Arc<ServerMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    // Запуск отправки задачей tokio; вызывается внутри среды выполнения
+    // сервера. Хэндл задачи позволяет дождаться ее завершения после stop().
+    pub fn start(self) -> Result<JoinHandle<()>, QuoteError> {
         info!(
-            "Starting UDP sender for client {} to {}",
-            self.client_id, self.config.udp_addr
+            client_id = %self.client_id,
+            udp_addr = %self.config.udp_addr,
+            tickers = %self.config.tickers.join(","),
+            "Starting UDP sender"
         );
-        info!(
-            "Subscribed to {} tickers: {:?}",
-            self.quote_receivers.len(),
-            self.config.tickers
+
+        let target = self.parse_udp_addr(&self.config.udp_addr)?;
+        debug!(client_id = %self.client_id, udp_addr = %target, "Parsed UDP address");
+
+        let udp_socket = std::net::UdpSocket::bind(local_addr_for(&target))?;
+        udp_socket.set_nonblocking(true)?;
+        let udp_socket = UdpSocket::from_std(udp_socket)?;
+
+        // События задачи отправителя идут с client_id и адресом получателя,
+        // без спана соединения, запустившего отправку: отправка его переживает
+        let span = error_span!(
+            parent: None,
+            "udp_sender",
+            client_id = %self.client_id,
+            udp_addr = %target
         );
+        let transmitter = Transmitter {
+            client_id: self.client_id,
+            socket: udp_socket,
+            destination: Destination::new(target, self.metrics.clone()),
+            state: self.config.sender.clone(),
+            link: self
+                .impairment
+                .is_active()
+                .then(|| ImpairedLink::new(self.impairment)),
+            batch: Vec::with_capacity(self.send_batch),
+            max_batch: self.send_batch,
+            sent_count: 0,
+            errors_count: 0,
+            observer: self.observer,
+            metrics: self.metrics,
+        };
+        let run = run(transmitter, self.feed, self.config.options);
+        Ok(tokio::spawn(run.instrument(span)))
+    }
 
-        let target_addr = match self.parse_udp_addr(&self.config.udp_addr) {
-            Ok(addr) => {
-                debug!("Parsed UDP address for {}: {}", self.client_id, addr);
-                addr
-            }
-            Err(e) => {
-                error!("Failed to parse UDP address for {}: {}", self.client_id, e);
-                return;
+    fn parse_udp_addr(&self, addr_str: &str) -> Result<String, CommandError> {
+        if let Some(addr) = addr_str.strip_prefix("udp://") {
+            Ok(addr.to_string())
+        } else {
+            Err(CommandError::InvalidAddress(addr_str.to_string()))
+        }
+    }
+}
+
+// Сокет отправки должен быть того же семейства, что и адрес клиента;
+// неразрешившееся имя хоста считается IPv4
+fn local_addr_for(target: &str) -> SocketAddr {
+    let ipv6 = target
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .is_some_and(|addr| addr.is_ipv6());
+    let ip: IpAddr = if ipv6 {
+        Ipv6Addr::UNSPECIFIED.into()
+    } else {
+        Ipv4Addr::UNSPECIFIED.into()
+    };
+    SocketAddr::new(ip, 0)
+}
+
+// Одна задача на клиента: события всех его тикеров приходят из общей очереди
+async fn run(mut transmitter: Transmitter, mut feed: ClientFeed, options: StreamOptions) {
+    let state = transmitter.state.clone();
+    let mut sampler = Sampler::new(&options);
+    info!("UDP sender task started");
+
+    loop {
+        if state.is_stopped() {
+            debug!("UDP sender stop requested");
+            break;
+        }
+        transmitter.flush_due().await;
+        let message = match feed.try_recv() {
+            Some(message) => message,
+            None => {
+                // Пачка уходит, как только очередь опустела, и не ждет новых событий
+                transmitter.flush_batch().await;
+                tokio::select! {
+                    _ = state.stop_requested() => continue,
+                    message = feed.recv() => match message {
+                        Some(message) => message,
+                        None => {
+                            debug!("Subscription closed");
+                            break;
+                        }
+                    },
+                    _ = tokio::time::sleep(transmitter.poll_interval()) => {
+                        state.record_queue(0, Duration::ZERO);
+                        continue;
+                    }
+                }
             }
         };
+        // Полученное событие - самое старое из ждавших в очереди
+        state.record_queue(feed.len() + 1, message.created.elapsed());
+
+        // С CONFLATE накопившиеся котировки тикеров заменяются последними
+        let batch = if options.conflate {
+            conflate(message, &mut feed)
+        } else {
+            vec![message]
+        };
 
-        let udp_socket = match UdpSocket::bind("127.0.0.1:0") {
-            Ok(socket) => {
-                debug!("UDP socket created for client {}", self.client_id);
-                socket
+        for message in batch {
+            // Пропускаем данные, которые клиент не запрашивал
+            if !options.accepts(&message.event) || !sampler.admit(&message.event) {
+                continue;
             }
-            Err(e) => {
-                error!("Failed to create UDP socket for {}: {}", self.client_id, e);
-                return;
+
+            // JSON уже сериализован генератором, общий для всех клиентов
+            transmitter.emit(message.payload).await;
+        }
+    }
+
+    info!(
+        sent = transmitter.sent_count,
+        errors = transmitter.errors_count,
+        "UDP sender stopped"
+    );
+}
+
+// Отправка датаграмм клиенту, при имитации плохой сети - через линию задержки.
+// Датаграммы копятся в пачку и уходят одним вызовом sendmmsg на Linux.
+struct Transmitter {
+    client_id: String,
+    socket: UdpSocket,
+    destination: Destination,
+    state: Arc<SenderState>,
+    link: Option<ImpairedLink>,
+    batch: Vec<Arc<[u8]>>,
+    max_batch: usize,
+    sent_count: u64,
+    errors_count: u64,
+    observer: Arc<dyn ServerObserver>,
+    metrics: Arc<ServerMetrics>,
+}
+
+impl Transmitter {
+    async fn emit(&mut self, payload: Arc<[u8]>) {
+        match &mut self.link {
+            Some(link) => {
+                link.push(payload);
+                self.flush_due().await;
             }
-        };
+            None => self.send(payload).await,
+        }
+    }
 
-        thread::spawn(move || {
-            let mut sent_count = 0;
-            let mut errors_count = 0;
+    // Отправка задержанных датаграмм, время которых наступило
+    async fn flush_due(&mut self) {
+        while let Some(payload) = self.link.as_mut().and_then(|link| link.pop_due()) {
+            self.send(payload).await;
+        }
+    }
 
-            info!("UDP sender thread started for client {}", self.client_id);
+    // Ожидание событий не дольше, чем до следующей задержанной датаграммы
+    fn poll_interval(&self) -> Duration {
+        self.link
+            .as_ref()
+            .and_then(|link| link.next_due_in())
+            .map_or(IDLE_POLL_INTERVAL, |due| due.min(IDLE_POLL_INTERVAL))
+    }
 
-            // Запускаем отдельный поток для каждого ресивера
-            let mut handles = Vec::new();
+    async fn send(&mut self, payload: Arc<[u8]>) {
+        self.batch.push(payload);
+        if self.batch.len() >= self.max_batch {
+            self.flush_batch().await;
+        }
+    }
 
-            for (i, receiver) in self.quote_receivers.into_iter().enumerate() {
-                let udp_socket = udp_socket.try_clone().expect("Failed to clone UDP socket");
-                let target_addr = target_addr.clone();
-                let client_id = self.client_id.clone();
+    async fn flush_batch(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let batch = std::mem::take(&mut self.batch);
 
-                let handle = thread::spawn(move || {
-                    let mut thread_sent_count = 0;
-                    let mut thread_errors_count = 0;
+        // На паузе после ошибок события не отправляются
+        let Some(target_addr) = self.destination.ready(&self.client_id, &self.state).await else {
+            self.recycle(batch);
+            return;
+        };
 
-                    debug!("Started receiver thread {} for client {}", i, client_id);
+        let started = Instant::now();
+        let (sent, error) = match udp_batch::send_batch(&self.socket, target_addr, &batch).await {
+            Ok(()) => (batch.len(), None),
+            Err((sent, e)) => (sent, Some(e)),
+        };
+        let elapsed = started.elapsed();
+        if sent > 0 {
+            self.destination.succeeded(&self.client_id, &self.state);
+        }
+        if let Some(e) = error {
+            // Остаток пачки пропадает, как и все события на время паузы
+            self.errors_count += 1;
+            self.destination
+                .failed(&self.client_id, &self.state, &e.to_string());
+        }
+        let mut bytes = 0;
+        for payload in &batch[..sent] {
+            self.sent_count += 1;
+            bytes += payload.len();
+            self.state.record_send(payload.len());
+            self.observer
+                .on_quote_sent(&self.client_id, payload, self.sent_count);
+        }
+        if sent > 0 {
+            self.metrics.record_sent(sent, bytes);
+            self.observer
+                .on_batch_sent(&self.client_id, sent, bytes, elapsed);
+        }
+        self.recycle(batch);
+    }
 
-                    for quote in receiver.iter() {
-                        let json_data = quote.to_json();
+    // Буфер пачки переиспользуется, чтобы не выделять память на каждую отправку
+    fn recycle(&mut self, mut batch: Vec<Arc<[u8]>>) {
+        batch.clear();
+        self.batch = batch;
+    }
+}
 
-                        if let Err(e) = udp_socket.send_to(json_data.as_bytes(), &target_addr) {
-                            error!(
-                                "Failed to send quote in thread {} for client {}: {}",
-                                i, client_id, e
-                            );
-                            thread_errors_count += 1;
+// Событие и все, что уже ждет в очереди клиента, без промежуточных сделок и
+// котировок bid/ask: из них остается только последняя каждого вида по каждому
+// тикеру. Статусы, бары и обновления стакана (они инкрементальные)
+// сохраняются в исходном порядке.
+fn conflate(first: FeedMessage, feed: &mut ClientFeed) -> Vec<FeedMessage> {
+    let pending: Vec<FeedMessage> = std::iter::once(first)
+        .chain(std::iter::from_fn(|| feed.try_recv()))
+        .collect();
+    if pending.len() == 1 {
+        return pending;
+    }
 
-                            if thread_errors_count > 5 {
-                                break;
-                            }
-                        } else {
-                            thread_sent_count += 1;
+    // Индекс последнего события каждого вида по тикеру; true - сделка
+    let kind = |event: &QuoteEvent| match event {
+        QuoteEvent::Trade(_) => Some(true),
+        QuoteEvent::Quote(_) => Some(false),
+        _ => None,
+    };
+    let mut last: HashMap<(&str, bool), usize> = HashMap::new();
+    for (i, message) in pending.iter().enumerate() {
+        if let Some(is_trade) = kind(&message.event) {
+            last.insert((message.event.ticker(), is_trade), i);
+        }
+    }
+    let keep: Vec<bool> = pending
+        .iter()
+        .enumerate()
+        .map(|(i, message)| match kind(&message.event) {
+            Some(is_trade) => last[&(message.event.ticker(), is_trade)] == i,
+            None => true,
+        })
+        .collect();
+    let total = pending.len();
+    let batch: Vec<FeedMessage> = pending
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(message, keep)| keep.then_some(message))
+        .collect();
+    trace!(total, kept = batch.len(), "Conflated queued events");
+    batch
+}
 
-                            if thread_sent_count % 50 == 0 {
-                                trace!(
-                                    "Thread {} for client {} sent {} quotes",
-                                    i, client_id, thread_sent_count
-                                );
-                            }
-                        }
-                    }
+// Прореживание сделок и котировок bid/ask по параметрам RATE и EVERY, отдельно
+// для каждого тикера и вида события. Статусы и стакан проходят всегда.
+struct Sampler {
+    every: u64,
+    min_interval_ms: Option<u64>,
+    state: HashMap<(String, bool), SampleState>,
+}
+
+#[derive(Default)]
+struct SampleState {
+    seen: u64,
+    last_sent_ms: Option<u64>,
+}
+
+impl Sampler {
+    fn new(options: &StreamOptions) -> Self {
+        Sampler {
+            every: options.every.unwrap_or(1) as u64,
+            min_interval_ms: options.max_rate.map(|rate| 1000 / rate.max(1) as u64),
+            state: HashMap::new(),
+        }
+    }
 
-                    (thread_sent_count, thread_errors_count)
-                });
+    fn admit(&mut self, event: &QuoteEvent) -> bool {
+        let is_trade = match event {
+            QuoteEvent::Trade(_) => true,
+            QuoteEvent::Quote(_) => false,
+            _ => return true,
+        };
+        if self.every <= 1 && self.min_interval_ms.is_none() {
+            return true;
+        }
 
-                handles.push(handle);
+        let state = self
+            .state
+            .entry((event.ticker().to_string(), is_trade))
+            .or_default();
+        state.seen += 1;
+        // Первое событие проходит, затем каждое every-е
+        if !(state.seen - 1).is_multiple_of(self.every) {
+            return false;
+        }
+        if let Some(min_interval) = self.min_interval_ms {
+            let now = current_millis();
+            if let Some(last) = state.last_sent_ms
+                && now.saturating_sub(last) < min_interval
+            {
+                return false;
             }
+            state.last_sent_ms = Some(now);
+        }
+        true
+    }
+}
 
-            // Ждем завершения всех потоков
-            for (i, handle) in handles.into_iter().enumerate() {
-                match handle.join() {
-                    Ok((thread_sent, thread_errors)) => {
-                        sent_count += thread_sent;
-                        errors_count += thread_errors;
-                        debug!(
-                            "Receiver thread {} finished: sent={}, errors={}",
-                            i, thread_sent, thread_errors
-                        );
-                    }
-                    Err(e) => {
-                        error!("Receiver thread {} panicked: {:?}", i, e);
-                    }
+// Адрес клиента с экспоненциальной паузой после ошибок отправки. При каждой
+// повторной попытке имя хоста разрешается заново: адрес клиента мог смениться.
+struct Destination {
+    target: String,
+    resolved: Option<SocketAddr>,
+    failures: u32,
+    retry_at_ms: u64,
+    metrics: Arc<ServerMetrics>,
+}
+
+impl Destination {
+    fn new(target: String, metrics: Arc<ServerMetrics>) -> Self {
+        Destination {
+            target,
+            resolved: None,
+            failures: 0,
+            retry_at_ms: 0,
+            metrics,
+        }
+    }
+
+    // Адрес для отправки; None - идет пауза или адрес не разрешился
+    async fn ready(&mut self, client_id: &str, state: &SenderState) -> Option<SocketAddr> {
+        if self.failures > 0 && current_millis() < self.retry_at_ms {
+            return None;
+        }
+        if self.resolved.is_none() || self.failures > 0 {
+            let resolved = tokio::net::lookup_host(self.target.as_str()).await;
+            match resolved.map(|mut addrs| addrs.next()) {
+                Ok(Some(addr)) => self.resolved = Some(addr),
+                Ok(None) => {
+                    self.failed(client_id, state, "address resolved to nothing");
+                    return None;
+                }
+                Err(e) => {
+                    self.failed(client_id, state, &e.to_string());
+                    return None;
                 }
             }
+        }
+        self.resolved
+    }
 
-            info!(
-                "UDP sender for client {} stopped. Sent {} quotes, errors: {}",
-                self.client_id, sent_count, errors_count
-            );
-        });
+    fn failed(&mut self, client_id: &str, state: &SenderState, reason: &str) {
+        state.record_error();
+        self.metrics.record_send_error();
+        self.failures += 1;
+        let backoff = INITIAL_BACKOFF
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(MAX_BACKOFF);
+        self.retry_at_ms = current_millis() + backoff.as_millis() as u64;
+        if !state.degraded.swap(true, Ordering::Relaxed) {
+            warn!(%client_id, udp_addr = %self.target, reason, "Client degraded: send failed");
+        }
+        debug!(
+            udp_addr = %self.target,
+            failures = self.failures,
+            ?backoff,
+            "Send failed, retrying later"
+        );
     }
 
-    fn parse_udp_addr(&self, addr_str: &str) -> Result<String, String> {
-        if let Some(addr) = addr_str.strip_prefix("udp://") {
-            Ok(addr.to_string())
-        } else {
-            Err(format!("Invalid UDP address format: {}", addr_str))
+    fn succeeded(&mut self, client_id: &str, state: &SenderState) {
+        if self.failures > 0 {
+            self.failures = 0;
+            if state.degraded.swap(false, Ordering::Relaxed) {
+                info!(%client_id, udp_addr = %self.target, "Client recovered");
+            }
         }
     }
 }
@@ -0,0 +1,81 @@
+use quote_common::{TestClient, TestServer};
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Лимит одновременных соединений: молчащие подключения не занимают его
+// навсегда, а клиент сверх лимита сразу получает отказ, а не ждет без ответа
+
+// Первая строка, которую сервер отправил новому подключению
+fn first_line(addr: SocketAddr) -> String {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    line.trim_end().to_string()
+}
+
+#[test]
+fn full_pool_rejects_at_once() {
+    let server = TestServer::builder()
+        .with_config(|config| {
+            config.transport.connection_workers = 2;
+            config.transport.idle_timeout_secs = 0;
+        })
+        .start()
+        .unwrap();
+    let _first = server.client().unwrap();
+    let _second = server.client().unwrap();
+
+    let started = Instant::now();
+    assert_eq!(
+        first_line(server.addr()),
+        "ERR 429 RATE_LIMITED server busy, try again later"
+    );
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn idle_connections_free_the_pool() {
+    let server = TestServer::builder()
+        .with_config(|config| {
+            config.transport.connection_workers = 2;
+            config.transport.idle_timeout_secs = 1;
+        })
+        .start()
+        .unwrap();
+    let mut idle = server.client().unwrap();
+    let mut streaming = server.client().unwrap();
+    assert_eq!(
+        streaming.stream(&["AAPL"], "").unwrap(),
+        "STREAMING_STARTED"
+    );
+    assert!(first_line(server.addr()).starts_with("ERR 429"));
+
+    // Молчащее соединение без потока закрывается, соединение с потоком остается
+    assert_eq!(
+        idle.read_line().unwrap(),
+        "ERR 429 RATE_LIMITED idle for 1s, disconnecting"
+    );
+    assert!(idle.read_line().is_err());
+    thread::sleep(Duration::from_millis(1500));
+    assert!(streaming.ping().unwrap());
+    assert_eq!(
+        streaming.command("ID").unwrap(),
+        format!("ID {}", streaming.session())
+    );
+
+    // Освободившийся поток достается следующему клиенту
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let client = loop {
+        match TestClient::connect(&server) {
+            Ok(client) => break client,
+            Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Err(e) => panic!("no free connection worker: {}", e),
+        }
+    };
+    assert!(!client.session().is_empty());
+}
@@ -0,0 +1,140 @@
+use quote_common::{QuoteEvent, TestServer};
+use std::time::Duration;
+
+// Сквозной сценарий клиента на тестовом сервере: STREAM, котировки по UDP,
+// PING и STOP
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[test]
+fn stream_ping_stop() {
+    let server = TestServer::builder()
+        .with_tickers(&["AAPL", "TSLA"])
+        .start()
+        .unwrap();
+    let mut client = server.client().unwrap();
+    assert!(!client.session().is_empty());
+
+    assert_eq!(client.stream(&["AAPL"], "").unwrap(), "STREAMING_STARTED");
+    let quotes = client.recv_quotes(3, TIMEOUT).unwrap();
+    assert_eq!(quotes.len(), 3);
+    assert!(quotes.iter().all(|quote| quote.ticker == "AAPL"));
+
+    assert!(client.ping().unwrap());
+
+    assert_eq!(client.stop().unwrap(), "STREAMING_STOPPED");
+    client.drain().unwrap();
+    assert!(
+        client
+            .recv_quote(Duration::from_millis(200))
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[test]
+fn stream_trades_and_quotes() {
+    let server = TestServer::start().unwrap();
+    let mut client = server.client().unwrap();
+
+    assert_eq!(
+        client.stream(&["TSLA"], "BOTH").unwrap(),
+        "STREAMING_STARTED"
+    );
+    let (mut trades, mut quotes) = (0, 0);
+    while trades == 0 || quotes == 0 {
+        match client.recv_event(TIMEOUT).unwrap() {
+            Some(QuoteEvent::Trade(trade)) => {
+                assert_eq!(trade.ticker, "TSLA");
+                trades += 1;
+            }
+            Some(QuoteEvent::Quote(quote)) => {
+                assert_eq!(quote.ticker, "TSLA");
+                assert!(quote.bid < quote.ask);
+                quotes += 1;
+            }
+            Some(_) => {}
+            None => panic!("no trade and bid/ask quote within {:?}", TIMEOUT),
+        }
+    }
+}
+
+#[test]
+fn stream_rejects_unknown_ticker() {
+    let server = TestServer::start().unwrap();
+    let mut client = server.client().unwrap();
+
+    let reply = client.stream(&["XYZ"], "").unwrap();
+    assert!(reply.starts_with("ERR 404 UNKNOWN_TICKER"), "{}", reply);
+    assert!(
+        client
+            .recv_event(Duration::from_millis(200))
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[test]
+fn kick_closes_the_session_connection() {
+    let server = TestServer::builder()
+        .with_tickers(&["AAPL"])
+        .start()
+        .unwrap();
+    let mut victim = server.client().unwrap();
+    let mut admin = server.client().unwrap();
+    assert_eq!(victim.stream(&["AAPL"], "").unwrap(), "STREAMING_STARTED");
+
+    let kicked = format!("KICKED {}", victim.session());
+    assert_eq!(
+        admin
+            .command(&format!("KICK {}", victim.session()))
+            .unwrap(),
+        kicked
+    );
+    assert_eq!(victim.read_line().unwrap(), kicked);
+    assert!(victim.stream(&["AAPL"], "").is_err());
+}
+
+#[test]
+fn conflated_stream_keeps_every_ticker() {
+    let server = TestServer::builder()
+        .with_tickers(&["AAPL", "TSLA"])
+        .with_interval_ms(1)
+        .start()
+        .unwrap();
+    let mut client = server.client().unwrap();
+
+    assert_eq!(
+        client.stream(&["AAPL", "TSLA"], "CONFLATE").unwrap(),
+        "STREAMING_STARTED"
+    );
+    let quotes = client.recv_quotes(50, TIMEOUT).unwrap();
+    for ticker in ["AAPL", "TSLA"] {
+        assert!(
+            quotes.iter().any(|quote| quote.ticker == ticker),
+            "{}",
+            ticker
+        );
+    }
+}
+
+#[test]
+fn shutdown_warns_streaming_clients() {
+    let server = TestServer::builder()
+        .with_tickers(&["AAPL"])
+        .start()
+        .unwrap();
+    let mut client = server.client().unwrap();
+    assert_eq!(client.stream(&["AAPL"], "").unwrap(), "STREAMING_STARTED");
+    assert!(client.recv_quote(TIMEOUT).unwrap().is_some());
+
+    server.shutdown();
+    assert_eq!(client.read_line().unwrap(), "SERVER_SHUTTING_DOWN");
+    client.drain().unwrap();
+    assert!(
+        client
+            .recv_quote(Duration::from_millis(200))
+            .unwrap()
+            .is_none()
+    );
+}
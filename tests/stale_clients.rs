@@ -0,0 +1,64 @@
+use quote_common::{TestClient, TestServer};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Ручные часы общие для процесса, поэтому эти тесты живут в своем бинарнике
+
+const PING_TIMEOUT_SECS: u64 = 5;
+
+// UDP адреса клиентов из ответа CLIENTS
+fn streaming_addrs(admin: &mut TestClient) -> Vec<String> {
+    let lines = admin.command_until("CLIENTS", "CLIENTS_END").unwrap();
+    lines
+        .iter()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|client| client["udp_addr"].as_str().map(str::to_string))
+        .collect()
+}
+
+// Сборщик устаревших клиентов обходит их раз в секунду реального времени
+fn wait_for(admin: &mut TestClient, done: impl Fn(&[String]) -> bool) -> Vec<String> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let addrs = streaming_addrs(admin);
+        if done(&addrs) || Instant::now() > deadline {
+            return addrs;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn clients_without_ping_are_removed() {
+    let server = TestServer::builder()
+        .with_tickers(&["AAPL"])
+        .with_mock_clock(1_700_000_000_000)
+        .with_config(|config| config.ping_timeout_secs = PING_TIMEOUT_SECS)
+        .start()
+        .unwrap();
+    let mut admin = server.client().unwrap();
+    let mut alive = server.client().unwrap();
+    let mut silent = server.client().unwrap();
+    let alive_addr = format!("udp://{}", alive.udp_addr());
+    let silent_addr = format!("udp://{}", silent.udp_addr());
+
+    assert_eq!(alive.stream(&["AAPL"], "").unwrap(), "STREAMING_STARTED");
+    assert_eq!(silent.stream(&["AAPL"], "").unwrap(), "STREAMING_STARTED");
+    assert_eq!(streaming_addrs(&mut admin).len(), 2);
+
+    // Пока таймаут не истек, оба клиента на месте
+    server.advance(Duration::from_secs(PING_TIMEOUT_SECS - 2));
+    assert!(alive.ping().unwrap());
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(streaming_addrs(&mut admin).len(), 2);
+
+    // silent не пинговал дольше таймаута, alive пинговал 3 секунды назад
+    server.advance(Duration::from_secs(3));
+    let addrs = wait_for(&mut admin, |addrs| addrs.len() < 2);
+    assert_eq!(addrs, vec![alive_addr]);
+    assert!(!addrs.contains(&silent_addr));
+
+    // Без PING уходит и второй
+    server.advance(Duration::from_secs(PING_TIMEOUT_SECS + 1));
+    assert!(wait_for(&mut admin, |addrs| addrs.is_empty()).is_empty());
+}
@@ -0,0 +1,158 @@
+use quote_common::toml_lite::{self, TomlError};
+use serde_json::{Value, json};
+
+// Что принимает и что отвергает разбор TOML, через который идут tickers.toml,
+// server.toml и файл ключей
+
+fn parse(input: &str) -> Value {
+    toml_lite::parse(input).unwrap_or_else(|e| panic!("{}: {}", input, e))
+}
+
+fn parse_err(input: &str) -> TomlError {
+    match toml_lite::parse(input) {
+        Ok(value) => panic!("{} parsed as {}", input, value),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn tables_and_scalars() {
+    let value = parse(
+        "# комментарий\n\
+         title = \"quotes\"\n\
+         \n\
+         [tickers.AAPL]\n\
+         initial_price = 180.5 # цена\n\
+         base_volume = 1_000\n\
+         mask = 0xff\n\
+         halted = false\n\
+         tags = [\"tech\", 'us',]\n",
+    );
+    assert_eq!(
+        value,
+        json!({
+            "title": "quotes",
+            "tickers": {"AAPL": {
+                "initial_price": 180.5,
+                "base_volume": 1000,
+                "mask": 255,
+                "halted": false,
+                "tags": ["tech", "us"],
+            }},
+        })
+    );
+}
+
+#[test]
+fn dotted_keys() {
+    let value = parse("a.b = 1\na.c = 2\n[t]\nx.y.z = true\n\"q.k\".w = 3\n");
+    assert_eq!(
+        value,
+        json!({
+            "a": {"b": 1, "c": 2},
+            "t": {"x": {"y": {"z": true}}, "q.k": {"w": 3}},
+        })
+    );
+}
+
+#[test]
+fn arrays_of_tables() {
+    let value =
+        parse("[[keys]]\nname = \"ops\"\n\n[[keys]]\nname = \"bot\"\n[keys.limits]\nrate = 5\n");
+    assert_eq!(
+        value,
+        json!({"keys": [
+            {"name": "ops"},
+            {"name": "bot", "limits": {"rate": 5}},
+        ]})
+    );
+}
+
+#[test]
+fn inline_tables() {
+    let value = parse("band = { percent = 10.0, window.secs = 60 }\nempty = {}\n");
+    assert_eq!(
+        value,
+        json!({
+            "band": {"percent": 10.0, "window": {"secs": 60}},
+            "empty": {},
+        })
+    );
+}
+
+#[test]
+fn dates_and_times_stay_strings() {
+    let value = parse("open = 09:30:00\nday = 2024-01-02\nat = 2024-01-02T09:30:00Z\n");
+    assert_eq!(
+        value,
+        json!({"open": "09:30:00", "day": "2024-01-02", "at": "2024-01-02T09:30:00Z"})
+    );
+}
+
+#[test]
+fn string_escapes() {
+    let value = parse(r#"s = "a\tb\n\"c\" \\ \u00e9\u20ac""#);
+    assert_eq!(value, json!({"s": "a\tb\n\"c\" \\ é€"}));
+
+    let literal = parse(r"path = 'C:\data\tickers.toml'");
+    assert_eq!(literal, json!({"path": r"C:\data\tickers.toml"}));
+
+    assert!(
+        parse_err(r#"s = "\uZZZZ""#)
+            .message
+            .contains("invalid escape")
+    );
+    assert!(parse_err(r#"s = "\q""#).message.contains("invalid escape"));
+}
+
+#[test]
+fn duplicate_keys() {
+    let error = parse_err("a = 1\nb = 2\na = 3\n");
+    assert_eq!(error.line, 3);
+    assert!(error.message.contains("duplicate key 'a'"));
+
+    assert!(
+        parse_err("[t]\nx = 1\nx = 2\n")
+            .message
+            .contains("duplicate key 'x'")
+    );
+    assert!(
+        parse_err("t = { x = 1, x = 2 }\n")
+            .message
+            .contains("duplicate key 'x'")
+    );
+    let error = parse_err("a = 1\na.b = 2\n");
+    assert_eq!(error.line, 2);
+    assert!(error.message.contains("not a table"));
+}
+
+#[test]
+fn multi_line_strings_are_rejected() {
+    let error = parse_err("s = \"\"\"\nline\n\"\"\"\n");
+    assert_eq!(error.line, 1);
+    assert_eq!(error.message, "multi-line strings are not supported");
+    assert_eq!(
+        parse_err("s = '''\nline\n'''\n").message,
+        "multi-line strings are not supported"
+    );
+}
+
+#[test]
+fn malformed_input() {
+    assert!(
+        parse_err("a = \"open\n")
+            .message
+            .contains("unterminated string")
+    );
+    assert!(parse_err("a = 1 2\n").message.contains("after value"));
+    assert!(parse_err("a = [1, 2\n").message.contains("in array"));
+    assert!(parse_err("a = tru\n").message.contains("invalid value"));
+    assert!(parse_err("= 1\n").message.contains("expected key"));
+    assert!(parse_err("[t\n").message.contains("expected ']'"));
+}
+
+#[test]
+fn crlf_line_endings() {
+    let value = parse("[t]\r\na = 1\r\nb = \"x\"\r\n");
+    assert_eq!(value, json!({"t": {"a": 1, "b": "x"}}));
+}